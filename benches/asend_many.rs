@@ -0,0 +1,70 @@
+//! Feeding values to the sink of an async generator: one `asend` per value vs `asend_many`.
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::asyncio::AsyncGenerator;
+
+/// Async generator feeding the sent integers to a sink discarding them.
+#[pyfunction]
+fn drain_sink() -> AsyncGenerator {
+    let stream = futures::stream::repeat_with(|| PyResult::Ok(()));
+    AsyncGenerator::from_stream(stream).with_sink(futures::sink::drain::<i64>())
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def asend(values):
+    agen = drain_sink()
+    start = time.perf_counter()
+    for value in values:
+        await agen.asend(value)
+    return time.perf_counter() - start
+
+async def asend_many(values):
+    agen = drain_sink()
+    start = time.perf_counter()
+    await agen.asend_many(values)
+    return time.perf_counter() - start
+
+def run(mode, n):
+    return asyncio.run(globals()[mode](range(n)))
+"#;
+
+fn asend_many(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let run = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("drain_sink", wrap_pyfunction!(drain_sink, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok(PyObject::from(py.eval("run", Some(globals), None)?))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("asend_many");
+    let n = 1000;
+    group.throughput(Throughput::Elements(n));
+    for mode in ["asend", "asend_many"] {
+        group.bench_with_input(BenchmarkId::new(mode, n), &n, |b, &n| {
+            // the event loop startup is not measured
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    (0..iters)
+                        .map(|_| {
+                            run.call1(py, (mode, n))
+                                .unwrap()
+                                .extract::<f64>(py)
+                                .unwrap()
+                        })
+                        .map(Duration::from_secs_f64)
+                        .sum()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, asend_many);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+//! Per-step overhead of backend coroutines, driven with `send(None)` inside their event loop,
+//! i.e. the cost of the object yielded to the event loop at each suspension.
+//!
+//! A spurious step, i.e. without wake since the previous one, reuses the yielded object when the
+//! backend allows it (asyncio), while a woken step renews it.
+use std::{task::Poll, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+
+/// Asyncio coroutine never completing, woken at each step if `woken`.
+#[pyfunction]
+fn asyncio_pending(woken: bool) -> pyo3_async::asyncio::Coroutine {
+    pyo3_async::asyncio::Coroutine::from_future(futures::future::poll_fn(move |cx| {
+        if woken {
+            cx.waker().wake_by_ref();
+        }
+        Poll::<PyResult<()>>::Pending
+    }))
+}
+
+/// Trio coroutine never completing, nor woken, each step yielding
+/// `wait_task_rescheduled(abort_func)`.
+#[pyfunction]
+fn trio_pending() -> pyo3_async::trio::Coroutine {
+    pyo3_async::trio::Coroutine::from_future(futures::future::poll_fn(|_| {
+        Poll::<PyResult<()>>::Pending
+    }))
+}
+
+const CODE: &str = r#"
+import asyncio
+import functools
+import time
+
+import trio
+
+async def steps(coroutine, n):
+    send = coroutine.send
+    start = time.perf_counter()
+    for _ in range(n):
+        send(None)
+    return time.perf_counter() - start
+
+def run(backend, factory, n):
+    if backend == "asyncio":
+        return asyncio.run(steps(factory(), n))
+    return trio.run(steps, factory(), n)
+
+def asyncio_factory(woken):
+    return functools.partial(asyncio_pending, woken)
+"#;
+
+fn backend_step(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let (run, cases) = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("asyncio_pending", wrap_pyfunction!(asyncio_pending, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        let asyncio_factory = py.eval("asyncio_factory", Some(globals), None)?;
+        let trio_pending = PyObject::from(wrap_pyfunction!(trio_pending, py)?);
+        let cases = [
+            (
+                "asyncio",
+                "spurious",
+                asyncio_factory.call1((false,))?.into(),
+            ),
+            ("asyncio", "woken", asyncio_factory.call1((true,))?.into()),
+            ("trio", "spurious", trio_pending),
+        ];
+        PyResult::Ok((PyObject::from(py.eval("run", Some(globals), None)?), cases))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("backend_step");
+    for (backend, name, factory) in &cases {
+        group.bench_function(format!("{backend}/{name}"), |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let elapsed = run.call1(py, (*backend, factory, iters)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, backend_step);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+//! Overhead of starting a coroutine, awaited in asyncio: `sniffio` coroutines sniff the running
+//! backend on their first step, while `asyncio` coroutines only check the running loop.
+use std::{task::Poll, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+
+/// Coroutine suspended once, woken in the polling thread.
+fn suspend_once() -> impl std::future::Future<Output = PyResult<()>> + Send {
+    let mut suspended = false;
+    futures::future::poll_fn(move |cx| {
+        if suspended {
+            return Poll::Ready(Ok(()));
+        }
+        suspended = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+}
+
+#[pyfunction]
+fn asyncio_coroutine() -> pyo3_async::asyncio::Coroutine {
+    pyo3_async::asyncio::Coroutine::from_future(suspend_once())
+}
+
+#[pyfunction]
+fn sniffio_coroutine() -> pyo3_async::sniffio::Coroutine {
+    pyo3_async::sniffio::Coroutine::from_future(suspend_once())
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def main(factory, n):
+    start = time.perf_counter()
+    for _ in range(n):
+        await factory()
+    return time.perf_counter() - start
+
+def run(factory, n):
+    return asyncio.run(main(factory, n))
+"#;
+
+fn coroutine_start(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let (run, asyncio_coroutine, sniffio_coroutine) = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok((
+            PyObject::from(py.eval("run", Some(globals), None)?),
+            PyObject::from(wrap_pyfunction!(asyncio_coroutine, py)?),
+            PyObject::from(wrap_pyfunction!(sniffio_coroutine, py)?),
+        ))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("coroutine_start");
+    for (name, factory) in [
+        ("asyncio", &asyncio_coroutine),
+        ("sniffio", &sniffio_coroutine),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let elapsed = run.call1(py, (factory, iters)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, coroutine_start);
+criterion_main!(benches);
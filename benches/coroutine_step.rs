@@ -0,0 +1,62 @@
+//! Per-step overhead of a coroutine driven with `send(None)`, i.e. the cost of the coroutine
+//! machinery around a future poll, compared to a Python generator.
+use std::{task::Poll, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::manual::Coroutine;
+
+/// Coroutine never completing, woken at each step.
+#[pyfunction]
+fn pending() -> Coroutine {
+    Coroutine::from_step_fn(|_, cx| {
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+}
+
+const CODE: &str = r#"
+import time
+
+def generator():
+    while True:
+        yield
+
+def run(coroutine, n):
+    send = coroutine.send
+    start = time.perf_counter()
+    for _ in range(n):
+        send(None)
+    return time.perf_counter() - start
+"#;
+
+fn coroutine_step(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let (run, generator, pending) = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        py.run(CODE, Some(globals), None)?;
+        let eval = |name| PyResult::Ok(PyObject::from(py.eval(name, Some(globals), None)?));
+        PyResult::Ok((
+            eval("run")?,
+            eval("generator")?,
+            wrap_pyfunction!(pending, py)?.into_py(py),
+        ))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("coroutine_step");
+    for (name, factory) in [("coroutine", &pending), ("generator", &generator)] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let coroutine = factory.call0(py).unwrap();
+                    let elapsed = run.call1(py, (coroutine, iters)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, coroutine_step);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+//! Iteration of a Python async generator with `AsyncGeneratorWrapper`: polled as a `Stream`,
+//! acquiring the GIL at each poll, vs wrapped in `WithGilToken`, reusing the GIL token of the
+//! coroutine poll.
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::StreamExt;
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::{
+    asyncio::{AsyncGeneratorWrapper, Coroutine, WithGilToken},
+    PyFuture, PyStream,
+};
+
+/// Count the items of a [`PyStream`].
+struct Count<S>(S, usize);
+
+impl<S: PyStream + Unpin> PyFuture for Count<S> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        while let Some(item) = ready!(Pin::new(&mut this.0).poll_next_py(py, cx)) {
+            item?;
+            this.1 += 1;
+        }
+        Poll::Ready(Ok(this.1.into_py(py)))
+    }
+}
+
+/// Count the items of an async generator, with `WithGilToken` if `gil_token`.
+#[pyfunction]
+fn count(async_generator: &PyAny, gil_token: bool) -> Coroutine {
+    let mut wrapper = AsyncGeneratorWrapper::new(async_generator);
+    if gil_token {
+        return Coroutine::from_future(Count(WithGilToken(wrapper), 0));
+    }
+    Coroutine::from_future(async move {
+        let mut count = 0;
+        while let Some(item) = wrapper.next().await {
+            item?;
+            count += 1;
+        }
+        PyResult::Ok(count)
+    })
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def gen(n):
+    for i in range(n):
+        yield i
+
+async def main(n, gil_token):
+    start = time.perf_counter()
+    assert await count(gen(n), gil_token) == n
+    return time.perf_counter() - start
+
+def run(n, gil_token):
+    return asyncio.run(main(n, gil_token))
+"#;
+
+fn gil_token(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let run = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("count", wrap_pyfunction!(count, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok(PyObject::from(py.eval("run", Some(globals), None)?))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("gil_token");
+    for (name, gil_token) in [("with_gil", false), ("gil_token", true)] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let elapsed = run.call1(py, (iters, gil_token)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gil_token);
+criterion_main!(benches);
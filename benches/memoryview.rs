@@ -0,0 +1,31 @@
+//! Handing Rust byte chunks to Python: zero-copy `memoryview` vs `bytes` copy.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use pyo3::{prelude::*, types::PyBytes};
+use pyo3_async::io;
+
+fn memoryview(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let mut group = c.benchmark_group("memoryview");
+    for size in [1 << 10, 1 << 16, 1 << 20] {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("memoryview", size), &size, |b, &size| {
+            b.iter_batched(
+                || vec![42u8; size],
+                |chunk| Python::with_gil(|py| io::memoryview(py, chunk).unwrap()),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("bytes", size), &size, |b, &size| {
+            // the nested GIL pool releases the `bytes` object at each iteration
+            b.iter_batched(
+                || vec![42u8; size],
+                |chunk| Python::with_gil(|py| PyObject::from(PyBytes::new(py, &chunk))),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, memoryview);
+criterion_main!(benches);
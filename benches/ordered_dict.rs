@@ -0,0 +1,34 @@
+//! Conversion of a `BTreeMap` into a Python `dict`: `IntoPyOrdered` vs pyo3 `IntoPy`, to be
+//! compared with and without the `presized-dict` feature, e.g.
+//! `cargo bench --bench ordered_dict --features presized-dict`.
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use pyo3::prelude::*;
+use pyo3_async::convert::IntoPyOrdered;
+
+fn ordered_dict(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let mut group = c.benchmark_group("ordered_dict");
+    for len in [10, 1000, 100_000] {
+        let map: BTreeMap<u64, u64> = (0..len).map(|i| (i, i)).collect();
+        group.bench_with_input(BenchmarkId::new("ordered", len), &map, |b, map| {
+            b.iter_batched(
+                || map.clone(),
+                |map| Python::with_gil(|py| map.into_py_ordered(py).unwrap()),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("into_py", len), &map, |b, map| {
+            b.iter_batched(
+                || map.clone(),
+                |map| Python::with_gil(|py| map.into_py(py)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ordered_dict);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! GIL contention of async generators wrapping CPU-bound streams, generated by the
+//! `#[pyo3_async::pyfunction]` macro with and without `allow_threads`.
+//!
+//! The measured time is not the iteration duration, but the longest gap between two ticks of a
+//! concurrent Python thread, i.e. how long the thread is blocked waiting for the GIL.
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{Stream, StreamExt};
+use pyo3::{prelude::*, types::PyDict};
+
+const ITEMS: u64 = 10;
+const WORK: Duration = Duration::from_millis(20);
+
+/// Stream yielding `ITEMS` items, each produced by `WORK` of CPU work.
+fn work() -> impl Stream<Item = PyResult<u64>> + Send + 'static {
+    futures::stream::iter(0..ITEMS).map(|i| {
+        let start = Instant::now();
+        while start.elapsed() < WORK {
+            std::hint::spin_loop();
+        }
+        Ok(i)
+    })
+}
+
+#[pyo3_async::pyfunction(asyncio, allow_threads)]
+fn released() -> impl Stream<Item = PyResult<u64>> + Send + 'static {
+    work()
+}
+
+#[pyo3_async::pyfunction(asyncio)]
+fn held() -> impl Stream<Item = PyResult<u64>> + Send + 'static {
+    work()
+}
+
+const CODE: &str = r#"
+import asyncio
+import threading
+import time
+
+def ticker(gaps, stop):
+    last = time.perf_counter()
+    while not stop.is_set():
+        time.sleep(0.001)
+        now = time.perf_counter()
+        gaps.append(now - last)
+        last = now
+
+async def main(generator):
+    gaps, stop = [], threading.Event()
+    thread = threading.Thread(target=ticker, args=(gaps, stop))
+    thread.start()
+    async for _ in generator():
+        pass
+    stop.set()
+    thread.join()
+    return max(gaps)
+
+def run(n, generator):
+    return sum(asyncio.run(main(generator)) for _ in range(n))
+"#;
+
+fn stream_allow_threads(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let (run, released, held) = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok((
+            PyObject::from(py.eval("run", Some(globals), None)?),
+            PyObject::from(wrap_pyfunction!(async_released, py)?),
+            PyObject::from(wrap_pyfunction!(async_held, py)?),
+        ))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("stream_allow_threads");
+    group.sample_size(10);
+    for (name, generator) in [("released", &released), ("held", &held)] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let max_gaps = run.call1(py, (iters, generator)).unwrap();
+                    Duration::from_secs_f64(max_gaps.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stream_allow_threads);
+criterion_main!(benches);
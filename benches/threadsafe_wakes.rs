@@ -0,0 +1,95 @@
+//! Cost of threadsafe wakes of asyncio coroutines, to be compared with and without the
+//! `coalesce-wakes` and `batch-wakes` features, e.g.
+//! `cargo bench --bench threadsafe_wakes --features coalesce-wakes`.
+//!
+//! Without coalescing, each wake schedules its own `call_soon_threadsafe` callback, even when the
+//! coroutine has already been woken (`repeated`). Without batching, each coroutine woken at the
+//! same time schedules its own callback too (`concurrent`).
+use std::{
+    sync::{mpsc, Mutex, OnceLock},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::asyncio::Coroutine;
+
+/// Wakes the received wakers, the given number of times each, from a dedicated thread.
+fn remote_waker() -> &'static Mutex<mpsc::Sender<(Waker, usize)>> {
+    static SENDER: OnceLock<Mutex<mpsc::Sender<(Waker, usize)>>> = OnceLock::new();
+    SENDER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<(Waker, usize)>();
+        std::thread::spawn(move || {
+            for (waker, wakes) in receiver {
+                (0..wakes).for_each(|_| waker.wake_by_ref());
+            }
+        });
+        Mutex::new(sender)
+    })
+}
+
+/// Coroutine suspended once, woken `wakes` times from the remote waker thread.
+#[pyfunction]
+fn remote_wakes(wakes: usize) -> Coroutine {
+    let mut suspended = false;
+    Coroutine::from_future(futures::future::poll_fn(move |cx| {
+        if suspended {
+            return Poll::Ready(PyResult::Ok(()));
+        }
+        suspended = true;
+        let waker = cx.waker().clone();
+        remote_waker().lock().unwrap().send((waker, wakes)).unwrap();
+        Poll::Pending
+    }))
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def repeated(n):
+    for _ in range(n):
+        await remote_wakes(100)
+
+async def concurrent(n):
+    for _ in range(n):
+        await asyncio.gather(*(remote_wakes(1) for _ in range(100)))
+
+async def main(case, n):
+    start = time.perf_counter()
+    await case(n)
+    elapsed = time.perf_counter() - start
+    # let the remaining wake callbacks run before closing the loop
+    await asyncio.sleep(0.01)
+    return elapsed
+
+def run(case, n):
+    return asyncio.run(main(globals()[case], n))
+"#;
+
+fn threadsafe_wakes(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let run = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("remote_wakes", wrap_pyfunction!(remote_wakes, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok(PyObject::from(py.eval("run", Some(globals), None)?))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("threadsafe_wakes");
+    for case in ["repeated", "concurrent"] {
+        group.bench_function(case, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let elapsed = run.call1(py, (case, iters)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, threadsafe_wakes);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Latency of threadsafe wakes with `WakePriority::Normal` vs `WakePriority::Immediate`, with
+//! `coalesce-wakes`/`batch-wakes` features enabled.
+use std::{
+    sync::{mpsc, Mutex, OnceLock},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::{asyncio::Coroutine, WakePriority};
+
+/// Wakes the received wakers from a dedicated thread.
+fn remote_waker() -> &'static Mutex<mpsc::Sender<Waker>> {
+    static SENDER: OnceLock<Mutex<mpsc::Sender<Waker>>> = OnceLock::new();
+    SENDER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Waker>();
+        std::thread::spawn(move || receiver.into_iter().for_each(Waker::wake));
+        Mutex::new(sender)
+    })
+}
+
+/// Coroutine suspended once, woken from the remote waker thread.
+#[pyfunction]
+fn remote_wake(immediate: bool) -> Coroutine {
+    let priority = match immediate {
+        true => WakePriority::Immediate,
+        false => WakePriority::Normal,
+    };
+    let mut woken = false;
+    let future = futures::future::poll_fn(move |cx| {
+        if woken {
+            return Poll::Ready(PyResult::Ok(()));
+        }
+        woken = true;
+        remote_waker()
+            .lock()
+            .unwrap()
+            .send(cx.waker().clone())
+            .unwrap();
+        Poll::Pending
+    });
+    Coroutine::from_future(future).with_wake_priority(priority)
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def main(n, immediate):
+    start = time.perf_counter()
+    for _ in range(n):
+        await remote_wake(immediate)
+    return time.perf_counter() - start
+
+def run(n, immediate):
+    return asyncio.run(main(n, immediate))
+"#;
+
+fn wake_priority(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let run = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("remote_wake", wrap_pyfunction!(remote_wake, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok(PyObject::from(py.eval("run", Some(globals), None)?))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("wake_priority");
+    for (name, immediate) in [("normal", false), ("immediate", true)] {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let elapsed = run.call1(py, (iters, immediate)).unwrap();
+                    Duration::from_secs_f64(elapsed.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, wake_priority);
+criterion_main!(benches);
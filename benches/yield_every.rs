@@ -0,0 +1,86 @@
+//! Event loop starvation by a CPU-bound future, with and without `yield_every` budget.
+//!
+//! The measured time is not the duration of the future, but the longest gap between two steps of
+//! a concurrent asyncio task, i.e. how long the event loop is blocked by the future.
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pyo3::{prelude::*, types::PyDict};
+use pyo3_async::{asyncio::Coroutine, budget, PyFutureExt};
+
+const BURSTS: usize = 10;
+const BURST: Duration = Duration::from_millis(4);
+
+/// Coroutine doing `BURSTS` CPU bursts of `BURST`, reaching a checkpoint after each one, with
+/// a `budget` in milliseconds if provided.
+#[pyfunction]
+fn bursts(budget: Option<u64>) -> Coroutine {
+    let future = async {
+        for _ in 0..BURSTS {
+            let start = Instant::now();
+            while start.elapsed() < BURST {
+                std::hint::spin_loop();
+            }
+            budget::checkpoint().await;
+        }
+        PyResult::Ok(())
+    };
+    match budget {
+        Some(budget) => Coroutine::from_future(future.yield_every(Duration::from_millis(budget))),
+        None => Coroutine::from_future(future),
+    }
+}
+
+const CODE: &str = r#"
+import asyncio
+import time
+
+async def ticker(gaps):
+    last = time.perf_counter()
+    while True:
+        await asyncio.sleep(0)
+        now = time.perf_counter()
+        gaps.append(now - last)
+        last = now
+
+async def main(budget):
+    gaps = []
+    task = asyncio.create_task(ticker(gaps))
+    await asyncio.sleep(0)
+    await bursts(budget)
+    # let the ticker record the last gap
+    await asyncio.sleep(0)
+    task.cancel()
+    return max(gaps)
+
+def run(n, budget):
+    return sum(asyncio.run(main(budget)) for _ in range(n))
+"#;
+
+fn yield_every(c: &mut Criterion) {
+    pyo3::prepare_freethreaded_python();
+    let run = Python::with_gil(|py| {
+        let globals = PyDict::new(py);
+        globals.set_item("bursts", wrap_pyfunction!(bursts, py)?)?;
+        py.run(CODE, Some(globals), None)?;
+        PyResult::Ok(PyObject::from(py.eval("run", Some(globals), None)?))
+    })
+    .unwrap();
+    let mut group = c.benchmark_group("yield_every");
+    group.sample_size(10);
+    for budget in [None, Some(10), Some(1)] {
+        let id = budget.map_or("none".into(), |ms| format!("{ms}ms"));
+        group.bench_with_input(BenchmarkId::new("max_gap", id), &budget, |b, &budget| {
+            b.iter_custom(|iters| {
+                Python::with_gil(|py| {
+                    let max_gaps = run.call1(py, (iters, budget)).unwrap();
+                    Duration::from_secs_f64(max_gaps.extract(py).unwrap())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, yield_every);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+fn main() {
+    // `Py_LIMITED_API`/`PyPy` cfgs, e.g. to exclude private CPython API
+    pyo3_build_config::use_pyo3_cfgs();
+    println!("cargo:rustc-check-cfg=cfg(Py_LIMITED_API)");
+    println!("cargo:rustc-check-cfg=cfg(PyPy)");
+    println!("cargo:rustc-check-cfg=cfg(Py_3_11)");
+}
@@ -0,0 +1,42 @@
+//! Plugin exposing awaitables through the C ABI of `pyo3_async::erased`, without registering any
+//! pyclass; the host wraps them into its own coroutines.
+use std::{future::poll_fn, task::Poll};
+
+use pyo3::{exceptions::PyValueError, ffi, prelude::*};
+use pyo3_async::erased::ErasedPyFuture;
+
+/// Count down from `n`, yielding to the event loop at each step, and return `"liftoff"`;
+/// raise `ValueError` if `n` is negative.
+#[no_mangle]
+pub extern "C" fn erased_plugin_countdown(n: i64) -> ErasedPyFuture {
+    ErasedPyFuture::new(async move {
+        if n < 0 {
+            return Err(PyValueError::new_err("negative countdown"));
+        }
+        let mut remaining = n;
+        poll_fn(|cx| {
+            if remaining == 0 {
+                return Poll::Ready(());
+            }
+            remaining -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await;
+        Ok("liftoff")
+    })
+}
+
+/// Never complete, holding a reference to `obj` until dropped.
+///
+/// # Safety
+///
+/// `obj` must be a valid object pointer, borrowed for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn erased_plugin_hold(obj: *mut ffi::PyObject) -> ErasedPyFuture {
+    let obj: PyObject = Python::with_gil(|py| Py::from_borrowed_ptr(py, obj));
+    ErasedPyFuture::new(async move {
+        let _obj = obj;
+        std::future::pending::<PyResult<()>>().await
+    })
+}
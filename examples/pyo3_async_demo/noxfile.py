@@ -0,0 +1,11 @@
+"""Build the demo extension with maturin, and run its test suite against every backend."""
+import nox
+
+
+@nox.session
+def tests(session):
+    session.install("maturin", "pytest", "sniffio", "trio", "uvloop")
+    # the erased plugin is loaded dynamically from its build directory
+    session.run("cargo", "build", "--manifest-path", "../erased_plugin/Cargo.toml", external=True)
+    session.run("maturin", "develop")
+    session.run("pytest", "tests")
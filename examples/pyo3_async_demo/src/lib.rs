@@ -0,0 +1,1233 @@
+//! End-to-end sample extension exercising the public API, tested by `tests/test_demo.py`.
+// emitted by pyo3 0.20 macros with recent compilers
+#![allow(non_local_definitions)]
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use futures::{AsyncBufReadExt, FutureExt, SinkExt, Stream, StreamExt, TryStreamExt};
+use pyo3::{
+    exceptions::{PyConnectionRefusedError, PyImportError, PyRuntimeError, PyValueError},
+    ffi,
+    prelude::*,
+    sync::GILOnceCell,
+    types::PyBytes,
+};
+use pyo3_async::{
+    asyncio::{self, TaskContext},
+    budget, combinators,
+    conversions::{PyDateTime, PyDecimal, PyDuration},
+    erased::ErasedPyFuture,
+    io::PyAsyncReader,
+    progress::ProgressFuture,
+    runtime::{self, AbortOnDrop},
+    sniffio::{AsyncGenerator, Broadcast, Coroutine},
+    ErrorPolicy, LagPolicy, PyFuture, PyFutureExt, PyStreamExt,
+};
+
+fn tokio() -> &'static tokio::runtime::Runtime {
+    static RT: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RT.get_or_init(|| tokio::runtime::Runtime::new().unwrap())
+}
+
+/// Sleep on the tokio runtime, the timer task being aborted if the future is dropped.
+fn sleep(seconds: f64) -> AbortOnDrop<()> {
+    runtime::spawn(tokio().handle(), async move {
+        tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        PyResult::Ok(())
+    })
+}
+
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Count the drops of a cancelled future.
+struct DropGuard;
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Async function.
+#[pyo3_async::pyfunction(sniffio)]
+async fn add(a: i64, b: i64, delay: f64) -> PyResult<i64> {
+    sleep(delay).await?;
+    Ok(a + b)
+}
+
+/// Async function in `asyncio` context, which can also be called synchronously with `result`.
+#[pyo3_async::pyfunction(asyncio)]
+async fn multiply(a: i64, b: i64, delay: f64) -> PyResult<i64> {
+    sleep(delay).await?;
+    Ok(a * b)
+}
+
+/// Async function to be cancelled, counting its drops.
+#[pyo3_async::pyfunction(sniffio)]
+async fn cancellable_sleep(seconds: f64) -> PyResult<()> {
+    let _guard = DropGuard;
+    sleep(seconds).await
+}
+
+/// Sleep counting the drops of its future with a guard.
+#[pyfunction]
+fn guarded_sleep(seconds: f64) -> Coroutine {
+    Coroutine::from_future_with_guard(sleep(seconds), || {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// Sleep in a spawned tokio task, counting the drops of the task future, or panic in the task.
+#[pyfunction]
+fn spawned_sleep(seconds: f64, panic: bool) -> Coroutine {
+    Coroutine::from_future(runtime::spawn(tokio().handle(), async move {
+        let _guard = DropGuard;
+        assert!(!panic, "spawned task panicked");
+        tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        PyResult::Ok(())
+    }))
+}
+
+#[pyfunction]
+fn dropped_count() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Never completing future, counting its drop, even if it has never been polled.
+fn guarded_pending() -> impl Future<Output = PyResult<()>> {
+    let guard = DropGuard;
+    async move {
+        let _guard = guard;
+        futures::future::pending().await
+    }
+}
+
+/// Poll once a `select` of a ready future and a pending one, or a `join` of a failing future and
+/// a pending one, returning whether the pending future has been dropped while the combined
+/// future is still alive.
+#[pyfunction]
+fn combinator_drops_pending(py: Python, combinator: &str) -> PyResult<bool> {
+    let dropped = DROPPED.load(Ordering::Relaxed);
+    let mut combined: Pin<Box<dyn PyFuture>> = match combinator {
+        "select" => Box::pin(combinators::select(
+            async { PyResult::Ok(0) },
+            guarded_pending(),
+        )),
+        "join" => Box::pin(combinators::join(
+            async { PyResult::<()>::Err(PyValueError::new_err("join error")) },
+            guarded_pending(),
+        )),
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown combinator {combinator}"
+            )))
+        }
+    };
+    let waker = futures::task::noop_waker();
+    let poll = combined
+        .as_mut()
+        .poll_py(py, &mut Context::from_waker(&waker));
+    assert!(poll.is_ready());
+    let res = DROPPED.load(Ordering::Relaxed) == dropped + 1;
+    drop(combined);
+    Ok(res)
+}
+
+/// Sleep concurrently for both durations, returning them.
+#[pyfunction]
+fn join_sleeps(a: f64, b: f64) -> Coroutine {
+    let sleep_then = |seconds| async move {
+        sleep(seconds).await?;
+        PyResult::Ok(seconds)
+    };
+    Coroutine::from_future(combinators::join(sleep_then(a), sleep_then(b)))
+}
+
+/// CPU-bound async function, releasing the GIL while computing.
+#[pyo3_async::pyfunction(sniffio, allow_threads)]
+async fn fibonacci(n: u64) -> PyResult<u64> {
+    fn fib(n: u64) -> u64 {
+        if n < 2 {
+            n
+        } else {
+            fib(n - 1) + fib(n - 2)
+        }
+    }
+    Ok(fib(n))
+}
+
+/// Async function calling back into Python while its coroutine has released the GIL.
+#[pyo3_async::pyfunction(sniffio, allow_threads)]
+async fn reenter_without_gil(callback: PyObject) -> PyResult<PyObject> {
+    Python::with_gil(|py| callback.call0(py))
+}
+
+/// Async generator.
+#[pyo3_async::pyfunction(sniffio)]
+fn count(until: u64, tick: f64) -> impl Stream<Item = PyResult<u64>> + Send + 'static {
+    futures::stream::unfold(0, move |i| async move {
+        if i == until {
+            return None;
+        }
+        if let Err(err) = sleep(tick).await {
+            return Some((Err(err), until));
+        }
+        Some((Ok(i), i + 1))
+    })
+}
+
+/// Async generator built asynchronously, e.g. after opening a connection, which is refused if
+/// `until` is zero.
+#[pyo3_async::pyfunction(sniffio, allow_threads)]
+async fn connect_count(
+    until: u64,
+    delay: f64,
+) -> PyResult<impl Stream<Item = PyResult<u64>> + Send + 'static> {
+    sleep(delay).await?;
+    if until == 0 {
+        return Err(PyConnectionRefusedError::new_err("connection refused"));
+    }
+    Ok(futures::stream::iter((0..until).map(Ok)))
+}
+
+/// Await a Python awaitable from Rust, and return its result doubled.
+#[pyfunction]
+fn await_double(awaitable: PyObject) -> Coroutine {
+    Coroutine::from_future(async move {
+        let result = pyo3_async::await_py(awaitable).await?;
+        Python::with_gil(|py| result.call_method1(py, "__mul__", (2,)))
+    })
+}
+
+/// Read the lines of a Python async reader, refilling a buffer of the given capacity.
+#[pyfunction]
+fn read_lines(reader: PyObject, capacity: usize) -> Coroutine {
+    Coroutine::from_future(async move {
+        let reader = PyAsyncReader::with_capacity(capacity, reader);
+        let lines: Vec<String> = reader.lines().try_collect().await?;
+        PyResult::Ok(lines)
+    })
+}
+
+/// Stream counting indefinitely, reset to `0` when a `ValueError` is thrown into it.
+struct Resettable(u64);
+
+impl pyo3_async::PyStream for Resettable {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        _cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = Pin::into_inner(self);
+        this.0 += 1;
+        Poll::Ready(Some(Ok((this.0 - 1).into_py(py))))
+    }
+
+    fn throw_py(self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        if !exc.is_instance_of::<PyValueError>(py) {
+            return Err(exc);
+        }
+        Pin::into_inner(self).0 = 0;
+        Ok(())
+    }
+}
+
+/// Async generator counting indefinitely, reset by `athrow(ValueError)` (see [`Resettable`]).
+#[pyfunction]
+fn resettable_count() -> AsyncGenerator {
+    AsyncGenerator::from_stream(Resettable(0))
+}
+
+/// Stream counting up to `until`, whose asynchronous cleanup sleeps, then appends the number of
+/// yielded items to `flushed`, or raises if `fail_close`.
+struct Flushing {
+    next: u64,
+    until: u64,
+    flushed: PyObject,
+    fail_close: bool,
+    flush: Option<AbortOnDrop<()>>,
+}
+
+impl pyo3_async::PyStream for Flushing {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        _cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = Pin::into_inner(self);
+        if this.next == this.until {
+            return Poll::Ready(None);
+        }
+        this.next += 1;
+        Poll::Ready(Some(Ok((this.next - 1).into_py(py))))
+    }
+}
+
+impl pyo3_async::PyStreamClose for Flushing {
+    fn poll_close_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<()>> {
+        let this = Pin::into_inner(self);
+        let flush = this.flush.get_or_insert_with(|| sleep(0.01));
+        if let Poll::Ready(res) = flush.poll_unpin(cx) {
+            res?;
+            if this.fail_close {
+                return Poll::Ready(Err(PyRuntimeError::new_err("flush failed")));
+            }
+            this.flushed.call_method1(py, "append", (this.next,))?;
+            return Poll::Ready(Ok(()));
+        }
+        Poll::Pending
+    }
+}
+
+/// Async generator counting up to `until`, flushed asynchronously by `aclose` (see [`Flushing`]).
+#[pyfunction]
+fn flushing_count(until: u64, flushed: PyObject, fail_close: bool) -> AsyncGenerator {
+    AsyncGenerator::from_closeable_stream(Flushing {
+        next: 0,
+        until,
+        flushed,
+        fail_close,
+        flush: None,
+    })
+}
+
+/// Async generator awaiting a Python finalizer when exhausted or closed.
+#[pyfunction]
+fn count_finalized(until: u64, finalizer: PyObject) -> AsyncGenerator {
+    let stream = futures::stream::iter((0..until).map(PyResult::Ok));
+    AsyncGenerator::from_stream_with_finalizer(stream, finalizer)
+}
+
+/// Async generator counting up to `until`, appending each item pulled from the stream to
+/// `pulled`.
+#[pyfunction]
+fn count_pulled(until: u64, pulled: PyObject) -> AsyncGenerator {
+    let stream = futures::stream::iter(0..until).map(move |i| {
+        Python::with_gil(|py| pulled.call_method1(py, "append", (i,)))?;
+        PyResult::Ok(i)
+    });
+    AsyncGenerator::from_stream(stream)
+}
+
+/// Async generator counting up to `until`, whose stream is instantiated on first iteration,
+/// appending `None` to `created` when it is.
+#[pyfunction]
+fn lazy_count(until: u64, created: PyObject) -> AsyncGenerator {
+    AsyncGenerator::from_stream_fn(move || {
+        Python::with_gil(|py| created.call_method1(py, "append", (py.None(),))).unwrap();
+        futures::stream::iter((0..until).map(PyResult::Ok))
+    })
+}
+
+/// Python identifier of the thread where the last [`ThreadGuard`] was dropped.
+static DROPPED_IN: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Guard recording the thread where it's dropped.
+struct ThreadGuard;
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        let ident = Python::with_gil(|py| {
+            let threading = py.import("threading")?;
+            threading.call_method0("get_ident")?.extract()
+        });
+        *DROPPED_IN.lock().unwrap() = Some(ident.unwrap());
+    }
+}
+
+/// Async generator counting up to `until`, its stream recording the thread where it's dropped.
+#[pyfunction]
+fn thread_guarded_count(until: u64, drop_on_gc: bool) -> AsyncGenerator {
+    *DROPPED_IN.lock().unwrap() = None;
+    let guard = ThreadGuard;
+    let stream = futures::stream::iter(0..until).map(move |i| {
+        let _ = &guard;
+        PyResult::Ok(i)
+    });
+    let agen = AsyncGenerator::from_stream(stream);
+    if drop_on_gc {
+        agen.drop_on_gc()
+    } else {
+        agen
+    }
+}
+
+/// Python identifier of the thread where the stream of the last [`thread_guarded_count`] was
+/// dropped, if it was.
+#[pyfunction]
+fn dropped_in() -> Option<u64> {
+    *DROPPED_IN.lock().unwrap()
+}
+
+/// Coroutine returning `list(range(n))`, converted `chunk_size` elements per step.
+#[pyfunction]
+fn chunked_range(n: u64, chunk_size: usize) -> Coroutine {
+    let future = async move { PyResult::Ok((0..n).collect::<Vec<_>>()) };
+    Coroutine::from_future(future.convert_chunked(chunk_size))
+}
+
+/// Coroutine doing `bursts` CPU bursts of 2ms, reaching a budget checkpoint after each one, with
+/// a 1ms budget.
+#[pyfunction]
+fn budgeted_bursts(bursts: usize) -> Coroutine {
+    let future = async move {
+        for _ in 0..bursts {
+            let start = std::time::Instant::now();
+            while start.elapsed() < Duration::from_millis(2) {
+                std::hint::spin_loop();
+            }
+            budget::checkpoint().await;
+        }
+        PyResult::Ok(())
+    };
+    Coroutine::from_future(future.yield_every(Duration::from_millis(1)))
+}
+
+/// Coroutine returning `spins` after returning `Pending` as many times without registering its
+/// waker, rescheduled by [`PyFutureExt::poll_loop`].
+#[pyfunction]
+fn spinning(spins: usize) -> Coroutine {
+    let mut remaining = spins;
+    let future = futures::future::poll_fn(move |_| {
+        if remaining == 0 {
+            return Poll::Ready(PyResult::Ok(spins));
+        }
+        remaining -= 1;
+        Poll::Pending
+    });
+    Coroutine::from_future(future.poll_loop(spins))
+}
+
+/// Coroutine returning its number of steps, waking itself to yield to the event loop until it
+/// reaches `n` steps.
+#[pyfunction]
+fn self_waking_steps(n: u64) -> Coroutine {
+    let mut steps = 0;
+    Coroutine::from_step_fn(move |py, cx| {
+        steps += 1;
+        if steps < n {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(steps.into_py(py)))
+    })
+}
+
+/// Map `range(n)` with an async Python callable, at most `limit` calls being awaited
+/// concurrently (asyncio only).
+#[pyfunction]
+#[pyo3(signature = (n, mapper, limit, ordered = true))]
+fn map_concurrent(
+    n: u64,
+    mapper: PyObject,
+    limit: usize,
+    ordered: bool,
+) -> asyncio::AsyncGenerator {
+    let stream = futures::stream::iter((0..n).map(PyResult::Ok));
+    let order = if ordered {
+        asyncio::MapOrder::Ordered
+    } else {
+        asyncio::MapOrder::Completion
+    };
+    asyncio::AsyncGenerator::map_concurrent(stream, mapper, limit, order)
+}
+
+/// Async generator yielding the progress of a download of `chunks` chunks, then returning
+/// "done" with `StopAsyncIteration`, or failing at chunk `fail_at`; its drops are counted.
+#[pyfunction]
+fn download(chunks: u64, fail_at: Option<u64>) -> AsyncGenerator {
+    let future = ProgressFuture::new(|progress| async move {
+        let _guard = DropGuard;
+        for chunk in 1..=chunks {
+            sleep(0.001).await?;
+            if fail_at == Some(chunk) {
+                return Err(PyConnectionRefusedError::new_err("download failed"));
+            }
+            progress.send(chunk);
+        }
+        PyResult::Ok("done")
+    });
+    AsyncGenerator::from_stream(future)
+}
+
+/// Counting stream shared between subscribers, keeping at most `buffer` items.
+#[pyfunction]
+fn broadcast_count(until: u64, buffer: usize, skip_lagged: bool) -> Broadcast {
+    let stream = futures::stream::iter((0..until).map(PyResult::Ok));
+    let lag = match skip_lagged {
+        true => LagPolicy::Skip,
+        false => LagPolicy::Error,
+    };
+    AsyncGenerator::broadcast(stream, buffer, lag)
+}
+
+/// Coroutine pending until an exception is thrown into it, which it then raises, appending its
+/// cancellation message, if any, to `messages`.
+#[pyfunction]
+fn record_cancel_message(messages: PyObject) -> asyncio::Coroutine {
+    let thrown = Arc::new(Mutex::new(None::<PyErr>));
+    let future = {
+        let thrown = thrown.clone();
+        futures::future::poll_fn(move |_| match thrown.lock().unwrap().take() {
+            Some(err) => Poll::Ready(Err::<(), _>(err)),
+            None => Poll::Pending,
+        })
+    };
+    let throw = move |py: Python, err: Option<PyErr>| {
+        let Some(err) = err else { return };
+        if let Ok(Some(message)) = asyncio::cancelled_message(py, &err) {
+            messages.call_method1(py, "append", (message,)).unwrap();
+        }
+        *thrown.lock().unwrap() = Some(err);
+    };
+    asyncio::Coroutine::new(Box::pin(future), Some(Box::new(throw)))
+}
+
+/// Coroutine panicking when polled, with a no-op throw callback so that `close` polls it too.
+#[pyfunction]
+fn panicking() -> Coroutine {
+    let future = async { panic!("boom") as PyResult<()> };
+    Coroutine::new(Box::pin(future), Some(Box::new(|_, _| {})))
+}
+
+/// Async generator yielding `after` items, then panicking; each poll of the stream is appended
+/// to `polls`.
+#[pyfunction]
+#[pyo3(signature = (after = 0, polls = None))]
+fn panicking_stream(after: u64, polls: Option<PyObject>) -> AsyncGenerator {
+    let mut i = 0;
+    AsyncGenerator::from_stream(futures::stream::poll_fn(
+        move |_| -> std::task::Poll<Option<PyResult<u64>>> {
+            if let Some(polls) = &polls {
+                Python::with_gil(|py| polls.call_method1(py, "append", (i,))).unwrap();
+            }
+            if i == after {
+                panic!("boom");
+            }
+            i += 1;
+            Poll::Ready(Some(Ok(i - 1)))
+        },
+    ))
+}
+
+/// Async generator yielding the indexes of `delays` after sleeping each delay, raising
+/// `TimeoutError` when an item takes more than `timeout` (asyncio only).
+#[pyfunction]
+#[pyo3(signature = (delays, timeout, terminate = false))]
+fn stalling(delays: Vec<f64>, timeout: f64, terminate: bool) -> asyncio::AsyncGenerator {
+    let stream =
+        futures::stream::iter(delays.into_iter().enumerate()).then(|(i, delay)| async move {
+            sleep(delay).await?;
+            PyResult::Ok(i)
+        });
+    let policy = if terminate {
+        ErrorPolicy::RaiseAndTerminate
+    } else {
+        ErrorPolicy::RaiseAndContinue
+    };
+    asyncio::AsyncGenerator::from_stream(stream)
+        .error_policy(policy)
+        .item_timeout(Duration::from_secs_f64(timeout))
+}
+
+/// Async generator yielding `0..n`, the items in `errors` being replaced by a `ValueError`,
+/// raised (terminating the generator if `terminate`), or passed to `on_error` if given.
+#[pyfunction]
+#[pyo3(signature = (n, errors, terminate = false, on_error = None))]
+fn failing_items(
+    n: usize,
+    errors: Vec<usize>,
+    terminate: bool,
+    on_error: Option<PyObject>,
+) -> AsyncGenerator {
+    let stream = futures::stream::iter(0..n).map(move |i| {
+        if errors.contains(&i) {
+            return Err(PyValueError::new_err(i));
+        }
+        Ok(i)
+    });
+    let policy = match (on_error, terminate) {
+        (Some(on_error), _) => ErrorPolicy::YieldAsValue(on_error),
+        (None, true) => ErrorPolicy::RaiseAndTerminate,
+        (None, false) => ErrorPolicy::RaiseAndContinue,
+    };
+    AsyncGenerator::from_stream(stream).error_policy(policy)
+}
+
+/// Async generator yielding the indexes of `delays`, each after its delay, and `"ping"` each
+/// time no item arrives within `interval`.
+#[pyfunction]
+fn heartbeat(py: Python, delays: Vec<f64>, interval: f64) -> AsyncGenerator {
+    let stream =
+        futures::stream::iter(delays.into_iter().enumerate()).then(|(i, delay)| async move {
+            sleep(delay).await?;
+            PyResult::Ok(i)
+        });
+    let interval = Duration::from_secs_f64(interval);
+    AsyncGenerator::from_stream(stream.heartbeat(interval, "ping".into_py(py)))
+}
+
+/// Async generator yielding `count` chunks of `chunk_size` bytes, as zero-copy `memoryview`s.
+#[pyfunction]
+fn byte_chunks(chunk_size: usize, count: usize) -> AsyncGenerator {
+    let chunks = futures::stream::iter(0..count).map(move |i| vec![i as u8; chunk_size]);
+    AsyncGenerator::from_byte_stream(chunks.map(PyResult::Ok))
+}
+
+static PULLED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Async generator yielding `bytes` of the given sizes, prefetched up to `max_bytes`.
+#[pyfunction]
+fn buffered_chunks(sizes: Vec<usize>, max_bytes: usize) -> AsyncGenerator {
+    PULLED_BYTES.store(0, Ordering::Relaxed);
+    let chunks = futures::stream::iter(sizes).map(|size| {
+        PULLED_BYTES.fetch_add(size, Ordering::Relaxed);
+        Python::with_gil(|py| PyResult::Ok(PyBytes::new(py, &vec![0; size]).to_object(py)))
+    });
+    let size_of = |py: Python, chunk: &PyObject| chunk.as_ref(py).len().unwrap_or(0);
+    AsyncGenerator::from_stream(chunks.buffered_bytes(max_bytes, size_of))
+}
+
+/// Total size of the chunks pulled by the last [`buffered_chunks`] generator.
+#[pyfunction]
+fn pulled_bytes() -> usize {
+    PULLED_BYTES.load(Ordering::Relaxed)
+}
+
+#[pyfunction]
+fn map_tasks(items: Vec<PyObject>, f: PyObject, concurrency: usize) -> asyncio::AsyncGenerator {
+    asyncio::map_tasks(items, f, concurrency)
+}
+
+#[pyfunction]
+#[pyo3(signature = (handler, n, off_loop = false))]
+fn call_handler(handler: &PyAny, n: usize, off_loop: bool) -> PyResult<asyncio::Coroutine> {
+    let handler = asyncio::PyAsyncCallable::new(handler)?;
+    let calls = async move {
+        let mut results = Vec::with_capacity(n);
+        for i in 0..n {
+            results.push(handler.call((i,)).await?);
+        }
+        PyResult::Ok(results)
+    };
+    Ok(if off_loop {
+        asyncio::Coroutine::from_future(runtime::spawn(tokio().handle(), calls))
+    } else {
+        asyncio::Coroutine::from_future(calls)
+    })
+}
+
+#[pyfunction]
+fn notify_handler(handler: &PyAny, n: usize) -> PyResult<asyncio::Coroutine> {
+    let handler = asyncio::PyAsyncCallable::new(handler)?;
+    let notify = runtime::spawn(tokio().handle(), async move {
+        for i in 0..n {
+            handler.call_ignore_result((i,))?;
+        }
+        PyResult::Ok(())
+    });
+    Ok(asyncio::Coroutine::from_future(notify))
+}
+
+#[pyfunction]
+fn race_sleep(delay: f64, awaitable: &PyAny) -> PyResult<asyncio::Coroutine> {
+    asyncio::race(
+        async move {
+            let _guard = DropGuard;
+            sleep(delay).await?;
+            PyResult::Ok(delay)
+        },
+        awaitable,
+    )
+}
+
+/// Task calling `callback` from a Rust coroutine, spawned in `task_group` or in the running
+/// event loop.
+#[pyfunction]
+#[pyo3(signature = (callback, name = None, context = None, inherit = false, task_group = None))]
+fn spawn_callback(
+    py: Python,
+    callback: PyObject,
+    name: Option<&str>,
+    context: Option<PyObject>,
+    inherit: bool,
+    task_group: Option<&PyAny>,
+) -> PyResult<PyObject> {
+    let context = match (context, inherit) {
+        (Some(context), _) => TaskContext::Explicit(context),
+        (None, true) => TaskContext::Inherit,
+        (None, false) => TaskContext::Copy,
+    };
+    let future = async move { Python::with_gil(|py| callback.call0(py)) };
+    match task_group {
+        Some(task_group) => asyncio::spawn_in_task_group(task_group, future, name, context),
+        None => asyncio::spawn(py, future, name, context),
+    }
+}
+
+/// Native coroutine sleeping on the tokio runtime, for callers requiring `async def` ones.
+#[pyfunction]
+fn native_sleep(py: Python, seconds: f64) -> PyResult<PyObject> {
+    asyncio::to_native_coroutine(py, asyncio::Coroutine::from_future(sleep(seconds)))
+}
+
+/// Coroutine woken from a tokio thread.
+#[pyfunction]
+fn remote_wake() -> asyncio::Coroutine {
+    asyncio::Coroutine::from_future(sleep(0.0001))
+}
+
+/// Last wake latency of a coroutine, in seconds.
+#[pyfunction]
+fn wake_latency(coroutine: &PyCell<asyncio::Coroutine>) -> Option<f64> {
+    let latency = coroutine.get().last_wake_latency();
+    latency.map(|latency| latency.as_secs_f64())
+}
+
+static WOKEN: Mutex<(bool, Option<Waker>)> = Mutex::new((false, None));
+
+/// Coroutine pending until [`wake`] is called, with the waker of its last poll.
+#[pyfunction]
+fn wait_woken() -> asyncio::Coroutine {
+    *WOKEN.lock().unwrap() = (false, None);
+    asyncio::Coroutine::from_future(futures::future::poll_fn(|cx| {
+        let mut woken = WOKEN.lock().unwrap();
+        if woken.0 {
+            return Poll::Ready(PyResult::Ok(()));
+        }
+        woken.1 = Some(cx.waker().clone());
+        Poll::Pending
+    }))
+}
+
+/// Wake the coroutine returned by [`wait_woken`] from the calling thread.
+#[pyfunction]
+fn wake() {
+    let waker = {
+        let mut woken = WOKEN.lock().unwrap();
+        woken.0 = true;
+        woken.1.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+fn cancel_on_drop(policy: Option<&str>) -> PyResult<Option<asyncio::CancelOnDrop>> {
+    Ok(match policy {
+        None => None,
+        Some("ignore") => Some(asyncio::CancelOnDrop::IgnoreError),
+        Some("panic") => Some(asyncio::CancelOnDrop::PanicOnError),
+        Some("log") => Some(asyncio::CancelOnDrop::LogOnError),
+        Some(policy) => return Err(PyValueError::new_err(format!("unknown policy {policy}"))),
+    })
+}
+
+/// Poll an awaitable once, then drop it, returning whether it was pending.
+#[pyfunction]
+fn abandon_awaitable(awaitable: &PyAny, policy: Option<&str>) -> PyResult<asyncio::Coroutine> {
+    let mut wrapper = asyncio::AwaitableWrapper::new(awaitable)?;
+    if let Some(cancel_on_drop) = cancel_on_drop(policy)? {
+        wrapper = wrapper.with_cancel_on_drop(cancel_on_drop);
+    }
+    Ok(asyncio::Coroutine::from_future(async move {
+        let pending = futures::poll!(&mut wrapper).is_pending();
+        drop(wrapper);
+        PyResult::Ok(pending)
+    }))
+}
+
+/// Await an awaitable, polling it `polls` more times before its completion, like when it's
+/// combined with other futures.
+#[pyfunction]
+fn await_repolled(awaitable: &PyAny, polls: usize) -> PyResult<asyncio::Coroutine> {
+    let mut wrapper = asyncio::AwaitableWrapper::new(awaitable)?;
+    let mut remaining = polls;
+    Ok(asyncio::Coroutine::from_future(futures::future::poll_fn(
+        move |cx| {
+            let poll = wrapper.poll_unpin(cx);
+            if poll.is_pending() && remaining > 0 {
+                remaining -= 1;
+                cx.waker().wake_by_ref();
+            }
+            poll
+        },
+    )))
+}
+
+/// Poll a future once, then drop it, returning whether it was pending.
+#[pyfunction]
+fn abandon_future(future: PyObject, policy: Option<&str>) -> PyResult<asyncio::Coroutine> {
+    let mut wrapper = asyncio::FutureWrapper::new(future, cancel_on_drop(policy)?);
+    Ok(asyncio::Coroutine::from_future(async move {
+        let pending = futures::poll!(&mut wrapper).is_pending();
+        drop(wrapper);
+        PyResult::Ok(pending)
+    }))
+}
+
+/// Wait for a future, cancelling it when dropped, registering its done callback with `context`.
+#[pyfunction]
+#[pyo3(signature = (future, policy = None, context = None))]
+fn wait_future(
+    future: PyObject,
+    policy: Option<&str>,
+    context: Option<PyObject>,
+) -> PyResult<asyncio::Coroutine> {
+    let mut wrapper = asyncio::FutureWrapper::new(future, cancel_on_drop(policy)?);
+    if let Some(context) = context {
+        wrapper = wrapper.with_context(context);
+    }
+    Ok(asyncio::Coroutine::from_future(wrapper))
+}
+
+/// Get the first item of an async generator, poll the second once, then drop the wrapper.
+#[pyfunction]
+fn abandon_async_generator(
+    async_generator: &PyAny,
+    policy: Option<&str>,
+) -> PyResult<asyncio::Coroutine> {
+    let mut wrapper = asyncio::AsyncGeneratorWrapper::new(async_generator);
+    if let Some(cancel_on_drop) = cancel_on_drop(policy)? {
+        wrapper = wrapper.with_cancel_on_drop(cancel_on_drop);
+    }
+    Ok(asyncio::Coroutine::from_future(async move {
+        let first = wrapper.next().await.transpose()?;
+        let _ = futures::poll!(wrapper.next());
+        drop(wrapper);
+        PyResult::Ok(first)
+    }))
+}
+
+/// Get the first item of an async generator, optionally poll the second once, then drop the
+/// wrapper (in `trio` context).
+#[pyfunction]
+fn abandon_trio_async_generator(
+    async_generator: &PyAny,
+    pending: bool,
+) -> pyo3_async::trio::Coroutine {
+    let mut wrapper = pyo3_async::trio::AsyncGeneratorWrapper::new(async_generator);
+    pyo3_async::trio::Coroutine::from_future(async move {
+        let first = wrapper.next().await.transpose()?;
+        if pending {
+            let _ = futures::poll!(wrapper.next());
+        }
+        drop(wrapper);
+        PyResult::Ok(first)
+    })
+}
+
+/// Wait for a `threading.Event`, in the default executor, or polling it every millisecond.
+#[pyfunction]
+fn wait_event(event: &PyAny, executor: bool) -> asyncio::Coroutine {
+    let wait = match executor {
+        true => asyncio::EventWait::Executor,
+        false => asyncio::EventWait::Poll(Duration::from_millis(1)),
+    };
+    asyncio::Coroutine::from_future(asyncio::wait_threading_event(event, wait))
+}
+
+/// Coroutine suspended `n` times, each time woken from another thread.
+#[pyfunction]
+fn remote_wakes(n: usize) -> Coroutine {
+    let mut suspensions = 0;
+    Coroutine::from_future(futures::future::poll_fn(move |cx| {
+        if suspensions == n {
+            return Poll::Ready(PyResult::Ok(()));
+        }
+        suspensions += 1;
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || waker.wake());
+        Poll::Pending
+    }))
+}
+
+/// Coroutine woken once from a thread after `seconds`, even if it has been dropped before.
+#[pyfunction]
+fn delayed_wake(seconds: f64) -> Coroutine {
+    let mut spawned = false;
+    Coroutine::from_future(futures::future::poll_fn(move |cx| {
+        if spawned {
+            return Poll::Ready(PyResult::Ok(()));
+        }
+        spawned = true;
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs_f64(seconds));
+            waker.wake();
+        });
+        Poll::Pending
+    }))
+}
+
+/// Spawn a sleep in a trio nursery, counting its drops.
+#[pyfunction]
+fn spawn_sleep(py: Python, nursery: &PyAny, seconds: f64) -> PyResult<()> {
+    pyo3_async::trio::spawn_in_nursery(py, nursery, async move {
+        let _guard = DropGuard;
+        sleep(seconds).await
+    })
+}
+
+/// Coroutine of a specific backend, instead of a `sniffio` one.
+#[pyfunction]
+fn backend_coroutine(py: Python, backend: &str) -> PyResult<PyObject> {
+    let future = async { PyResult::Ok(42) };
+    match backend {
+        "asyncio" => Ok(asyncio::Coroutine::from_future(future).into_py(py)),
+        "trio" => Ok(pyo3_async::trio::Coroutine::from_future(future).into_py(py)),
+        _ => Err(PyValueError::new_err(format!("unknown backend {backend}"))),
+    }
+}
+
+/// Function exported by the erased plugin, a separate cdylib with its own copy of pyo3, loaded
+/// from `ERASED_PLUGIN` environment variable, or from its default build directory.
+fn plugin_fn<T: Copy>(py: Python, name: &str) -> PyResult<T> {
+    static PLUGIN: GILOnceCell<libloading::Library> = GILOnceCell::new();
+    let plugin = PLUGIN.get_or_try_init(py, || {
+        let path = std::env::var_os("ERASED_PLUGIN").map_or_else(
+            || {
+                let target = concat!(env!("CARGO_MANIFEST_DIR"), "/../erased_plugin/target/debug");
+                Path::new(target).join(libloading::library_filename("erased_plugin"))
+            },
+            PathBuf::from,
+        );
+        // SAFETY: the plugin has no initialization routine
+        unsafe { libloading::Library::new(&path) }
+            .map_err(|err| PyImportError::new_err(format!("{}: {err}", path.display())))
+    })?;
+    // SAFETY: `T` is the signature of the exported function
+    let symbol = unsafe { plugin.get::<T>(name.as_bytes()) };
+    Ok(*symbol.map_err(|err| PyImportError::new_err(err.to_string()))?)
+}
+
+/// Coroutine wrapping an erased future returned by the plugin through the C ABI.
+#[pyfunction]
+fn plugin_countdown(py: Python, n: i64) -> PyResult<Coroutine> {
+    let countdown =
+        plugin_fn::<extern "C" fn(i64) -> ErasedPyFuture>(py, "erased_plugin_countdown")?;
+    Ok(Coroutine::from_erased(countdown(n)))
+}
+
+/// Never-completing coroutine, holding `obj` in the plugin until dropped.
+#[pyfunction]
+fn plugin_hold(py: Python, obj: PyObject) -> PyResult<Coroutine> {
+    let hold = plugin_fn::<unsafe extern "C" fn(*mut ffi::PyObject) -> ErasedPyFuture>(
+        py,
+        "erased_plugin_hold",
+    )?;
+    // SAFETY: `obj` is borrowed for the duration of the call
+    Ok(Coroutine::from_erased(unsafe { hold(obj.as_ptr()) }))
+}
+
+static SINK_COUNT: AtomicUsize = AtomicUsize::new(0);
+static SINK_SUM: AtomicI64 = AtomicI64::new(0);
+
+/// Async generator feeding the sent integers to a bounded channel, drained by a tokio task, and
+/// yielding the number of integers received so far.
+#[pyfunction]
+fn summing_sink(capacity: usize) -> asyncio::AsyncGenerator {
+    SINK_COUNT.store(0, Ordering::Relaxed);
+    SINK_SUM.store(0, Ordering::Relaxed);
+    let (sender, mut receiver) = futures::channel::mpsc::channel::<i64>(capacity);
+    tokio().spawn(async move {
+        while let Some(value) = receiver.next().await {
+            SINK_SUM.fetch_add(value, Ordering::Relaxed);
+            SINK_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let received =
+        futures::stream::repeat_with(|| PyResult::Ok(SINK_COUNT.load(Ordering::Relaxed)));
+    let sink = sender.sink_map_err(|err| PyRuntimeError::new_err(err.to_string()));
+    asyncio::AsyncGenerator::from_stream(received).with_sink(sink)
+}
+
+static STALLED_RECEIVER: Mutex<Option<futures::channel::mpsc::Receiver<i64>>> = Mutex::new(None);
+
+/// Async generator feeding the sent integers to a bounded channel, which is not drained.
+#[pyfunction]
+fn stalled_sink(capacity: usize) -> asyncio::AsyncGenerator {
+    let (sender, receiver) = futures::channel::mpsc::channel::<i64>(capacity);
+    *STALLED_RECEIVER.lock().unwrap() = Some(receiver);
+    let sink = sender.sink_map_err(|err| PyRuntimeError::new_err(err.to_string()));
+    asyncio::AsyncGenerator::from_stream(futures::stream::pending::<PyResult<()>>()).with_sink(sink)
+}
+
+/// Drain the channel of the last [`stalled_sink`], returning the queued integers.
+#[pyfunction]
+fn stalled_queued() -> Vec<i64> {
+    let mut receiver = STALLED_RECEIVER.lock().unwrap();
+    let receiver = receiver.as_mut().unwrap();
+    std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+}
+
+#[pyfunction]
+fn sink_received() -> (usize, i64) {
+    let count = SINK_COUNT.load(Ordering::Relaxed);
+    (count, SINK_SUM.load(Ordering::Relaxed))
+}
+
+/// Coroutine returning a value of the given kind built from `secs`, converted by an adapter of
+/// `pyo3_async::conversions`, or by pyo3 for `chrono` types.
+#[pyfunction]
+fn converted(kind: &str, secs: u64) -> PyResult<Coroutine> {
+    let duration = Duration::from_secs(secs);
+    Ok(match kind {
+        "duration" => Coroutine::from_future(async move { PyResult::Ok(PyDuration(duration)) }),
+        "system_time" => {
+            let time = UNIX_EPOCH + duration;
+            Coroutine::from_future(async move { PyResult::Ok(PyDateTime(time)) })
+        }
+        "decimal" => {
+            let decimal = rust_decimal::Decimal::new(secs as i64, 2);
+            Coroutine::from_future(async move { PyResult::Ok(PyDecimal(decimal)) })
+        }
+        "chrono_duration" => {
+            let duration = chrono::Duration::seconds(secs as i64);
+            Coroutine::from_future(async move { PyResult::Ok(duration) })
+        }
+        "chrono_datetime" => {
+            let time = chrono::DateTime::from_timestamp(secs as i64, 0).unwrap();
+            Coroutine::from_future(async move { PyResult::Ok(time) })
+        }
+        _ => return Err(PyValueError::new_err(format!("unknown kind {kind}"))),
+    })
+}
+
+static LIVE_COUNTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Object counting its live instances, to detect leaked references.
+#[pyclass]
+struct Counted;
+
+impl Counted {
+    fn new() -> Self {
+        LIVE_COUNTED.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        LIVE_COUNTED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[pyfunction]
+fn live_counted() -> usize {
+    LIVE_COUNTED.load(Ordering::Relaxed)
+}
+
+/// Coroutine suspended `n` times, returning a [`Counted`].
+#[pyfunction]
+fn suspend(n: usize) -> asyncio::Coroutine {
+    let mut remaining = n;
+    asyncio::Coroutine::from_future(futures::future::poll_fn(move |cx| {
+        if remaining == 0 {
+            return Poll::Ready(PyResult::Ok(Counted::new()));
+        }
+        remaining -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }))
+}
+
+/// Buffer shared between a future and the footprint attached to its coroutine.
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl pyo3_async::diagnostics::MemoryFootprint for SharedBuffer {
+    fn approx_bytes(&self) -> usize {
+        self.0.lock().unwrap().capacity()
+    }
+}
+
+/// Coroutine buffering `size` bytes until it's awaited, returning the buffer length.
+#[pyfunction]
+fn buffered(size: usize) -> asyncio::Coroutine {
+    let buffer = Arc::new(Mutex::new(vec![0; size]));
+    let footprint = SharedBuffer(buffer.clone());
+    asyncio::Coroutine::from_future(async move {
+        PyResult::Ok(std::mem::take(&mut *buffer.lock().unwrap()).len())
+    })
+    .with_footprint(footprint)
+}
+
+/// Async generator yielding `n` [`Counted`], suspended before each one.
+#[pyfunction]
+fn counted_stream(n: usize) -> asyncio::AsyncGenerator {
+    let stream = futures::stream::iter(0..n).then(|_| {
+        let mut suspended = false;
+        futures::future::poll_fn(move |cx| {
+            if suspended {
+                return Poll::Ready(PyResult::Ok(Counted::new()));
+            }
+            suspended = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+    });
+    asyncio::AsyncGenerator::from_stream(stream)
+}
+
+/// Drain an async generator into a bounded channel, returning the receiver, as an async
+/// generator, and the draining coroutine.
+#[pyfunction]
+fn drained(
+    async_generator: &PyAny,
+    capacity: usize,
+) -> (asyncio::AsyncGenerator, asyncio::Coroutine) {
+    let (receiver, drain) = asyncio::drain_to_channel(async_generator, capacity);
+    (asyncio::AsyncGenerator::from_stream(receiver), drain)
+}
+
+/// Value watched with a tokio `watch` channel.
+#[pyclass]
+struct Watched(Option<tokio::sync::watch::Sender<i64>>);
+
+#[pymethods]
+impl Watched {
+    #[new]
+    fn new(value: i64) -> Self {
+        Self(Some(tokio::sync::watch::channel(value).0))
+    }
+
+    fn set(&self, value: i64) {
+        if let Some(sender) = &self.0 {
+            sender.send_replace(value);
+        }
+    }
+
+    /// Drop the sender, terminating the subscribed streams.
+    fn close(&mut self) {
+        self.0.take();
+    }
+
+    /// Async generator yielding the latest value at each change.
+    fn subscribe(&self, initial: bool) -> PyResult<AsyncGenerator> {
+        let sender = self
+            .0
+            .as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("closed"))?;
+        let stream = pyo3_async::channel::watch_stream(sender.subscribe(), initial);
+        Ok(AsyncGenerator::from_stream(stream))
+    }
+}
+
+/// Class with async methods.
+#[pyclass]
+struct Counter {
+    value: i64,
+}
+
+#[pyo3_async::pymethods(sniffio)]
+impl Counter {
+    #[new]
+    fn new() -> Self {
+        Self { value: 0 }
+    }
+
+    #[getter]
+    fn value(&self) -> i64 {
+        self.value
+    }
+
+    async fn incr(self_: Py<Self>, delay: f64) -> PyResult<i64> {
+        sleep(delay).await?;
+        Python::with_gil(|py| {
+            let mut this = self_.borrow_mut(py);
+            this.value += 1;
+            Ok(this.value)
+        })
+    }
+}
+
+#[pymodule]
+fn pyo3_async_demo(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    pyo3_async::init(py)?;
+    m.add_function(wrap_pyfunction!(async_add, m)?)?;
+    m.add_function(wrap_pyfunction!(async_multiply, m)?)?;
+    m.add_function(wrap_pyfunction!(async_cancellable_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(guarded_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(spawned_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(dropped_count, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_future, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_event, m)?)?;
+    m.add_function(wrap_pyfunction!(remote_wakes, m)?)?;
+    m.add_function(wrap_pyfunction!(delayed_wake, m)?)?;
+    m.add_function(wrap_pyfunction!(combinator_drops_pending, m)?)?;
+    m.add_function(wrap_pyfunction!(join_sleeps, m)?)?;
+    m.add_function(wrap_pyfunction!(async_fibonacci, m)?)?;
+    m.add_function(wrap_pyfunction!(async_reenter_without_gil, m)?)?;
+    m.add_function(wrap_pyfunction!(async_count, m)?)?;
+    m.add_function(wrap_pyfunction!(async_connect_count, m)?)?;
+    m.add_function(wrap_pyfunction!(await_double, m)?)?;
+    m.add_function(wrap_pyfunction!(read_lines, m)?)?;
+    m.add_function(wrap_pyfunction!(flushing_count, m)?)?;
+    m.add_function(wrap_pyfunction!(resettable_count, m)?)?;
+    m.add_function(wrap_pyfunction!(count_finalized, m)?)?;
+    m.add_function(wrap_pyfunction!(count_pulled, m)?)?;
+    m.add_function(wrap_pyfunction!(lazy_count, m)?)?;
+    m.add_function(wrap_pyfunction!(thread_guarded_count, m)?)?;
+    m.add_function(wrap_pyfunction!(dropped_in, m)?)?;
+    m.add_function(wrap_pyfunction!(chunked_range, m)?)?;
+    m.add_function(wrap_pyfunction!(self_waking_steps, m)?)?;
+    m.add_function(wrap_pyfunction!(budgeted_bursts, m)?)?;
+    m.add_function(wrap_pyfunction!(spinning, m)?)?;
+    m.add_function(wrap_pyfunction!(map_concurrent, m)?)?;
+    m.add_function(wrap_pyfunction!(download, m)?)?;
+    m.add_function(wrap_pyfunction!(broadcast_count, m)?)?;
+    m.add_function(wrap_pyfunction!(record_cancel_message, m)?)?;
+    m.add_function(wrap_pyfunction!(panicking, m)?)?;
+    m.add_function(wrap_pyfunction!(panicking_stream, m)?)?;
+    m.add_function(wrap_pyfunction!(stalling, m)?)?;
+    m.add_function(wrap_pyfunction!(failing_items, m)?)?;
+    m.add_function(wrap_pyfunction!(call_handler, m)?)?;
+    m.add_function(wrap_pyfunction!(notify_handler, m)?)?;
+    m.add_function(wrap_pyfunction!(byte_chunks, m)?)?;
+    m.add_function(wrap_pyfunction!(converted, m)?)?;
+    m.add_function(wrap_pyfunction!(live_counted, m)?)?;
+    m.add_function(wrap_pyfunction!(suspend, m)?)?;
+    m.add_function(wrap_pyfunction!(counted_stream, m)?)?;
+    m.add_function(wrap_pyfunction!(drained, m)?)?;
+    m.add_function(wrap_pyfunction!(buffered_chunks, m)?)?;
+    m.add_function(wrap_pyfunction!(pulled_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(map_tasks, m)?)?;
+    m.add_function(wrap_pyfunction!(heartbeat, m)?)?;
+    m.add_function(wrap_pyfunction!(race_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(spawn_callback, m)?)?;
+    m.add_function(wrap_pyfunction!(native_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(remote_wake, m)?)?;
+    m.add_function(wrap_pyfunction!(wake_latency, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_woken, m)?)?;
+    m.add_function(wrap_pyfunction!(wake, m)?)?;
+    m.add_function(wrap_pyfunction!(abandon_awaitable, m)?)?;
+    m.add_function(wrap_pyfunction!(await_repolled, m)?)?;
+    m.add_function(wrap_pyfunction!(abandon_future, m)?)?;
+    m.add_function(wrap_pyfunction!(abandon_async_generator, m)?)?;
+    m.add_function(wrap_pyfunction!(spawn_sleep, m)?)?;
+    m.add_function(wrap_pyfunction!(backend_coroutine, m)?)?;
+    m.add_function(wrap_pyfunction!(abandon_trio_async_generator, m)?)?;
+    m.add_function(wrap_pyfunction!(buffered, m)?)?;
+    m.add_function(wrap_pyfunction!(plugin_countdown, m)?)?;
+    m.add_function(wrap_pyfunction!(plugin_hold, m)?)?;
+    m.add_function(wrap_pyfunction!(summing_sink, m)?)?;
+    m.add_function(wrap_pyfunction!(sink_received, m)?)?;
+    m.add_function(wrap_pyfunction!(stalled_sink, m)?)?;
+    m.add_function(wrap_pyfunction!(stalled_queued, m)?)?;
+    m.add_class::<Counter>()?;
+    m.add_class::<Watched>()?;
+    m.add_function(wrap_pyfunction!(
+        pyo3_async::introspection::py_supported_backends,
+        m
+    )?)?;
+    Ok(())
+}
@@ -0,0 +1,1917 @@
+import asyncio
+import collections.abc
+import contextvars
+import datetime
+import decimal
+import gc
+import inspect
+import os
+import random
+import subprocess
+import sys
+import threading
+import time
+import weakref
+
+import pytest
+
+import pyo3_async_demo as demo
+
+BACKENDS = ["asyncio", "trio", "uvloop"]
+
+
+@pytest.fixture(params=BACKENDS)
+def backend(request):
+    pytest.importorskip(request.param)
+    return request.param
+
+
+def run(backend, main):
+    if backend == "trio":
+        import trio
+
+        return trio.run(main)
+    import asyncio
+
+    if backend == "uvloop":
+        import uvloop
+
+        return uvloop.run(main())
+    return asyncio.run(main())
+
+
+async def sleep(backend, seconds):
+    if backend == "trio":
+        import trio
+
+        await trio.sleep(seconds)
+    else:
+        import asyncio
+
+        await asyncio.sleep(seconds)
+
+
+async def move_on_after(backend, seconds, coro):
+    """Await `coro`, cancelling it after `seconds`; return whether it was cancelled."""
+    if backend == "trio":
+        import trio
+
+        with trio.move_on_after(seconds) as scope:
+            await coro
+        return scope.cancelled_caught
+    import asyncio
+
+    try:
+        await asyncio.wait_for(coro, seconds)
+    except asyncio.TimeoutError:
+        return True
+    return False
+
+
+def test_supported_backends():
+    assert set(demo.supported_backends()) == {"asyncio", "trio", "sniffio"}
+
+
+def test_async_function(backend):
+    async def main():
+        coro = demo.add(1, 2, 0.01)
+        assert coro.backend == "sniffio(unresolved)"
+        assert await coro == 3
+        expected = "sniffio(trio)" if backend == "trio" else "sniffio(asyncio)"
+        assert coro.backend == expected
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("coro_backend", ["asyncio", "trio"])
+def test_coroutine_backend(coro_backend):
+    coro = demo.backend_coroutine(coro_backend)
+    assert coro.backend == coro_backend
+    coro.close()
+    # the backend of a closed coroutine is still known
+    assert coro.backend == coro_backend
+
+
+def test_async_generator_backend(backend):
+    async def main():
+        agen = demo.count(3, 0)
+        assert agen.backend == "sniffio(unresolved)"
+        assert await agen.__anext__() == 0
+        expected = "sniffio(trio)" if backend == "trio" else "sniffio(asyncio)"
+        assert agen.backend == expected
+        await agen.aclose()
+        assert agen.backend == expected
+
+    run(backend, main)
+    assert demo.counted_stream(0).backend == "asyncio"
+
+
+def test_name():
+    for obj in (demo.add(1, 2, 0), demo.count(3, 0)):
+        ty = type(obj).__name__
+        assert obj.name is None
+        assert repr(obj).startswith(f"<{ty} object at 0x")
+        obj.name = "named"
+        assert obj.name == "named"
+        assert repr(obj).startswith(f"<{ty} name='named' at 0x")
+        with pytest.raises(TypeError):
+            obj.name = 42
+        assert obj.name == "named"
+        obj.name = None
+        assert obj.name is None
+        assert repr(obj).startswith(f"<{ty} object at 0x")
+
+
+def test_async_methods(backend):
+    async def main():
+        counter = demo.Counter()
+        assert await counter.incr(0.01) == 1
+        assert await counter.incr(0) == 2
+        assert counter.value == 2
+
+    run(backend, main)
+
+
+def test_async_generator(backend):
+    async def main():
+        assert [i async for i in demo.count(3, 0.01)] == [0, 1, 2]
+
+    run(backend, main)
+
+
+def test_async_stream_construction(backend):
+    async def main():
+        assert [i async for i in demo.connect_count(3, 0.01)] == [0, 1, 2]
+        agen = demo.connect_count(0, 0.01)
+        with pytest.raises(ConnectionRefusedError):
+            await agen.__anext__()
+        # the failed construction exhausts the async generator
+        with pytest.raises(StopAsyncIteration):
+            await agen.__anext__()
+
+    run(backend, main)
+
+
+def test_allow_threads(backend):
+    # a thread can only run while the Rust computation releases the GIL
+    ticks = 0
+    done = False
+
+    def ticker():
+        nonlocal ticks
+        while not done:
+            ticks += 1
+            time.sleep(0.0001)
+
+    async def main():
+        nonlocal done
+        thread = threading.Thread(target=ticker)
+        thread.start()
+        try:
+            assert await demo.fibonacci(32) == 2178309
+        finally:
+            done = True
+            thread.join()
+
+    run(backend, main)
+    assert ticks > 10
+
+
+def test_await_python_awaitable(backend):
+    async def python_value():
+        await sleep(backend, 0.01)
+        return 21
+
+    async def main():
+        assert await demo.await_double(python_value()) == 42
+
+    run(backend, main)
+
+
+def test_await_python_awaitable_base_exception(backend):
+    class Interrupt(BaseException):
+        pass
+
+    async def python_interrupt():
+        raise Interrupt
+
+    async def main():
+        # not caught as an `Exception`, it's still raised into the coroutine awaiting it
+        with pytest.raises(Interrupt):
+            await demo.await_double(python_interrupt())
+
+    run(backend, main)
+
+
+def test_await_python_awaitable_error_and_cancellation(backend):
+    cancelled = False
+
+    async def python_error():
+        raise ValueError("python error")
+
+    async def python_sleep():
+        nonlocal cancelled
+        try:
+            await sleep(backend, 10)
+        except BaseException:
+            cancelled = True
+            raise
+
+    async def main():
+        with pytest.raises(ValueError, match="python error"):
+            await demo.await_double(python_error())
+        # the cancellation of the task suspended in the coroutine is raised into it, and
+        # dropping its future cancels the awaitable
+        assert await move_on_after(backend, 0.01, demo.await_double(python_sleep()))
+        # trio system task is cancelled asynchronously
+        await sleep(backend, 0.01)
+        assert cancelled
+
+    run(backend, main)
+
+
+class MemoryReader:
+    """In-memory emulation of the async reader protocol."""
+
+    def __init__(self, backend, data):
+        self.backend = backend
+        self.data = data
+        self.reads = []
+
+    async def read(self, n):
+        self.reads.append(n)
+        await sleep(self.backend, 0)
+        chunk, self.data = self.data[:n], self.data[n:]
+        return chunk
+
+
+def test_async_reader(backend):
+    async def main():
+        reader = MemoryReader(backend, b"a\nbb\n\nccc")
+        assert await demo.read_lines(reader, 3) == ["a", "bb", "", "ccc"]
+        # the capacity is passed to each read, until the empty EOF one
+        assert reader.reads == [3] * 5
+
+    run(backend, main)
+
+
+def test_async_reader_error(backend):
+    class FailingReader:
+        async def read(self, n):
+            raise ValueError("read error")
+
+    async def main():
+        with pytest.raises(ValueError, match="read error"):
+            await demo.read_lines(FailingReader(), 3)
+
+    run(backend, main)
+
+
+def test_async_reader_trio_file():
+    trio = pytest.importorskip("trio")
+    import io
+
+    async def main():
+        file = trio.wrap_file(io.BytesIO(b"first\nsecond\n"))
+        assert await demo.read_lines(file, 4) == ["first", "second"]
+
+    trio.run(main)
+
+
+def test_async_reader_aiofiles():
+    aiofiles = pytest.importorskip("aiofiles")
+    import tempfile
+
+    async def main(path):
+        async with aiofiles.open(path, "rb") as file:
+            assert await demo.read_lines(file, 4) == ["first", "second"]
+
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "lines.txt")
+        with open(path, "wb") as file:
+            file.write(b"first\nsecond\n")
+        asyncio.run(main(path))
+
+
+def test_gather_failed_coroutine():
+    async def job(i):
+        if i == 1:
+            raise ValueError(f"job {i} failed")
+        return i
+
+    async def main():
+        coroutines = [demo.await_double(job(i)) for i in range(3)]
+        results = await asyncio.gather(*coroutines, return_exceptions=True)
+        assert results[0] == 0 and results[2] == 4
+        assert isinstance(results[1], ValueError)
+        # throwing into the failed coroutine raises its exception again
+        with pytest.raises(ValueError) as exc_info:
+            coroutines[1].throw(asyncio.CancelledError())
+        assert exc_info.value is results[1]
+        coroutines[1].close()
+
+    asyncio.run(main())
+
+
+def test_failed_coroutine_collected():
+    class Marker:
+        pass
+
+    async def fail():
+        raise ValueError("failed")
+
+    async def main():
+        # the traceback kept with the exception references this frame, thus the coroutine
+        marker = Marker()
+        coroutine = demo.await_double(fail())
+        with pytest.raises(ValueError):
+            await coroutine
+        return weakref.ref(marker)
+
+    ref = asyncio.run(main())
+    gc.collect()
+    assert ref() is None
+
+
+def test_awaitable_single_done_callback():
+    class CountingFuture(asyncio.Future):
+        def __init__(self):
+            super().__init__()
+            self.callbacks = 0
+
+        def add_done_callback(self, fn, *, context=None):
+            self.callbacks += 1
+            super().add_done_callback(fn, context=context)
+
+    async def main():
+        future = CountingFuture()
+        asyncio.get_running_loop().call_later(0.01, future.set_result, 42)
+        assert await demo.await_repolled(future, 10) == 42
+        # the callback is registered once, whatever the number of polls
+        assert future.callbacks == 1
+
+    asyncio.run(main())
+
+
+def test_cancellation(backend):
+    async def main():
+        dropped = demo.dropped_count()
+        assert await move_on_after(backend, 0.05, demo.cancellable_sleep(10))
+        assert demo.dropped_count() == dropped + 1
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("combinator", ["select", "join"])
+def test_combinator_drops_pending(combinator):
+    assert demo.combinator_drops_pending(combinator)
+
+
+def test_join():
+    async def main():
+        assert await demo.join_sleeps(0.02, 0.01) == (0.02, 0.01)
+
+    asyncio.run(main())
+
+
+def test_drop_guard(backend):
+    async def main():
+        dropped = demo.dropped_count()
+        # completion
+        await demo.guarded_sleep(0)
+        assert demo.dropped_count() == dropped + 1
+        # cancellation
+        assert await move_on_after(backend, 0.01, demo.guarded_sleep(10))
+        assert demo.dropped_count() == dropped + 2
+        # close
+        demo.guarded_sleep(10).close()
+        assert demo.dropped_count() == dropped + 3
+        # abandonment
+        coroutine = demo.guarded_sleep(10)
+        del coroutine
+        assert demo.dropped_count() == dropped + 4
+
+    run(backend, main)
+
+
+def test_spawned_task_aborted(backend):
+    async def main():
+        dropped = demo.dropped_count()
+        await demo.spawned_sleep(0, False)
+        assert demo.dropped_count() == dropped + 1
+        # cancelling the coroutine aborts the tokio task, whose future is dropped in its thread
+        assert await move_on_after(backend, 0.01, demo.spawned_sleep(10, False))
+        for _ in range(100):
+            if demo.dropped_count() == dropped + 2:
+                break
+            await sleep(backend, 0.001)
+        assert demo.dropped_count() == dropped + 2
+        # a panic of the task is raised
+        with pytest.raises(RuntimeError, match="spawned task panicked"):
+            await demo.spawned_sleep(0, True)
+
+    run(backend, main)
+
+
+def test_generator_cancellation(backend):
+    async def consume():
+        async for _ in demo.count(10, 10):
+            pass
+
+    async def main():
+        assert await move_on_after(backend, 0.05, consume())
+
+    run(backend, main)
+
+
+def test_async_generator_finalizer(backend):
+    calls = []
+
+    async def finalizer():
+        await sleep(backend, 0.001)
+        calls.append(None)
+
+    async def main():
+        # exhaustion
+        agen = demo.count_finalized(3, finalizer)
+        assert [i async for i in agen] == [0, 1, 2]
+        assert len(calls) == 1
+        await agen.aclose()
+        assert len(calls) == 1
+        # close
+        agen = demo.count_finalized(3, finalizer)
+        assert await agen.__anext__() == 0
+        await agen.aclose()
+        assert len(calls) == 2
+        await agen.aclose()
+        assert len(calls) == 2
+
+    run(backend, main)
+
+
+def test_athrow_into_stream(backend):
+    async def main():
+        agen = demo.resettable_count()
+        assert [await agen.__anext__() for _ in range(3)] == [0, 1, 2]
+        # handled by the stream, `athrow` returns the next item
+        assert await agen.athrow(ValueError()) == 0
+        assert await agen.__anext__() == 1
+        # not handled, the exception is raised
+        with pytest.raises(KeyError):
+            await agen.athrow(KeyError())
+
+    run(backend, main)
+
+
+def test_athrow_not_awaited(backend):
+    async def main():
+        agen = demo.resettable_count()
+        assert [await agen.__anext__() for _ in range(3)] == [0, 1, 2]
+        # like Python async generators, the exception is only thrown when awaited
+        agen.athrow(ValueError()).close()
+        assert await agen.__anext__() == 3
+
+    run(backend, main)
+
+
+def test_closeable_stream(backend):
+    async def main():
+        flushed = []
+        agen = demo.flushing_count(3, flushed, False)
+        assert await agen.__anext__() == 0
+        # the asynchronous cleanup is completed when `aclose` returns
+        assert await agen.aclose() is None
+        assert flushed == [1]
+        await agen.aclose()
+        assert flushed == [1]
+        agen = demo.flushing_count(3, flushed, True)
+        assert await agen.__anext__() == 0
+        with pytest.raises(RuntimeError, match="flush failed"):
+            await agen.aclose()
+        assert flushed == [1]
+
+    run(backend, main)
+
+
+def test_watch_stream(backend):
+    async def main():
+        watched = demo.Watched(0)
+        agen = watched.subscribe(True)
+        # the current value is yielded first
+        assert await agen.__anext__() == 0
+        # intermediate updates are coalesced
+        for i in range(1, 4):
+            watched.set(i)
+        assert await agen.__anext__() == 3
+        # the stream awaits the next change
+        threading.Timer(0.01, watched.set, (4,)).start()
+        assert await agen.__anext__() == 4
+        # without initial value, only changes are yielded; closing terminates the streams
+        later = watched.subscribe(False)
+        watched.set(5)
+        assert await later.__anext__() == 5
+        watched.close()
+        assert [i async for i in agen] == [5]
+        assert [i async for i in later] == []
+
+    run(backend, main)
+
+
+def test_broadcast(backend):
+    async def main():
+        broadcast = demo.broadcast_count(5, 5, False)
+        first, second = broadcast.subscribe(), broadcast()
+        # each subscriber receives every item, the buffered ones included
+        assert [i async for i in first] == [0, 1, 2, 3, 4]
+        assert [i async for i in second] == [0, 1, 2, 3, 4]
+        # a late subscriber only receives the items yielded after its subscription
+        broadcast = demo.broadcast_count(5, 5, False)
+        first = broadcast.subscribe()
+        assert await first.__anext__() == 0
+        late = broadcast.subscribe()
+        assert [i async for i in first] == [1, 2, 3, 4]
+        assert [i async for i in late] == [1, 2, 3, 4]
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("skip_lagged", [False, True])
+def test_broadcast_lag(backend, skip_lagged):
+    async def main():
+        broadcast = demo.broadcast_count(5, 2, skip_lagged)
+        first, lagging = broadcast.subscribe(), broadcast.subscribe()
+        assert [i async for i in first] == [0, 1, 2, 3, 4]
+        # the lagging subscriber resumes with the oldest buffered item
+        if not skip_lagged:
+            with pytest.raises(RuntimeError, match="lagged behind by 3 items"):
+                await lagging.__anext__()
+        assert [i async for i in lagging] == [3, 4]
+
+    run(backend, main)
+
+
+def test_progress(backend):
+    async def main():
+        assert [progress async for progress in demo.download(3, None)] == [1, 2, 3]
+        # the result is passed to the final `StopAsyncIteration`
+        agen = demo.download(2, None)
+        assert await agen.__anext__() == 1
+        assert await agen.__anext__() == 2
+        with pytest.raises(StopAsyncIteration) as exc_info:
+            await agen.__anext__()
+        assert exc_info.value.args == ("done",)
+        # the error of the future is raised as is, after the updates sent before it
+        agen = demo.download(3, 2)
+        assert await agen.__anext__() == 1
+        with pytest.raises(ConnectionRefusedError, match="download failed"):
+            await agen.__anext__()
+        # closing the generator drops the future
+        dropped = demo.dropped_count()
+        agen = demo.download(3, None)
+        assert await agen.__anext__() == 1
+        await agen.aclose()
+        assert demo.dropped_count() == dropped + 1
+
+    run(backend, main)
+
+
+def test_async_generator_aclose(backend):
+    async def main():
+        pulled = []
+        agen = demo.count_pulled(3, pulled)
+        assert await agen.__anext__() == 0
+        # without throw callback, the stream is dropped without pulling another item
+        assert await agen.aclose() is None
+        assert pulled == [0]
+        # closing a terminated generator is a no-op
+        assert await agen.aclose() is None
+        with pytest.raises(StopAsyncIteration):
+            await agen.__anext__()
+
+    run(backend, main)
+
+
+def test_lazy_async_generator(backend):
+    async def main():
+        created = []
+        agen = demo.lazy_count(3, created)
+        assert created == []
+        assert [i async for i in agen] == [0, 1, 2]
+        assert created == [None]
+        # closing a generator never iterated doesn't instantiate its stream
+        created.clear()
+        agen = demo.lazy_count(3, created)
+        await agen.aclose()
+        with pytest.raises(StopAsyncIteration):
+            await agen.__anext__()
+        assert created == []
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("drop_on_gc", [False, True])
+def test_async_generator_gc(backend, drop_on_gc):
+    async def main():
+        agen = demo.thread_guarded_count(3, drop_on_gc)
+        assert await agen.__anext__() == 0
+
+        def abandon():
+            nonlocal agen
+            del agen
+
+        # the abandoned generator is finalized in another thread
+        thread = threading.Thread(target=abandon)
+        thread.start()
+        thread.join()
+        if drop_on_gc:
+            assert demo.dropped_in() == thread.ident
+        else:
+            # the drop is scheduled in the event loop thread
+            assert demo.dropped_in() is None
+            for _ in range(100):
+                await sleep(backend, 0.001)
+                if demo.dropped_in() is not None:
+                    break
+            assert demo.dropped_in() == threading.get_ident()
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("n, chunk_size", [(1000, 100), (1_000_000, 100_000)])
+def test_chunked_conversion(backend, n, chunk_size):
+    ticks = []
+
+    async def ticker():
+        while True:
+            ticks.append(time.perf_counter())
+            await sleep(backend, 0)
+
+    async def convert():
+        start = time.perf_counter()
+        result = await demo.chunked_range(n, chunk_size)
+        return result, start, time.perf_counter()
+
+    async def main():
+        if backend == "trio":
+            import trio
+
+            async with trio.open_nursery() as nursery:
+                nursery.start_soon(ticker)
+                await trio.sleep(0)
+                result, start, end = await convert()
+                nursery.cancel_scope.cancel()
+        else:
+            task = asyncio.ensure_future(ticker())
+            await asyncio.sleep(0)
+            result, start, end = await convert()
+            task.cancel()
+        assert result == list(range(n))
+        # the other task runs once per step of the conversion, i.e. per chunk, while it's in
+        # progress; trio runs the tasks of a batch in random order, so the ticks of the first
+        # and last steps may happen before/after the conversion
+        during = [tick for tick in ticks if start < tick < end]
+        assert len(during) >= n // chunk_size - 2
+
+    run(backend, main)
+
+
+def test_budget_checkpoint(backend):
+    bursts = 5
+    ticks = 0
+
+    async def ticker():
+        nonlocal ticks
+        while True:
+            ticks += 1
+            await sleep(backend, 0)
+
+    async def main():
+        if backend == "trio":
+            import trio
+
+            async with trio.open_nursery() as nursery:
+                nursery.start_soon(ticker)
+                await trio.sleep(0)
+                await demo.budgeted_bursts(bursts)
+                nursery.cancel_scope.cancel()
+        else:
+            task = asyncio.ensure_future(ticker())
+            await asyncio.sleep(0)
+            await demo.budgeted_bursts(bursts)
+            task.cancel()
+        # the budget is exhausted by each burst, the other task runs at each checkpoint
+        assert ticks >= bursts
+
+    run(backend, main)
+
+
+def test_poll_loop(backend):
+    async def main():
+        # rescheduled through the event loop, though the future never registers its waker
+        assert await demo.spinning(3) == 3
+
+    run(backend, main)
+
+
+def test_step_fn_self_wake(backend):
+    async def main():
+        # waking itself, the step function is resumed after yielding to the event loop
+        assert await demo.self_waking_steps(3) == 3
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("ordered", [True, False])
+def test_map_concurrent(backend, ordered):
+    if backend == "trio":
+        pytest.skip("asyncio only")
+    import asyncio
+
+    running, max_running = 0, 0
+
+    async def mapper(i):
+        nonlocal running, max_running
+        running += 1
+        max_running = max(max_running, running)
+        try:
+            await asyncio.sleep(random.uniform(0.001, 0.01))
+        finally:
+            running -= 1
+        if i == 7:
+            raise ValueError(i)
+        return i * i
+
+    async def main():
+        results, errors = [], []
+        agen = demo.map_concurrent(20, mapper, 4, ordered)
+        while True:
+            try:
+                results.append(await agen.__anext__())
+            except StopAsyncIteration:
+                break
+            except ValueError as err:
+                errors.append(err.args[0])
+        squares = [i * i for i in range(20) if i != 7]
+        assert results == squares if ordered else sorted(results) == squares
+        assert errors == [7]
+        assert max_running == 4
+
+    run(backend, main)
+
+
+def test_map_concurrent_cancellation(backend):
+    if backend == "trio":
+        pytest.skip("asyncio only")
+    import asyncio
+
+    started, cancelled = 0, 0
+
+    async def mapper(i):
+        nonlocal started, cancelled
+        started += 1
+        try:
+            await asyncio.sleep(10)
+        except (asyncio.CancelledError, GeneratorExit):
+            cancelled += 1
+            raise
+
+    async def main():
+        agen = demo.map_concurrent(10, mapper, 3)
+        assert await move_on_after(backend, 0.05, agen.__anext__())
+        await agen.aclose()
+        assert started == cancelled == 3
+
+    run(backend, main)
+
+
+def test_async_generator_protocol(backend):
+    async def main():
+        agen = demo.count(10, 0)
+        assert isinstance(agen, collections.abc.AsyncGenerator)
+        assert await agen.asend(None) == 0
+        assert await agen.asend(value=None) == 1
+        # `__anext__` mixin of the ABC calls `asend(None)`
+        assert await collections.abc.AsyncGenerator.__anext__(agen) == 2
+        for args in [
+            (ValueError,),
+            (ValueError, "msg"),
+            (ValueError, ("msg",)),
+            (ValueError("msg"),),
+            (ValueError, ValueError("msg"), None),
+        ]:
+            with pytest.raises(ValueError):
+                await agen.athrow(*args)
+        with pytest.raises(ValueError, match="msg"):
+            await agen.athrow(ValueError, "msg")
+        with pytest.raises(TypeError):
+            agen.athrow(ValueError("msg"), "msg")
+        with pytest.raises(TypeError):
+            agen.athrow(42)
+        # `aclose` mixin of the ABC calls `athrow(GeneratorExit)`
+        await collections.abc.AsyncGenerator.aclose(agen)
+
+    run(backend, main)
+
+
+def test_coroutine_throw():
+    coro = demo.cancellable_sleep(10)
+    with pytest.raises(ValueError, match="msg"):
+        coro.throw(ValueError, "msg")
+    coro = demo.cancellable_sleep(10)
+    with pytest.raises(TypeError):
+        coro.throw(ValueError("msg"), "msg")
+    coro.close()
+
+
+REENTRANT_CALLS = {"send": (None,), "throw": (RuntimeError,), "close": ()}
+
+
+@pytest.mark.parametrize("method", list(REENTRANT_CALLS))
+def test_coroutine_reentrancy(method):
+    import asyncio
+
+    async def reenter():
+        with pytest.raises(ValueError, match="coroutine already executing"):
+            getattr(coro, method)(*REENTRANT_CALLS[method])
+        return 21
+
+    async def main():
+        return await coro
+
+    coro = demo.await_double(reenter())
+    assert asyncio.run(main()) == 42
+
+
+@pytest.mark.parametrize("method", list(REENTRANT_CALLS))
+def test_coroutine_reentrancy_without_gil(method):
+    import asyncio
+
+    errors = []
+
+    def reenter():
+        try:
+            getattr(coro, method)(*REENTRANT_CALLS[method])
+        except ValueError as err:
+            errors.append(str(err))
+
+    def callback():
+        # from the polling thread, then from another one, the GIL being released by the poll
+        reenter()
+        thread = threading.Thread(target=reenter)
+        thread.start()
+        thread.join()
+        return 42
+
+    async def main():
+        return await coro
+
+    coro = demo.reenter_without_gil(callback)
+    assert asyncio.run(main()) == 42
+    assert errors == ["coroutine already executing"] * 2
+
+
+def test_coroutine_result():
+    import asyncio
+
+    assert demo.multiply(2, 3, 0.01).result() == 6
+    with pytest.raises(asyncio.TimeoutError):
+        demo.multiply(2, 3, 10).result(timeout=0.01)
+
+    async def main():
+        coro = demo.multiply(2, 3, 0)
+        with pytest.raises(RuntimeError, match="running event loop"):
+            coro.result()
+        return await coro
+
+    assert asyncio.run(main()) == 6
+
+
+def test_coroutine_result_unsupported():
+    import asyncio
+
+    with pytest.raises(RuntimeError, match="throw callback"):
+        demo.record_cancel_message([]).result()
+
+    async def start(coro):
+        coro.send(None)
+
+    started = demo.suspend(1)
+    asyncio.run(start(started))
+    with pytest.raises(RuntimeError, match="already started"):
+        started.result()
+    started.close()
+    awaited = demo.multiply(2, 3, 0)
+    asyncio.run(awaited)
+    with pytest.raises(RuntimeError, match="already awaited"):
+        awaited.result()
+
+
+def test_coroutine_result_close_error():
+    import asyncio
+
+    class Policy(asyncio.DefaultEventLoopPolicy):
+        def new_event_loop(self):
+            loop = super().new_event_loop()
+            close = loop.close
+
+            def failing_close():
+                close()
+                raise OSError("close failed")
+
+            loop.close = failing_close
+            return loop
+
+    unraisables = []
+    policy, hook = asyncio.get_event_loop_policy(), sys.unraisablehook
+    asyncio.set_event_loop_policy(Policy())
+    sys.unraisablehook = unraisables.append
+    try:
+        # the outcome is kept, the close error being reported or attached as context
+        assert demo.multiply(2, 3, 0).result() == 6
+        assert [type(u.exc_value) for u in unraisables] == [OSError]
+        with pytest.raises(asyncio.TimeoutError) as exc_info:
+            demo.multiply(2, 3, 10).result(timeout=0.01)
+        assert isinstance(exc_info.value.__context__, OSError)
+    finally:
+        asyncio.set_event_loop_policy(policy)
+        sys.unraisablehook = hook
+
+
+def assert_panics(call):
+    with pytest.raises(BaseException, match="boom") as exc_info:
+        call()
+    assert type(exc_info.value).__name__ == "PanicException"
+
+
+@pytest.mark.parametrize("entry_point", ["send", "__next__", "throw", "close"])
+def test_coroutine_panic(entry_point):
+    import asyncio
+
+    async def main():
+        coro = demo.panicking()
+        calls = {
+            "send": lambda: coro.send(None),
+            "__next__": lambda: next(coro),
+            "throw": lambda: coro.throw(ValueError),
+            "close": coro.close,
+        }
+        assert_panics(calls[entry_point])
+        # the panicked future has been dropped
+        with pytest.raises(RuntimeError, match="cannot reuse already awaited coroutine"):
+            coro.send(None)
+
+    asyncio.run(main())
+
+
+def test_async_generator_first_asend(backend):
+    async def main():
+        agen = demo.count(3, 0)
+        with pytest.raises(TypeError, match="just-started async generator"):
+            await agen.asend(1)
+        # the generator is left unstarted, then values are ignored
+        assert await agen.asend(None) == 0
+        assert await agen.asend(1) == 1
+
+    run(backend, main)
+
+
+def test_async_generator_panic(backend):
+    async def main():
+        agen = demo.panicking_stream()
+        anext = agen.__anext__()
+        assert_panics(lambda: anext.send(None))
+        with pytest.raises(RuntimeError, match="previously panicked: boom"):
+            await agen.asend(None)
+
+    run(backend, main)
+
+
+def test_async_generator_panic_remembered(backend):
+    async def main():
+        polls = []
+        agen = demo.panicking_stream(3, polls)
+        assert [await agen.__anext__() for _ in range(3)] == [0, 1, 2]
+        anext = agen.__anext__()
+        assert_panics(lambda: anext.send(None))
+        assert polls == [0, 1, 2, 3]
+        for _ in range(2):
+            with pytest.raises(RuntimeError, match="previously panicked: boom"):
+                await agen.__anext__()
+        # the panicked stream is not polled anymore
+        assert polls == [0, 1, 2, 3]
+
+    run(backend, main)
+
+
+def test_drain_to_channel():
+    pulled = []
+
+    async def gen():
+        for i in range(10):
+            pulled.append(i)
+            yield i
+        raise ValueError("end")
+
+    async def main():
+        receiver, drain = demo.drained(gen(), 2)
+        task = asyncio.create_task(drain)
+        await asyncio.sleep(0.01)
+        # `capacity + 1` items are buffered, the next one is not pulled
+        assert pulled == [0, 1, 2]
+        assert await receiver.__anext__() == 0
+        await asyncio.sleep(0.01)
+        assert pulled == [0, 1, 2, 3]
+        items = []
+        with pytest.raises(ValueError, match="end"):
+            async for item in receiver:
+                items.append(item)
+        assert items == list(range(1, 10))
+        await task
+        # the sender is dropped with the draining coroutine
+        assert [i async for i in receiver] == []
+
+    asyncio.run(main())
+
+
+def test_async_generator_error_policy(backend):
+    async def collect(agen):
+        items = []
+        while True:
+            try:
+                items.append(await agen.__anext__())
+            except ValueError as err:
+                items.append(f"error {err}")
+            except StopAsyncIteration:
+                return items
+
+    async def main():
+        agen = demo.failing_items(4, [1, 2])
+        assert await collect(agen) == [0, "error 1", "error 2", 3]
+        agen = demo.failing_items(4, [1, 2], terminate=True)
+        assert await collect(agen) == [0, "error 1"]
+        agen = demo.failing_items(4, [1, 2], on_error=lambda err: f"yielded {err}")
+        assert [i async for i in agen] == [0, "yielded 1", "yielded 2", 3]
+
+    run(backend, main)
+
+
+@pytest.mark.parametrize("terminate", [False, True])
+def test_async_generator_item_timeout(terminate):
+    import asyncio
+
+    async def main():
+        agen = demo.stalling([0, 0.25, 0], 0.1, terminate)
+        assert await agen.__anext__() == 0
+        with pytest.raises(asyncio.TimeoutError):
+            await agen.__anext__()
+        if terminate:
+            with pytest.raises(StopAsyncIteration):
+                await agen.__anext__()
+            return
+        # the stream survives, still waiting for the stalled item
+        timeouts = 1
+        while True:
+            try:
+                item = await agen.__anext__()
+                break
+            except asyncio.TimeoutError:
+                timeouts += 1
+        assert item == 1 and timeouts == 2
+        assert await agen.__anext__() == 2
+        with pytest.raises(StopAsyncIteration):
+            await agen.__anext__()
+
+    asyncio.run(main())
+
+
+def test_async_callable():
+    import asyncio
+
+    async def main():
+        loop_thread = threading.get_ident()
+        threads = []
+
+        async def handler(i):
+            await asyncio.sleep(0)
+            threads.append(threading.get_ident())
+            return 2 * i
+
+        for off_loop in (False, True):
+            threads.clear()
+            results = await demo.call_handler(handler, 100, off_loop)
+            assert results == [2 * i for i in range(100)]
+            assert threads == [loop_thread] * 100
+        threads.clear()
+        await demo.notify_handler(handler, 100)
+        while len(threads) < 100:
+            await asyncio.sleep(0.01)
+        assert threads == [loop_thread] * 100
+
+    asyncio.run(main())
+
+
+def test_map_concurrent_tasks():
+    import asyncio
+
+    running, max_running, cancelled = 0, 0, []
+
+    async def work(i):
+        nonlocal running, max_running
+        running += 1
+        max_running = max(max_running, running)
+        try:
+            await asyncio.sleep(0.01 * (5 - i % 5))
+            if i == 3:
+                raise ValueError(i)
+            return i
+        except asyncio.CancelledError:
+            cancelled.append(i)
+            raise
+        finally:
+            running -= 1
+
+    async def main():
+        agen = demo.map_tasks(list(range(10)), work, 3)
+        results, errors = [], []
+        while True:
+            try:
+                results.append(await agen.__anext__())
+            except ValueError as err:
+                errors.append(err.args[0])
+            except StopAsyncIteration:
+                break
+        # results are yielded as they complete
+        assert results[0] == 2
+        assert sorted(results) == [i for i in range(10) if i != 3]
+        assert errors == [3]
+        assert max_running == 3
+        # tasks make progress while the async generator is not iterated
+        agen = demo.map_tasks([2, 4], work, 2)
+        assert await agen.__anext__() == 4
+        await asyncio.sleep(0.05)
+        assert running == 0
+        assert await agen.__anext__() == 2
+        # in-flight tasks are cancelled on close
+        agen = demo.map_tasks([4, 0, 1], work, 3)
+        assert await agen.__anext__() == 4
+        await agen.aclose()
+        await asyncio.sleep(0)
+        assert sorted(cancelled) == [0, 1]
+
+    asyncio.run(main())
+
+
+def test_async_generator_sink_backpressure():
+    async def main():
+        agen = demo.stalled_sink(1)
+        await agen.asend_many([0])
+        for i in range(1, 5):
+            with pytest.raises(asyncio.TimeoutError):
+                await asyncio.wait_for(agen.asend_many([i]), 0.01)
+        # the batches share the sender, so the channel only holds its capacity plus the
+        # single slot of the sender, instead of a slot per batch
+        assert demo.stalled_queued() == [0, 1]
+
+    asyncio.run(main())
+
+
+def test_async_generator_asend_many():
+    import asyncio
+
+    n = 10_000
+    expected = (n, sum(range(n)))
+
+    async def wait_received():
+        while demo.sink_received()[0] < n:
+            await asyncio.sleep(0.001)
+
+    async def main():
+        agen = demo.summing_sink(64)
+        for i in range(n):
+            await agen.asend(i)
+        await wait_received()
+        assert demo.sink_received() == expected
+
+        agen = demo.summing_sink(64)
+        assert await agen.asend_many(range(n)) is None
+        await wait_received()
+        assert demo.sink_received() == expected
+
+        # a conversion error reports the failing index, and no value is fed
+        with pytest.raises(TypeError) as exc_info:
+            await agen.asend_many([1, 2, "x", 4])
+        assert exc_info.value.index == 2
+        await asyncio.sleep(0.01)
+        assert demo.sink_received() == expected
+
+    asyncio.run(main())
+
+
+def test_race():
+    import asyncio
+
+    finalized = []
+
+    async def slow(result, delay):
+        try:
+            await asyncio.sleep(delay)
+            return result
+        finally:
+            finalized.append(result)
+
+    async def failing():
+        raise ValueError
+
+    async def main():
+        dropped = demo.dropped_count()
+        assert await demo.race_sleep(10, slow("python", 0.01)) == ("python", "python")
+        # the losing future has been dropped
+        assert demo.dropped_count() == dropped + 1
+        assert await demo.race_sleep(0.01, slow("cancelled", 10)) == ("rust", 0.01)
+        # the losing awaitable has been closed
+        assert finalized == ["python", "cancelled"]
+        # an error completes the race too
+        with pytest.raises(ValueError):
+            await demo.race_sleep(10, failing())
+
+    asyncio.run(main())
+
+
+def test_wrong_event_loop():
+    import asyncio
+
+    trio = pytest.importorskip("trio")
+
+    async def main(backend):
+        return await demo.backend_coroutine(backend)
+
+    assert asyncio.run(main("asyncio")) == 42
+    assert trio.run(main, "trio") == 42
+    msg = r"this coroutine requires a trio event loop; it was awaited under asyncio \(detected\)"
+    with pytest.raises(RuntimeError, match=msg):
+        asyncio.run(main("trio"))
+    msg = r"this coroutine requires an asyncio event loop; it was awaited under trio \(detected\)"
+    with pytest.raises(RuntimeError, match=msg):
+        trio.run(main, "asyncio")
+    msg = "it was awaited outside of any event loop"
+    for backend in ("asyncio", "trio"):
+        with pytest.raises(RuntimeError, match=msg):
+            demo.backend_coroutine(backend).send(None)
+
+
+def test_erased_plugin():
+    async def main():
+        result = await demo.plugin_countdown(3)
+        assert result == "liftoff"
+        # the result has been released by the plugin pyo3, instead of leaking in its pool
+        assert sys.getrefcount(result) == 2
+        with pytest.raises(ValueError, match="negative countdown"):
+            await demo.plugin_countdown(-1)
+
+    asyncio.run(main())
+
+
+def test_erased_plugin_drop():
+    class Held:
+        pass
+
+    held = Held()
+    ref = weakref.ref(held)
+    coroutine = demo.plugin_hold(held)
+    del held
+    assert ref() is not None
+    # the reference held by the plugin is released when the coroutine is dropped
+    del coroutine
+    assert ref() is None
+
+
+@pytest.mark.parametrize("executor", [False, True])
+def test_wait_threading_event(executor):
+    async def main():
+        event = threading.Event()
+        threading.Timer(0.01, event.set).start()
+        await demo.wait_event(event, executor)
+        assert event.is_set()
+
+    asyncio.run(main())
+
+
+def test_wait_threading_event_dropped():
+    event = threading.Event()
+    # fallback release of the executor thread, if the wait were not bounded
+    timer = threading.Timer(10, event.set)
+    timer.start()
+
+    async def main():
+        with pytest.raises(asyncio.TimeoutError):
+            await asyncio.wait_for(demo.wait_event(event, True), 0.01)
+
+    start = time.monotonic()
+    try:
+        # `asyncio.run` shuts down the default executor, waiting for the blocked thread
+        asyncio.run(main())
+        assert time.monotonic() - start < 5
+    finally:
+        timer.cancel()
+
+
+def test_waker_future_states():
+    async def main():
+        # a threadsafe wake not run yet leaves the future pending, which is not an error
+        coroutine = demo.wait_woken()
+        coroutine.send(None)
+        thread = threading.Thread(target=demo.wake)
+        thread.start()
+        thread.join()
+        with pytest.raises(StopIteration):
+            coroutine.send(None)
+        # the future cancelled externally cancels the coroutine, dropping its future
+        dropped = demo.dropped_count()
+        coroutine = demo.cancellable_sleep(10)
+        coroutine.send(None).cancel()
+        with pytest.raises(asyncio.CancelledError):
+            coroutine.send(None)
+        assert demo.dropped_count() == dropped + 1
+        # an exception set on the future is raised into the coroutine
+        coroutine = demo.cancellable_sleep(10)
+        coroutine.send(None).set_exception(ValueError("future error"))
+        with pytest.raises(ValueError, match="future error"):
+            coroutine.send(None)
+
+    asyncio.run(main())
+
+
+def test_coroutine_as_future():
+    async def main():
+        coroutine = demo.multiply(2, 3, 0.01)
+        future = coroutine.as_future()
+        assert isinstance(future, asyncio.Future)
+        assert coroutine.as_future() is future
+        done = []
+        future.add_done_callback(done.append)
+        # the coroutine is driven by the task, it cannot be awaited anymore
+        with pytest.raises(RuntimeError, match="await the future instead"):
+            await coroutine
+        assert await future == 6
+        await asyncio.sleep(0)
+        assert done == [future]
+        # an awaited coroutine cannot be converted
+        coroutine = demo.multiply(2, 3, 0)
+        assert await coroutine == 6
+        with pytest.raises(RuntimeError, match="already awaited"):
+            coroutine.as_future()
+
+    asyncio.run(main())
+
+
+def test_cancelled_message():
+    async def main():
+        messages = []
+        task = asyncio.create_task(demo.record_cancel_message(messages))
+        await asyncio.sleep(0)
+        task.cancel("shutdown")
+        with pytest.raises(asyncio.CancelledError):
+            await task
+        assert messages == ["shutdown"]
+        # other exceptions have no cancellation message
+        coroutine = demo.record_cancel_message(messages)
+        coroutine.send(None)
+        with pytest.raises(ValueError):
+            coroutine.throw(ValueError("not cancelled"))
+        assert messages == ["shutdown"]
+
+    asyncio.run(main())
+
+
+def test_remote_wake_callback_reused():
+    callbacks = []
+
+    class RecordingLoop(asyncio.SelectorEventLoop):
+        def call_soon_threadsafe(self, callback, *args, **kwargs):
+            callbacks.append(callback)
+            return super().call_soon_threadsafe(callback, *args, **kwargs)
+
+    loop = RecordingLoop()
+    try:
+        loop.run_until_complete(demo.remote_wakes(3))
+        loop.run_until_complete(demo.remote_wakes(3))
+    finally:
+        loop.close()
+    # a single callback is allocated for all the wakes
+    assert len(callbacks) == 6 and all(cb is callbacks[0] for cb in callbacks)
+
+
+def test_trio_remote_wake_callback_reused():
+    trio = pytest.importorskip("trio")
+    callbacks = []
+    run_sync_soon = trio.lowlevel.TrioToken.run_sync_soon
+
+    def recording_run_sync_soon(self, sync_fn, *args, **kwargs):
+        callbacks.append(sync_fn)
+        return run_sync_soon(self, sync_fn, *args, **kwargs)
+
+    async def main():
+        await demo.remote_wakes(10)
+
+    trio.lowlevel.TrioToken.run_sync_soon = recording_run_sync_soon
+    try:
+        trio.run(main)
+    finally:
+        trio.lowlevel.TrioToken.run_sync_soon = run_sync_soon
+    # the callback is cached by the waker, which is reused unless the waking thread still
+    # holds it when the coroutine is resumed
+    assert len(callbacks) == 10 and len(set(map(id, callbacks))) < 10
+
+
+def test_trio_stale_wake():
+    trio = pytest.importorskip("trio")
+
+    async def main():
+        with trio.move_on_after(0.01):
+            await demo.delayed_wake(0.03)
+        # the wake of the cancelled coroutine comes while the task sleeps, it must not
+        # reschedule it early
+        start = trio.current_time()
+        await trio.sleep(0.1)
+        assert trio.current_time() - start >= 0.1
+
+    trio.run(main)
+
+
+def test_spawn_in_nursery():
+    trio = pytest.importorskip("trio")
+
+    async def main():
+        # the nursery waits for its children
+        async with trio.open_nursery() as nursery:
+            demo.spawn_sleep(nursery, 0.01)
+        dropped = demo.dropped_count()
+        # the nursery cancellation drops the spawned future
+        async with trio.open_nursery() as nursery:
+            demo.spawn_sleep(nursery, 10)
+            await trio.sleep(0.01)
+            nursery.cancel_scope.cancel()
+        assert demo.dropped_count() == dropped + 1
+        with pytest.raises(RuntimeError, match="closed"):
+            demo.spawn_sleep(nursery, 0)
+
+    trio.run(main)
+
+
+class UncancellableFuture(asyncio.Future):
+    def cancel(self, msg=None):
+        raise RuntimeError("uncancellable")
+
+
+@pytest.fixture
+def log_records():
+    import logging
+
+    class Handler(logging.Handler):
+        def emit(self, record):
+            records.append(record)
+
+    records = []
+    handler = Handler()
+    logger = logging.getLogger("pyo3_async")
+    logger.addHandler(handler)
+    try:
+        yield records
+    finally:
+        logger.removeHandler(handler)
+
+
+@pytest.mark.parametrize("pending", [False, True])
+def test_trio_async_generator_drop(pending, log_records):
+    trio = pytest.importorskip("trio")
+    events = []
+
+    async def gen():
+        try:
+            yield 0
+            await trio.sleep(10)
+        finally:
+            # still running when `aclose` is scheduled, if the pending step is not awaited
+            with trio.CancelScope(shield=True):
+                await trio.sleep(0.01)
+            events.append("finalized")
+            if not pending:
+                raise ValueError("aclose error")
+
+    async def main():
+        assert await demo.abandon_trio_async_generator(gen(), pending) == 0
+        await trio.sleep(0.1)
+
+    trio.run(main)
+    assert events == ["finalized"]
+    if pending:
+        assert not log_records
+    else:
+        [record] = log_records
+        assert isinstance(record.exc_info[1], ValueError)
+
+
+def test_drop_while_raising():
+    async def main():
+        future = asyncio.get_running_loop().create_future()
+        coroutine = demo.wait_future(future, "panic")
+        coroutine.send(None)
+        holder = [coroutine]
+        del coroutine
+        # the argument, last reference to the suspended coroutine, is released by the
+        # interpreter with the TypeError already raised, and its drop cancels the future
+        with pytest.raises(TypeError):
+            int(holder.pop())
+        assert future.cancelled()
+
+    asyncio.run(main())
+
+
+def test_spurious_step_yields_same_future():
+    async def wait(a, b):
+        return await a + await b
+
+    async def main():
+        loop = asyncio.get_running_loop()
+        a, b = loop.create_future(), loop.create_future()
+        coro = demo.await_repolled(wait(a, b), 0)
+        yielded = coro.send(None)
+        # not woken in between, the same future is yielded again
+        assert coro.send(None) is yielded
+        a.set_result(1)
+        await asyncio.sleep(0)
+        assert yielded.done()
+        # a genuine wake renews the yielded future
+        renewed = coro.send(None)
+        assert renewed is not yielded
+        assert coro.send(None) is renewed
+        b.set_result(2)
+        await asyncio.sleep(0)
+        with pytest.raises(StopIteration) as exc_info:
+            coro.send(None)
+        assert exc_info.value.value == 3
+
+    asyncio.run(main())
+
+
+@pytest.mark.parametrize("required", [False, True])
+@pytest.mark.parametrize("with_context", [False, True])
+def test_done_callback_context(required, with_context):
+    import contextvars
+
+    contexts = []
+    missing = object()
+
+    class ContextFuture(asyncio.Future):
+        if required:
+
+            def add_done_callback(self, fn, *, context):
+                contexts.append(context)
+                super().add_done_callback(fn, context=context)
+
+        else:
+
+            def add_done_callback(self, fn, *, context=missing):
+                contexts.append(context)
+                kwargs = {} if context is missing else {"context": context}
+                super().add_done_callback(fn, **kwargs)
+
+    context = contextvars.copy_context() if with_context else None
+
+    async def main():
+        future = ContextFuture()
+        asyncio.get_running_loop().call_later(0.01, future.set_result, 42)
+        assert await demo.wait_future(future, context=context) == 42
+
+    asyncio.run(main())
+    if with_context:
+        assert contexts == [context]
+    elif required:
+        # rejected without the keyword, then registered with `context=None`
+        assert contexts == [None]
+    else:
+        assert contexts == [missing]
+
+
+def test_cancel_on_drop_awaitable(log_records):
+    async def wait(future):
+        await future
+
+    async def ready():
+        return 42
+
+    async def main():
+        loop = asyncio.get_running_loop()
+        future = loop.create_future()
+        assert await demo.abandon_awaitable(wait(future), None)
+        assert not future.cancelled()
+        future = loop.create_future()
+        assert await demo.abandon_awaitable(wait(future), "ignore")
+        assert future.cancelled()
+        # a completed awaitable is not cancelled
+        assert not await demo.abandon_awaitable(ready(), "panic")
+        assert await demo.abandon_awaitable(wait(UncancellableFuture()), "ignore")
+        assert not log_records
+        assert await demo.abandon_awaitable(wait(UncancellableFuture()), "log")
+        [record] = log_records
+        assert record.getMessage() == "Cancel error while dropping AwaitableWrapper"
+        assert str(record.exc_info[1]) == "uncancellable"
+        msg = "Cancel error while dropping AwaitableWrapper"
+        with pytest.raises(BaseException, match=msg):
+            await demo.abandon_awaitable(wait(UncancellableFuture()), "panic")
+
+    asyncio.run(main())
+
+
+def test_cancel_on_drop_future(log_records):
+    async def main():
+        loop = asyncio.get_running_loop()
+        future = loop.create_future()
+        assert await demo.abandon_future(future, None)
+        assert not future.cancelled()
+        assert await demo.abandon_future(future, "ignore")
+        assert future.cancelled()
+        assert await demo.abandon_future(UncancellableFuture(), "ignore")
+        assert not log_records
+        assert await demo.abandon_future(UncancellableFuture(), "log")
+        [record] = log_records
+        msg = "Cancel error while dropping FutureWrapper"
+        assert record.getMessage() == msg
+        with pytest.raises(BaseException, match=msg):
+            await demo.abandon_future(UncancellableFuture(), "panic")
+
+    asyncio.run(main())
+
+
+def test_cancel_on_drop_async_generator():
+    finalized = []
+
+    async def gen(name):
+        try:
+            yield name
+            await asyncio.sleep(10)
+            yield None
+        finally:
+            finalized.append(name)
+
+    async def main():
+        kept = gen("kept")
+        assert await demo.abandon_async_generator(kept, None) == "kept"
+        closed = gen("closed")
+        assert await demo.abandon_async_generator(closed, "log") == "closed"
+        for _ in range(3):
+            await asyncio.sleep(0)
+        # `aclose` has been scheduled for the second one only
+        assert finalized == ["closed"]
+
+    asyncio.run(main())
+
+
+def test_cancel_on_drop_async_generator_error(log_records):
+    async def stubborn():
+        yield "stubborn"
+        try:
+            await asyncio.sleep(10)
+        except asyncio.CancelledError:
+            raise RuntimeError("uncancellable")
+        yield None
+
+    async def main():
+        assert await demo.abandon_async_generator(stubborn(), "ignore") == "stubborn"
+        assert not log_records
+        assert await demo.abandon_async_generator(stubborn(), "log") == "stubborn"
+        [record] = log_records
+        msg = "Cancel error while dropping AsyncGeneratorWrapper"
+        assert record.getMessage() == msg
+        assert str(record.exc_info[1]) == "uncancellable"
+        with pytest.raises(BaseException, match=msg):
+            await demo.abandon_async_generator(stubborn(), "panic")
+
+    asyncio.run(main())
+
+
+@pytest.mark.skipif(not hasattr(os, "fork"), reason="requires os.fork")
+def test_wake_after_fork():
+    class CountingLoop(asyncio.SelectorEventLoop):
+        threadsafe_calls = 0
+
+        def call_soon_threadsafe(self, *args, **kwargs):
+            self.threadsafe_calls += 1
+            return super().call_soon_threadsafe(*args, **kwargs)
+
+    async def main():
+        loop = asyncio.get_running_loop()
+        task = loop.create_task(demo.wait_woken())
+        await asyncio.sleep(0)
+        # same thread, the wake is direct
+        demo.wake()
+        await task
+        assert loop.threadsafe_calls == 0
+        task = loop.create_task(demo.wait_woken())
+        await asyncio.sleep(0)
+        pid = os.fork()
+        if pid == 0:
+            # the child thread inherits the thread-locals of the parent one, but the waker
+            # captured before the fork must not take the fast-path; asyncio resets the running
+            # loop in the child, so the wake is only checked to be scheduled threadsafe
+            exit_code = 1
+            try:
+                demo.wake()
+                exit_code = 0 if loop.threadsafe_calls == 1 else 2
+            finally:
+                os._exit(exit_code)
+        demo.wake()
+        await task
+        assert loop.threadsafe_calls == 0
+        _, status = os.waitpid(pid, 0)
+        assert os.waitstatus_to_exitcode(status) == 0
+
+    loop = CountingLoop()
+    try:
+        loop.run_until_complete(main())
+    finally:
+        loop.close()
+
+
+def test_sniffio_import_retried():
+    pytest.importorskip("trio")
+    # run in a fresh interpreter, as the sniffio import is cached once it succeeds
+    code = """
+import asyncio, sys
+sys.modules["sniffio"] = None
+import pyo3_async_demo as demo
+
+# without sniffio, only asyncio is detected
+assert asyncio.run(demo.add(1, 2, 0)) == 3
+try:
+    demo.add(1, 2, 0).send(None)
+except RuntimeError as err:
+    assert "install it to enable trio detection" in str(err)
+else:
+    raise AssertionError("no error raised")
+
+# the failed import is not cached, so trio is detected once sniffio is importable
+del sys.modules["sniffio"]
+import trio
+
+async def main():
+    return await demo.add(1, 2, 0)
+
+assert trio.run(main) == 3
+"""
+    env = {**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)}
+    result = subprocess.run([sys.executable, "-c", code], env=env, capture_output=True)
+    assert result.returncode == 0, result.stderr.decode()
+
+
+def test_heartbeat(backend):
+    async def main():
+        items = [item async for item in demo.heartbeat([0, 0.25, 0], 0.1)]
+        # pings are only yielded during the quiet period
+        assert items[:2] == [0, "ping"] and items[-2:] == [1, 2]
+        assert set(items[1:-2]) == {"ping"}
+
+    run(backend, main)
+
+
+def test_wake_latency():
+    async def main():
+        for _ in range(10):
+            coroutine = demo.remote_wake()
+            assert demo.wake_latency(coroutine) is None
+            await coroutine
+            assert demo.wake_latency(coroutine) > 0
+
+    asyncio.run(main())
+
+
+def test_native_coroutine():
+    native = demo.native_sleep(0.001)
+    assert inspect.iscoroutine(native)
+    assert asyncio.iscoroutine(native)
+    assert asyncio.run(native) is None
+
+
+def test_spawn_task_context():
+    var = contextvars.ContextVar("var", default="unset")
+
+    def set_var(value):
+        return lambda: var.set(value)
+
+    async def main():
+        var.set("current")
+        task = demo.spawn_callback(var.get, name="rust-task")
+        assert task.get_name() == "rust-task"
+        assert await task == "current"
+        # a copy of the current context
+        await demo.spawn_callback(set_var("copy"))
+        assert var.get() == "current"
+        # the explicit context itself, or a copy of it before Python 3.11
+        explicit = contextvars.Context()
+        explicit.run(var.set, "explicit")
+        assert await demo.spawn_callback(var.get, context=explicit) == "explicit"
+        await demo.spawn_callback(set_var("explicit task"), context=explicit)
+        expected = "explicit task" if sys.version_info >= (3, 11) else "explicit"
+        assert explicit.run(var.get) == expected
+        # the context of the current task, or a copy of it before Python 3.12
+        assert await demo.spawn_callback(var.get, inherit=True) == "current"
+        await demo.spawn_callback(set_var("inherit"), inherit=True)
+        expected = "inherit" if sys.version_info >= (3, 12) else "current"
+        assert var.get() == expected
+
+    asyncio.run(main())
+
+
+@pytest.mark.skipif(sys.version_info < (3, 11), reason="asyncio.TaskGroup requires 3.11")
+def test_spawn_in_task_group():
+    var = contextvars.ContextVar("var", default="unset")
+
+    async def main():
+        var.set("current")
+        async with asyncio.TaskGroup() as task_group:
+            child = demo.spawn_callback(var.get, name="child", task_group=task_group)
+        assert child.get_name() == "child"
+        assert child.result() == "current"
+
+    asyncio.run(main())
+
+
+@pytest.mark.parametrize("max_bytes", [0, 250, 1000])
+def test_buffered_bytes(max_bytes):
+    sizes = [100] * 10
+
+    async def main():
+        consumed, buffered = 0, []
+        async for chunk in demo.buffered_chunks(sizes, max_bytes):
+            consumed += len(chunk)
+            buffered.append(demo.pulled_bytes() - consumed)
+        return consumed, buffered
+
+    consumed, buffered = asyncio.run(main())
+    assert consumed == sum(sizes)
+    assert max(buffered) == max(0, (max_bytes - 1) // 100 * 100)
+
+
+def test_byte_stream_memoryview():
+    chunk_size, count = 1 << 10, 10
+
+    async def main():
+        views = [chunk async for chunk in demo.byte_chunks(chunk_size, count)]
+        # views keep their buffer alive after the generator has been dropped
+        for i, view in enumerate(views):
+            assert isinstance(view, memoryview) and view.readonly
+            assert len(view) == chunk_size
+            assert view[0] == view[-1] == i
+        with pytest.raises(TypeError):
+            views[0][0] = 42
+
+    asyncio.run(main())
+
+
+def test_conversions():
+    async def main(kind, secs):
+        return await demo.converted(kind, secs)
+
+    utc = datetime.timezone.utc
+    day = datetime.datetime(1970, 1, 2, tzinfo=utc)
+    assert asyncio.run(main("duration", 90)) == datetime.timedelta(seconds=90)
+    assert asyncio.run(main("system_time", 86400)) == day
+    assert asyncio.run(main("decimal", 150)) == decimal.Decimal("1.50")
+    assert asyncio.run(main("chrono_duration", 90)) == datetime.timedelta(seconds=90)
+    assert asyncio.run(main("chrono_datetime", 86400)) == day
+    # fallible conversions raise instead of panicking
+    with pytest.raises(OverflowError):
+        asyncio.run(main("duration", 1 << 62))
+    with pytest.raises(OverflowError):
+        asyncio.run(main("system_time", 1 << 40))
+
+
+def test_footprint():
+    size = 1 << 16
+
+    async def main():
+        coroutine = demo.buffered(size)
+        buffered = sys.getsizeof(coroutine)
+        assert buffered >= sys.getsizeof(demo.buffered(0)) + size
+        assert await coroutine == size
+        # the footprint follows the buffer released by the future
+        assert sys.getsizeof(coroutine) <= buffered - size
+
+    asyncio.run(main())
+
+
+def test_suspension_leaks():
+    async def main(n):
+        await demo.suspend(n)
+        async for _ in demo.counted_stream(n):
+            pass
+
+    # warm up caches, e.g. interned strings
+    asyncio.run(main(10))
+    gc.collect()
+    blocks = sys.getallocatedblocks()
+    asyncio.run(main(1000))
+    gc.collect()
+    # neither the suspensions nor the yielded objects retain allocations
+    assert sys.getallocatedblocks() - blocks < 100
+    assert demo.live_counted() == 0
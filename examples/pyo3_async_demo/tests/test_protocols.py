@@ -0,0 +1,62 @@
+import asyncio
+import collections.abc
+import typing
+
+import pytest
+
+import pyo3_async_demo as demo
+
+COROUTINE_PROTOCOLS = ["Awaitable", "Coroutine"]
+ASYNC_GENERATOR_PROTOCOLS = ["AsyncIterable", "AsyncIterator", "AsyncGenerator"]
+PROTOCOLS = COROUTINE_PROTOCOLS + ASYNC_GENERATOR_PROTOCOLS
+
+
+def assert_protocols(obj, expected):
+    for protocol in PROTOCOLS:
+        for module in (collections.abc, typing):
+            conform = isinstance(obj, getattr(module, protocol))
+            assert conform == (protocol in expected), (module.__name__, protocol)
+
+
+def assert_coroutine(coroutine):
+    assert_protocols(coroutine, COROUTINE_PROTOCOLS)
+    assert asyncio.iscoroutine(coroutine)
+    # `__await__` must return an iterator, and a generator for `typing.Generator` users
+    iterator = coroutine.__await__()
+    assert isinstance(iterator, collections.abc.Generator)
+    assert isinstance(iterator, typing.Generator)
+    coroutine.close()
+
+
+@pytest.mark.parametrize(
+    "factory",
+    [
+        lambda: demo.plugin_countdown(1),
+        lambda: demo.remote_wake(),
+    ],
+)
+def test_coroutine_protocols(factory):
+    async def main():
+        assert_coroutine(factory())
+
+    asyncio.run(main())
+
+
+@pytest.mark.parametrize(
+    "factory",
+    [
+        lambda: demo.count_finalized(1, lambda: None),
+        lambda: demo.summing_sink(1),
+    ],
+)
+def test_async_generator_protocols(factory):
+    async def main():
+        async_generator = factory()
+        assert_protocols(async_generator, ASYNC_GENERATOR_PROTOCOLS)
+        assert async_generator.__aiter__() is async_generator
+        assert_coroutine(async_generator.__anext__())
+        assert_coroutine(async_generator.asend(None))
+        assert_coroutine(async_generator.athrow(ValueError()))
+        assert_coroutine(async_generator.aclose())
+
+    asyncio.run(main())
@@ -1,20 +1,11 @@
-use proc_macro::TokenStream;
+use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
-use syn::{
-    parse::Parser, parse_macro_input, parse_quote, parse_quote_spanned, punctuated::Punctuated,
-    spanned::Spanned,
-};
+use syn::{parse::Parser, parse_quote, parse_quote_spanned, spanned::Spanned};
 
-const MODULES: [&str; 3] = ["asyncio", "trio", "sniffio"];
+#[cfg(test)]
+mod tests;
 
-macro_rules! unwrap {
-    ($result:expr) => {
-        match $result {
-            Ok(ok) => ok,
-            Err(err) => return err.into_compile_error().into(),
-        }
-    };
-}
+const MODULES: [&str; 3] = ["asyncio", "trio", "sniffio"];
 
 struct Options {
     module: syn::Path,
@@ -37,13 +28,219 @@ fn parse_options(attr: TokenStream) -> syn::Result<Options> {
         }
         Ok(())
     });
-    module_parser.parse(attr)?;
+    module_parser.parse2(attr)?;
     Ok(Options {
         module: module.unwrap_or_else(|| parse_quote!(asyncio)),
         allow_threads,
     })
 }
 
+/// Per-function options, passed with `#[pyo3_async(...)]` attribute.
+#[derive(Default)]
+struct FnOptions {
+    convert_ordered: bool,
+    map_err: Option<syn::Path>,
+}
+
+fn parse_fn_options(attrs: &[syn::Attribute]) -> syn::Result<FnOptions> {
+    let mut options = FnOptions::default();
+    for attr in attrs {
+        if !attr.meta.path().is_ident("pyo3_async") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("convert") {
+                let convert: syn::Ident = meta.value()?.parse()?;
+                if convert != "ordered" {
+                    return Err(syn::Error::new_spanned(convert, "expected `ordered`"));
+                }
+                options.convert_ordered = true;
+            } else if meta.path.is_ident("map_err") {
+                options.map_err = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("invalid option"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+fn is_pyo3_attr(attr: &syn::Attribute) -> bool {
+    attr.meta.path().is_ident("pyo3") || attr.meta.path().is_ident("pyo3_async")
+}
+
+fn last_ident(ty: &syn::Type) -> Option<&syn::Ident> {
+    match ty {
+        syn::Type::Path(path) => Some(&path.path.segments.last()?.ident),
+        _ => None,
+    }
+}
+
+/// Owned type to use instead of a borrowed/GIL-bound argument type, if any.
+fn owned_type(ty: &syn::Type) -> Option<syn::Type> {
+    match ty {
+        syn::Type::Reference(syn::TypeReference { elem, .. }) => {
+            let ident = last_ident(elem).map(ToString::to_string);
+            Some(match (&**elem, ident.as_deref()) {
+                (_, Some("str")) => parse_quote!(String),
+                (_, Some("PyAny")) => parse_quote!(pyo3::PyObject),
+                (_, Some("PyCell")) => owned_type(elem)?,
+                (_, Some(name)) if name.starts_with("Py") => parse_quote!(pyo3::Py<#elem>),
+                (syn::Type::Slice(slice), _) => {
+                    let elem = owned_type(&slice.elem).unwrap_or_else(|| (*slice.elem).clone());
+                    parse_quote!(Vec<#elem>)
+                }
+                (elem, _) => owned_type(elem).unwrap_or_else(|| elem.clone()),
+            })
+        }
+        syn::Type::Path(path) => {
+            let last = path.path.segments.last()?;
+            if last.ident == "PyRef" || last.ident == "PyRefMut" || last.ident == "PyCell" {
+                let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+                    return None;
+                };
+                let inner = args.args.iter().find_map(|arg| match arg {
+                    syn::GenericArgument::Type(ty) => Some(ty),
+                    _ => None,
+                })?;
+                return Some(parse_quote!(pyo3::Py<#inner>));
+            }
+            let mut owned = path.clone();
+            let mut changed = false;
+            for segment in &mut owned.path.segments {
+                if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                    for arg in &mut args.args {
+                        if let syn::GenericArgument::Type(ty) = arg {
+                            if let Some(owned_ty) = owned_type(ty) {
+                                *ty = owned_ty;
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+            changed.then_some(syn::Type::Path(owned))
+        }
+        syn::Type::Tuple(tuple) => {
+            let mut owned = tuple.clone();
+            let mut changed = false;
+            for elem in &mut owned.elems {
+                if let Some(owned_elem) = owned_type(elem) {
+                    *elem = owned_elem;
+                    changed = true;
+                }
+            }
+            changed.then_some(syn::Type::Tuple(owned))
+        }
+        syn::Type::Paren(syn::TypeParen { elem, .. })
+        | syn::Type::Group(syn::TypeGroup { elem, .. }) => owned_type(elem),
+        _ => None,
+    }
+}
+
+/// Check that arguments can be moved into the future, i.e. are not borrowed/GIL-bound.
+fn check_owned_inputs(sig: &syn::Signature) -> syn::Result<()> {
+    for arg in &sig.inputs {
+        match arg {
+            syn::FnArg::Receiver(recv) if recv.reference.is_some() => {
+                return Err(syn::Error::new_spanned(
+                    recv,
+                    "async methods cannot borrow `self`, use `self_: Py<Self>` instead",
+                ));
+            }
+            syn::FnArg::Typed(syn::PatType { ty, .. }) => {
+                if last_ident(ty).is_some_and(|ident| ident == "Python") {
+                    return Err(syn::Error::new_spanned(
+                        ty,
+                        "`Python` token cannot be passed to async functions, use `Python::with_gil` instead",
+                    ));
+                }
+                if let Some(owned) = owned_type(ty) {
+                    let msg = format!(
+                        "async function arguments must be owned (`Send + 'static`), use `{}` instead",
+                        owned.to_token_stream().to_string().replace(' ', "")
+                    );
+                    return Err(syn::Error::new_spanned(ty, msg));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether the type is `impl Stream<...>`.
+fn is_impl_stream(ty: &syn::Type) -> bool {
+    let syn::Type::ImplTrait(impl_trait) = ty else {
+        return false;
+    };
+    impl_trait.bounds.iter().any(|bound| match bound {
+        syn::TypeParamBound::Trait(bound) => bound
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Stream"),
+        _ => false,
+    })
+}
+
+/// Whether the function returns `impl Stream<...>`, to be wrapped in an async generator.
+fn returns_stream(sig: &syn::Signature) -> bool {
+    let syn::ReturnType::Type(_, ty) = &sig.output else {
+        return false;
+    };
+    is_impl_stream(ty)
+}
+
+/// Whether the function returns `Result<impl Stream<...>, E>`/`PyResult<impl Stream<...>>`, to
+/// be awaited by the first `__anext__` of an async generator.
+fn returns_stream_result(sig: &syn::Signature) -> bool {
+    let syn::ReturnType::Type(_, ty) = &sig.output else {
+        return false;
+    };
+    let syn::Type::Path(path) = &**ty else {
+        return false;
+    };
+    let Some(last) = path.path.segments.last() else {
+        return false;
+    };
+    if last.ident != "Result" && last.ident != "PyResult" {
+        return false;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return false;
+    };
+    matches!(args.args.first(), Some(syn::GenericArgument::Type(ty)) if is_impl_stream(ty))
+}
+
+/// Success type of a function returning `Result<T, E>`/`PyResult<T>`.
+fn result_type(output: &syn::ReturnType) -> Option<&syn::Type> {
+    let syn::ReturnType::Type(_, ty) = output else {
+        return None;
+    };
+    let syn::Type::Path(path) = &**ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "Result" && last.ident != "PyResult" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        syn::GenericArgument::Type(syn::Type::ImplTrait(_)) => None,
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+/// Whether the function must be wrapped, in a coroutine or an async generator.
+fn is_async(sig: &syn::Signature) -> bool {
+    sig.asyncness.is_some() || returns_stream(sig)
+}
+
 fn build_coroutine(
     path: impl ToTokens,
     attrs: &mut Vec<syn::Attribute>,
@@ -51,13 +248,20 @@ fn build_coroutine(
     block: &mut syn::Block,
     options: &Options,
 ) -> syn::Result<()> {
+    check_owned_inputs(sig)?;
+    let fn_options = parse_fn_options(attrs)?;
     attrs.retain(|attr| attr.meta.path().is_ident("pyo3"));
     let mut has_name = false;
     for attr in attrs.iter() {
-        has_name |= attr
-            .parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)?
-            .into_iter()
-            .any(|meta| matches!(meta, syn::Meta::NameValue(nv) if nv.path.is_ident("name")));
+        // options are passed through to pyo3, values are not parsed as expressions because
+        // signatures may contain `/` and `*` markers, e.g. `signature = (a, /, b, *, c)`
+        attr.parse_nested_meta(|meta| {
+            has_name |= meta.path.is_ident("name");
+            if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<proc_macro2::TokenTree>()?;
+            }
+            Ok(())
+        })?;
     }
     if !has_name {
         let name = format!("{}", &sig.ident);
@@ -65,9 +269,32 @@ fn build_coroutine(
     }
     let ident = sig.ident.clone();
     sig.ident = format_ident!("async_{ident}");
+    let stream_future = sig.asyncness.is_some() && returns_stream_result(sig);
+    let stream = sig.asyncness.is_none() || stream_future;
+    if stream && fn_options.convert_ordered {
+        return Err(syn::Error::new_spanned(
+            &sig.output,
+            "`convert` option is not supported for streams",
+        ));
+    }
     sig.asyncness = None;
     let module = &options.module;
-    let coro_path = quote!(::pyo3_async::#module::Coroutine);
+    let (coro_path, from) = if stream_future {
+        (
+            quote!(::pyo3_async::#module::AsyncGenerator),
+            quote!(from_stream_future),
+        )
+    } else if stream {
+        (
+            quote!(::pyo3_async::#module::AsyncGenerator),
+            quote!(from_stream),
+        )
+    } else {
+        (
+            quote!(::pyo3_async::#module::Coroutine),
+            quote!(from_future),
+        )
+    };
     let params = sig.inputs.iter().map(|arg| match arg {
         syn::FnArg::Receiver(_) => quote!(self),
         syn::FnArg::Typed(syn::PatType { pat, .. }) => quote!(#pat),
@@ -76,14 +303,35 @@ fn build_coroutine(
     if matches!(sig.output, syn::ReturnType::Default) {
         future = quote!(async move {#future.await; pyo3::PyResult::Ok(())})
     }
+    if options.allow_threads && stream_future {
+        // both the construction and the stream release the GIL
+        future = quote!(async move { #future.await.map(::pyo3_async::AllowThreads) });
+    }
     if options.allow_threads {
         future = quote!(::pyo3_async::AllowThreads(#future));
     }
+    if fn_options.convert_ordered {
+        future = quote!(::pyo3_async::convert::Ordered(#future));
+    }
+    let mut coroutine = quote!(#coro_path::#from(#future));
+    if let Some(map_err) = &fn_options.map_err {
+        coroutine = quote!(#coroutine.with_map_err(#map_err));
+    }
     // return statement because `parse_quote_spanned` doesn't work otherwise
     block.stmts = vec![parse_quote_spanned! { block.span() =>
         #[allow(clippy::needless_return)]
-        return #coro_path::from_future(#future);
+        return #coroutine;
     }];
+    // checked separately, to report a missing conversion on the return type, instead of an
+    // unsatisfied `PyFuture` bound pointing into the expansion
+    if let Some(ty) = result_type(&sig.output).filter(|_| !stream && !fn_options.convert_ordered) {
+        block.stmts.insert(
+            0,
+            parse_quote_spanned! { ty.span() =>
+                ::pyo3_async::conversions::assert_convertible::<#ty>();
+            },
+        );
+    }
     sig.output = parse_quote_spanned!(sig.output.span() => -> #coro_path);
     Ok(())
 }
@@ -114,35 +362,209 @@ fn build_coroutine(
 /// #[pyo3(name = "print")]
 /// pub fn async_print(s: String) -> ::pyo3_async::asyncio::Coroutine {
 ///     ::pyo3_async::asyncio::Coroutine::from_future(::pyo3_async::AllowThreads(
-///         async move { print(s).await; Ok(()) }
+///         async move { print(s).await; pyo3::PyResult::Ok(()) }
 ///     ))
 /// }
 /// ```
 ///
+///
+/// Function options can be passed with `#[pyo3_async(...)]` attribute:
+/// - `convert = ordered`: convert the mapping result into a `dict` preserving its iteration
+///   order (see [`IntoPyOrdered`]).
+/// - `map_err = path::to::mapper`: map the error raised by the coroutine, or by each item of
+///   the async generator, with `fn(Python, PyErr) -> PyErr`, e.g. into the exception classes of
+///   the module.
+///
+/// ```rust
+/// #[pyo3_async::pyfunction]
+/// #[pyo3_async(convert = ordered)]
+/// async fn sorted_counts(words: Vec<String>) -> pyo3::PyResult<std::collections::BTreeMap<String, usize>> {
+///     let mut counts = std::collections::BTreeMap::new();
+///     for word in words {
+///         *counts.entry(word).or_default() += 1;
+///     }
+///     Ok(counts)
+/// }
+/// ```
+///
+/// ```rust
+/// use pyo3::{create_exception, exceptions::*, prelude::*};
+///
+/// create_exception!(api, NotFound, PyException);
+/// create_exception!(api, Denied, PyException);
+///
+/// enum ApiError {
+///     NotFound(String),
+///     Denied,
+/// }
+///
+/// impl From<ApiError> for PyErr {
+///     fn from(err: ApiError) -> Self {
+///         match err {
+///             ApiError::NotFound(key) => PyKeyError::new_err(key),
+///             ApiError::Denied => PyPermissionError::new_err(()),
+///         }
+///     }
+/// }
+///
+/// fn api_error(py: Python, err: PyErr) -> PyErr {
+///     if err.is_instance_of::<PyKeyError>(py) {
+///         NotFound::new_err(err.value(py).to_string())
+///     } else if err.is_instance_of::<PyPermissionError>(py) {
+///         Denied::new_err(())
+///     } else {
+///         err
+///     }
+/// }
+///
+/// #[pyo3_async::pyfunction]
+/// #[pyo3_async(map_err = api_error)]
+/// async fn get(key: String) -> Result<String, ApiError> {
+///     match key.as_str() {
+///         "secret" => Err(ApiError::Denied),
+///         _ => Err(ApiError::NotFound(key)),
+///     }
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let locals = pyo3::types::PyDict::new(py);
+///     locals.set_item("get", wrap_pyfunction!(async_get, py)?)?;
+///     locals.set_item("NotFound", py.get_type::<NotFound>())?;
+///     locals.set_item("Denied", py.get_type::<Denied>())?;
+///     let code = r#"
+/// import asyncio
+/// for key, exc in [("key", NotFound), ("secret", Denied)]:
+///     try:
+///         asyncio.run(get(key))
+///     except Exception as err:
+///         assert isinstance(err, exc), err
+///     else:
+///         assert False
+/// "#;
+///     py.run(code, None, Some(locals))
+/// })
+/// .unwrap();
+/// ```
+///
+/// `#[pyo3(...)]` options are passed to the generated function, including signatures with
+/// positional-only (`/`) and keyword-only (`*`) markers.
+///
+/// ```rust
+/// #[pyo3_async::pyfunction]
+/// #[pyo3(signature = (a, /, b, *, c))]
+/// async fn sum(a: usize, b: usize, c: usize) -> pyo3::PyResult<usize> {
+///     Ok(a + b + c)
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// pyo3::Python::with_gil(|py| {
+///     let sum = pyo3::wrap_pyfunction!(async_sum, py).unwrap();
+///     let locals = pyo3::types::PyDict::new(py);
+///     locals.set_item("sum", sum).unwrap();
+///     py.run("sum(1, 2, c=3).close(); sum(1, b=2, c=3).close()", None, Some(locals))
+///         .unwrap();
+///     assert!(py.run("sum(a=1, b=2, c=3)", None, Some(locals)).is_err());
+///     assert!(py.run("sum(1, 2, 3)", None, Some(locals)).is_err());
+/// });
+/// ```
+///
+/// Non-async functions returning `impl Stream<Item = Result<T, E>>` are wrapped in an async
+/// generator instead; with `allow_threads`, GIL is released while the stream produces an item,
+/// and held for its conversion into a Python object.
+///
+/// ```rust
+/// #[pyo3_async::pyfunction(asyncio, allow_threads)]
+/// fn squares(n: u64) -> impl futures::Stream<Item = pyo3::PyResult<u64>> + Send + 'static {
+///     futures::stream::iter((0..n).map(|i| Ok(i * i)))
+/// }
+/// ```
+/// generates
+/// ```rust
+/// fn squares(n: u64) -> impl futures::Stream<Item = pyo3::PyResult<u64>> + Send + 'static {
+///     futures::stream::iter((0..n).map(|i| Ok(i * i)))
+/// }
+/// #[::pyo3::pyfunction]
+/// #[pyo3(name = "squares")]
+/// fn async_squares(n: u64) -> ::pyo3_async::asyncio::AsyncGenerator {
+///     ::pyo3_async::asyncio::AsyncGenerator::from_stream(::pyo3_async::AllowThreads(squares(n)))
+/// }
+/// ```
+///
+/// Async functions returning `Result<impl Stream<...>, E>` are also wrapped in an async
+/// generator, whose first `__anext__` awaits the stream construction, e.g. opening a
+/// connection, raising its error if it fails (see
+/// [`asyncio::AsyncGenerator::from_stream_future`]).
+///
+/// ```rust
+/// #[pyo3_async::pyfunction]
+/// async fn countdown(
+///     n: u64,
+/// ) -> pyo3::PyResult<impl futures::Stream<Item = pyo3::PyResult<u64>> + Send + 'static> {
+///     Ok(futures::stream::iter((0..n).rev().map(Ok)))
+/// }
+/// ```
+/// generates
+/// ```rust
+/// async fn countdown(
+///     n: u64,
+/// ) -> pyo3::PyResult<impl futures::Stream<Item = pyo3::PyResult<u64>> + Send + 'static> {
+///     Ok(futures::stream::iter((0..n).rev().map(Ok)))
+/// }
+/// #[::pyo3::pyfunction]
+/// #[pyo3(name = "countdown")]
+/// fn async_countdown(n: u64) -> ::pyo3_async::asyncio::AsyncGenerator {
+///     ::pyo3_async::asyncio::AsyncGenerator::from_stream_future(countdown(n))
+/// }
+/// ```
+///
+/// Arguments are moved into the future, so they must be `Send + 'static`; borrowed or GIL-bound
+/// arguments are rejected with the owned type to use instead, e.g. `String` for `&str`,
+/// `Py<PyDict>` for `&PyDict` or `Vec<PyObject>` for `Vec<&PyAny>` (diagnostics are checked in
+/// `tests/ui`):
+///
+/// ```text
+/// error: async function arguments must be owned (`Send + 'static`), use `String` instead
+///  --> src/lib.rs:2:19
+///   |
+/// 2 | async fn print(s: &str) {}
+///   |                   ^^^^
+/// ```
+///
 /// [`pyo3::pyfunction`]: https://docs.rs/pyo3/latest/pyo3/attr.pyfunction.html
 /// [`AllowThreads`]: https://docs.rs/pyo3-async/latest/pyo3_async/struct.AllowThreads.html
+/// [`IntoPyOrdered`]: https://docs.rs/pyo3-async/latest/pyo3_async/convert/trait.IntoPyOrdered.html
+/// [`asyncio::AsyncGenerator::from_stream_future`]: https://docs.rs/pyo3-async/latest/pyo3_async/asyncio/struct.AsyncGenerator.html#method.from_stream_future
 #[proc_macro_attribute]
-pub fn pyfunction(attr: TokenStream, input: TokenStream) -> TokenStream {
-    let options = unwrap!(parse_options(attr));
-    let mut func = parse_macro_input!(input as syn::ItemFn);
-    if func.sig.asyncness.is_none() {
-        return quote!(#[::pyo3::pyfunction] #func).into();
+pub fn pyfunction(
+    attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    expand_pyfunction(attr.into(), input.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_pyfunction(attr: TokenStream, input: TokenStream) -> syn::Result<TokenStream> {
+    let options = parse_options(attr)?;
+    let mut func: syn::ItemFn = syn::parse2(input)?;
+    if !is_async(&func.sig) {
+        return Ok(quote!(#[::pyo3::pyfunction] #func));
     }
     let mut coro = func.clone();
-    unwrap!(build_coroutine(
+    build_coroutine(
         &func.sig.ident,
         &mut coro.attrs,
         &mut coro.sig,
         &mut coro.block,
-        &options
-    ));
-    func.attrs.retain(|attr| !attr.meta.path().is_ident("pyo3"));
-    let expanded = quote! {
+        &options,
+    )?;
+    func.attrs.retain(|attr| !is_pyo3_attr(attr));
+    Ok(quote! {
         #func
         #[::pyo3::pyfunction]
         #coro
-    };
-    expanded.into()
+    })
 }
 
 /// [`pyo3::pymethods`] with async support.
@@ -192,7 +614,7 @@ pub fn pyfunction(attr: TokenStream, input: TokenStream) -> TokenStream {
 ///
 ///     #[pyo3(name = "incr_async")]
 ///     fn async_incr_async(self_: pyo3::Py<Self>) -> ::pyo3_async::trio::Coroutine {
-///         ::pyo3_async::trio::Coroutine::from_future(Counter::incr_async(self_))
+///         ::pyo3_async::trio::Coroutine::from_future(Self::incr_async(self_))
 ///     }
 /// }
 /// impl Counter {
@@ -206,18 +628,129 @@ pub fn pyfunction(attr: TokenStream, input: TokenStream) -> TokenStream {
 /// }
 /// ```
 ///
+///
+/// Because the Python name is preserved, async special methods keep their protocol meaning, e.g.
+/// async `__call__` makes instances callable, returning a coroutine:
+///
+/// ```rust
+/// #[pyo3::pyclass]
+/// struct Adder(usize);
+///
+/// #[pyo3_async::pymethods]
+/// impl Adder {
+///     // used in Python as `await adder(1)`
+///     async fn __call__(self_: pyo3::Py<Self>, n: usize) -> pyo3::PyResult<usize> {
+///         pyo3::Python::with_gil(|gil| Ok(self_.borrow(gil).0 + n))
+///     }
+/// }
+/// ```
+///
+/// Async methods can be combined with sync ones and with pyo3 method attributes (`#[getter]`,
+/// `#[staticmethod]`, `#[classmethod]`, `#[pyo3(signature = ...)]`); original methods are called
+/// through `Self`, so the impl type can be given by any path, e.g. a type alias.
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::{PyDict, PyType}};
+///
+/// #[pyclass]
+/// struct Counter {
+///     #[pyo3(get, set)]
+///     value: usize,
+/// }
+///
+/// type Alias = Counter;
+///
+/// #[pyo3_async::pymethods]
+/// impl Alias {
+///     #[new]
+///     fn new() -> Self {
+///         Self { value: 0 }
+///     }
+///
+///     fn incr_sync(&mut self) -> usize {
+///         self.value += 1;
+///         self.value
+///     }
+///
+///     async fn incr(self_: Py<Self>) -> PyResult<usize> {
+///         Python::with_gil(|gil| {
+///             let mut this = self_.borrow_mut(gil);
+///             this.value += 1;
+///             Ok(this.value)
+///         })
+///     }
+///
+///     #[pyo3(signature = (n = 1))]
+///     async fn add(self_: Py<Self>, n: usize) -> PyResult<usize> {
+///         Python::with_gil(|gil| Ok(self_.borrow(gil).value + n))
+///     }
+///
+///     #[getter]
+///     async fn doubled(self_: Py<Self>) -> PyResult<usize> {
+///         Python::with_gil(|gil| Ok(self_.borrow(gil).value * 2))
+///     }
+///
+///     #[staticmethod]
+///     async fn zero() -> PyResult<usize> {
+///         Ok(0)
+///     }
+///
+///     #[classmethod]
+///     async fn name(cls: Py<PyType>) -> PyResult<String> {
+///         Python::with_gil(|gil| cls.as_ref(gil).name().map(String::from))
+///     }
+///
+///     #[staticmethod]
+///     fn range(n: usize) -> impl futures::Stream<Item = PyResult<usize>> + Send + 'static {
+///         futures::stream::iter((0..n).map(Ok))
+///     }
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("Counter", py.get_type::<Counter>())?;
+///     let code = r#"
+/// import asyncio
+/// async def main():
+///     counter = Counter()
+///     assert await counter.incr() == 1
+///     assert counter.incr_sync() == 2
+///     assert await counter.add() == 3
+///     assert await counter.add(n=3) == 5
+///     assert await counter.doubled == 4
+///     assert await Counter.zero() == 0
+///     assert await Counter.name() == "Counter"
+///     counter.value = 3
+///     assert [i async for i in Counter.range(counter.value)] == [0, 1, 2]
+/// asyncio.run(main())
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+///
 /// [`pyo3::pymethods`]: https://docs.rs/pyo3/latest/pyo3/attr.pymethods.html
 /// [`AllowThreads`]: https://docs.rs/pyo3-async/latest/pyo3_async/struct.AllowThreads.html
 #[proc_macro_attribute]
-pub fn pymethods(attr: TokenStream, input: TokenStream) -> TokenStream {
-    let options = unwrap!(parse_options(attr));
-    let mut r#impl = parse_macro_input!(input as syn::ItemImpl);
+pub fn pymethods(
+    attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    expand_pymethods(attr.into(), input.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_pymethods(attr: TokenStream, input: TokenStream) -> syn::Result<TokenStream> {
+    let options = parse_options(attr)?;
+    let mut r#impl: syn::ItemImpl = syn::parse2(input)?;
     let (async_methods, items) = r#impl.items.into_iter().partition::<Vec<_>, _>(
-        |item| matches!(item, syn::ImplItem::Fn(func) if func.sig.asyncness.is_some()),
+        |item| matches!(item, syn::ImplItem::Fn(func) if is_async(&func.sig)),
     );
     r#impl.items = items;
     if async_methods.is_empty() {
-        return quote!(#[::pyo3::pymethods] #r#impl).into();
+        return Ok(quote!(#[::pyo3::pymethods] #r#impl));
     }
     let mut async_impl = r#impl.clone();
     async_impl.items = async_methods;
@@ -227,18 +760,17 @@ pub fn pymethods(attr: TokenStream, input: TokenStream) -> TokenStream {
             unreachable!()
         };
         let mut coro = method.clone();
-        let self_ty = &r#impl.self_ty;
         let method_name = &method.sig.ident;
-        unwrap!(build_coroutine(
-            quote!(#self_ty::#method_name),
+        // `Self` instead of the impl type, which may have generic arguments, e.g. `Counter<u32>`
+        // which would require a turbofish in expression position
+        build_coroutine(
+            quote!(Self::#method_name),
             &mut coro.attrs,
             &mut coro.sig,
             &mut coro.block,
-            &options
-        ));
-        method
-            .attrs
-            .retain(|attr| !attr.meta.path().is_ident("pyo3"));
+            &options,
+        )?;
+        method.attrs.retain(|attr| !is_pyo3_attr(attr));
         method.attrs.retain(|attr| {
             if ["getter", "classmethod", "staticmethod"]
                 .iter()
@@ -251,10 +783,9 @@ pub fn pymethods(attr: TokenStream, input: TokenStream) -> TokenStream {
         });
         r#impl.items.push(syn::ImplItem::Fn(coro));
     }
-    let expanded = quote! {
+    Ok(quote! {
         #[::pyo3::pymethods]
         #r#impl
         #async_impl
-    };
-    expanded.into()
+    })
 }
@@ -0,0 +1,162 @@
+//! Expansion snapshots, covering impl shapes which cannot be compiled by every pyo3 version,
+//! e.g. generic impls, rejected by `#[pyo3::pymethods]` itself.
+//!
+//! Snapshots are reviewed with `cargo insta review`, or regenerated with `INSTA_UPDATE=always`.
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::{expand_pyfunction, expand_pymethods};
+
+fn expand(
+    expand: fn(TokenStream, TokenStream) -> syn::Result<TokenStream>,
+    attr: TokenStream,
+    input: TokenStream,
+) -> String {
+    let expanded = expand(attr, input).unwrap_or_else(syn::Error::into_compile_error);
+    prettyplease::unparse(&syn::parse2(expanded).unwrap())
+}
+
+#[test]
+fn pyfunction() {
+    let input = quote! {
+        #[pyo3(signature = (a, b = 1))]
+        async fn add(a: usize, b: usize) -> PyResult<usize> {
+            Ok(a + b)
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pyfunction, quote!(), input));
+}
+
+#[test]
+fn pyfunction_sync() {
+    let input = quote! {
+        fn add(a: usize, b: usize) -> usize {
+            a + b
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pyfunction, quote!(), input));
+}
+
+#[test]
+fn pyfunction_options() {
+    let input = quote! {
+        #[pyo3_async(map_err = api_error, convert = ordered)]
+        async fn get(key: String, keys: Vec<String>) -> Result<IndexMap<String, String>, ApiError> {
+            todo!()
+        }
+    };
+    insta::assert_snapshot!(expand(
+        expand_pyfunction,
+        quote!(trio, allow_threads),
+        input
+    ));
+}
+
+#[test]
+fn pyfunction_stream() {
+    let input = quote! {
+        async fn countdown(n: u64) -> PyResult<impl Stream<Item = PyResult<u64>> + Send + 'static> {
+            Ok(futures::stream::iter((0..n).rev().map(Ok)))
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pyfunction, quote!(sniffio), input));
+}
+
+#[test]
+fn pyfunction_borrowed_input() {
+    let input = quote! {
+        async fn print(s: &str) {}
+    };
+    insta::assert_snapshot!(expand(expand_pyfunction, quote!(), input));
+}
+
+#[test]
+fn pyfunction_invalid_option() {
+    let input = quote! {
+        async fn noop() {}
+    };
+    insta::assert_snapshot!(expand(expand_pyfunction, quote!(asyncio, trio), input));
+}
+
+#[test]
+fn pymethods_generic() {
+    let input = quote! {
+        impl<T: Clone + Send + 'static> Counter<T>
+        where
+            T: IntoPy<PyObject>,
+        {
+            fn sync_get(&self) -> T {
+                self.0.clone()
+            }
+
+            async fn get(self_: Py<Self>) -> PyResult<T> {
+                Python::with_gil(|py| Ok(self_.borrow(py).0.clone()))
+            }
+
+            async fn set(self_: Py<Self>, value: T) -> PyResult<()> {
+                Python::with_gil(|py| self_.borrow_mut(py).0 = value);
+                Ok(())
+            }
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pymethods, quote!(), input));
+}
+
+#[test]
+fn pymethods_concrete_instantiation() {
+    let input = quote! {
+        impl Counter<u32> {
+            async fn incr(self_: Py<Self>) -> PyResult<u32> {
+                todo!()
+            }
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pymethods, quote!(trio), input));
+}
+
+#[test]
+fn pymethods_attributes() {
+    let input = quote! {
+        impl Counter {
+            #[new]
+            fn new() -> Self {
+                Self(0)
+            }
+
+            #[getter]
+            async fn doubled(self_: Py<Self>) -> PyResult<usize> {
+                todo!()
+            }
+
+            #[staticmethod]
+            #[pyo3(signature = (n = 1))]
+            async fn zero(n: usize) -> PyResult<usize> {
+                todo!()
+            }
+
+            #[classmethod]
+            async fn name(cls: Py<PyType>) -> PyResult<String> {
+                todo!()
+            }
+
+            #[staticmethod]
+            fn range(n: usize) -> impl Stream<Item = PyResult<usize>> + Send + 'static {
+                futures::stream::iter((0..n).map(Ok))
+            }
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pymethods, quote!(allow_threads), input));
+}
+
+#[test]
+fn pymethods_sync_only() {
+    let input = quote! {
+        impl Counter {
+            fn incr(&mut self) -> usize {
+                self.0 += 1;
+                self.0
+            }
+        }
+    };
+    insta::assert_snapshot!(expand(expand_pymethods, quote!(), input));
+}
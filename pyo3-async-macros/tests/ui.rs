@@ -0,0 +1,7 @@
+//! Diagnostics of the macros, checked against the `.stderr` snapshots of `tests/ui`.
+//!
+//! Snapshots are regenerated with `TRYBUILD=overwrite`.
+#[test]
+fn ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
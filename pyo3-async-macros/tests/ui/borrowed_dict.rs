@@ -0,0 +1,4 @@
+#[pyo3_async::pyfunction]
+async fn keys(dict: &pyo3::types::PyDict) {}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[pyo3::pyclass]
+struct Counter(usize);
+
+#[pyo3_async::pymethods]
+impl Counter {
+    async fn get(&self) -> usize {
+        self.0
+    }
+}
+
+fn main() {}
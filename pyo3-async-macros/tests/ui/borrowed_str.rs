@@ -0,0 +1,4 @@
+#[pyo3_async::pyfunction]
+async fn print(s: &str) {}
+
+fn main() {}
@@ -0,0 +1,4 @@
+#[pyo3_async::pyfunction]
+async fn count(objs: Vec<&pyo3::PyAny>) {}
+
+fn main() {}
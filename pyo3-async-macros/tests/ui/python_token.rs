@@ -0,0 +1,4 @@
+#[pyo3_async::pyfunction]
+async fn version(py: pyo3::Python<'_>) {}
+
+fn main() {}
@@ -29,7 +29,15 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let waker = cx.waker();
-        Python::with_gil(|gil| gil.allow_threads(|| this.0.poll(&mut Context::from_waker(waker))))
+        #[cfg(feature = "diagnostics")]
+        crate::diagnostics::set_gil_released();
+        Python::with_gil(|gil| {
+            gil.allow_threads(|| {
+                #[cfg(feature = "strict-checks")]
+                let _scope = crate::strict::release_gil();
+                this.0.poll(&mut Context::from_waker(waker))
+            })
+        })
     }
 }
 
@@ -43,10 +51,20 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
         let waker = cx.waker();
+        #[cfg(feature = "diagnostics")]
+        crate::diagnostics::set_gil_released();
         Python::with_gil(|gil| {
-            gil.allow_threads(|| this.0.poll_next(&mut Context::from_waker(waker)))
+            gil.allow_threads(|| {
+                #[cfg(feature = "strict-checks")]
+                let _scope = crate::strict::release_gil();
+                this.0.poll_next(&mut Context::from_waker(waker))
+            })
         })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
 /// Extension trait to allow threads while polling [`Future`] or [`Stream`].
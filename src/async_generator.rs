@@ -1,75 +1,585 @@
 use std::{
+    future::Future,
     marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
     sync::{Arc, Mutex},
     task::{ready, Context, Poll},
 };
 
-use pyo3::{exceptions::PyStopAsyncIteration, prelude::*};
+use futures::{Sink, SinkExt};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyStopAsyncIteration, PyTypeError},
+    panic::PanicException,
+    prelude::*,
+    types::{PyDict, PyTuple},
+};
+
+#[cfg(feature = "debug")]
+use crate::debug;
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::MemoryFootprint;
+use crate::{
+    compat::{self, intern},
+    coroutine::MapErr,
+    sniffio::{await_py, AwaitPy},
+    utils, PyFuture, PyStream, PyStreamClose, ThrowCallback,
+};
+
+/// Policy applied to errors yielded by the stream of an async generator (see
+/// [`asyncio::AsyncGenerator::error_policy`](crate::asyncio::AsyncGenerator::error_policy)).
+#[derive(Debug, Clone, Default)]
+pub enum ErrorPolicy {
+    /// Raise the error from `__anext__`, the stream being still iterable. This is the default.
+    #[default]
+    RaiseAndContinue,
+    /// Raise the error from `__anext__`, then drop the stream, so subsequent calls raise
+    /// `StopAsyncIteration`.
+    RaiseAndTerminate,
+    /// Yield the result of the callable called with the exception as a regular item.
+    YieldAsValue(PyObject),
+}
+
+struct StreamState {
+    stream: Option<Pin<Box<dyn PyStreamClose>>>,
+    // message of a panic raised while polling the stream, which has then been dropped
+    panic: Option<String>,
+}
+
+type SharedStream = Arc<Mutex<StreamState>>;
+
+/// [`PyStream`] without cleanup.
+pub(crate) struct NoClose(pub(crate) Pin<Box<dyn PyStream>>);
+
+impl PyStream for NoClose {
+    fn poll_next_py(
+        mut self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        self.0.as_mut().poll_next_py(py, cx)
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        self.0.size_hint_py()
+    }
+
+    fn throw_py(mut self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.0.as_mut().throw_py(py, exc)
+    }
+}
+
+impl PyStreamClose for NoClose {
+    fn poll_close_py(self: Pin<&mut Self>, _py: Python, _cx: &mut Context) -> Poll<PyResult<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+type StreamFactory = Box<dyn FnOnce() -> Pin<Box<dyn PyStream>> + Send>;
+
+/// [`PyStream`] instantiated by a factory on first poll.
+pub(crate) struct LazyStream {
+    factory: Option<StreamFactory>,
+    stream: Option<Pin<Box<dyn PyStream>>>,
+}
+
+impl LazyStream {
+    pub(crate) fn new(factory: StreamFactory) -> Self {
+        Self {
+            factory: Some(factory),
+            stream: None,
+        }
+    }
+}
+
+impl PyStream for LazyStream {
+    fn poll_next_py(
+        mut self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        if let Some(factory) = self.factory.take() {
+            self.stream = Some(factory());
+        }
+        self.stream.as_mut().unwrap().as_mut().poll_next_py(py, cx)
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        self.stream.as_ref().map_or((0, None), |s| s.size_hint_py())
+    }
+
+    fn throw_py(mut self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.as_mut().throw_py(py, exc),
+            None => Err(exc),
+        }
+    }
+}
+
+impl PyStreamClose for LazyStream {
+    fn poll_close_py(
+        mut self: Pin<&mut Self>,
+        _py: Python,
+        _cx: &mut Context,
+    ) -> Poll<PyResult<()>> {
+        // a stream closed before being iterated is never instantiated
+        self.factory.take();
+        Poll::Ready(Ok(()))
+    }
+}
 
-use crate::{PyFuture, PyStream, ThrowCallback};
+/// [`PyStream`] built by a future, driven by the first polls.
+pub(crate) struct StreamFuture<F, S> {
+    future: Option<Pin<Box<F>>>,
+    stream: Option<Pin<Box<S>>>,
+}
+
+impl<F, S> StreamFuture<F, S> {
+    pub(crate) fn new(future: F) -> Self {
+        Self {
+            future: Some(Box::pin(future)),
+            stream: None,
+        }
+    }
+}
 
-type SharedStream = Arc<Mutex<Option<Pin<Box<dyn PyStream>>>>>;
+impl<F, S, E> PyStream for StreamFuture<F, S>
+where
+    F: Future<Output = Result<S, E>> + Send,
+    S: PyStream,
+    PyErr: From<E>,
+{
+    fn poll_next_py(
+        mut self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        if let Some(future) = self.future.as_mut() {
+            let res = ready!(future.as_mut().poll(cx));
+            self.future = None;
+            match res {
+                Ok(stream) => self.stream = Some(Box::pin(stream)),
+                // the stream is then exhausted
+                Err(err) => return Poll::Ready(Some(Err(err.into()))),
+            }
+        }
+        match self.stream.as_mut() {
+            Some(stream) => stream.as_mut().poll_next_py(py, cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        match &self.stream {
+            Some(stream) => stream.size_hint_py(),
+            None if self.future.is_some() => (0, None),
+            None => (0, Some(0)),
+        }
+    }
+
+    fn throw_py(mut self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.as_mut().throw_py(py, exc),
+            None => Err(exc),
+        }
+    }
+}
+
+/// [`PyStreamClose`] awaiting a Python finalizer when the stream is exhausted or closed.
+pub(crate) struct Finalized {
+    stream: Pin<Box<dyn PyStream>>,
+    // taken when called, so it runs at most once
+    finalizer: Option<PyObject>,
+    finalizing: Option<AwaitPy>,
+    exhausted: bool,
+}
+
+impl Finalized {
+    pub(crate) fn new(stream: Pin<Box<dyn PyStream>>, finalizer: PyObject) -> Self {
+        Self {
+            stream,
+            finalizer: Some(finalizer),
+            finalizing: None,
+            exhausted: false,
+        }
+    }
+
+    fn poll_finalizer(&mut self, py: Python, cx: &mut Context) -> Poll<PyResult<()>> {
+        if let Some(finalizer) = self.finalizer.take() {
+            self.finalizing = Some(await_py(finalizer.call0(py)?));
+        }
+        let Some(finalizing) = self.finalizing.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let res = ready!(finalizing.poll_gil(py, cx));
+        self.finalizing = None;
+        Poll::Ready(res.map(drop))
+    }
+}
+
+impl PyStream for Finalized {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = Pin::into_inner(self);
+        if !this.exhausted {
+            match ready!(this.stream.as_mut().poll_next_py(py, cx)) {
+                Some(res) => return Poll::Ready(Some(res)),
+                None => this.exhausted = true,
+            }
+        }
+        // an error raised by the finalizer is raised by `__anext__`, like an error raised in the
+        // `finally` block of a Python async generator
+        Poll::Ready(ready!(this.poll_finalizer(py, cx)).err().map(Err))
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint_py()
+    }
+
+    fn throw_py(mut self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.stream.as_mut().throw_py(py, exc)
+    }
+}
+
+impl PyStreamClose for Finalized {
+    fn poll_close_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<()>> {
+        Pin::into_inner(self).poll_finalizer(py, cx)
+    }
+}
 
 struct PyStreamNext {
     stream: SharedStream,
+    error_policy: ErrorPolicy,
+    map_err: Option<MapErr>,
     close: bool,
+    closing: Option<PyResult<PyObject>>,
+}
+
+impl PyStreamNext {
+    /// Poll the next item, or the stream cleanup if closing; the returned boolean tells if the
+    /// stream is terminated and must be dropped.
+    fn poll_stream(
+        &mut self,
+        stream: &mut Pin<Box<dyn PyStreamClose>>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<(PyResult<PyObject>, bool)> {
+        let err = || Err(PyStopAsyncIteration::new_err(py.None()));
+        if self.close {
+            if self.closing.is_none() {
+                let opt_res = ready!(stream.as_mut().poll_next_py(py, cx));
+                self.closing = Some(opt_res.unwrap_or_else(err));
+            }
+            let res = ready!(stream.as_mut().poll_close_py(py, cx));
+            return Poll::Ready((res.and(self.closing.take().unwrap()), true));
+        }
+        Poll::Ready(match ready!(stream.as_mut().poll_next_py(py, cx)) {
+            Some(Err(mut err)) => {
+                if let Some(map_err) = &self.map_err {
+                    err = map_err(py, err);
+                }
+                match &self.error_policy {
+                    ErrorPolicy::RaiseAndContinue => (Err(err), false),
+                    ErrorPolicy::RaiseAndTerminate => (Err(err), true),
+                    ErrorPolicy::YieldAsValue(map) => (map.call1(py, (err,)), false),
+                }
+            }
+            Some(res) => (res, false),
+            None => (err(), true),
+        })
+    }
 }
 
 impl PyFuture for PyStreamNext {
     fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
-        let err = || Err(PyStopAsyncIteration::new_err(py.None()));
         let this = Pin::into_inner(self);
-        let mut guard = this.stream.lock().unwrap();
-        let Some(ref mut stream) = *guard else {
-            return Poll::Ready(err());
+        let shared = this.stream.clone();
+        let mut state = shared.lock().unwrap();
+        let Some(stream) = state.stream.as_mut() else {
+            // like Python async generators, closing a terminated generator is a no-op
+            if this.close && state.panic.is_none() {
+                return Poll::Ready(Ok(py.None()));
+            }
+            return Poll::Ready(Err(match &state.panic {
+                Some(msg) => {
+                    PyRuntimeError::new_err(format!("async generator previously panicked: {msg}"))
+                }
+                None => PyStopAsyncIteration::new_err(py.None()),
+            }));
         };
-        let opt_res = ready!(stream.as_mut().poll_next_py(py, cx));
-        if let Some(res) = opt_res {
-            if this.close {
-                *guard = None;
+        // a panicking stream is dropped, and the panic is raised again by subsequent calls,
+        // instead of polling again a stream in a possibly broken state
+        match panic::catch_unwind(AssertUnwindSafe(|| this.poll_stream(stream, py, cx))) {
+            Ok(Poll::Ready((res, terminated))) => {
+                if terminated {
+                    state.stream = None;
+                }
+                Poll::Ready(res)
+            }
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => {
+                let msg = utils::panic_message(&*payload);
+                state.stream = None;
+                state.panic = Some(msg.clone());
+                Poll::Ready(Err(PanicException::new_err(msg)))
             }
-            return Poll::Ready(res);
         }
-        *guard = None;
-        Poll::Ready(err())
+    }
+}
+
+/// Future feeding a batch of converted values to the sink of an async generator.
+type SinkFeed = Pin<Box<dyn Future<Output = PyResult<()>> + Send>>;
+
+/// Sink of the values sent to an async generator, converting a batch with the GIL held, and
+/// returning the future feeding it.
+pub(crate) type SendSink = Box<dyn FnMut(Python, Vec<&PyAny>) -> PyResult<SinkFeed> + Send>;
+
+/// Set the index of the failing item of a batch as the `index` attribute of the exception.
+fn item_error(py: Python, index: usize, err: PyErr) -> PyErr {
+    // the attribute is informative, the original error is raised anyway
+    err.value(py).setattr(intern!(py, "index"), index).ok();
+    err
+}
+
+pub(crate) fn send_sink<T, S>(sink: S) -> SendSink
+where
+    S: Sink<T> + Unpin + Send + 'static,
+    T: for<'py> FromPyObject<'py> + Send + 'static,
+    PyErr: From<S::Error>,
+{
+    // a single sink shared by the batches, instead of a clone per batch, which would bypass
+    // the backpressure of channels like `futures::channel::mpsc`, giving a slot to each sender
+    let sink = Arc::new(futures::lock::Mutex::new(sink));
+    Box::new(move |py, values| {
+        let items = (values.into_iter().enumerate())
+            .map(|(i, value)| value.extract::<T>().map_err(|err| item_error(py, i, err)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let sink = sink.clone();
+        Ok(Box::pin(async move {
+            let mut sink = sink.lock().await;
+            for (i, item) in items.into_iter().enumerate() {
+                if let Err(err) = sink.feed(item).await {
+                    return Err(Python::with_gil(|py| item_error(py, i, err.into())));
+                }
+            }
+            Ok(sink.flush().await?)
+        }))
+    })
+}
+
+/// Values sent with `asend`/`asend_many` fed to the sink, before awaiting the next item for
+/// `asend`.
+struct SendFeed {
+    feed: Option<SinkFeed>,
+    next: Option<PyStreamNext>,
+}
+
+impl PyFuture for SendFeed {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        if let Some(feed) = &mut this.feed {
+            let waker = cx.waker();
+            // sinks may wake the coroutine while holding a lock taken by their `poll_ready`,
+            // e.g. `futures::channel::mpsc`, and the waker acquires the GIL, so the GIL must
+            // not be held while polling them
+            let poll = py.allow_threads(|| feed.as_mut().poll(&mut Context::from_waker(waker)));
+            ready!(poll)?;
+            this.feed = None;
+        }
+        match &mut this.next {
+            Some(next) => Pin::new(next).poll_py(py, cx),
+            None => Poll::Ready(Ok(py.None())),
+        }
+    }
+}
+
+/// Exception thrown with `athrow` into the stream (see [`PyStream::throw_py`]) on the first
+/// poll of the returned coroutine, like Python async generators, before awaiting the next item.
+struct ThrowNext {
+    exc: Option<PyErr>,
+    next: PyStreamNext,
+}
+
+impl PyFuture for ThrowNext {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        if let Some(exc) = this.exc.take() {
+            let mut state = this.next.stream.lock().unwrap();
+            match state.stream.as_mut() {
+                Some(stream) => stream.as_mut().throw_py(py, exc)?,
+                None => return Poll::Ready(Err(exc)),
+            }
+        }
+        Pin::new(&mut this.next).poll_py(py, cx)
     }
 }
 
 pub(crate) trait CoroutineFactory {
     type Coroutine: IntoPy<PyObject>;
     fn coroutine(future: impl PyFuture + 'static) -> Self::Coroutine;
+    fn run_soon_threadsafe(py: Python) -> PyResult<PyObject>;
+    const BACKEND: &'static str;
+    fn current_backend(py: Python) -> &'static str;
 }
 
 pub(crate) struct AsyncGenerator<C> {
     stream: SharedStream,
     throw: Option<ThrowCallback>,
+    // like Python async generators, the first value sent must be `None`
+    started: bool,
+    // captured on first iteration, to finalize the stream in the event loop thread
+    run_soon_threadsafe: Option<PyObject>,
+    // resolved on first iteration
+    backend: Option<&'static str>,
+    pub(crate) drop_on_gc: bool,
+    pub(crate) error_policy: ErrorPolicy,
+    pub(crate) map_err: Option<MapErr>,
+    pub(crate) sink: Option<SendSink>,
+    name: Option<String>,
+    #[cfg(feature = "diagnostics")]
+    pub(crate) footprint: Option<Box<dyn MemoryFootprint + Send>>,
+    #[cfg(feature = "debug")]
+    registration: debug::Registration,
     _phantom: PhantomData<C>,
 }
 
 impl<C> AsyncGenerator<C> {
-    pub(crate) fn new(stream: Pin<Box<dyn PyStream>>, throw: Option<ThrowCallback>) -> Self {
+    pub(crate) fn new(stream: Pin<Box<dyn PyStreamClose>>, throw: Option<ThrowCallback>) -> Self {
         Self {
-            stream: Arc::new(Mutex::new(Some(stream))),
+            stream: Arc::new(Mutex::new(StreamState {
+                stream: Some(stream),
+                panic: None,
+            })),
             throw,
+            started: false,
+            run_soon_threadsafe: None,
+            backend: None,
+            drop_on_gc: false,
+            error_policy: ErrorPolicy::default(),
+            map_err: None,
+            sink: None,
+            name: None,
+            #[cfg(feature = "diagnostics")]
+            footprint: None,
+            #[cfg(feature = "debug")]
+            registration: debug::Registration::new(debug::LiveKind::AsyncGenerator),
             _phantom: PhantomData,
         }
     }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn set_name(&mut self, name: Option<String>) {
+        #[cfg(feature = "debug")]
+        self.registration.set_name(name.as_deref());
+        self.name = name;
+    }
+}
+
+impl<C> AsyncGenerator<C> {
+    /// Wrap the stream with an adapter, e.g. in a builder method.
+    pub(crate) fn map_stream(
+        &mut self,
+        f: impl FnOnce(Pin<Box<dyn PyStreamClose>>) -> Pin<Box<dyn PyStreamClose>>,
+    ) {
+        let mut state = self.stream.lock().unwrap();
+        state.stream = state.stream.take().map(f);
+    }
 }
 
 impl<C: CoroutineFactory> AsyncGenerator<C> {
     pub(crate) fn _next(&mut self, py: Python, close: bool) -> PyResult<PyObject> {
-        let stream = self.stream.clone();
-        Ok(C::coroutine(PyStreamNext { stream, close }).into_py(py))
+        let next = self.next_future(py, close);
+        Ok(C::coroutine(next).into_py(py))
+    }
+
+    fn next_future(&mut self, py: Python, close: bool) -> PyStreamNext {
+        // captured once, instead of being looked up for each item
+        if !self.started {
+            self.backend = Some(C::current_backend(py));
+            if !self.drop_on_gc {
+                self.run_soon_threadsafe = C::run_soon_threadsafe(py).ok();
+            }
+        }
+        self.started = true;
+        PyStreamNext {
+            stream: self.stream.clone(),
+            error_policy: self.error_policy.clone(),
+            map_err: self.map_err.clone(),
+            close,
+            // without throw callback, the stream is not expected to terminate itself when
+            // closed, so it's not polled for a last item
+            closing: (close && self.throw.is_none()).then(|| Ok(py.None())),
+        }
     }
 
     pub(crate) fn next(&mut self, py: Python) -> PyResult<PyObject> {
         self._next(py, false)
     }
 
+    pub(crate) fn remaining_hint(&self) -> (usize, Option<usize>) {
+        let state = self.stream.lock().unwrap();
+        state
+            .stream
+            .as_ref()
+            .map_or((0, Some(0)), |s| s.size_hint_py())
+    }
+
+    pub(crate) fn backend(&self) -> &'static str {
+        self.backend.unwrap_or(C::BACKEND)
+    }
+
+    pub(crate) fn send(&mut self, py: Python, value: &PyAny) -> PyResult<PyObject> {
+        if let (Some(sink), false) = (&mut self.sink, value.is_none()) {
+            let feed = match sink(py, vec![value]) {
+                Ok(feed) => feed,
+                Err(exc) => return Ok(C::coroutine(async move { Err::<(), _>(exc) }).into_py(py)),
+            };
+            let feed = SendFeed {
+                feed: Some(feed),
+                next: Some(self.next_future(py, false)),
+            };
+            return Ok(C::coroutine(feed).into_py(py));
+        }
+        if !self.started && !value.is_none() {
+            let exc =
+                PyTypeError::new_err("can't send non-None value to a just-started async generator");
+            return Ok(C::coroutine(async move { Err::<(), _>(exc) }).into_py(py));
+        }
+        self.next(py)
+    }
+
+    pub(crate) fn send_many(&mut self, py: Python, values: &PyAny) -> PyResult<PyObject> {
+        let Some(sink) = &mut self.sink else {
+            let exc = PyTypeError::new_err("async generator has no sink");
+            return Ok(C::coroutine(async move { Err::<(), _>(exc) }).into_py(py));
+        };
+        let feed = values
+            .iter()
+            .and_then(|values| values.collect::<PyResult<Vec<_>>>())
+            .and_then(|values| sink(py, values));
+        Ok(match feed {
+            Ok(feed) => C::coroutine(SendFeed {
+                feed: Some(feed),
+                next: None,
+            })
+            .into_py(py),
+            Err(exc) => C::coroutine(async move { Err::<(), _>(exc) }).into_py(py),
+        })
+    }
+
     pub(crate) fn throw(&mut self, py: Python, exc: PyErr) -> PyResult<PyObject> {
         let Some(throw) = &mut self.throw else {
-            return Ok(C::coroutine(async move { Err::<(), _>(exc) }).into_py(py));
+            let throw_next = ThrowNext {
+                exc: Some(exc),
+                next: self.next_future(py, false),
+            };
+            return Ok(C::coroutine(throw_next).into_py(py));
         };
         throw(py, Some(exc));
         self._next(py, false)
@@ -82,3 +592,35 @@ impl<C: CoroutineFactory> AsyncGenerator<C> {
         self._next(py, true)
     }
 }
+
+impl<C> Drop for AsyncGenerator<C> {
+    // Mimic Python async generator finalization: if the stream was not exhausted nor closed, and is
+    // not used by a pending `__anext__`/`aclose` coroutine, the throw callback is called with
+    // `None`, and the stream is dropped in the event loop thread where it was iterated.
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.stream) > 1 {
+            return;
+        }
+        let Some(stream) = self.stream.lock().unwrap().stream.take() else {
+            return;
+        };
+        Python::with_gil(|py| {
+            utils::preserve_exception(py, || {
+                if let Some(throw) = &mut self.throw {
+                    throw(py, None);
+                }
+                let Some(run_soon_threadsafe) = self.run_soon_threadsafe.take() else {
+                    return;
+                };
+                let stream = Mutex::new(Some(stream));
+                let drop_stream = move |_: &PyTuple, _: Option<&PyDict>| {
+                    stream.lock().unwrap().take();
+                };
+                let scheduled = compat::new_closure(py, drop_stream)
+                    .and_then(|drop_stream| run_soon_threadsafe.call1(py, (drop_stream,)));
+                // the event loop may be closed, the stream is then dropped with the closure
+                scheduled.ok();
+            })
+        });
+    }
+}
@@ -0,0 +1,68 @@
+//! Backend-agnostic state of the `AsyncResource` async context manager, generated in each backend
+//! module.
+//!
+//! `__aenter__` awaits the acquisition future, keeping a clone of the acquired resource, which
+//! is passed to the release callback by `__aexit__`, with the exception raised in the `async
+//! with` block, if any. Each of them can be awaited only once.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::PyFuture;
+
+type Release = Box<dyn FnOnce(Option<PyErr>) -> Pin<Box<dyn PyFuture>> + Send>;
+
+pub(crate) struct AsyncResource {
+    acquire: Option<Pin<Box<dyn PyFuture>>>,
+    release: Option<Release>,
+}
+
+impl AsyncResource {
+    pub(crate) fn new<T, A, R, RF>(acquire: A, release: R) -> Self
+    where
+        T: Clone + IntoPy<PyObject> + Send + 'static,
+        A: Future<Output = PyResult<T>> + Send + 'static,
+        R: FnOnce(T, Option<PyErr>) -> RF + Send + 'static,
+        RF: Future<Output = PyResult<()>> + Send + 'static,
+    {
+        let resource = Arc::new(Mutex::new(None));
+        let acquired = resource.clone();
+        let acquire = async move {
+            let resource = acquire.await?;
+            *acquired.lock().unwrap() = Some(resource.clone());
+            PyResult::Ok(resource)
+        };
+        let release = move |exc| -> Pin<Box<dyn PyFuture>> {
+            Box::pin(async move {
+                // the resource may have not been acquired, e.g. if acquisition failed
+                let Some(resource) = resource.lock().unwrap().take() else {
+                    return Ok(false);
+                };
+                release(resource, exc).await?;
+                PyResult::Ok(false)
+            })
+        };
+        Self {
+            acquire: Some(Box::pin(acquire)),
+            release: Some(Box::new(release)),
+        }
+    }
+
+    pub(crate) fn acquire(&mut self) -> PyResult<Pin<Box<dyn PyFuture>>> {
+        self.acquire
+            .take()
+            .ok_or_else(|| PyRuntimeError::new_err("resource has already been acquired"))
+    }
+
+    pub(crate) fn release(&mut self, exc: Option<PyErr>) -> PyResult<Pin<Box<dyn PyFuture>>> {
+        let release = self
+            .release
+            .take()
+            .ok_or_else(|| PyRuntimeError::new_err("resource has already been released"))?;
+        Ok(release(exc))
+    }
+}
@@ -1,83 +1,554 @@
 //! `asyncio` compatible coroutine and async generator implementation.
+//!
+//! # Example
+//!
+//! A coroutine future must be `Send + 'static`, so it cannot capture GIL-bound references like
+//! `&PyAny`; the Python objects have to be converted into Rust state first, which is then moved
+//! into the future. [`coroutine_from`] codifies this extract-then-move pattern:
+//!
+//! ```rust
+//! use pyo3::{prelude::*, types::PyDict};
+//! use pyo3_async::asyncio;
+//!
+//! #[pyfunction]
+//! fn total_length(words: &PyAny) -> PyResult<asyncio::Coroutine> {
+//!     asyncio::coroutine_from(
+//!         // executed with the GIL held, before the coroutine is returned
+//!         |_py| words.extract::<Vec<String>>(),
+//!         // the extracted `Vec<String>` is moved into the future
+//!         |words| async move { PyResult::Ok(words.iter().map(String::len).sum::<usize>()) },
+//!     )
+//! }
+//!
+//! pyo3::prepare_freethreaded_python();
+//! Python::with_gil(|py| {
+//!     let globals = PyDict::new(py);
+//!     globals.set_item("total_length", wrap_pyfunction!(total_length, py)?)?;
+//!     let code = r#"
+//! import asyncio
+//! assert asyncio.run(total_length(["async", "rust"])) == 9
+//! try:
+//!     total_length([0])
+//! except TypeError:
+//!     pass
+//! else:
+//!     assert False
+//! "#;
+//!     py.run(code, Some(globals), None)
+//! })
+//! .unwrap();
+//! ```
+#[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{
     future::Future,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{ready, Context, Poll},
+    time::Duration,
 };
 
-use futures::{FutureExt, Stream, StreamExt};
+use futures::{
+    channel::mpsc,
+    stream::{FuturesOrdered, FuturesUnordered},
+    task::AtomicWaker,
+    FutureExt, Stream, StreamExt,
+};
+use pin_project::pin_project;
 use pyo3::{
-    exceptions::{PyStopAsyncIteration, PyStopIteration},
-    intern,
+    exceptions::{PyRuntimeError, PyStopAsyncIteration, PyStopIteration, PyTypeError},
     prelude::*,
+    sync::GILOnceCell,
+    types::{PyDict, PySet, PyTuple},
 };
 
-use crate::{coroutine, utils};
+use crate::{
+    compat::{self, intern},
+    coroutine, sniffio, utils, PyFuture, PyStream, PyStreamClose,
+};
 
-utils::module!(Asyncio, "asyncio", Future);
+crate::cached_import!(
+    pub(crate) Asyncio,
+    "asyncio",
+    CancelledError,
+    Future,
+    TimeoutError,
+    current_task,
+    ensure_future,
+    _get_running_loop,
+    get_running_loop,
+    new_event_loop,
+    run_coroutine_threadsafe,
+    sleep
+);
+crate::cached_import!(Contextvars, "contextvars", copy_context);
 
 fn asyncio_future(py: Python) -> PyResult<PyObject> {
     Asyncio::get(py)?.Future.call0(py)
 }
 
+/// Message of an `asyncio.CancelledError`, e.g. passed to `Task.cancel(msg)`.
+///
+/// Returns `None` if the error is not a `CancelledError` or has no message. It can be used in
+/// [`ThrowCallback`](crate::ThrowCallback) to distinguish cancellation reasons.
+pub fn cancelled_message(py: Python, err: &PyErr) -> PyResult<Option<PyObject>> {
+    if !err.is_instance(py, Asyncio::get(py)?.CancelledError.as_ref(py)) {
+        return Ok(None);
+    }
+    let args = err.value(py).getattr(intern!(py, "args"))?;
+    Ok(args.get_item(0).ok().map(Into::into))
+}
+
+/// Per suspension, an `asyncio.Future` is instantiated and yielded to the task, then its result
+/// is set by the wake, and finally checked with `done` when resumed. Method names are interned
+/// and the `(None,)` arguments of `set_result` are cached, so the only other allocation is the
+/// arguments tuple of a wake scheduled from another thread with `call_soon_threadsafe`, its
+/// callback being cached too; this bound is checked by the demo tests.
 pub(crate) struct Waker {
+    #[cfg(not(feature = "batch-wakes"))]
     call_soon_threadsafe: PyObject,
     future: PyObject,
+    // used to report wake errors, as there may be no coroutine left to raise them
+    event_loop: PyObject,
+    task: PyObject,
+    // set when `Future.set_result` is already scheduled, as the future can be woken only once
+    #[cfg(feature = "coalesce-wakes")]
+    wake_scheduled: AtomicBool,
+    #[cfg(feature = "batch-wakes")]
+    batch: Arc<WakeBatch>,
 }
 
-impl coroutine::CoroutineWaker for Waker {
-    fn new(py: Python) -> PyResult<Self> {
+impl Waker {
+    fn done(&self, py: Python) -> PyResult<bool> {
+        let done = self.future.call_method0(py, intern!(py, "done"))?;
+        compat::is_true(py, &done)
+    }
+
+    /// Schedule the resolution of the future with its own `call_soon_threadsafe` callback.
+    fn schedule_wake(&self, py: Python, call_soon_threadsafe: &PyObject) {
+        static WAKE_FUTURE: GILOnceCell<PyObject> = GILOnceCell::new();
+        let wake_future = WAKE_FUTURE.get_or_try_init(py, || {
+            PyResult::Ok(wrap_pyfunction!(wake_future, py)?.into())
+        });
+        let res = wake_future.and_then(|wake| {
+            let args = (wake, &self.future, &self.event_loop, &self.task);
+            call_soon_threadsafe.call1(py, args)
+        });
+        // e.g. the event loop is closed
+        if let Err(err) = res {
+            report_wake_error(py, &self.event_loop, &self.task, err);
+        }
+    }
+}
+
+/// `call_soon_threadsafe` callback of [`Waker::schedule_wake`], the future being passed as
+/// argument, so the callback is not allocated for each wake.
+#[pyfunction]
+fn wake_future(py: Python, future: PyObject, event_loop: PyObject, task: PyObject) {
+    if let Err(err) = set_result(py, &future) {
+        report_wake_error(py, &event_loop, &task, err);
+    }
+}
+
+fn set_result(py: Python, future: &PyObject) -> PyResult<()> {
+    static NONE_ARGS: GILOnceCell<Py<PyTuple>> = GILOnceCell::new();
+    // the future may already be done if the coroutine was woken by several sources, or if the
+    // task has been cancelled in the meantime
+    if !compat::is_true(py, &future.call_method0(py, intern!(py, "done"))?)? {
+        let args = NONE_ARGS.get_or_init(py, || PyTuple::new(py, [py.None()]).into());
+        future.call_method1(py, intern!(py, "set_result"), args.as_ref(py))?;
+    }
+    Ok(())
+}
+
+/// Report a wake error to the event loop exception handler, falling back to
+/// `sys.unraisablehook`.
+fn report_wake_error(py: Python, event_loop: &PyObject, task: &PyObject, err: PyErr) {
+    let call_handler = || {
+        let context = PyDict::new(py);
+        context.set_item("message", "Exception while waking pyo3-async coroutine")?;
+        context.set_item("exception", err.value(py))?;
+        if !task.is_none(py) {
+            context.set_item("task", task)?;
+        }
+        event_loop.call_method1(py, intern!(py, "call_exception_handler"), (context,))
+    };
+    if let Err(handler_err) = call_handler() {
+        err.write_unraisable(py, None);
+        handler_err.write_unraisable(py, None);
+    }
+}
+
+/// Threadsafe wakes of the coroutines of an event loop, queued to be processed by a single
+/// callback, instead of scheduling one `call_soon_threadsafe` callback per wake.
+#[cfg(feature = "batch-wakes")]
+struct WakeBatch {
+    event_loop: PyObject,
+    call_soon_threadsafe: PyObject,
+    // futures to resolve, with the task to report errors
+    sender: std::sync::mpsc::Sender<(PyObject, PyObject)>,
+    // only locked by the drain callback
+    receiver: Mutex<std::sync::mpsc::Receiver<(PyObject, PyObject)>>,
+    // set while the drain callback is scheduled
+    scheduled: AtomicBool,
+}
+
+#[cfg(feature = "batch-wakes")]
+impl WakeBatch {
+    /// Batch of the event loop, shared by the wakers created in its thread.
+    fn get(py: Python, event_loop: &PyObject) -> PyResult<Arc<Self>> {
+        thread_local! {
+            static WAKE_BATCH: std::cell::RefCell<Option<Arc<WakeBatch>>> =
+                const { std::cell::RefCell::new(None) };
+        }
+        WAKE_BATCH.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            match cell.as_ref() {
+                Some(batch) if batch.event_loop.is(event_loop) => Ok(batch.clone()),
+                _ => {
+                    let (sender, receiver) = std::sync::mpsc::channel();
+                    let batch = Arc::new(WakeBatch {
+                        event_loop: event_loop.clone_ref(py),
+                        call_soon_threadsafe: event_loop
+                            .getattr(py, intern!(py, "call_soon_threadsafe"))?,
+                        sender,
+                        receiver: Mutex::new(receiver),
+                        scheduled: AtomicBool::new(false),
+                    });
+                    *cell = Some(batch.clone());
+                    Ok(batch)
+                }
+            }
+        })
+    }
+
+    fn wake(self: &Arc<Self>, py: Python, future: PyObject, task: PyObject) {
+        // the receiver cannot be dropped while the batch is alive
+        self.sender.send((future, task)).unwrap();
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let batch = self.clone();
+        let drain = move |args: &PyTuple, _: Option<&PyDict>| batch.drain(args.py());
+        let res = compat::new_closure(py, drain)
+            .and_then(|drain| self.call_soon_threadsafe.call1(py, (drain,)));
+        // e.g. the event loop is closed, the queued wakes are then reported
+        if let Err(err) = res {
+            self.scheduled.store(false, Ordering::Release);
+            for (_, task) in self.receiver.lock().unwrap().try_iter() {
+                report_wake_error(py, &self.event_loop, &task, err.clone_ref(py));
+            }
+        }
+    }
+
+    fn drain(&self, py: Python) {
+        // reset before draining, so a wake queued after the drain schedules a new callback
+        self.scheduled.store(false, Ordering::Release);
+        for (future, task) in self.receiver.lock().unwrap().try_iter() {
+            // the coroutine may have completed since, `set_result` skipping done futures
+            if let Err(err) = set_result(py, &future) {
+                report_wake_error(py, &self.event_loop, &task, err);
+            }
+        }
+    }
+}
+
+impl Waker {
+    /// Instantiate the waker with the result of `asyncio.current_task()`.
+    pub(crate) fn with_task(py: Python, task: PyObject) -> PyResult<Self> {
         let future = asyncio_future(py)?;
-        let call_soon_threadsafe = future
-            .call_method0(py, intern!(py, "get_loop"))?
-            .getattr(py, intern!(py, "call_soon_threadsafe"))?;
+        let event_loop = future.call_method0(py, intern!(py, "get_loop"))?;
         Ok(Waker {
-            call_soon_threadsafe,
+            #[cfg(not(feature = "batch-wakes"))]
+            call_soon_threadsafe: event_loop.getattr(py, intern!(py, "call_soon_threadsafe"))?,
+            #[cfg(feature = "batch-wakes")]
+            batch: WakeBatch::get(py, &event_loop)?,
             future,
+            event_loop,
+            task,
+            #[cfg(feature = "coalesce-wakes")]
+            wake_scheduled: AtomicBool::new(false),
         })
     }
+}
 
+impl coroutine::CoroutineWaker for Waker {
+    const BACKEND: &'static str = "asyncio";
+
+    fn new(py: Python) -> PyResult<Self> {
+        let asyncio = Asyncio::get(py)?;
+        if asyncio._get_running_loop.call0(py)?.is_none(py) {
+            return Err(sniffio::wrong_async_library(py, "an asyncio event loop"));
+        }
+        // the coroutine may be polled outside of a task
+        let task = (asyncio.current_task.call0(py)).unwrap_or_else(|_| py.None());
+        Self::with_task(py, task)
+    }
+
+    // Equivalent to `future.__await__().__next__()`, without allocating the iterator.
     fn yield_(&self, py: Python) -> PyResult<PyObject> {
-        self.future
-            .call_method0(py, intern!(py, "__await__"))?
-            .call_method0(py, intern!(py, "__next__"))
+        let future = self.future.as_ref(py);
+        future.setattr(intern!(py, "_asyncio_future_blocking"), true)?;
+        Ok(future.into())
+    }
+
+    fn checkpoint(&self, py: Python) -> PyResult<PyObject> {
+        // bare yield makes the task reschedule itself
+        Ok(py.None())
+    }
+
+    fn run_soon_threadsafe(py: Python) -> PyResult<PyObject> {
+        Asyncio::get(py)?
+            .get_running_loop
+            .call0(py)?
+            .getattr(py, intern!(py, "call_soon_threadsafe"))
     }
 
     fn wake(&self, py: Python) {
-        self.future
-            .call_method1(py, intern!(py, "set_result"), (py.None(),))
-            .expect("error while calling EventLoop.call_soon_threadsafe");
+        if let Err(err) = set_result(py, &self.future) {
+            report_wake_error(py, &self.event_loop, &self.task, err);
+        }
     }
 
     fn wake_threadsafe(&self, py: Python) {
-        let set_result = self
-            .future
-            .getattr(py, intern!(py, "set_result"))
-            .expect("error while calling Future.set_result");
-        self.call_soon_threadsafe
-            .call1(py, (set_result, py.None()))
-            .expect("error while calling EventLoop.call_soon_threadsafe");
+        #[cfg(feature = "coalesce-wakes")]
+        if self.wake_scheduled.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        #[cfg(feature = "batch-wakes")]
+        (self.batch).wake(py, self.future.clone_ref(py), self.task.clone_ref(py));
+        #[cfg(not(feature = "batch-wakes"))]
+        self.schedule_wake(py, &self.call_soon_threadsafe);
+    }
+
+    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+    fn wake_threadsafe_immediate(&self, py: Python) {
+        #[cfg(feature = "batch-wakes")]
+        let call_soon_threadsafe = &self.batch.call_soon_threadsafe;
+        #[cfg(not(feature = "batch-wakes"))]
+        let call_soon_threadsafe = &self.call_soon_threadsafe;
+        self.schedule_wake(py, call_soon_threadsafe);
     }
 
     fn update(&mut self, py: Python) -> PyResult<()> {
         self.future = Asyncio::get(py)?.Future.call0(py)?;
+        #[cfg(feature = "coalesce-wakes")]
+        self.wake_scheduled.store(false, Ordering::Relaxed);
         Ok(())
     }
 
+    fn reusable(&self, py: Python) -> bool {
+        // the future may have been cancelled externally
+        !self.done(py).unwrap_or(true)
+    }
+
     fn raise(&self, py: Python) -> PyResult<()> {
+        // the future may still be pending if the poll races with a threadsafe wake
+        if !self.done(py)? {
+            return Ok(());
+        }
+        // if the future has been cancelled externally, `CancelledError` is raised, and then
+        // handled like an exception thrown into the coroutine
         self.future.call_method0(py, intern!(py, "result"))?;
         Ok(())
     }
 }
 
-utils::generate!(Waker);
+utils::generate!(
+    Waker,
+    coroutine_methods {
+        /// Wrap the coroutine into an `asyncio.Task` using `asyncio.ensure_future`; the same
+        /// task is returned by subsequent calls.
+        ///
+        /// The coroutine is then driven by the task, so awaiting it directly, or calling its
+        /// `send` method, raises `RuntimeError`; the task must be awaited instead.
+        fn as_future(&self, py: Python) -> PyResult<PyObject> {
+            let ensure_future = &Asyncio::get(py)?.ensure_future;
+            self.0.detach(py, |coro| ensure_future.call1(py, (Self(coro),)))
+        }
+
+        /// Await `coro` with a timeout, like `asyncio.wait_for`.
+        ///
+        /// `TimeoutError` is raised on expiry, and `coro` is cancelled, i.e. its pending future
+        /// is cancelled and `coro` is closed. Contrary to `asyncio.wait_for`, `coro` is not
+        /// wrapped in a task, and the timeout is implemented with [`timeout_loop`].
+        #[classmethod]
+        #[pyo3(signature = (coro, timeout))]
+        fn wait_for(
+            _cls: &::pyo3::types::PyType,
+            coro: &PyAny,
+            timeout: Option<f64>,
+        ) -> PyResult<Self> {
+            let awaitable = AwaitableWrapper::new(coro)?;
+            let timeout = timeout.map(|t| timeout_loop(futures::future::pending(), t));
+            Ok(Self::from_future(WaitFor { awaitable, timeout }))
+        }
+
+        /// Block until the coroutine completes, like `concurrent.futures.Future.result`, for
+        /// APIs supporting both sync and async calling conventions.
+        ///
+        /// The coroutine is run in a temporary event loop, created in the current thread and
+        /// closed afterwards, so it must not await objects bound to another event loop; it is
+        /// then consumed, like after being awaited. `TimeoutError` is raised on expiry of
+        /// `timeout`, and the coroutine is cancelled (see `wait_for`).
+        ///
+        /// It must not be called from within a running event loop, which would be blocked;
+        /// `RuntimeError` is raised instead, and the coroutine must be awaited.
+        #[pyo3(signature = (timeout = None))]
+        fn result(&self, py: Python, timeout: Option<f64>) -> PyResult<PyObject> {
+            let asyncio = Asyncio::get(py)?;
+            if asyncio.get_running_loop.call0(py).is_ok() {
+                return Err(PyRuntimeError::new_err(
+                    "result() cannot be called from a running event loop, await the coroutine instead",
+                ));
+            }
+            let future = self.0.take_future()?;
+            let coro = Py::new(py, Self::new(future, None))?;
+            let coro = Self::wait_for(py.get_type::<Self>(), coro.as_ref(py), timeout)?;
+            let event_loop = asyncio.new_event_loop.call0(py)?;
+            let res = event_loop.call_method1(py, intern!(py, "run_until_complete"), (coro,));
+            // the outcome is not replaced by an error closing the event loop
+            if let Err(close_err) = event_loop.call_method0(py, intern!(py, "close")) {
+                let context = intern!(py, "__context__");
+                match &res {
+                    Err(err) if err.value(py).getattr(context).is_ok_and(PyAny::is_none) => {
+                        let close_err = close_err.into_value(py);
+                        err.value(py).setattr(context, close_err).ok();
+                    }
+                    _ => close_err.write_unraisable(py, None),
+                }
+            }
+            res
+        }
+    }
+);
+
+/// `context` passed to `add_done_callback` of wrapped futures, and the registered callback.
+///
+/// The callback is registered once per future, waking the waker stored in a shared slot, which
+/// is updated at each poll; polling the wrapper several times before the future completion, e.g.
+/// when combined with other futures, doesn't accumulate callbacks on the future.
+#[derive(Debug, Default)]
+struct CallbackContext {
+    context: Option<PyObject>,
+    // set when a future has rejected the registration without `context` keyword
+    keyword: bool,
+    // waker of the last poll, woken by the callback
+    waker: Arc<AtomicWaker>,
+    // callback registered on the current future, removed when it's cancelled on drop
+    callback: Option<PyObject>,
+}
+
+impl CallbackContext {
+    /// Store the waker, and register the done callback if it's not already registered on the
+    /// current future; `context` keyword is passed if a context has been provided, or if
+    /// required by the future, e.g. a `Future` subclass mandating it.
+    fn register(
+        &mut self,
+        py: Python,
+        future: &PyObject,
+        waker: &std::task::Waker,
+    ) -> PyResult<()> {
+        self.waker.register(waker);
+        if self.callback.is_some() {
+            return Ok(());
+        }
+        let slot = self.waker.clone();
+        let callback = compat::new_closure(py, move |_, _| slot.wake())?;
+        let add_done_callback = intern!(py, "add_done_callback");
+        if self.context.is_none() && !self.keyword {
+            match future.call_method1(py, add_done_callback, (callback,)) {
+                Err(err) if err.is_instance_of::<PyTypeError>(py) => self.keyword = true,
+                res => {
+                    res?;
+                    self.callback = Some(callback.into());
+                    return Ok(());
+                }
+            }
+        }
+        let kwargs = PyDict::new(py);
+        kwargs.set_item(intern!(py, "context"), &self.context)?;
+        future.call_method(py, add_done_callback, (callback,), Some(kwargs))?;
+        self.callback = Some(callback.into());
+        Ok(())
+    }
+
+    /// Switch to a new future, the callback of the previous one waking a discarded slot.
+    fn reset(&mut self) {
+        self.waker = Arc::default();
+        self.callback = None;
+    }
+
+    /// Remove the registered callback, so it doesn't outlive the wrapper; it's skipped for
+    /// futures without `remove_done_callback`, e.g. `concurrent.futures.Future`.
+    fn remove_done_callback(&mut self, py: Python, future: &PyObject) -> PyResult<()> {
+        let remove_done_callback = intern!(py, "remove_done_callback");
+        if let Some(callback) = self.callback.take() {
+            if future.as_ref(py).hasattr(remove_done_callback)? {
+                future.call_method1(py, remove_done_callback, (callback,))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maximum number of already done futures consumed by a single poll of [`AwaitableWrapper`].
+const MAX_READY_STEPS: usize = 32;
 
 /// [`Future`] wrapper for a Python awaitable (in `asyncio` context).
 ///
 /// The future should be polled in the thread where the event loop is running.
+///
+/// The awaitable is driven like `asyncio.Task` does: it is resumed once the yielded future is
+/// done, the future result, or exception, being retrieved by the awaitable itself, so nested
+/// coroutines can catch it; a bare `yield`, e.g. `asyncio.sleep(0)`, reschedules the wrapper.
+///
+/// Futures yielded by the awaitable which are already done, e.g. cached results, are skipped
+/// within the same poll, instead of costing an event loop iteration each.
+///
+/// # Example
+///
+/// ```rust
+/// use std::task::{Context, Poll};
+///
+/// use futures::{task::noop_waker, FutureExt};
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::AwaitableWrapper;
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     let code = r#"
+/// import asyncio
+/// class Cached:
+///     def __await__(self):
+///         loop = asyncio.new_event_loop()
+///         for i in range(3):
+///             future = loop.create_future()
+///             future.set_result(i)
+///             yield future
+///         loop.close()
+///         return "done"
+/// "#;
+///     py.run(code, Some(globals), None)?;
+///     let awaitable = py.eval("Cached()", Some(globals), None)?;
+///     let mut wrapper = AwaitableWrapper::new(awaitable)?;
+///     let waker = noop_waker();
+///     let poll = wrapper.as_mut(py).poll_unpin(&mut Context::from_waker(&waker));
+///     let Poll::Ready(res) = poll else {
+///         panic!("not completed in a single poll")
+///     };
+///     assert_eq!(res?.extract::<&str>(py)?, "done");
+///     PyResult::Ok(())
+/// })
+/// .unwrap();
+/// ```
 pub struct AwaitableWrapper {
     future_iter: PyObject,
     future: Option<PyObject>,
+    callback_context: CallbackContext,
+    cancel_on_drop: Option<CancelOnDrop>,
+    done: bool,
 }
 
 impl AwaitableWrapper {
@@ -88,9 +559,30 @@ impl AwaitableWrapper {
                 .call_method0(intern!(awaitable.py(), "__await__"))?
                 .extract()?,
             future: None,
+            callback_context: CallbackContext::default(),
+            cancel_on_drop: None,
+            done: false,
         })
     }
 
+    /// Pass `context` to the `add_done_callback` method of the awaited futures.
+    ///
+    /// By default, the callback is registered without context, unless the future requires the
+    /// `context` keyword; `None` is then passed.
+    pub fn with_context(mut self, context: impl Into<PyObject>) -> Self {
+        self.callback_context.context = Some(context.into());
+        self
+    }
+
+    /// Cancel the pending future, removing its callback, and close the awaitable, if the
+    /// wrapper is dropped before completion; errors are handled following the provided policy.
+    ///
+    /// By default, nothing is done on drop, the awaitable being left suspended.
+    pub fn with_cancel_on_drop(mut self, cancel_on_drop: CancelOnDrop) -> Self {
+        self.cancel_on_drop = Some(cancel_on_drop);
+        self
+    }
+
     /// GIL-bound [`Future`] reference.
     pub fn as_mut<'a>(
         &'a mut self,
@@ -98,31 +590,86 @@ impl AwaitableWrapper {
     ) -> impl Future<Output = PyResult<PyObject>> + Unpin + 'a {
         utils::WithGil { inner: self, py }
     }
+
+    fn cancel_future(&mut self, py: Python) -> PyResult<()> {
+        if let Some(future) = self.future.take() {
+            self.callback_context.remove_done_callback(py, &future)?;
+            future.call_method0(py, intern!(py, "cancel"))?;
+        }
+        Ok(())
+    }
+
+    /// Cancel the pending future, and close the awaitable if it can be.
+    pub(crate) fn cancel(&mut self, py: Python) -> PyResult<()> {
+        self.cancel_future(py)?;
+        if self.future_iter.as_ref(py).hasattr(intern!(py, "close"))? {
+            self.future_iter.call_method0(py, intern!(py, "close"))?;
+        }
+        Ok(())
+    }
+
+    /// Cancel the pending future, and throw `CancelledError` into the awaitable, like a task
+    /// cancellation; contrary to `close`, it leaves the async generator of an `__anext__`
+    /// awaitable in a state where it can be closed.
+    fn throw_cancelled(&mut self, py: Python) -> PyResult<()> {
+        self.cancel_future(py)?;
+        let cancelled_error = &Asyncio::get(py)?.CancelledError;
+        let throw = intern!(py, "throw");
+        match (self.future_iter).call_method1(py, throw, (cancelled_error.call0(py)?,)) {
+            Err(err) if err.is_instance_of::<PyStopIteration>(py) => Ok(()),
+            Err(err) if err.matches(py, cancelled_error) => Ok(()),
+            Err(err) => Err(err),
+            // the cancellation has been caught, and the awaitable has yielded again
+            Ok(_) => Ok(()),
+        }
+    }
 }
 
-impl<'a> Future for utils::WithGil<'_, &'a mut AwaitableWrapper> {
+impl Future for utils::WithGil<'_, &mut AwaitableWrapper> {
     type Output = PyResult<PyObject>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if let Some(fut) = self.inner.future.as_ref() {
-            fut.call_method0(self.py, intern!(self.py, "result"))?;
-        }
-        match self
-            .inner
-            .future_iter
-            .call_method0(self.py, intern!(self.py, "__next__"))
-        {
-            Ok(future) => {
-                let callback = utils::wake_callback(self.py, cx.waker().clone())?;
-                future.call_method1(self.py, intern!(self.py, "add_done_callback"), (callback,))?;
-                self.inner.future = Some(future);
-                Poll::Pending
+        let py = self.py;
+        let inner = &mut *self.inner;
+        let mut steps = 0;
+        loop {
+            if let Some(fut) = inner.future.as_ref() {
+                // the wrapper may be polled before the future completion, e.g. when combined
+                // with other futures, so the waker is updated in case it has changed; already
+                // done futures are skipped without waiting for a callback, up to a bound not to
+                // starve the event loop
+                if steps == MAX_READY_STEPS
+                    || !compat::is_true(py, &fut.call_method0(py, intern!(py, "done"))?)?
+                {
+                    (inner.callback_context).register(py, fut, cx.waker())?;
+                    return Poll::Pending;
+                }
+                // like `asyncio.Task`, the future result is not retrieved here, but by the
+                // awaitable when resumed, e.g. in `Future.__await__`, so an exception is raised
+                // inside it, where it can be caught by a nested coroutine
+            }
+            steps += 1;
+            match inner.future_iter.call_method0(py, intern!(py, "__next__")) {
+                // bare yield, e.g. `asyncio.sleep(0)`, relinquishes control for one iteration
+                Ok(future) if future.is_none(py) => {
+                    inner.future = None;
+                    inner.callback_context.reset();
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Ok(future) => {
+                    inner.future = Some(future);
+                    inner.callback_context.reset();
+                }
+                Err(err) if err.is_instance_of::<PyStopIteration>(py) => {
+                    inner.done = true;
+                    return Poll::Ready(Ok(err.value(py).getattr(intern!(py, "value"))?.into()));
+                }
+                Err(err) => {
+                    inner.done = true;
+                    return Poll::Ready(Err(err));
+                }
             }
-            Err(err) if err.is_instance_of::<PyStopIteration>(self.py) => Poll::Ready(Ok(err
-                .value(self.py)
-                .getattr(intern!(self.py, "value"))?
-                .into())),
-            Err(err) => Poll::Ready(Err(err)),
         }
     }
 }
@@ -131,10 +678,158 @@ impl Future for AwaitableWrapper {
     type Output = PyResult<PyObject>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("AwaitableWrapper");
         Python::with_gil(|gil| Pin::into_inner(self).as_mut(gil).poll_unpin(cx))
     }
 }
 
+impl Drop for AwaitableWrapper {
+    fn drop(&mut self) {
+        let Some(cancel) = self.cancel_on_drop.filter(|_| !self.done) else {
+            return;
+        };
+        Python::with_gil(|gil| {
+            let res = utils::preserve_exception(gil, || self.cancel(gil));
+            cancel.handle_error(gil, "AwaitableWrapper", res);
+        });
+    }
+}
+
+/// Wrap the awaitable back into a coroutine.
+///
+/// ```rust
+/// use pyo3::prelude::*;
+/// use pyo3_async::asyncio::{AwaitableWrapper, Coroutine};
+///
+/// #[pyfunction]
+/// fn rewrap(awaitable: &PyAny) -> PyResult<Coroutine> {
+///     Ok(AwaitableWrapper::new(awaitable)?.into())
+/// }
+/// ```
+impl From<AwaitableWrapper> for Coroutine {
+    fn from(value: AwaitableWrapper) -> Self {
+        Self::from_future(value)
+    }
+}
+
+/// [`Future`] awaiting a Python awaitable, with a fast path for the coroutines of this module.
+///
+/// When the awaitable is a [`Coroutine`] which has never been polled, its future is taken out
+/// and polled directly, instead of driving the coroutine through its Python protocol with an
+/// [`AwaitableWrapper`]; the coroutine then raises like an already awaited one. It makes
+/// layered Rust APIs cheap, e.g. a Rust function awaiting the coroutine returned by another one.
+/// The errors are still mapped by [`Coroutine::with_map_err`], but the coroutine name is not
+/// kept, as no coroutine object is involved anymore.
+///
+/// Other awaitables, as well as coroutines already polled or with a throw callback, fall back
+/// to [`AwaitableWrapper`]. Coroutines of other extension modules, compiled with their own copy
+/// of this crate, are not recognized.
+///
+/// The future should be polled in the thread where the event loop is running.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{AwaitableWrapper, Coroutine, FastAwaitable};
+///
+/// #[pyfunction]
+/// fn answer() -> Coroutine {
+///     Coroutine::from_future(async {
+///         for _ in 0..3 {
+///             let sleep = Python::with_gil(|py| {
+///                 AwaitableWrapper::new(py.import("asyncio")?.call_method1("sleep", (0.001,))?)
+///             })?;
+///             sleep.await?;
+///         }
+///         PyResult::Ok(42)
+///     })
+/// }
+///
+/// #[pyfunction]
+/// fn through_python(coroutine: &PyAny) -> PyResult<Coroutine> {
+///     let wrapper = AwaitableWrapper::new(coroutine)?;
+///     Ok(Coroutine::from_future(async move { wrapper.await }))
+/// }
+///
+/// #[pyfunction]
+/// fn fast(coroutine: &PyAny) -> PyResult<Coroutine> {
+///     let awaitable = FastAwaitable::new(coroutine)?;
+///     assert!(matches!(awaitable, FastAwaitable::Rust(_)));
+///     Ok(Coroutine::from_future(async move { awaitable.await }))
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("answer", wrap_pyfunction!(answer, py)?)?;
+///     globals.set_item("through_python", wrap_pyfunction!(through_python, py)?)?;
+///     globals.set_item("fast", wrap_pyfunction!(fast, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// class CountingLoop(asyncio.SelectorEventLoop):
+///     def call_soon(self, *args, **kwargs):
+///         self.calls += 1
+///         return super().call_soon(*args, **kwargs)
+/// def run(coroutine):
+///     loop = CountingLoop()
+///     loop.calls = 0
+///     try:
+///         return loop.run_until_complete(coroutine), loop.calls
+///     finally:
+///         loop.close()
+/// slow_result, slow_calls = run(through_python(answer()))
+/// fast_result, fast_calls = run(fast(answer()))
+/// assert slow_result == fast_result == 42
+/// assert fast_calls < slow_calls, (fast_calls, slow_calls)
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub enum FastAwaitable {
+    /// Future taken out of a [`Coroutine`].
+    Rust(Pin<Box<dyn PyFuture>>),
+    /// Fallback for other awaitables.
+    Python(AwaitableWrapper),
+}
+
+impl FastAwaitable {
+    /// Wrap a Python awaitable, taking the future out of it if it's a [`Coroutine`].
+    pub fn new(awaitable: &PyAny) -> PyResult<Self> {
+        if let Ok(coroutine) = awaitable.downcast::<PyCell<Coroutine>>() {
+            if let Ok(future) = compat::get(coroutine).0.take_future() {
+                return Ok(Self::Rust(future));
+            }
+        }
+        Ok(Self::Python(AwaitableWrapper::new(awaitable)?))
+    }
+
+    fn poll_gil(&mut self, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        match self {
+            Self::Rust(future) => future.as_mut().poll_py(py, cx),
+            Self::Python(wrapper) => wrapper.as_mut(py).poll_unpin(cx),
+        }
+    }
+}
+
+impl PyFuture for WithGilToken<FastAwaitable> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        Pin::into_inner(self).0.poll_gil(py, cx)
+    }
+}
+
+impl Future for FastAwaitable {
+    type Output = PyResult<PyObject>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("FastAwaitable");
+        Python::with_gil(|gil| Pin::into_inner(self).poll_gil(gil, cx))
+    }
+}
+
 /// [`Future`] wrapper for Python future.
 ///
 /// Because its duck-typed, it can work either with [`asyncio.Future`](https://docs.python.org/3/library/asyncio-future.html#asyncio.Future) or [`concurrent.futures.Future`](https://docs.python.org/3/library/concurrent.futures.html#concurrent.futures.Future).
@@ -142,13 +837,50 @@ impl Future for AwaitableWrapper {
 pub struct FutureWrapper {
     future: PyObject,
     cancel_on_drop: Option<CancelOnDrop>,
+    callback_context: CallbackContext,
 }
 
-/// Cancel-on-drop error handling policy (see [`FutureWrapper::new`]).
+/// Cancel-on-drop error handling policy (see [`FutureWrapper::new`],
+/// [`AwaitableWrapper::with_cancel_on_drop`] and [`AsyncGeneratorWrapper::with_cancel_on_drop`]).
 #[derive(Debug, Copy, Clone)]
 pub enum CancelOnDrop {
     IgnoreError,
+    /// Panic in `Drop`, which aborts the process if the wrapper is dropped while unwinding.
     PanicOnError,
+    /// Log the error with a warning, using `tracing` if the `tracing` feature is enabled, or
+    /// the `pyo3_async` Python logger otherwise.
+    LogOnError,
+}
+
+impl CancelOnDrop {
+    pub(crate) fn handle_error(self, py: Python, wrapper: &str, res: PyResult<()>) {
+        let Err(err) = res else {
+            return;
+        };
+        match self {
+            Self::IgnoreError => {}
+            Self::PanicOnError => panic!("Cancel error while dropping {wrapper}: {err:?}"),
+            #[cfg(feature = "tracing")]
+            Self::LogOnError => {
+                let _ = py;
+                ::tracing::warn!(target: "pyo3_async", "Cancel error while dropping {wrapper}: {err}");
+            }
+            #[cfg(not(feature = "tracing"))]
+            Self::LogOnError => {
+                let log = || {
+                    let logger = py
+                        .import(intern!(py, "logging"))?
+                        .call_method1(intern!(py, "getLogger"), ("pyo3_async",))?;
+                    let kwargs = PyDict::new(py);
+                    kwargs.set_item(intern!(py, "exc_info"), err)?;
+                    let msg = "Cancel error while dropping %s";
+                    logger.call_method(intern!(py, "warning"), (msg, wrapper), Some(kwargs))
+                };
+                // there is nowhere to report a logging error
+                utils::preserve_exception(py, log).ok();
+            }
+        }
+    }
 }
 
 impl FutureWrapper {
@@ -160,9 +892,19 @@ impl FutureWrapper {
         Self {
             future: future.into(),
             cancel_on_drop,
+            callback_context: CallbackContext::default(),
         }
     }
 
+    /// Pass `context` to the `add_done_callback` method of the future.
+    ///
+    /// By default, the callback is registered without context, unless the future requires the
+    /// `context` keyword; `None` is then passed.
+    pub fn with_context(mut self, context: impl Into<PyObject>) -> Self {
+        self.callback_context.context = Some(context.into());
+        self
+    }
+
     /// GIL-bound [`Future`] reference.
     pub fn as_mut<'a>(
         &'a mut self,
@@ -172,16 +914,12 @@ impl FutureWrapper {
     }
 }
 
-impl<'a> Future for utils::WithGil<'_, &'a mut FutureWrapper> {
+impl Future for utils::WithGil<'_, &mut FutureWrapper> {
     type Output = PyResult<PyObject>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self
-            .inner
-            .future
-            .call_method0(self.py, intern!(self.py, "done"))?
-            .is_true(self.py)?
-        {
+        let done = (self.inner.future).call_method0(self.py, intern!(self.py, "done"))?;
+        if compat::is_true(self.py, &done)? {
             self.inner.cancel_on_drop = None;
             return Poll::Ready(
                 self.inner
@@ -189,12 +927,9 @@ impl<'a> Future for utils::WithGil<'_, &'a mut FutureWrapper> {
                     .call_method0(self.py, intern!(self.py, "result")),
             );
         }
-        let callback = utils::wake_callback(self.py, cx.waker().clone())?;
-        self.inner.future.call_method1(
-            self.py,
-            intern!(self.py, "add_done_callback"),
-            (callback,),
-        )?;
+        let py = self.py;
+        let inner = &mut *self.inner;
+        (inner.callback_context).register(py, &inner.future, cx.waker())?;
         Poll::Pending
     }
 }
@@ -203,29 +938,267 @@ impl Future for FutureWrapper {
     type Output = PyResult<PyObject>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("FutureWrapper");
         Python::with_gil(|gil| Pin::into_inner(self).as_mut(gil).poll_unpin(cx))
     }
 }
 
+/// Wrap the future back into a coroutine.
+///
+/// ```rust
+/// use pyo3::prelude::*;
+/// use pyo3_async::asyncio::{CancelOnDrop, Coroutine, FutureWrapper};
+///
+/// #[pyfunction]
+/// fn rewrap(future: PyObject) -> Coroutine {
+///     FutureWrapper::new(future, Some(CancelOnDrop::IgnoreError)).into()
+/// }
+/// ```
+impl From<FutureWrapper> for Coroutine {
+    fn from(value: FutureWrapper) -> Self {
+        Self::from_future(value)
+    }
+}
+
 impl Drop for FutureWrapper {
     fn drop(&mut self) {
         if let Some(cancel) = self.cancel_on_drop {
-            let res = Python::with_gil(|gil| self.future.call_method0(gil, intern!(gil, "cancel")));
-            if let (Err(err), CancelOnDrop::PanicOnError) = (res, cancel) {
-                panic!("Cancel error while dropping FutureWrapper: {err:?}");
+            Python::with_gil(|gil| {
+                let cancel_future = || {
+                    self.callback_context
+                        .remove_done_callback(gil, &self.future)?;
+                    self.future.call_method0(gil, intern!(gil, "cancel"))?;
+                    Ok(())
+                };
+                let res = utils::preserve_exception(gil, cancel_future);
+                cancel.handle_error(gil, "FutureWrapper", res);
+            });
+        }
+    }
+}
+
+/// Python async callable, e.g. an `async def` handler, callable from any Rust thread.
+///
+/// The callable is bound to the event loop running when it's wrapped, or to the one passed to
+/// [`PyAsyncCallable::with_event_loop`]. It is always called in the calling thread, with the GIL
+/// held; then, from the event loop thread, the returned awaitable is driven by an
+/// [`AwaitableWrapper`], while from another thread, e.g. in a tokio task, it is submitted to the
+/// event loop with `asyncio.run_coroutine_threadsafe`, and awaited through a [`FutureWrapper`]
+/// cancelling it on drop. In the latter case, the callable must return a coroutine.
+///
+/// # Example
+///
+/// ```rust
+/// use futures::channel::oneshot;
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{Coroutine, PyAsyncCallable};
+///
+/// #[pyfunction]
+/// fn call_twice(handler: &PyAny) -> PyResult<Coroutine> {
+///     let handler = PyAsyncCallable::new(handler)?;
+///     Ok(Coroutine::from_future(async move {
+///         // called from the event loop thread
+///         let first = handler.call((1,)).await?;
+///         // called from another thread, without blocking the event loop
+///         let (tx, rx) = oneshot::channel();
+///         std::thread::spawn(move || tx.send(futures::executor::block_on(handler.call((2,)))));
+///         let second = rx.await.unwrap()?;
+///         PyResult::Ok((first, second))
+///     }))
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("call_twice", wrap_pyfunction!(call_twice, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// async def double(i):
+///     await asyncio.sleep(0)
+///     return 2 * i
+/// async def main():
+///     return await call_twice(double)
+/// assert asyncio.run(main()) == (2, 4)
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PyAsyncCallable {
+    callable: PyObject,
+    event_loop: PyObject,
+    tasks: Py<PySet>,
+}
+
+impl PyAsyncCallable {
+    /// Wrap a Python async callable, bound to the running event loop.
+    ///
+    /// Raises `RuntimeError` if there is no running event loop.
+    pub fn new(callable: &PyAny) -> PyResult<Self> {
+        let py = callable.py();
+        let event_loop = Asyncio::get(py)?.get_running_loop.call0(py)?;
+        Self::with_event_loop(callable, event_loop.as_ref(py))
+    }
+
+    /// Wrap a Python async callable, bound to the given event loop.
+    pub fn with_event_loop(callable: &PyAny, event_loop: &PyAny) -> PyResult<Self> {
+        Ok(Self {
+            callable: callable.into(),
+            event_loop: event_loop.into(),
+            tasks: PySet::empty(callable.py())?.into(),
+        })
+    }
+
+    fn on_loop(&self, py: Python) -> PyResult<bool> {
+        let running = Asyncio::get(py)?._get_running_loop.call0(py)?;
+        Ok(compat::as_ptr(&running) == compat::as_ptr(&self.event_loop))
+    }
+
+    /// Call the callable, and return a future of its result.
+    ///
+    /// Errors raised by the call itself are returned when the future is polled.
+    pub fn call(&self, args: impl IntoPy<Py<PyTuple>>) -> PyAsyncCall {
+        let call = Python::with_gil(|py| {
+            let awaitable = self.callable.call1(py, args)?;
+            if self.on_loop(py)? {
+                return Ok(CallState::OnLoop(AwaitableWrapper::new(
+                    awaitable.as_ref(py),
+                )?));
+            }
+            let future = (Asyncio::get(py)?.run_coroutine_threadsafe)
+                .call1(py, (awaitable, &self.event_loop))?;
+            let cancel_on_drop = Some(CancelOnDrop::IgnoreError);
+            Ok(CallState::OffLoop(FutureWrapper::new(
+                future,
+                cancel_on_drop,
+            )))
+        });
+        PyAsyncCall(call.unwrap_or_else(|err| CallState::Failed(Some(err))))
+    }
+
+    /// Call the callable, and schedule the returned coroutine as an event loop task, without
+    /// waiting for it.
+    ///
+    /// The task is referenced until it's done, so it cannot be garbage collected in between; its
+    /// exception, if any, is reported by the event loop like any unretrieved task exception.
+    pub fn call_ignore_result(&self, args: impl IntoPy<Py<PyTuple>>) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let coroutine = self.callable.call1(py, args)?;
+            if self.on_loop(py)? {
+                return create_task(py, &self.event_loop, &self.tasks, coroutine);
             }
+            let event_loop = self.event_loop.clone_ref(py);
+            let tasks = self.tasks.clone_ref(py);
+            let callback = compat::new_closure(py, move |args, _| {
+                let py = args.py();
+                create_task(py, &event_loop, &tasks, coroutine.clone_ref(py))
+            })?;
+            let call_soon = intern!(py, "call_soon_threadsafe");
+            self.event_loop.call_method1(py, call_soon, (callback,))?;
+            Ok(())
+        })
+    }
+}
+
+fn create_task(
+    py: Python,
+    event_loop: &PyObject,
+    tasks: &Py<PySet>,
+    coroutine: PyObject,
+) -> PyResult<()> {
+    let task = event_loop.call_method1(py, intern!(py, "create_task"), (coroutine,))?;
+    reference_until_done(py, tasks, &task)
+}
+
+fn reference_until_done(py: Python, tasks: &Py<PySet>, task: &PyObject) -> PyResult<()> {
+    tasks.as_ref(py).add(task)?;
+    let discard = tasks.getattr(py, intern!(py, "discard"))?;
+    task.call_method1(py, intern!(py, "add_done_callback"), (discard,))?;
+    Ok(())
+}
+
+/// [`Future`] returned by [`PyAsyncCallable::call`].
+///
+/// It can be polled from any thread, but the event loop one, where it's GIL-bound like
+/// [`AwaitableWrapper`].
+pub struct PyAsyncCall(CallState);
+
+enum CallState {
+    Failed(Option<PyErr>),
+    OnLoop(AwaitableWrapper),
+    OffLoop(FutureWrapper),
+}
+
+impl Future for PyAsyncCall {
+    type Output = PyResult<PyObject>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.0 {
+            CallState::Failed(err) => {
+                Poll::Ready(Err(err.take().expect("polled after completion")))
+            }
+            CallState::OnLoop(wrapper) => wrapper.poll_unpin(cx),
+            // the concurrent future is threadsafe, so the GIL is acquired without strict checks
+            CallState::OffLoop(wrapper) => Python::with_gil(|py| wrapper.as_mut(py).poll_unpin(cx)),
         }
     }
 }
 
 /// [`Stream`] wrapper for a Python async generator (in `asyncio` context).
 ///
+/// Each `__anext__` awaitable is driven by an [`AwaitableWrapper`], so the generator can await
+/// other coroutines between its yields.
+///
 /// The stream should be polled in the thread where the event loop is running.
 ///
+/// # Example
+///
+/// ```rust
+/// use futures::TryStreamExt;
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{AsyncGeneratorWrapper, Coroutine};
+///
+/// #[pyfunction]
+/// fn collect(async_generator: &PyAny) -> Coroutine {
+///     let stream = AsyncGeneratorWrapper::new(async_generator);
+///     Coroutine::from_future(stream.try_collect::<Vec<_>>())
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("collect", wrap_pyfunction!(collect, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// async def fetch(i):
+///     loop = asyncio.get_running_loop()
+///     future = loop.create_future()
+///     loop.call_soon(future.set_exception, ValueError(i))
+///     try:
+///         await future
+///     except ValueError as err:
+///         await asyncio.sleep(0)
+///         return err.args[0] * 10
+/// async def agen():
+///     for i in range(3):
+///         yield await fetch(i)
+/// assert asyncio.run(collect(agen())) == [0, 10, 20]
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+///
 /// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
 pub struct AsyncGeneratorWrapper {
     async_generator: PyObject,
     next: Option<AwaitableWrapper>,
+    cancel_on_drop: Option<CancelOnDrop>,
+    // captured on first poll, to schedule `aclose` on drop
+    event_loop: Option<PyObject>,
+    exhausted: bool,
 }
 
 impl AsyncGeneratorWrapper {
@@ -234,9 +1207,23 @@ impl AsyncGeneratorWrapper {
         Self {
             async_generator: async_generator.into(),
             next: None,
+            cancel_on_drop: None,
+            event_loop: None,
+            exhausted: false,
         }
     }
 
+    /// Cancel the pending `__anext__`, and schedule `aclose` as a task of the event loop where
+    /// the async generator was iterated, if the wrapper is dropped before exhaustion; errors,
+    /// e.g. if the event loop is closed, are handled following the provided policy.
+    ///
+    /// By default, nothing is done on drop, the async generator being finalized by the event
+    /// loop hooks when garbage collected.
+    pub fn with_cancel_on_drop(mut self, cancel_on_drop: CancelOnDrop) -> Self {
+        self.cancel_on_drop = Some(cancel_on_drop);
+        self
+    }
+
     /// GIL-bound [`Stream`] reference.
     ///
     /// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
@@ -248,23 +1235,30 @@ impl AsyncGeneratorWrapper {
     }
 }
 
-impl<'a> Stream for utils::WithGil<'_, &'a mut AsyncGeneratorWrapper> {
+impl Stream for utils::WithGil<'_, &mut AsyncGeneratorWrapper> {
     type Item = PyResult<PyObject>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let py = self.py;
+        if self.inner.cancel_on_drop.is_some() && self.inner.event_loop.is_none() {
+            self.inner.event_loop = Some(Asyncio::get(py)?._get_running_loop.call0(py)?);
+        }
         if self.inner.next.is_none() {
             let next = self
                 .inner
                 .async_generator
-                .as_ref(self.py)
-                .call_method0(intern!(self.py, "__anext__"))?;
+                .as_ref(py)
+                .call_method0(intern!(py, "__anext__"))?;
             self.inner.next = Some(AwaitableWrapper::new(next)?);
         }
         let res = ready!(self.inner.next.as_mut().unwrap().poll_unpin(cx));
         self.inner.next = None;
         Poll::Ready(match res {
             Ok(obj) => Some(Ok(obj)),
-            Err(err) if err.is_instance_of::<PyStopAsyncIteration>(self.py) => None,
+            Err(err) if err.is_instance_of::<PyStopAsyncIteration>(py) => {
+                self.inner.exhausted = true;
+                None
+            }
             Err(err) => Some(Err(err)),
         })
     }
@@ -274,6 +1268,1153 @@ impl Stream for AsyncGeneratorWrapper {
     type Item = PyResult<PyObject>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("AsyncGeneratorWrapper");
         Python::with_gil(|gil| Pin::into_inner(self).as_mut(gil).poll_next_unpin(cx))
     }
 }
+
+impl Drop for AsyncGeneratorWrapper {
+    fn drop(&mut self) {
+        let Some(cancel) = self.cancel_on_drop.filter(|_| !self.exhausted) else {
+            return;
+        };
+        Python::with_gil(|gil| {
+            // not polled, or polled outside of an event loop
+            let Some(event_loop) = self.event_loop.take().filter(|lp| !lp.is_none(gil)) else {
+                return;
+            };
+            let schedule_aclose = || {
+                // the pending `__anext__` must be cancelled before closing the async generator
+                if let Some(mut next) = self.next.take() {
+                    next.throw_cancelled(gil)?;
+                }
+                let spawn_aclose = {
+                    let event_loop = event_loop.clone_ref(gil);
+                    let async_generator = self.async_generator.clone_ref(gil);
+                    compat::new_closure(gil, move |args, _| {
+                        let py = args.py();
+                        let aclose = async_generator.call_method0(py, intern!(py, "aclose"))?;
+                        create_task(py, &event_loop, aclose_tasks(py)?, aclose)
+                    })?
+                };
+                let call_soon = intern!(gil, "call_soon_threadsafe");
+                event_loop.call_method1(gil, call_soon, (spawn_aclose,))?;
+                Ok(())
+            };
+            let res = utils::preserve_exception(gil, schedule_aclose);
+            cancel.handle_error(gil, "AsyncGeneratorWrapper", res);
+        });
+    }
+}
+
+/// Tasks awaiting `aclose` of dropped [`AsyncGeneratorWrapper`], referenced until done.
+fn aclose_tasks(py: Python<'_>) -> PyResult<&Py<PySet>> {
+    static ACLOSE_TASKS: GILOnceCell<Py<PySet>> = GILOnceCell::new();
+    ACLOSE_TASKS.get_or_try_init(py, || Ok(PySet::empty(py)?.into()))
+}
+
+/// [`PyFuture`]/[`PyStream`] adapter for [`AwaitableWrapper`], [`FastAwaitable`],
+/// [`FutureWrapper`] and [`AsyncGeneratorWrapper`], polling them with the GIL token passed to
+/// [`PyFuture::poll_py`]/[`PyStream::poll_next_py`], instead of acquiring the GIL.
+///
+/// Wrappers implement [`Future`]/[`Stream`] by acquiring the GIL at each poll, which is
+/// redundant when they are composed in another `PyFuture`/`PyStream`, e.g. passed to
+/// [`Coroutine::from_future`]; this adapter avoids the nested acquisition.
+///
+/// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+pub struct WithGilToken<T>(pub T);
+
+impl PyFuture for WithGilToken<AwaitableWrapper> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        Pin::into_inner(self).0.as_mut(py).poll_unpin(cx)
+    }
+}
+
+impl PyFuture for WithGilToken<FutureWrapper> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        Pin::into_inner(self).0.as_mut(py).poll_unpin(cx)
+    }
+}
+
+impl PyStream for WithGilToken<AsyncGeneratorWrapper> {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        Pin::into_inner(self).0.as_mut(py).poll_next_unpin(cx)
+    }
+}
+
+/// [`PyStream`] flattening a stream of Python async generators (see
+/// [`PyStreamExt::flatten_async`](crate::PyStreamExt::flatten_async)).
+///
+/// Each async generator is iterated to exhaustion, using [`AsyncGeneratorWrapper`], before
+/// polling the next one; items which are not async generators are yielded as `TypeError`.
+///
+/// The stream should be polled in the thread where the event loop is running.
+#[pin_project]
+pub struct FlattenAsync<S> {
+    #[pin]
+    stream: S,
+    current: Option<AsyncGeneratorWrapper>,
+}
+
+impl<S> FlattenAsync<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream,
+            current: None,
+        }
+    }
+}
+
+impl<S: PyStream> PyStream for FlattenAsync<S> {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let mut this = self.project();
+        loop {
+            if let Some(current) = this.current.as_mut() {
+                if let Some(res) = ready!(current.as_mut(py).poll_next_unpin(cx)) {
+                    return Poll::Ready(Some(res));
+                }
+                *this.current = None;
+            }
+            let item = match ready!(this.stream.as_mut().poll_next_py(py, cx)) {
+                Some(Ok(item)) => item.into_ref(py),
+                res => return Poll::Ready(res),
+            };
+            if !item.hasattr(intern!(py, "__anext__"))? {
+                let msg = format!("expected an async generator, found {}", item.get_type());
+                return Poll::Ready(Some(Err(PyTypeError::new_err(msg))));
+            }
+            *this.current = Some(AsyncGeneratorWrapper::new(item));
+        }
+    }
+}
+
+/// Drain a stream of `(key, value)` pairs into a `dict`, returned by the coroutine.
+///
+/// If a key is yielded several times, the last value wins. An item which is not a 2-tuple
+/// raises `TypeError`, as well as a non-hashable key.
+pub fn collect_into_dict(stream: impl PyStream + 'static) -> Coroutine {
+    Coroutine::from_future(CollectIntoDict {
+        stream: Box::pin(stream),
+        dict: None,
+    })
+}
+
+struct CollectIntoDict {
+    stream: Pin<Box<dyn PyStream>>,
+    dict: Option<Py<PyDict>>,
+}
+
+impl PyFuture for CollectIntoDict {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        let dict = this.dict.get_or_insert_with(|| PyDict::new(py).into());
+        while let Some(item) = ready!(this.stream.as_mut().poll_next_py(py, cx)) {
+            let item = item?.into_ref(py);
+            let pair = match item.downcast::<PyTuple>() {
+                Ok(tuple) if tuple.len() == 2 => tuple,
+                _ => {
+                    let msg = format!("expected (key, value) tuple, found {}", item.repr()?);
+                    return Poll::Ready(Err(PyTypeError::new_err(msg)));
+                }
+            };
+            dict.as_ref(py)
+                .set_item(pair.get_item(0)?, pair.get_item(1)?)?;
+        }
+        Poll::Ready(Ok(this.dict.take().unwrap().into()))
+    }
+}
+
+/// Drain a Python async generator into a bounded channel, with backpressure.
+///
+/// The returned coroutine must be scheduled on the event loop, e.g. awaited or wrapped in a
+/// task; it pulls items from the async generator and sends them, exceptions included, to the
+/// receiver consumed by Rust. As with [`mpsc::channel`], at most `capacity + 1` items are
+/// buffered; the coroutine then waits for the receiver to make room before pulling the next
+/// item.
+///
+/// The coroutine completes, dropping the sender, when the async generator is exhausted, or when
+/// the receiver is dropped. Cancelling the coroutine also drops the sender, so the receiver
+/// stream terminates after the buffered items.
+pub fn drain_to_channel(
+    async_generator: &PyAny,
+    capacity: usize,
+) -> (mpsc::Receiver<PyResult<PyObject>>, Coroutine) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let drain = DrainToChannel {
+        async_generator: AsyncGeneratorWrapper::new(async_generator),
+        sender,
+    };
+    (receiver, Coroutine::from_future(drain))
+}
+
+struct DrainToChannel {
+    async_generator: AsyncGeneratorWrapper,
+    sender: mpsc::Sender<PyResult<PyObject>>,
+}
+
+impl PyFuture for DrainToChannel {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        loop {
+            // the next item is pulled only once there is room in the channel
+            if ready!(this.sender.poll_ready(cx)).is_err() {
+                return Poll::Ready(Ok(py.None()));
+            }
+            let item = ready!(this.async_generator.as_mut(py).poll_next_unpin(cx));
+            let Some(item) = item else {
+                return Poll::Ready(Ok(py.None()));
+            };
+            if this.sender.start_send(item).is_err() {
+                return Poll::Ready(Ok(py.None()));
+            }
+        }
+    }
+}
+
+/// Run a [`PyFuture`] within a Python context where the context variable `var` is set to
+/// `value`.
+///
+/// The context is copied from the one of the task awaiting the coroutine, on first poll, and
+/// entered around each poll; Python code executed by the future, e.g. an awaitable driven by
+/// [`AwaitableWrapper`], or tasks it spawns, thus see `var` set to `value`. The context of the
+/// awaiting task is left unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{with_context_var, AwaitableWrapper};
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     let code = r#"
+/// import asyncio, contextvars
+/// var = contextvars.ContextVar("var", default=None)
+/// async def get_var():
+///     await asyncio.sleep(0.001)
+///     return var.get()
+/// async def main(coroutine):
+///     return await coroutine, var.get()
+/// "#;
+///     py.run(code, Some(globals), None)?;
+///     let var = globals.get_item("var")?.unwrap();
+///     let awaitable = globals.get_item("get_var")?.unwrap().call0()?;
+///     let coroutine = with_context_var(var, 42.into_py(py), AwaitableWrapper::new(awaitable)?);
+///     globals.set_item("coroutine", Py::new(py, coroutine)?)?;
+///     let result = py.eval("asyncio.run(main(coroutine))", Some(globals), None)?;
+///     assert_eq!(result.extract::<(i32, Option<i32>)>()?, (42, None));
+///     PyResult::Ok(())
+/// })
+/// .unwrap();
+/// ```
+pub fn with_context_var(
+    var: &PyAny,
+    value: PyObject,
+    future: impl PyFuture + 'static,
+) -> Coroutine {
+    Coroutine::from_future(WithContextVar {
+        #[cfg(not(any(Py_LIMITED_API, PyPy)))]
+        future: Box::pin(future),
+        #[cfg(any(Py_LIMITED_API, PyPy))]
+        future: Arc::new(Mutex::new(PollInContext {
+            future: Box::pin(future),
+            waker: None,
+            poll: None,
+            panic: None,
+        })),
+        var: var.into(),
+        value,
+        context: None,
+        #[cfg(any(Py_LIMITED_API, PyPy))]
+        poll_in_context: None,
+    })
+}
+
+struct WithContextVar {
+    #[cfg(not(any(Py_LIMITED_API, PyPy)))]
+    future: Pin<Box<dyn PyFuture>>,
+    #[cfg(any(Py_LIMITED_API, PyPy))]
+    future: Arc<Mutex<PollInContext>>,
+    var: PyObject,
+    value: PyObject,
+    // copied on first poll, to inherit the context of the awaiting task
+    context: Option<PyObject>,
+    // callable passed to `Context.run`, polling `future` in the context
+    #[cfg(any(Py_LIMITED_API, PyPy))]
+    poll_in_context: Option<PyObject>,
+}
+
+impl WithContextVar {
+    fn context(&mut self, py: Python) -> PyResult<PyObject> {
+        if self.context.is_none() {
+            let context = Contextvars::get(py)?.copy_context.call0(py)?;
+            let set = self.var.getattr(py, intern!(py, "set"))?;
+            context.call_method1(py, intern!(py, "run"), (set, &self.value))?;
+            self.context = Some(context);
+        }
+        Ok(self.context.as_ref().unwrap().clone_ref(py))
+    }
+}
+
+/// Exit the entered context when dropped, even if the polled future panics.
+#[cfg(not(any(Py_LIMITED_API, PyPy)))]
+struct ExitContext<'a>(&'a PyAny);
+
+#[cfg(not(any(Py_LIMITED_API, PyPy)))]
+impl Drop for ExitContext<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the GIL is held, and the context has been entered by the current thread
+        unsafe { pyo3::ffi::PyContext_Exit(compat::as_ptr(self.0)) };
+    }
+}
+
+#[cfg(not(any(Py_LIMITED_API, PyPy)))]
+impl PyFuture for WithContextVar {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        let context = this.context(py)?.into_ref(py);
+        // SAFETY: the GIL is held, and `context` is a `contextvars.Context`
+        if unsafe { pyo3::ffi::PyContext_Enter(compat::as_ptr(context)) } < 0 {
+            return Poll::Ready(Err(PyErr::fetch(py)));
+        }
+        let _exit = ExitContext(context);
+        this.future.as_mut().poll_py(py, cx)
+    }
+}
+
+/// Future polled by a callable passed to `Context.run`, as `PyContext_Enter`/`PyContext_Exit`
+/// are not available with the limited API.
+#[cfg(any(Py_LIMITED_API, PyPy))]
+struct PollInContext {
+    future: Pin<Box<dyn PyFuture>>,
+    waker: Option<std::task::Waker>,
+    poll: Option<Poll<PyResult<PyObject>>>,
+    // panic of the polled future, resumed outside of `Context.run`
+    panic: Option<Box<dyn std::any::Any + Send>>,
+}
+
+#[cfg(any(Py_LIMITED_API, PyPy))]
+impl PollInContext {
+    fn poll(&mut self, py: Python) {
+        let waker = self
+            .waker
+            .take()
+            .expect("waker set before running the context");
+        let mut cx = Context::from_waker(&waker);
+        let future = self.future.as_mut();
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.poll_py(py, &mut cx)))
+        {
+            Ok(poll) => self.poll = Some(poll),
+            Err(payload) => self.panic = Some(payload),
+        }
+    }
+}
+
+#[cfg(any(Py_LIMITED_API, PyPy))]
+impl PyFuture for WithContextVar {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        if this.poll_in_context.is_none() {
+            let state = this.future.clone();
+            let poll = compat::new_closure(py, move |args, _| {
+                state.lock().unwrap().poll(args.py());
+            })?;
+            this.poll_in_context = Some(poll.into());
+        }
+        this.future.lock().unwrap().waker = Some(cx.waker().clone());
+        let context = this.context(py)?;
+        let poll_in_context = this.poll_in_context.as_ref().unwrap();
+        context.call_method1(py, intern!(py, "run"), (poll_in_context,))?;
+        let mut state = this.future.lock().unwrap();
+        if let Some(payload) = state.panic.take() {
+            drop(state);
+            std::panic::resume_unwind(payload);
+        }
+        state.poll.take().expect("future polled in the context")
+    }
+}
+
+/// Order of the results yielded by [`AsyncGenerator::map_concurrent`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapOrder {
+    /// Results are yielded in the order of the stream items.
+    Ordered,
+    /// Results are yielded as soon as they complete.
+    Completion,
+}
+
+impl AsyncGenerator {
+    /// Map the items of a stream with an async Python callable, awaiting at most `limit`
+    /// results concurrently.
+    ///
+    /// Results are awaited with [`AwaitableWrapper`]s driven by the async generator itself,
+    /// without scheduling a task per item (see [`map_tasks`] for the task-based variant); a
+    /// callable returning a non-awaitable value is supported, the value being yielded as is.
+    /// Errors, of the stream as well as of the callable, are yielded in place of the result.
+    ///
+    /// When the async generator is closed or dropped, in-flight awaitables are cancelled, i.e.
+    /// their pending future is cancelled and they are closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn map_concurrent(
+        stream: impl PyStream + 'static,
+        f: PyObject,
+        limit: usize,
+        order: MapOrder,
+    ) -> Self {
+        assert!(limit > 0, "map_concurrent limit must be positive");
+        let in_flight = match order {
+            MapOrder::Ordered => InFlight::Ordered(FuturesOrdered::new()),
+            MapOrder::Completion => InFlight::Completion(FuturesUnordered::new()),
+        };
+        Self::from_stream(MapConcurrent {
+            stream: Box::pin(stream),
+            f,
+            limit,
+            in_flight,
+            exhausted: false,
+            spawn: false,
+        })
+    }
+
+    /// Raise `asyncio.TimeoutError` from `__anext__` when the stream yields no item within
+    /// `timeout`.
+    ///
+    /// A `loop.call_later` timer is armed when the stream becomes pending, and cancelled when an
+    /// item arrives, without scheduling a task per item like `asyncio.wait_for`. The timeout is
+    /// yielded as an error of the stream, so the [error policy](Self::error_policy) decides
+    /// whether the stream survives it: with the default
+    /// [`RaiseAndContinue`](crate::ErrorPolicy::RaiseAndContinue), the next `__anext__` resumes
+    /// waiting for the same item, with a new timer; with
+    /// [`RaiseAndTerminate`](crate::ErrorPolicy::RaiseAndTerminate), the stream is dropped.
+    ///
+    /// The timer is not reset by a cancelled `__anext__`, the next one inheriting the remaining
+    /// time, as the timeout applies to the interval between items.
+    pub fn item_timeout(mut self, timeout: Duration) -> Self {
+        self.0.map_stream(|stream| {
+            Box::pin(ItemTimeout {
+                stream,
+                timeout: timeout.as_secs_f64(),
+                timer: None,
+            })
+        });
+        self
+    }
+}
+
+/// Timer armed by [`ItemTimeout`], with the waker of the last poll.
+struct ItemTimer {
+    handle: PyObject,
+    state: Arc<Mutex<(bool, std::task::Waker)>>,
+}
+
+/// [`PyStreamClose`] yielding `asyncio.TimeoutError` when no item arrives within the timeout
+/// (see [`AsyncGenerator::item_timeout`]).
+struct ItemTimeout {
+    stream: Pin<Box<dyn PyStreamClose>>,
+    timeout: f64,
+    timer: Option<ItemTimer>,
+}
+
+impl ItemTimeout {
+    fn cancel_timer(&mut self, py: Python) -> PyResult<()> {
+        if let Some(timer) = self.timer.take() {
+            timer.handle.call_method0(py, intern!(py, "cancel"))?;
+        }
+        Ok(())
+    }
+}
+
+impl PyStream for ItemTimeout {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = Pin::into_inner(self);
+        if let Poll::Ready(item) = this.stream.as_mut().poll_next_py(py, cx) {
+            this.cancel_timer(py)?;
+            return Poll::Ready(item);
+        }
+        match &this.timer {
+            Some(timer) => {
+                let mut state = timer.state.lock().unwrap();
+                let (fired, waker) = &mut *state;
+                if !*fired {
+                    waker.clone_from(cx.waker());
+                    return Poll::Pending;
+                }
+            }
+            None => {
+                let state = Arc::new(Mutex::new((false, cx.waker().clone())));
+                let fire = state.clone();
+                let callback = compat::new_closure(py, move |_, _| {
+                    let mut state = fire.lock().unwrap();
+                    state.0 = true;
+                    state.1.wake_by_ref();
+                })?;
+                let handle = Asyncio::get(py)?.get_running_loop.call0(py)?.call_method1(
+                    py,
+                    intern!(py, "call_later"),
+                    (this.timeout, callback),
+                )?;
+                this.timer = Some(ItemTimer { handle, state });
+                return Poll::Pending;
+            }
+        }
+        this.timer = None;
+        let timeout_error = Asyncio::get(py)?.TimeoutError.call0(py)?;
+        Poll::Ready(Some(Err(PyErr::from_value(timeout_error.as_ref(py)))))
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        // each timeout is yielded as an additional item
+        (self.stream.size_hint_py().0, None)
+    }
+
+    fn throw_py(mut self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.stream.as_mut().throw_py(py, exc)
+    }
+}
+
+impl PyStreamClose for ItemTimeout {
+    fn poll_close_py(mut self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<()>> {
+        self.cancel_timer(py)?;
+        self.stream.as_mut().poll_close_py(py, cx)
+    }
+}
+
+impl Drop for ItemTimeout {
+    fn drop(&mut self) {
+        if self.timer.is_some() {
+            Python::with_gil(|gil| {
+                // the event loop may be closed
+                utils::preserve_exception(gil, || self.cancel_timer(gil)).ok();
+            });
+        }
+    }
+}
+
+/// Result of the mapping of an item, cancelled if dropped before completion.
+enum Mapped {
+    Awaiting(Option<AwaitableWrapper>),
+    Task(FutureWrapper),
+    Ready(Option<PyResult<PyObject>>),
+}
+
+impl Mapped {
+    fn new(py: Python, f: &PyObject, item: PyResult<PyObject>, spawn: bool) -> Self {
+        let call = || {
+            let res = f.call1(py, (item?,))?;
+            if !res.as_ref(py).hasattr(intern!(py, "__await__"))? {
+                return Ok(Self::Ready(Some(Ok(res))));
+            }
+            if spawn {
+                let task = Asyncio::get(py)?.ensure_future.call1(py, (res,))?;
+                let cancel_on_drop = Some(CancelOnDrop::IgnoreError);
+                return Ok(Self::Task(FutureWrapper::new(task, cancel_on_drop)));
+            }
+            Ok(Self::Awaiting(Some(AwaitableWrapper::new(res.as_ref(py))?)))
+        };
+        call().unwrap_or_else(|err| Self::Ready(Some(Err(err))))
+    }
+}
+
+impl Future for Mapped {
+    type Output = PyResult<PyObject>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::into_inner(self) {
+            Self::Awaiting(awaitable) => {
+                let res = ready!(awaitable.as_mut().unwrap().poll_unpin(cx));
+                *awaitable = None;
+                Poll::Ready(res)
+            }
+            Self::Task(task) => task.poll_unpin(cx),
+            Self::Ready(res) => Poll::Ready(res.take().unwrap()),
+        }
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        if let Self::Awaiting(Some(awaitable)) = self {
+            Python::with_gil(|gil| {
+                let cancel = || awaitable.cancel(gil);
+                // the event loop may be closed
+                utils::preserve_exception(gil, cancel).ok();
+            });
+        }
+    }
+}
+
+enum InFlight {
+    Ordered(FuturesOrdered<Mapped>),
+    Completion(FuturesUnordered<Mapped>),
+}
+
+struct MapConcurrent {
+    stream: Pin<Box<dyn PyStream>>,
+    f: PyObject,
+    limit: usize,
+    in_flight: InFlight,
+    exhausted: bool,
+    spawn: bool,
+}
+
+impl PyStream for MapConcurrent {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = Pin::into_inner(self);
+        let in_flight_len = |in_flight: &InFlight| match in_flight {
+            InFlight::Ordered(futures) => futures.len(),
+            InFlight::Completion(futures) => futures.len(),
+        };
+        while !this.exhausted && in_flight_len(&this.in_flight) < this.limit {
+            let Poll::Ready(item) = this.stream.as_mut().poll_next_py(py, cx) else {
+                break;
+            };
+            let Some(item) = item else {
+                this.exhausted = true;
+                break;
+            };
+            let mapped = Mapped::new(py, &this.f, item, this.spawn);
+            match &mut this.in_flight {
+                InFlight::Ordered(futures) => futures.push_back(mapped),
+                InFlight::Completion(futures) => futures.push(mapped),
+            }
+        }
+        let res = match &mut this.in_flight {
+            InFlight::Ordered(futures) => ready!(futures.poll_next_unpin(cx)),
+            InFlight::Completion(futures) => ready!(futures.poll_next_unpin(cx)),
+        };
+        match res {
+            Some(res) => Poll::Ready(Some(res)),
+            None if this.exhausted => Poll::Ready(None),
+            // nothing in flight, the stream is pending
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Map `items` with an async Python callable, running at most `concurrency` tasks concurrently,
+/// and yield the results as they complete.
+///
+/// This is `asyncio.as_completed` with bounded concurrency: contrary to
+/// [`AsyncGenerator::map_concurrent`], each awaitable is scheduled as a task with
+/// `asyncio.ensure_future`, so in-flight items make progress even while the async generator is
+/// not iterated. Errors of the callable are yielded in place of the result, the iteration going
+/// on with the remaining items.
+///
+/// When the async generator is closed or dropped, in-flight tasks are cancelled.
+///
+/// # Panics
+///
+/// Panics if `concurrency` is zero.
+pub fn map_tasks(items: Vec<PyObject>, f: PyObject, concurrency: usize) -> AsyncGenerator {
+    assert!(concurrency > 0, "map_tasks concurrency must be positive");
+    AsyncGenerator::from_stream(MapConcurrent {
+        stream: Box::pin(futures::stream::iter(items.into_iter().map(PyResult::Ok))),
+        f,
+        limit: concurrency,
+        in_flight: InFlight::Completion(FuturesUnordered::new()),
+        exhausted: false,
+        spawn: true,
+    })
+}
+
+/// Awaitable re-running a Rust future factory each time it's awaited.
+///
+/// Each `__await__` builds a new future with the factory, driven by a new [`Coroutine`], like
+/// calling an async function again, instead of raising "cannot reuse already awaited
+/// coroutine". It intentionally breaks the single-await contract of coroutines, so it's not a
+/// coroutine itself, i.e. it has no `send`/`throw`/`close`; concurrent awaits run independent
+/// futures.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::{
+///     atomic::{AtomicUsize, Ordering},
+///     Arc,
+/// };
+///
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::ReusableCoroutine;
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let runs = Arc::new(AtomicUsize::new(0));
+///     let counter = runs.clone();
+///     let reusable = ReusableCoroutine::new(move || {
+///         let counter = counter.clone();
+///         async move { PyResult::Ok(counter.fetch_add(1, Ordering::Relaxed) + 1) }
+///     });
+///     let globals = PyDict::new(py);
+///     globals.set_item("reusable", Py::new(py, reusable)?)?;
+///     let code = r#"
+/// import asyncio
+/// async def main():
+///     return [await reusable, await reusable]
+/// assert asyncio.run(main()) == [1, 2]
+/// "#;
+///     py.run(code, Some(globals), None)?;
+///     assert_eq!(runs.load(Ordering::Relaxed), 2);
+///     PyResult::Ok(())
+/// })
+/// .unwrap();
+/// ```
+#[pyclass]
+pub struct ReusableCoroutine(Box<dyn FnMut() -> Pin<Box<dyn PyFuture>> + Send>);
+
+impl ReusableCoroutine {
+    /// Wrap a future factory, called at each await.
+    pub fn new<F: PyFuture + 'static>(mut factory: impl FnMut() -> F + Send + 'static) -> Self {
+        Self(Box::new(move || Box::pin(factory())))
+    }
+}
+
+#[pymethods]
+impl ReusableCoroutine {
+    fn __await__(&mut self) -> Coroutine {
+        Coroutine::new((self.0)(), None)
+    }
+}
+
+/// Race a Rust future against a Python awaitable, resolving with a `(winner, result)` tuple,
+/// `winner` being `"rust"` or `"python"`.
+///
+/// Both sides are polled by the same coroutine, the awaitable being driven by an
+/// [`AwaitableWrapper`]. The first one to complete, successfully or not, wins; the loser is
+/// dropped, for the Rust future, or cancelled, for the Python awaitable, i.e. its pending future
+/// is cancelled and it is closed. Both are also dropped/cancelled if the coroutine is.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio;
+///
+/// #[pyfunction]
+/// fn fallback(awaitable: &PyAny) -> PyResult<asyncio::Coroutine> {
+///     asyncio::race(futures::future::pending::<PyResult<()>>(), awaitable)
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("fallback", wrap_pyfunction!(fallback, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// assert asyncio.run(fallback(asyncio.sleep(0.001, 42))) == ("python", 42)
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn race(future: impl PyFuture + 'static, awaitable: &PyAny) -> PyResult<Coroutine> {
+    Ok(Coroutine::from_future(Race {
+        future: Box::pin(future),
+        awaitable: AwaitableWrapper::new(awaitable)?,
+    }))
+}
+
+struct Race {
+    future: Pin<Box<dyn PyFuture>>,
+    awaitable: AwaitableWrapper,
+}
+
+impl PyFuture for Race {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        let (winner, res) = match this.future.as_mut().poll_py(py, cx) {
+            Poll::Ready(res) => ("rust", res),
+            Poll::Pending => ("python", ready!(this.awaitable.as_mut(py).poll_unpin(cx))),
+        };
+        Poll::Ready(res.map(|res| (winner, res).into_py(py)))
+    }
+}
+
+impl Drop for Race {
+    fn drop(&mut self) {
+        Python::with_gil(|gil| {
+            // no-op if the awaitable has completed
+            let cancel = || self.awaitable.cancel(gil);
+            // the event loop may be closed
+            utils::preserve_exception(gil, cancel).ok();
+        });
+    }
+}
+
+/// Apply a timeout to a [`PyFuture`], measured by the event loop clock.
+///
+/// The future is raced against `asyncio.sleep(seconds)`, driven by an [`AwaitableWrapper`];
+/// `asyncio.TimeoutError` is raised if the sleep completes first.
+///
+/// Contrary to a Rust timer (e.g. `tokio::time::timeout`), the timeout follows the event loop
+/// notion of time, so it stays deterministic when the loop clock is mocked, for example in test
+/// suites. On the other hand, it must be polled in the thread where the event loop is running.
+pub fn timeout_loop<F: PyFuture>(future: F, seconds: f64) -> TimeoutLoop<F> {
+    TimeoutLoop {
+        future,
+        seconds,
+        sleep: None,
+    }
+}
+
+/// [`PyFuture`] returned by [`timeout_loop`].
+#[pin_project]
+pub struct TimeoutLoop<F> {
+    #[pin]
+    future: F,
+    seconds: f64,
+    sleep: Option<AwaitableWrapper>,
+}
+
+impl<F: PyFuture> PyFuture for TimeoutLoop<F> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = self.project();
+        if let Poll::Ready(res) = this.future.poll_py(py, cx) {
+            return Poll::Ready(res);
+        }
+        if this.sleep.is_none() {
+            let sleep = Asyncio::get(py)?.sleep.call1(py, (*this.seconds,))?;
+            *this.sleep = Some(AwaitableWrapper::new(sleep.as_ref(py))?);
+        }
+        ready!(this.sleep.as_mut().unwrap().as_mut(py).poll_unpin(cx))?;
+        let timeout_error = Asyncio::get(py)?.TimeoutError.call0(py)?;
+        Poll::Ready(Err(PyErr::from_value(timeout_error.as_ref(py))))
+    }
+}
+
+struct WaitFor {
+    awaitable: AwaitableWrapper,
+    timeout: Option<TimeoutLoop<futures::future::Pending<PyResult<()>>>>,
+}
+
+impl PyFuture for WaitFor {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = Pin::into_inner(self);
+        if let Poll::Ready(res) = this.awaitable.as_mut(py).poll_unpin(cx) {
+            return Poll::Ready(res);
+        }
+        let Some(timeout) = this.timeout.as_mut() else {
+            return Poll::Pending;
+        };
+        let err = ready!(Pin::new(timeout).poll_py(py, cx)).unwrap_err();
+        this.awaitable.cancel(py)?;
+        Poll::Ready(Err(err))
+    }
+}
+
+/// Strategy of [`wait_threading_event`].
+#[derive(Debug, Copy, Clone)]
+pub enum EventWait {
+    /// Check `event.is_set()` at each interval, sleeping with `asyncio.sleep` in between.
+    Poll(Duration),
+    /// Call the blocking `event.wait(timeout)` in the default executor of the event loop, with
+    /// `loop.run_in_executor`, the wait being resubmitted every second until the event is set.
+    Executor,
+}
+
+// timeout of each blocking `event.wait` of `EventWait::Executor`, bounding how long an executor
+// thread stays blocked after the future is dropped
+const EXECUTOR_WAIT_SLICE: Duration = Duration::from_secs(1);
+
+/// Wait for a `threading.Event` to be set by another thread, without blocking the event loop.
+///
+/// With [`EventWait::Poll`], no thread is involved, but the wait costs an event loop timer per
+/// interval, and the completion is delayed by up to one interval after the event is set; a
+/// short interval is responsive, a long one is cheap. With [`EventWait::Executor`], the
+/// completion is immediate, but an executor thread is blocked for the whole wait, and the
+/// default executor has a limited number of them. As `event.wait` cannot be interrupted, a
+/// thread is still blocked up to a second after the future is dropped, which also
+/// delays `loop.shutdown_default_executor`, e.g. at the end of `asyncio.run`.
+///
+/// The event is checked on first poll, so an already set event completes without sleeping nor
+/// offloading. The future must be polled in the thread where the event loop is running.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{self, EventWait};
+///
+/// #[pyfunction]
+/// fn wait_event(event: &PyAny, executor: bool) -> asyncio::Coroutine {
+///     let wait = match executor {
+///         true => EventWait::Executor,
+///         false => EventWait::Poll(Duration::from_millis(1)),
+///     };
+///     asyncio::Coroutine::from_future(asyncio::wait_threading_event(event, wait))
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("wait_event", wrap_pyfunction!(wait_event, py)?)?;
+///     let code = r#"
+/// import asyncio, threading
+/// async def main(executor):
+///     event = threading.Event()
+///     threading.Timer(0.01, event.set).start()
+///     await wait_event(event, executor)
+///     assert event.is_set()
+/// asyncio.run(main(False))
+/// asyncio.run(main(True))
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn wait_threading_event(event: &PyAny, wait: EventWait) -> WaitThreadingEvent {
+    WaitThreadingEvent {
+        event: event.into(),
+        wait,
+        pending: None,
+    }
+}
+
+/// [`Future`] returned by [`wait_threading_event`].
+pub struct WaitThreadingEvent {
+    event: PyObject,
+    wait: EventWait,
+    // sleep between two checks, or executor future
+    pending: Option<Pending>,
+}
+
+enum Pending {
+    Sleep(AwaitableWrapper),
+    Executor(FutureWrapper),
+}
+
+impl WaitThreadingEvent {
+    fn poll_gil(&mut self, py: Python, cx: &mut Context) -> Poll<PyResult<()>> {
+        loop {
+            match &mut self.pending {
+                Some(Pending::Sleep(sleep)) => {
+                    ready!(sleep.as_mut(py).poll_unpin(cx))?;
+                }
+                // the wait may have timed out, the event is checked again
+                Some(Pending::Executor(wait)) => {
+                    ready!(wait.as_mut(py).poll_unpin(cx))?;
+                }
+                None => {}
+            }
+            let is_set = self.event.call_method0(py, intern!(py, "is_set"))?;
+            if compat::is_true(py, &is_set)? {
+                return Poll::Ready(Ok(()));
+            }
+            self.pending = Some(match self.wait {
+                EventWait::Poll(interval) => {
+                    let sleep = Asyncio::get(py)?
+                        .sleep
+                        .call1(py, (interval.as_secs_f64(),))?;
+                    let sleep = AwaitableWrapper::new(sleep.as_ref(py))?;
+                    Pending::Sleep(sleep.with_cancel_on_drop(CancelOnDrop::IgnoreError))
+                }
+                EventWait::Executor => {
+                    let event_loop = Asyncio::get(py)?.get_running_loop.call0(py)?;
+                    let wait = self.event.getattr(py, intern!(py, "wait"))?;
+                    let run_in_executor = intern!(py, "run_in_executor");
+                    let timeout = EXECUTOR_WAIT_SLICE.as_secs_f64();
+                    let args = (py.None(), wait, timeout);
+                    let future = event_loop.call_method1(py, run_in_executor, args)?;
+                    Pending::Executor(FutureWrapper::new(future, Some(CancelOnDrop::IgnoreError)))
+                }
+            });
+        }
+    }
+}
+
+impl Future for WaitThreadingEvent {
+    type Output = PyResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Python::with_gil(|gil| Pin::into_inner(self).poll_gil(gil, cx))
+    }
+}
+
+const NATIVE: &str = r#"
+async def native(awaitable):
+    return await awaitable
+"#;
+
+/// Wrap a [`Coroutine`] into a native Python coroutine, i.e. an `async def` coroutine object.
+///
+/// [`Coroutine`] implements the coroutine protocol, so it is accepted by `asyncio` and most
+/// libraries; registering its type with `collections.abc.Coroutine.register` also makes it pass
+/// `isinstance` checks, and `asyncio.iscoroutine`, hence `asyncio.run`, accepts it. However,
+/// `inspect.iscoroutine` only accepts native coroutines, as does code checking
+/// `types.CoroutineType`; this function is an escape hatch for such strict callers, at the cost
+/// of an additional frame per step. Conversely,
+/// native coroutines can be awaited in Rust with [`AwaitableWrapper`].
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio;
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let coroutine = asyncio::Coroutine::from_future(async { PyResult::Ok(42) });
+///     let globals = PyDict::new(py);
+///     globals.set_item("native", asyncio::to_native_coroutine(py, coroutine)?)?;
+///     let code = r#"
+/// import asyncio, inspect
+/// assert inspect.iscoroutine(native)
+/// assert asyncio.run(native) == 42
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn to_native_coroutine(py: Python, coroutine: Coroutine) -> PyResult<PyObject> {
+    static NATIVE_FN: GILOnceCell<PyObject> = GILOnceCell::new();
+    let native = NATIVE_FN.get_or_try_init(py, || {
+        let module = PyModule::from_code(py, NATIVE, "pyo3_async_native.py", "pyo3_async_native")?;
+        PyResult::Ok(module.getattr(intern!(py, "native"))?.into())
+    })?;
+    native.call1(py, (Py::new(py, coroutine)?,))
+}
+
+/// Context of a task spawned by [`spawn`] or [`spawn_in_task_group`].
+#[derive(Debug, Default)]
+pub enum TaskContext {
+    /// Share the context of the current task, so context variables set by the spawned task are
+    /// visible to the current one; it requires Python 3.12 (`Task.get_context`), and falls back
+    /// to [`TaskContext::Copy`] otherwise, or outside of a task.
+    Inherit,
+    /// Run in a copy of the current context, like `asyncio.create_task` does by default.
+    #[default]
+    Copy,
+    /// Run in the given `contextvars.Context`; before Python 3.11, whose `create_task` has no
+    /// `context` parameter, the task runs in a copy of it instead, so the context variables set
+    /// by the task are not visible in the given context.
+    Explicit(PyObject),
+}
+
+impl TaskContext {
+    fn resolve(self, py: Python) -> PyResult<Option<PyObject>> {
+        match self {
+            Self::Inherit => {
+                let task = Asyncio::get(py)?.current_task.call0(py)?;
+                let get_context = intern!(py, "get_context");
+                if task.is_none(py) || !task.as_ref(py).hasattr(get_context)? {
+                    return Ok(None);
+                }
+                Ok(Some(task.call_method0(py, get_context)?))
+            }
+            Self::Copy => Ok(None),
+            Self::Explicit(context) => Ok(Some(context)),
+        }
+    }
+}
+
+/// Call `create_task(coroutine, name=name, context=context)` of an event loop or a task group.
+fn create_named_task(
+    py: Python,
+    spawner: &PyAny,
+    future: impl PyFuture + 'static,
+    name: Option<&str>,
+    context: TaskContext,
+) -> PyResult<PyObject> {
+    let coroutine = Py::new(py, Coroutine::from_future(future))?;
+    let kwargs = PyDict::new(py);
+    if let Some(name) = name {
+        kwargs.set_item(intern!(py, "name"), name)?;
+    }
+    let create_task = spawner.getattr(intern!(py, "create_task"))?;
+    let task = match context.resolve(py)? {
+        Some(context) if py.version_info() < (3, 11) => {
+            // `context` parameter is not supported, but the task copies the current context
+            let args = (create_task, coroutine);
+            context.call_method(py, intern!(py, "run"), args, Some(kwargs))?
+        }
+        Some(context) => {
+            kwargs.set_item(intern!(py, "context"), context)?;
+            create_task.call((coroutine,), Some(kwargs))?.into()
+        }
+        None => create_task.call((coroutine,), Some(kwargs))?.into(),
+    };
+    Ok(task)
+}
+
+/// Spawn a [`PyFuture`] as a task of the running event loop, with an optional name and the
+/// given context, e.g. to keep supervision trees and structured logging navigable.
+///
+/// The task is returned, and referenced until it's done, so it cannot be garbage collected in
+/// between. Raises `RuntimeError` if there is no running event loop.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::asyncio::{self, TaskContext};
+///
+/// #[pyfunction]
+/// fn spawn_answer(py: Python) -> PyResult<PyObject> {
+///     let future = async { PyResult::Ok(42) };
+///     asyncio::spawn(py, future, Some("answer"), TaskContext::Copy)
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("spawn_answer", wrap_pyfunction!(spawn_answer, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// async def main():
+///     task = spawn_answer()
+///     assert task.get_name() == "answer"
+///     return await task
+/// assert asyncio.run(main()) == 42
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn spawn(
+    py: Python,
+    future: impl PyFuture + 'static,
+    name: Option<&str>,
+    context: TaskContext,
+) -> PyResult<PyObject> {
+    static SPAWNED_TASKS: GILOnceCell<Py<PySet>> = GILOnceCell::new();
+    let tasks = SPAWNED_TASKS.get_or_try_init(py, || PyResult::Ok(PySet::empty(py)?.into()))?;
+    let event_loop = Asyncio::get(py)?.get_running_loop.call0(py)?;
+    let task = create_named_task(py, event_loop.as_ref(py), future, name, context)?;
+    reference_until_done(py, tasks, &task)?;
+    Ok(task)
+}
+
+/// Spawn a [`PyFuture`] in an `asyncio.TaskGroup` (Python 3.11+), with an optional name and the
+/// given context; the task is returned.
+///
+/// Raises `RuntimeError` if the task group has not been entered, or is shutting down.
+pub fn spawn_in_task_group(
+    task_group: &PyAny,
+    future: impl PyFuture + 'static,
+    name: Option<&str>,
+    context: TaskContext,
+) -> PyResult<PyObject> {
+    create_named_task(task_group.py(), task_group, future, name, context)
+}
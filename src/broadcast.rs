@@ -0,0 +1,162 @@
+//! Fan-out of a single stream to several subscribers (see
+//! [`asyncio::AsyncGenerator::broadcast`](crate::asyncio::AsyncGenerator::broadcast)).
+//!
+//! The stream is shared behind a mutex, and polled by whichever subscriber has consumed all the
+//! buffered items; each item is pushed to a bounded buffer, from which the other subscribers
+//! clone it, the oldest items being dropped when it's full (see [`LagPolicy`]).
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::PyStream;
+
+/// Policy applied to a broadcast subscriber lagging behind the buffer (see
+/// [`asyncio::AsyncGenerator::broadcast`](crate::asyncio::AsyncGenerator::broadcast)).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Raise a `RuntimeError` from the lagging subscriber, which then resumes with the oldest
+    /// buffered item.
+    Error,
+    /// Skip the missed items, resuming with the oldest buffered item.
+    Skip,
+}
+
+struct State {
+    stream: Option<Pin<Box<dyn PyStream>>>,
+    buffer: VecDeque<PyResult<PyObject>>,
+    capacity: usize,
+    // index of the first buffered item
+    offset: u64,
+    wakers: Vec<Waker>,
+}
+
+impl State {
+    fn wake_all(&mut self) {
+        self.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+pub(crate) struct Broadcast {
+    state: Arc<Mutex<State>>,
+    lag: LagPolicy,
+}
+
+impl Broadcast {
+    pub(crate) fn new(stream: Pin<Box<dyn PyStream>>, capacity: usize, lag: LagPolicy) -> Self {
+        let state = State {
+            stream: Some(stream),
+            buffer: VecDeque::new(),
+            capacity: capacity.max(1),
+            offset: 0,
+            wakers: Vec::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            lag,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Subscriber {
+        let state = self.state.lock().unwrap();
+        Subscriber {
+            state: self.state.clone(),
+            next: state.offset + state.buffer.len() as u64,
+            lag: self.lag,
+        }
+    }
+
+    /// Consume the stream, discarding the items, so that they are buffered for subscribers.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn drive(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut driver = self.subscribe();
+        driver.lag = LagPolicy::Skip;
+        futures::future::poll_fn(move |cx| loop {
+            let poll = Python::with_gil(|py| Pin::new(&mut driver).poll_next_py(py, cx));
+            match poll {
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        })
+    }
+}
+
+/// Stream of a broadcast subscriber, which polls the shared stream when it has consumed all the
+/// buffered items.
+pub(crate) struct Subscriber {
+    state: Arc<Mutex<State>>,
+    next: u64,
+    lag: LagPolicy,
+}
+
+fn clone_item(py: Python, item: &PyResult<PyObject>) -> PyResult<PyObject> {
+    match item {
+        Ok(obj) => Ok(obj.clone_ref(py)),
+        Err(err) => Err(err.clone_ref(py)),
+    }
+}
+
+impl PyStream for Subscriber {
+    fn poll_next_py(
+        mut self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = &mut *self;
+        let mut state = this.state.lock().unwrap();
+        if this.next < state.offset {
+            let lagged = state.offset - this.next;
+            this.next = state.offset;
+            if this.lag == LagPolicy::Error {
+                let msg = format!("broadcast subscriber lagged behind by {lagged} items");
+                return Poll::Ready(Some(Err(PyRuntimeError::new_err(msg))));
+            }
+        }
+        if let Some(item) = state.buffer.get((this.next - state.offset) as usize) {
+            this.next += 1;
+            return Poll::Ready(Some(clone_item(py, item)));
+        }
+        let Some(stream) = state.stream.as_mut() else {
+            return Poll::Ready(None);
+        };
+        match stream.as_mut().poll_next_py(py, cx) {
+            Poll::Ready(Some(item)) => {
+                let res = clone_item(py, &item);
+                if state.buffer.len() == state.capacity {
+                    state.buffer.pop_front();
+                    state.offset += 1;
+                }
+                state.buffer.push_back(item);
+                state.wake_all();
+                this.next += 1;
+                Poll::Ready(Some(res))
+            }
+            Poll::Ready(None) => {
+                state.stream = None;
+                state.wake_all();
+                Poll::Ready(None)
+            }
+            Poll::Pending => {
+                // the stream only wakes its last poller, which then wakes the other subscribers
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        // the dropped subscriber may have been the last poller of the stream
+        if let Ok(mut state) = self.state.lock() {
+            state.wake_all();
+        }
+    }
+}
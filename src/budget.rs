@@ -0,0 +1,176 @@
+//! Cooperative scheduling of long-running futures.
+//!
+//! A future doing CPU work between its await points only returns `Pending` when it actually
+//! waits for something; the event loop is starved in the meantime, even if the GIL is released
+//! with [`AllowThreads`](crate::AllowThreads). [`YieldEvery`] assigns a poll-time budget to a
+//! future, and [`checkpoint`] forces a reschedule when the budget of the enclosing
+//! [`YieldEvery`] is exhausted.
+//!
+//! Conversely, a future returning `Pending` without registering its waker is never resumed;
+//! [`PollLoop`] reschedules it a bounded number of times.
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use futures::task::ArcWake;
+use pin_project::pin_project;
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::{coroutine, PyFuture};
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Future assigning a poll-time budget to its inner future.
+///
+/// Each poll starts a new budget, which the inner future consumes until it reaches a
+/// [`checkpoint`] after the budget is exhausted; it then returns `Pending` and is immediately
+/// woken, so the coroutine yields to the event loop before resuming.
+///
+/// Can be instantiated with [`PyFutureExt::yield_every`](crate::PyFutureExt::yield_every).
+#[derive(Debug)]
+#[pin_project]
+pub struct YieldEvery<F> {
+    #[pin]
+    future: F,
+    budget: Duration,
+}
+
+impl<F> YieldEvery<F> {
+    pub(crate) fn new(future: F, budget: Duration) -> Self {
+        Self { future, budget }
+    }
+}
+
+impl<F: Future> Future for YieldEvery<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let deadline = Instant::now() + *this.budget;
+        // restore the budget of an enclosing `YieldEvery`, even if polling panics
+        struct Restore(Option<Instant>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                DEADLINE.with(|d| d.set(self.0));
+            }
+        }
+        let _restore = Restore(DEADLINE.with(|d| d.replace(Some(deadline))));
+        this.future.poll(cx)
+    }
+}
+
+/// Yield to the event loop if the budget of the enclosing [`YieldEvery`] is exhausted.
+///
+/// Completes immediately outside of [`YieldEvery`], or when there is remaining budget. It should
+/// be awaited regularly in CPU-bound loops.
+pub fn checkpoint() -> Checkpoint {
+    Checkpoint { yielded: false }
+}
+
+/// Future returned by [`checkpoint`].
+#[derive(Debug)]
+pub struct Checkpoint {
+    yielded: bool,
+}
+
+impl Future for Checkpoint {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let exhausted = DEADLINE.with(|d| d.get().is_some_and(|d| Instant::now() >= d));
+        if self.yielded || !exhausted {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        coroutine::checkpoint(cx);
+        Poll::Pending
+    }
+}
+
+struct SpinWaker {
+    waker: Waker,
+    woken: AtomicBool,
+}
+
+impl ArcWake for SpinWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::Relaxed);
+        arc_self.waker.wake_by_ref();
+    }
+}
+
+/// Future rescheduling its inner future when it returns `Pending` without registering the
+/// waker, i.e. spins.
+///
+/// A spin is detected when the waker is neither woken during the poll nor retained by the inner
+/// future. The coroutine is then woken immediately, yielding to the event loop before polling
+/// again, at most `max_spins` consecutive times; the next spin raises `RuntimeError`.
+///
+/// It is a workaround for futures with dubious waker hygiene, not a substitute for proper
+/// wakers: each spin costs an event loop iteration, as busy-waiting does.
+///
+/// Can be instantiated with [`PyFutureExt::poll_loop`](crate::PyFutureExt::poll_loop).
+#[pin_project]
+pub struct PollLoop<F> {
+    #[pin]
+    future: F,
+    max_spins: usize,
+    spins: usize,
+    waker: Option<Arc<SpinWaker>>,
+}
+
+impl<F> PollLoop<F> {
+    pub(crate) fn new(future: F, max_spins: usize) -> Self {
+        Self {
+            future,
+            max_spins,
+            spins: 0,
+            waker: None,
+        }
+    }
+}
+
+impl<F: PyFuture> PyFuture for PollLoop<F> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = self.project();
+        // the waker is reused as long as the coroutine one doesn't change, so the inner future
+        // can keep the clone registered at a previous poll
+        let spin_waker = match this.waker {
+            Some(w) if w.waker.will_wake(cx.waker()) => w,
+            w => w.insert(Arc::new(SpinWaker {
+                waker: cx.waker().clone(),
+                woken: AtomicBool::new(false),
+            })),
+        };
+        spin_waker.woken.store(false, Ordering::Relaxed);
+        let waker = futures::task::waker(spin_waker.clone());
+        let poll = this.future.poll_py(py, &mut Context::from_waker(&waker));
+        drop(waker);
+        if poll.is_ready()
+            || spin_waker.woken.load(Ordering::Relaxed)
+            || Arc::strong_count(spin_waker) > 1
+        {
+            *this.spins = 0;
+            return poll;
+        }
+        if *this.spins == *this.max_spins {
+            return Poll::Ready(Err(PyRuntimeError::new_err(format!(
+                "future returned Pending without registering its waker {} consecutive times",
+                *this.spins + 1
+            ))));
+        }
+        *this.spins += 1;
+        coroutine::checkpoint(cx);
+        Poll::Pending
+    }
+}
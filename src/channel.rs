@@ -0,0 +1,27 @@
+//! Adapters exposing `tokio` channels as Python async iterables.
+use futures::Stream;
+use pyo3::prelude::*;
+use tokio::sync::watch;
+
+/// Wrap a [`watch::Receiver`] into a stream yielding only the latest value.
+///
+/// Each item awaits a change notification and yields the current value, so intermediate updates
+/// are coalesced instead of being buffered. If `initial` is true, the current value is yielded
+/// immediately as first item. The stream terminates when the sender is dropped.
+///
+/// The stream can then be exposed to Python using `AsyncGenerator::from_stream`.
+pub fn watch_stream<T>(
+    receiver: watch::Receiver<T>,
+    initial: bool,
+) -> impl Stream<Item = PyResult<T>> + Send
+where
+    T: Clone + Send + Sync + 'static,
+{
+    futures::stream::unfold((receiver, initial), |(mut receiver, initial)| async move {
+        if !initial {
+            receiver.changed().await.ok()?;
+        }
+        let value = receiver.borrow_and_update().clone();
+        Some((Ok(value), (receiver, false)))
+    })
+}
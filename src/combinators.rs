@@ -0,0 +1,158 @@
+//! Concurrency combinators for [`PyFuture`]s.
+//!
+//! Contrary to the combinators of the `futures` crate, the futures whose result is not needed
+//! anymore are dropped in the poll deciding it, not when the combined future is dropped: the
+//! loser of a [`select`], or the other future of a [`join`] short-circuited by an error,
+//! releases its resources right away, even if the combined future is kept, e.g. by an `async`
+//! block awaiting it in a `loop`.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project::pin_project;
+use pyo3::prelude::*;
+
+use crate::PyFuture;
+
+/// Future running two futures concurrently, returning their results as a tuple.
+///
+/// If one future fails, the other is dropped, and the error is returned.
+///
+/// Can be instantiated with [`join`].
+#[pin_project]
+pub struct Join<A, B> {
+    #[pin]
+    a: Option<A>,
+    #[pin]
+    b: Option<B>,
+    a_result: Option<PyObject>,
+    b_result: Option<PyObject>,
+}
+
+/// Run two futures concurrently, returning their results as a tuple (see [`Join`]).
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::{asyncio::Coroutine, combinators};
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let join = combinators::join(async { PyResult::Ok(1) }, async { PyResult::Ok("a") });
+///     let globals = PyDict::new(py);
+///     globals.set_item("join", Py::new(py, Coroutine::from_future(join))?)?;
+///     let code = r#"
+/// import asyncio
+/// assert asyncio.run(join) == (1, "a")
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn join<A: PyFuture, B: PyFuture>(a: A, b: B) -> Join<A, B> {
+    Join {
+        a: Some(a),
+        b: Some(b),
+        a_result: None,
+        b_result: None,
+    }
+}
+
+impl<A: PyFuture, B: PyFuture> PyFuture for Join<A, B> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let mut this = self.project();
+        if let Some(a) = this.a.as_mut().as_pin_mut() {
+            if let Poll::Ready(res) = a.poll_py(py, cx) {
+                this.a.set(None);
+                match res {
+                    Ok(obj) => *this.a_result = Some(obj),
+                    Err(err) => {
+                        this.b.set(None);
+                        return Poll::Ready(Err(err));
+                    }
+                }
+            }
+        }
+        if let Some(b) = this.b.as_mut().as_pin_mut() {
+            if let Poll::Ready(res) = b.poll_py(py, cx) {
+                this.b.set(None);
+                match res {
+                    Ok(obj) => *this.b_result = Some(obj),
+                    Err(err) => {
+                        this.a.set(None);
+                        return Poll::Ready(Err(err));
+                    }
+                }
+            }
+        }
+        match (this.a_result.take(), this.b_result.take()) {
+            (Some(a), Some(b)) => Poll::Ready(Ok((a, b).into_py(py))),
+            (a, b) => {
+                (*this.a_result, *this.b_result) = (a, b);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Future running two futures concurrently, returning the result of the first one to complete.
+///
+/// The other future is dropped as soon as the first one completes, with an error or not. If
+/// both complete in the same poll, the first future wins.
+///
+/// Can be instantiated with [`select`].
+#[pin_project]
+pub struct Select<A, B> {
+    #[pin]
+    a: Option<A>,
+    #[pin]
+    b: Option<B>,
+}
+
+/// Run two futures concurrently, returning the result of the first one to complete (see
+/// [`Select`]).
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::{asyncio::Coroutine, combinators};
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let never = futures::future::pending::<PyResult<()>>();
+///     let select = combinators::select(never, async { PyResult::Ok(42) });
+///     let globals = PyDict::new(py);
+///     globals.set_item("select", Py::new(py, Coroutine::from_future(select))?)?;
+///     let code = r#"
+/// import asyncio
+/// assert asyncio.run(select) == 42
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn select<A: PyFuture, B: PyFuture>(a: A, b: B) -> Select<A, B> {
+    Select {
+        a: Some(a),
+        b: Some(b),
+    }
+}
+
+impl<A: PyFuture, B: PyFuture> PyFuture for Select<A, B> {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let mut this = self.project();
+        let a = this.a.as_mut().as_pin_mut();
+        let mut poll = a.expect("Select polled after completion").poll_py(py, cx);
+        if poll.is_pending() {
+            poll = this.b.as_mut().as_pin_mut().unwrap().poll_py(py, cx);
+        }
+        if poll.is_ready() {
+            this.a.set(None);
+            this.b.set(None);
+        }
+        poll
+    }
+}
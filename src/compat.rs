@@ -0,0 +1,40 @@
+//! Shims for the pyo3 API changing between the supported versions.
+//!
+//! Every version-sensitive call of the crate goes through this module, so supporting a new pyo3
+//! version only requires updating it. The supported range is the one of the `pyo3` dependency,
+//! each bound being built in CI.
+use pyo3::{
+    callback::IntoPyCallbackOutput,
+    ffi,
+    prelude::*,
+    pyclass::boolean_struct::True,
+    types::{PyCFunction, PyDict, PyTuple},
+    AsPyPointer, PyClass,
+};
+
+pub(crate) use pyo3::{intern, pyclass::IterNextOutput};
+
+/// Create an anonymous Python function from a closure.
+pub(crate) fn new_closure<F, R>(py: Python<'_>, closure: F) -> PyResult<&PyCFunction>
+where
+    F: Fn(&PyTuple, Option<&PyDict>) -> R + Send + 'static,
+    R: IntoPyCallbackOutput<*mut ffi::PyObject>,
+{
+    PyCFunction::new_closure(py, None, None, closure)
+}
+
+/// Python truthiness of an object, i.e. `bool(obj)`.
+pub(crate) fn is_true(py: Python, obj: &PyObject) -> PyResult<bool> {
+    obj.is_true(py)
+}
+
+/// Raw pointer of an object, e.g. for its address or ffi calls.
+pub(crate) fn as_ptr<T: AsPyPointer + ?Sized>(obj: &T) -> *mut ffi::PyObject {
+    obj.as_ptr()
+}
+
+/// Content of a frozen pyclass instance, i.e. `PyCell::get`.
+pub(crate) fn get<T: PyClass<Frozen = True> + Sync>(cell: &PyCell<T>) -> &T {
+    // SAFETY: frozen pyclasses cannot be mutably borrowed
+    unsafe { cell.try_borrow_unguarded() }.expect("frozen pyclass is never mutably borrowed")
+}
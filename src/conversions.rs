@@ -0,0 +1,162 @@
+//! Conversions of coroutine results to Python objects.
+//!
+//! Coroutine results are converted with [`TryIntoPy`], implemented for every [`IntoPy`] type;
+//! the following adapters provide conversions missing from pyo3 itself, without requiring
+//! additional dependencies:
+//!
+//! | Rust                                  | Python                                   |
+//! |---------------------------------------|------------------------------------------|
+//! | [`PyDuration`]`(std::time::Duration)` | `datetime.timedelta`                     |
+//! | [`PyDateTime`]`(std::time::SystemTime)` | `datetime.datetime`, aware in UTC       |
+//! | [`PyDecimal`]`(impl Display)`         | `decimal.Decimal`, parsed from `Display` |
+//!
+//! Contrary to [`IntoPy`], the adapter conversions are fallible, e.g. a [`Duration`] above
+//! `datetime.timedelta.max` raises `OverflowError` when the coroutine completes. As a
+//! consequence, adapters cannot be nested in containers like tuples or `Vec`, which require
+//! [`IntoPy`] elements.
+//!
+//! `chrono` types are converted by pyo3, provided its `chrono` feature is enabled, which the
+//! `conversions` feature of this crate does:
+//!
+//! | Rust                                         | Python               |
+//! |----------------------------------------------|----------------------|
+//! | `chrono::Duration`                           | `datetime.timedelta` |
+//! | `chrono::NaiveDate`                          | `datetime.date`      |
+//! | `chrono::NaiveTime`                          | `datetime.time`      |
+//! | `chrono::NaiveDateTime`                      | `datetime.datetime`  |
+//! | `chrono::DateTime<Utc>`/`DateTime<FixedOffset>` | `datetime.datetime`, aware |
+//!
+//! `rust_decimal::Decimal` can be wrapped in [`PyDecimal`]; pyo3 `rust_decimal` feature is not
+//! enabled by this crate, as it's not available in pyo3 0.18.
+//!
+//! Functions generated by [`pyfunction`](crate::pyfunction)/[`pymethods`](crate::pymethods)
+//! check that their result type is convertible, reporting the missing feature or adapter instead
+//! of an error pointing into the macro expansion.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! use pyo3::{prelude::*, types::PyDict};
+//! use pyo3_async::{
+//!     asyncio::Coroutine,
+//!     conversions::{PyDateTime, PyDecimal, PyDuration},
+//! };
+//!
+//! pyo3::prepare_freethreaded_python();
+//! Python::with_gil(|py| {
+//!     let duration = Duration::from_micros(1_500_000);
+//!     let time = UNIX_EPOCH + Duration::from_secs(86400);
+//!     let coroutines = [
+//!         Coroutine::from_future(async move { PyResult::Ok(PyDuration(duration)) }),
+//!         Coroutine::from_future(async move { PyResult::Ok(PyDateTime(time)) }),
+//!         Coroutine::from_future(async { PyResult::Ok(PyDecimal("0.1")) }),
+//!     ];
+//!     let globals = PyDict::new(py);
+//!     let coroutines = coroutines.map(|coro| Py::new(py, coro).unwrap());
+//!     globals.set_item("coroutines", coroutines.to_object(py))?;
+//!     let code = r#"
+//! import asyncio, datetime, decimal
+//! async def main():
+//!     return [await coro for coro in coroutines]
+//! duration, time, number = asyncio.run(main())
+//! assert duration == datetime.timedelta(seconds=1.5)
+//! assert time == datetime.datetime(1970, 1, 2, tzinfo=datetime.timezone.utc)
+//! assert number == decimal.Decimal("0.1")
+//! "#;
+//!     py.run(code, Some(globals), None)
+//! })
+//! .unwrap();
+//! ```
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pyo3::prelude::*;
+
+use crate::compat::intern;
+
+crate::cached_import!(Datetime, "datetime", datetime, timedelta, timezone);
+crate::cached_import!(Decimal, "decimal", Decimal);
+
+/// Fallible conversion to a Python object, used for coroutine and async generator results.
+///
+/// It is implemented for every [`IntoPy<PyObject>`] type, and for the adapters of this module.
+pub trait TryIntoPy {
+    /// Convert `self` into a Python object.
+    fn try_into_py(self, py: Python) -> PyResult<PyObject>;
+}
+
+impl<T: IntoPy<PyObject>> TryIntoPy for T {
+    fn try_into_py(self, py: Python) -> PyResult<PyObject> {
+        Ok(self.into_py(py))
+    }
+}
+
+/// Type convertible to a Python object, i.e. implementing [`TryIntoPy`].
+///
+/// It is used by generated functions to check their result type.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be converted to a Python object",
+    note = "for `chrono` types, enable the `conversions` feature of pyo3-async",
+    note = "for `std::time` types, wrap the value in `pyo3_async::conversions::PyDuration`/`PyDateTime`",
+    note = "for `rust_decimal::Decimal`, wrap the value in `pyo3_async::conversions::PyDecimal`"
+)]
+pub trait Convertible: TryIntoPy {}
+
+impl<T: TryIntoPy> Convertible for T {}
+
+#[doc(hidden)]
+pub fn assert_convertible<T: Convertible>() {}
+
+fn timedelta(py: Python, duration: Duration) -> PyResult<PyObject> {
+    let args = (0, duration.as_secs(), duration.subsec_micros());
+    Datetime::get(py)?.timedelta.call1(py, args)
+}
+
+/// Adapter converting a [`Duration`] into `datetime.timedelta`, truncated to microseconds.
+///
+/// Conversion raises `OverflowError` above `datetime.timedelta.max`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PyDuration(pub Duration);
+
+impl TryIntoPy for PyDuration {
+    fn try_into_py(self, py: Python) -> PyResult<PyObject> {
+        timedelta(py, self.0)
+    }
+}
+
+/// Adapter converting a [`SystemTime`] into an aware `datetime.datetime` in UTC, truncated to
+/// microseconds.
+///
+/// Conversion raises `OverflowError` outside of `datetime.datetime` range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PyDateTime(pub SystemTime);
+
+impl TryIntoPy for PyDateTime {
+    fn try_into_py(self, py: Python) -> PyResult<PyObject> {
+        let datetime = Datetime::get(py)?;
+        let utc = datetime.timezone.getattr(py, intern!(py, "utc"))?;
+        let epoch = (datetime.datetime).call1(py, (1970, 1, 1, 0, 0, 0, 0, utc))?;
+        match self.0.duration_since(UNIX_EPOCH) {
+            Ok(since) => epoch.call_method1(py, intern!(py, "__add__"), (timedelta(py, since)?,)),
+            Err(err) => {
+                let before = timedelta(py, err.duration())?;
+                epoch.call_method1(py, intern!(py, "__sub__"), (before,))
+            }
+        }
+    }
+}
+
+/// Adapter converting a number into `decimal.Decimal`, parsed from its [`Display`](fmt::Display)
+/// representation, e.g. for `rust_decimal::Decimal`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PyDecimal<T>(pub T);
+
+impl<T: fmt::Display> TryIntoPy for PyDecimal<T> {
+    fn try_into_py(self, py: Python) -> PyResult<PyObject> {
+        Decimal::get(py)?.Decimal.call1(py, (self.0.to_string(),))
+    }
+}
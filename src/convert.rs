@@ -0,0 +1,210 @@
+//! Conversion of future results into Python objects.
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::BuildHasher,
+    iter::Peekable,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use pin_project::pin_project;
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyList},
+};
+
+use crate::{coroutine, PyFuture};
+
+/// Conversion of a mapping into a Python `dict`, preserving its iteration order.
+///
+/// Python `dict` keys are ordered by insertion, so keys are inserted following Rust iteration
+/// order, e.g. sorted for [`BTreeMap`]. The `dict` is pre-sized if `presized-dict` feature is
+/// enabled (not available with `abi3` or PyPy).
+pub trait IntoPyOrdered {
+    /// Convert the mapping into a Python `dict`.
+    fn into_py_ordered(self, py: Python) -> PyResult<PyObject>;
+}
+
+fn ordered_dict<K, V>(
+    py: Python,
+    len: usize,
+    items: impl IntoIterator<Item = (K, V)>,
+) -> PyResult<PyObject>
+where
+    K: IntoPy<PyObject>,
+    V: IntoPy<PyObject>,
+{
+    #[cfg(all(feature = "presized-dict", not(any(Py_LIMITED_API, PyPy))))]
+    // SAFETY: `_PyDict_NewPresized` returns a new reference, or null with an exception set
+    let dict: &PyDict = unsafe {
+        py.from_owned_ptr_or_err(pyo3::ffi::_PyDict_NewPresized(len as pyo3::ffi::Py_ssize_t))?
+    };
+    #[cfg(not(all(feature = "presized-dict", not(any(Py_LIMITED_API, PyPy)))))]
+    let dict = {
+        let _ = len;
+        PyDict::new(py)
+    };
+    for (key, value) in items {
+        dict.set_item(key.into_py(py), value.into_py(py))?;
+    }
+    Ok(dict.into())
+}
+
+impl<K, V> IntoPyOrdered for BTreeMap<K, V>
+where
+    K: IntoPy<PyObject>,
+    V: IntoPy<PyObject>,
+{
+    fn into_py_ordered(self, py: Python) -> PyResult<PyObject> {
+        ordered_dict(py, self.len(), self)
+    }
+}
+
+impl<K, V, S> IntoPyOrdered for HashMap<K, V, S>
+where
+    K: IntoPy<PyObject>,
+    V: IntoPy<PyObject>,
+{
+    fn into_py_ordered(self, py: Python) -> PyResult<PyObject> {
+        ordered_dict(py, self.len(), self)
+    }
+}
+
+impl<K, V> IntoPyOrdered for Vec<(K, V)>
+where
+    K: IntoPy<PyObject>,
+    V: IntoPy<PyObject>,
+{
+    fn into_py_ordered(self, py: Python) -> PyResult<PyObject> {
+        ordered_dict(py, self.len(), self)
+    }
+}
+
+/// [`PyFuture`] converting its mapping result with [`IntoPyOrdered`].
+///
+/// Can be instantiated with [`PyFutureExt::convert_ordered`](crate::PyFutureExt::convert_ordered).
+#[derive(Debug)]
+#[repr(transparent)]
+#[pin_project]
+pub struct Ordered<F>(#[pin] pub F);
+
+impl<F, T, E> PyFuture for Ordered<F>
+where
+    F: Future<Output = Result<T, E>> + Send,
+    T: IntoPyOrdered + Send,
+    E: Send,
+    PyErr: From<E>,
+{
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let mapping = ready!(self.project().0.poll(cx))?;
+        Poll::Ready(mapping.into_py_ordered(py))
+    }
+}
+
+/// Collection which can be converted incrementally into a Python object (see
+/// [`ChunkedConvert`]).
+pub trait ChunkedCollection: IntoIterator + Send {
+    /// Create the empty Python collection.
+    fn empty(py: Python) -> PyObject;
+    /// Push an item into the Python collection.
+    fn push(py: Python, collection: &PyAny, item: Self::Item) -> PyResult<()>;
+}
+
+impl<T> ChunkedCollection for Vec<T>
+where
+    T: IntoPy<PyObject> + Send,
+{
+    fn empty(py: Python) -> PyObject {
+        PyList::empty(py).into()
+    }
+
+    fn push(py: Python, collection: &PyAny, item: Self::Item) -> PyResult<()> {
+        collection.downcast::<PyList>()?.append(item.into_py(py))
+    }
+}
+
+impl<K, V, S> ChunkedCollection for HashMap<K, V, S>
+where
+    K: IntoPy<PyObject> + Send,
+    V: IntoPy<PyObject> + Send,
+    S: BuildHasher + Send,
+{
+    fn empty(py: Python) -> PyObject {
+        PyDict::new(py).into()
+    }
+
+    fn push(py: Python, collection: &PyAny, (key, value): Self::Item) -> PyResult<()> {
+        collection
+            .downcast::<PyDict>()?
+            .set_item(key.into_py(py), value.into_py(py))
+    }
+}
+
+impl<K, V> ChunkedCollection for BTreeMap<K, V>
+where
+    K: IntoPy<PyObject> + Send,
+    V: IntoPy<PyObject> + Send,
+{
+    fn empty(py: Python) -> PyObject {
+        PyDict::new(py).into()
+    }
+
+    fn push(py: Python, collection: &PyAny, (key, value): Self::Item) -> PyResult<()> {
+        collection
+            .downcast::<PyDict>()?
+            .set_item(key.into_py(py), value.into_py(py))
+    }
+}
+
+/// [`PyFuture`] converting its collection result incrementally.
+///
+/// At most `chunk_size` elements are converted per coroutine step; the coroutine then yields to
+/// the event loop, letting other tasks run, before resuming the conversion. The final Python
+/// collection is returned when complete.
+///
+/// Can be instantiated with [`PyFutureExt::convert_chunked`](crate::PyFutureExt::convert_chunked).
+#[pin_project]
+pub struct ChunkedConvert<F, C: IntoIterator> {
+    #[pin]
+    future: F,
+    chunk_size: usize,
+    state: Option<(Peekable<C::IntoIter>, PyObject)>,
+}
+
+impl<F, C: IntoIterator> ChunkedConvert<F, C> {
+    pub(crate) fn new(future: F, chunk_size: usize) -> Self {
+        Self {
+            future,
+            chunk_size: chunk_size.max(1),
+            state: None,
+        }
+    }
+}
+
+impl<F, C, E> PyFuture for ChunkedConvert<F, C>
+where
+    F: Future<Output = Result<C, E>> + Send,
+    C: ChunkedCollection,
+    C::IntoIter: Send,
+    C::Item: Send,
+    E: Send,
+    PyErr: From<E>,
+{
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let this = self.project();
+        if this.state.is_none() {
+            let collection = ready!(this.future.poll(cx))?;
+            *this.state = Some((collection.into_iter().peekable(), C::empty(py)));
+        }
+        let (items, collection) = this.state.as_mut().unwrap();
+        for item in items.by_ref().take(*this.chunk_size) {
+            C::push(py, collection.as_ref(py), item)?;
+        }
+        if items.peek().is_some() {
+            coroutine::checkpoint(cx);
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(this.state.take().unwrap().1))
+    }
+}
@@ -1,70 +1,594 @@
+#[cfg(any(feature = "diagnostics", feature = "tracing"))]
+use std::sync::atomic::AtomicU64;
+#[cfg(feature = "diagnostics")]
+use std::time::{Duration, Instant};
 use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
-    sync::Arc,
-    task::{Context, Poll},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+    task::{ready, Context, Poll},
 };
 
 use futures::task::ArcWake;
-use pyo3::{exceptions::PyRuntimeError, iter::IterNextOutput, prelude::*};
+use pin_project::pin_project;
+use pyo3::{
+    exceptions::{PyBaseException, PyRuntimeError, PyValueError},
+    panic::PanicException,
+    prelude::*,
+    types::IntoPyDict,
+    PyTraverseError, PyVisit,
+};
 
+#[cfg(feature = "debug")]
+use crate::debug;
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::MemoryFootprint;
 use crate::{
-    utils::{current_thread_id, ThreadId},
-    PyFuture, ThrowCallback,
+    compat::{intern, IterNextOutput},
+    utils::{self, current_thread_id, ThreadId},
+    PyFuture, ThrowCallback, YieldCallback,
 };
 
+thread_local! {
+    static CHECKPOINT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Wake the current task, making its coroutine yield a checkpoint to the event loop, i.e. a bare
+/// `yield` for `asyncio`, or `trio.lowlevel.cancel_shielded_checkpoint`, so other tasks can run
+/// before it's polled again. The future must then return `Pending`.
+///
+/// Other wakes of the coroutine waker, in the polling thread and while it's polled, are forwarded
+/// to the event loop like any other wake.
+pub(crate) fn checkpoint(cx: &Context) {
+    wake_as_checkpoint(|| cx.waker().wake_by_ref());
+}
+
+/// Run `f`, the first wake of the coroutine waker in the polling thread being handled like
+/// [`checkpoint`].
+pub(crate) fn wake_as_checkpoint<R>(f: impl FnOnce() -> R) -> R {
+    // reset even if `f` panics, or if the wake doesn't reach a coroutine waker, e.g. outside
+    // of a coroutine
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CHECKPOINT.with(|checkpoint| checkpoint.set(false));
+        }
+    }
+    CHECKPOINT.with(|checkpoint| checkpoint.set(true));
+    let _reset = Reset;
+    f()
+}
+
 pub(crate) trait CoroutineWaker: Sized {
+    /// Backend name, before the backend is resolved for lazily specialized wakers.
+    const BACKEND: &'static str;
     fn new(py: Python) -> PyResult<Self>;
+    /// Backend name of the waker instance.
+    fn backend(&self) -> &'static str {
+        Self::BACKEND
+    }
+    /// Backend name for the current event loop.
+    fn current_backend(_py: Python) -> &'static str {
+        Self::BACKEND
+    }
     fn yield_(&self, py: Python) -> PyResult<PyObject>;
+    /// Object to yield when the coroutine has been woken while being polled, in order to be
+    /// rescheduled immediately.
+    fn checkpoint(&self, py: Python) -> PyResult<PyObject>;
     fn wake(&self, py: Python);
     fn wake_threadsafe(&self, py: Python);
+    /// Threadsafe wake of a coroutine with [`WakePriority::Immediate`], never deferred by
+    /// coalescing or batching.
+    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+    fn wake_threadsafe_immediate(&self, py: Python) {
+        self.wake_threadsafe(py);
+    }
+    /// Callable scheduling a callback in the thread of the current event loop.
+    fn run_soon_threadsafe(py: Python) -> PyResult<PyObject>;
     fn update(&mut self, _py: Python) -> PyResult<()> {
         Ok(())
     }
     fn raise(&self, _py: Python) -> PyResult<()> {
         Ok(())
     }
+    /// Whether the waker can be reused as is, when the coroutine is polled again without having
+    /// been woken, e.g. because its last yielded object is still pending.
+    fn reusable(&self, _py: Python) -> bool {
+        true
+    }
+    /// Handle the value sent to resume the coroutine, e.g. trio sends an `outcome` which must be
+    /// unwrapped to raise cancellation.
+    fn unwrap_sent(&self, _py: Python, _value: &PyAny) -> PyResult<()> {
+        Ok(())
+    }
+}
+
+/// Latency priority of the wakes of a coroutine coming from another thread, e.g. a tokio task
+/// completing a channel send (see `with_wake_priority` on coroutines).
+///
+/// A threadsafe wake is always scheduled with the event loop threadsafe API, e.g.
+/// `loop.call_soon_threadsafe` for asyncio, which writes to the loop self-pipe; there is no
+/// supported API to resume the coroutine faster, so the priority only controls the deferral
+/// added by the `coalesce-wakes` and `batch-wakes` features, and is not available without them.
+/// trio wakes are never deferred.
+///
+/// The latency of both priorities is compared by the `wake_priority` bench.
+#[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum WakePriority {
+    /// Wakes may be coalesced (`coalesce-wakes` feature) or batched with the other wakes of the
+    /// event loop (`batch-wakes` feature). This is the default.
+    #[default]
+    Normal,
+    /// Each wake is scheduled on its own, never coalesced nor batched, trading throughput for
+    /// latency.
+    Immediate,
+}
+
+/// [`PyFuture`] calling a step function at each poll.
+#[pin_project]
+pub(crate) struct StepFn<F>(pub(crate) F);
+
+impl<F> PyFuture for StepFn<F>
+where
+    F: FnMut(Python, &mut Context) -> Poll<PyResult<PyObject>> + Send,
+{
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        // the step function wakes itself to yield to the event loop between steps
+        let step = self.project().0;
+        wake_as_checkpoint(|| step(py, cx))
+    }
+}
+
+/// [`PyFuture`] logging its error with `logger.exception`, and returning `None` instead.
+pub(crate) struct Logged {
+    pub(crate) future: Pin<Box<dyn PyFuture>>,
+    pub(crate) logger: PyObject,
+}
+
+impl PyFuture for Logged {
+    fn poll_py(mut self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let err = match ready!(self.future.as_mut().poll_py(py, cx)) {
+            Ok(res) => return Poll::Ready(Ok(res)),
+            Err(err) => err,
+        };
+        let log = || {
+            let kwargs = [(intern!(py, "exc_info"), err.value(py))].into_py_dict(py);
+            let msg = "Exception in pyo3-async background coroutine";
+            let exception = self.logger.getattr(py, intern!(py, "exception"))?;
+            exception.call(py, (msg,), Some(kwargs))
+        };
+        if let Err(log_err) = log() {
+            err.write_unraisable(py, None);
+            log_err.write_unraisable(py, None);
+        }
+        Poll::Ready(Ok(py.None()))
+    }
+}
+
+/// [`PyFuture`] running a guard after the future has been dropped, whatever the drop path.
+pub(crate) struct Guarded<G: FnOnce()> {
+    // dropped before the guard, as fields are dropped in declaration order
+    pub(crate) future: Pin<Box<dyn PyFuture>>,
+    pub(crate) _guard: Guard<G>,
+}
+
+pub(crate) struct Guard<G: FnOnce()>(pub(crate) Option<G>);
+
+// the guard is never pinned
+impl<G: FnOnce()> Unpin for Guard<G> {}
+
+impl<G: FnOnce()> Drop for Guard<G> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.0.take() {
+            guard();
+        }
+    }
+}
+
+impl<G: FnOnce() + Send> PyFuture for Guarded<G> {
+    fn poll_py(mut self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        self.future.as_mut().poll_py(py, cx)
+    }
+}
+
+/// [`PyFuture`] mapping its error, for a future taken out of a coroutine with
+/// [`Coroutine::take_future`].
+struct MappedErr {
+    future: Pin<Box<dyn PyFuture>>,
+    map_err: MapErr,
+}
+
+impl PyFuture for MappedErr {
+    fn poll_py(mut self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let res = ready!(self.future.as_mut().poll_py(py, cx));
+        Poll::Ready(res.map_err(|err| (self.map_err)(py, err)))
+    }
 }
 
 pub(crate) struct Waker<W> {
     inner: W,
     thread_id: ThreadId,
+    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+    priority: WakePriority,
+    polling: AtomicBool,
+    woken: AtomicBool,
+    // set when woken since the last poll, the waker must then be renewed
+    stale: AtomicBool,
+    #[cfg(feature = "tracing")]
+    coroutine_id: u64,
+    // poll cycle targeted by the waker, to correlate wakes with yields in traces
+    #[cfg(feature = "tracing")]
+    cycle: AtomicU64,
+    // first wake since the last poll, to measure the wake latency
+    #[cfg(feature = "diagnostics")]
+    woken_at: Mutex<Option<Instant>>,
 }
 
 impl<W: CoroutineWaker + Send + Sync> ArcWake for Waker<W> {
     fn wake_by_ref(arc_self: &Arc<Self>) {
-        if current_thread_id() == arc_self.thread_id {
+        let _first_wake = !arc_self.stale.swap(true, Ordering::Relaxed);
+        #[cfg(feature = "diagnostics")]
+        if _first_wake {
+            *arc_self
+                .woken_at
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+        }
+        let same_thread = current_thread_id() == arc_self.thread_id;
+        utils::trace!(
+            coroutine = arc_self.coroutine_id,
+            cycle = arc_self.cycle.load(Ordering::Relaxed),
+            thread = ?current_thread_id(),
+            same_thread,
+            polling = arc_self.polling.load(Ordering::Relaxed),
+            "wake"
+        );
+        if same_thread {
+            if arc_self.polling.load(Ordering::Relaxed)
+                && CHECKPOINT.with(|checkpoint| checkpoint.replace(false))
+            {
+                arc_self.woken.store(true, Ordering::Relaxed);
+                return;
+            }
             Python::with_gil(|gil| CoroutineWaker::wake(&arc_self.inner, gil))
         } else {
-            Python::with_gil(|gil| CoroutineWaker::wake_threadsafe(&arc_self.inner, gil))
+            #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+            Python::with_gil(|gil| match arc_self.priority {
+                WakePriority::Normal => CoroutineWaker::wake_threadsafe(&arc_self.inner, gil),
+                WakePriority::Immediate => arc_self.inner.wake_threadsafe_immediate(gil),
+            });
+            #[cfg(not(any(feature = "coalesce-wakes", feature = "batch-wakes")))]
+            Python::with_gil(|gil| CoroutineWaker::wake_threadsafe(&arc_self.inner, gil));
         }
     }
 }
 
+/// Mapping of the errors raised by coroutines and async generators.
+pub(crate) type MapErr = Arc<dyn Fn(Python, PyErr) -> PyErr + Send + Sync>;
+
+/// Python coroutine wrapping a [`PyFuture`], with interior mutability so that the generated
+/// pymethods take `&self`.
+///
+/// # Locking order
+///
+/// `state` is locked first, and stays locked while the future is polled, which may in turn lock
+/// other objects, e.g. the stream shared by an async generator, or the state of another
+/// coroutine. `info` is a leaf lock, never held while locking anything else.
+///
+/// `state` is only ever locked with `try_lock`, so a reentrant call, e.g. from the future being
+/// polled, or from another thread while the GIL is released by [`AllowThreads`], raises
+/// `ValueError: coroutine already executing`, like Python coroutines, instead of deadlocking.
+/// The held lock is the "polling" state of the coroutine: `close`/`throw` need it to take or
+/// drop the future, so they can never race a poll, even one having released the GIL.
+///
+/// The lock costs about 20ns per step compared to the `PyCell` borrow flag it has replaced,
+/// which is the price of the "already executing" error instead of "Already borrowed" (see the
+/// `coroutine_step` bench).
+///
+/// [`AllowThreads`]: crate::AllowThreads
 pub(crate) struct Coroutine<W> {
+    state: Mutex<State<W>>,
+    info: Mutex<Info>,
+    #[cfg(feature = "diagnostics")]
+    last_poll_released_gil: AtomicBool,
+    // in nanoseconds, `u64::MAX` if not measured yet
+    #[cfg(feature = "diagnostics")]
+    last_wake_latency: AtomicU64,
+    #[cfg(feature = "debug")]
+    registration: debug::Registration,
+}
+
+/// Coroutine attributes readable while it is executing.
+#[derive(Default)]
+struct Info {
+    name: Option<String>,
+    // resolved when the waker is created
+    backend: Option<&'static str>,
+    #[cfg(feature = "diagnostics")]
+    footprint: Option<Box<dyn MemoryFootprint + Send>>,
+}
+
+pub(crate) struct State<W> {
     future: Option<Pin<Box<dyn PyFuture>>>,
     throw: Option<ThrowCallback>,
     waker: Option<Arc<Waker<W>>>,
+    // Python object driving the future after it has been detached, e.g. an `asyncio.Task`
+    detached: Option<PyObject>,
+    // exception which has terminated the coroutine, raised again by subsequent `throw`; its
+    // traceback may reference the coroutine, so it's visited by the garbage collector
+    error: Option<Py<PyBaseException>>,
+    pub(crate) yield_: Option<YieldCallback>,
+    pub(crate) map_err: Option<MapErr>,
+    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+    pub(crate) wake_priority: WakePriority,
+    #[cfg(feature = "tracing")]
+    id: u64,
+    // incremented each time the waker is renewed
+    #[cfg(feature = "tracing")]
+    cycle: u64,
+}
+
+#[cfg(feature = "tracing")]
+impl<W> Drop for State<W> {
+    fn drop(&mut self) {
+        let pending = self.future.is_some();
+        utils::trace!(
+            coroutine = self.id,
+            cycle = self.cycle,
+            pending,
+            "coroutine dropped"
+        );
+    }
 }
 
 impl<W> Coroutine<W> {
     pub(crate) fn new(future: Pin<Box<dyn PyFuture>>, throw: Option<ThrowCallback>) -> Self {
         Self {
-            future: Some(future),
-            throw,
-            waker: None,
+            state: Mutex::new(State {
+                future: Some(future),
+                throw,
+                waker: None,
+                detached: None,
+                error: None,
+                yield_: None,
+                map_err: None,
+                #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+                wake_priority: WakePriority::Normal,
+                #[cfg(feature = "tracing")]
+                id: {
+                    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+                    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+                },
+                #[cfg(feature = "tracing")]
+                cycle: 0,
+            }),
+            info: Mutex::default(),
+            #[cfg(feature = "diagnostics")]
+            last_poll_released_gil: AtomicBool::new(false),
+            #[cfg(feature = "diagnostics")]
+            last_wake_latency: AtomicU64::new(u64::MAX),
+            #[cfg(feature = "debug")]
+            registration: debug::Registration::new(debug::LiveKind::Coroutine),
+        }
+    }
+
+    /// Mutable state, for builder methods.
+    pub(crate) fn state_mut(&mut self) -> &mut State<W> {
+        self.state.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn state(&self) -> PyResult<MutexGuard<'_, State<W>>> {
+        match self.state.try_lock() {
+            Ok(state) => Ok(state),
+            // the panic has been raised as `PanicException`, the coroutine stays usable like
+            // after any other exception
+            Err(TryLockError::Poisoned(err)) => Ok(err.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                Err(PyValueError::new_err("coroutine already executing"))
+            }
+        }
+    }
+
+    /// Visit the Python objects kept by the coroutine, for the garbage collector.
+    pub(crate) fn traverse(&self, visit: &PyVisit) -> Result<(), PyTraverseError> {
+        // an executing coroutine is not garbage, so it can be skipped
+        if let Ok(state) = self.state.try_lock() {
+            if let Some(error) = &state.error {
+                visit.call(error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Break the reference cycles found by the garbage collector.
+    pub(crate) fn clear(&self) {
+        if let Ok(mut state) = self.state.try_lock() {
+            state.error.take();
+        }
+    }
+
+    fn info(&self) -> MutexGuard<'_, Info> {
+        self.info.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn name(&self) -> Option<String> {
+        self.info().name.clone()
+    }
+
+    pub(crate) fn set_name(&self, name: Option<String>) {
+        #[cfg(feature = "debug")]
+        self.registration.set_name(name.as_deref());
+        self.info().name = name;
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn set_footprint(&mut self, footprint: Box<dyn MemoryFootprint + Send>) {
+        self.info
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .footprint = Some(footprint);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn footprint_bytes(&self) -> usize {
+        self.info().footprint.approx_bytes()
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn last_poll_released_gil(&self) -> bool {
+        self.last_poll_released_gil.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn last_wake_latency(&self) -> Option<Duration> {
+        match self.last_wake_latency.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Move the future into a new coroutine wrapped by `wrap`, e.g. in an `asyncio.Task`.
+    ///
+    /// The wrapper is cached and returned by subsequent calls, while polling this coroutine
+    /// raises an error.
+    pub(crate) fn detach(
+        &self,
+        py: Python,
+        wrap: impl FnOnce(Self) -> PyResult<PyObject>,
+    ) -> PyResult<PyObject> {
+        let mut state = self.state()?;
+        if let Some(ref detached) = state.detached {
+            return Ok(detached.clone_ref(py));
+        }
+        let Some(future) = state.future.take() else {
+            return Err(PyRuntimeError::new_err(
+                "cannot reuse already awaited coroutine",
+            ));
+        };
+        let detached = wrap(Self::new(future, state.throw.take()))?;
+        state.detached = Some(detached.clone_ref(py));
+        Ok(detached)
+    }
+
+    /// Take the future out of the coroutine, to be polled directly by another future instead of
+    /// being driven through Python; the coroutine then raises like an already awaited one.
+    ///
+    /// An error is returned if the coroutine is executing or has already been polled, as the
+    /// future may have registered the coroutine waker, or if it has a throw callback, which could
+    /// not be called anymore.
+    pub(crate) fn take_future(&self) -> PyResult<Pin<Box<dyn PyFuture>>> {
+        let mut state = self.state()?;
+        if state.throw.is_some() {
+            return Err(PyRuntimeError::new_err(
+                "coroutine with a throw callback can only be awaited",
+            ));
+        }
+        if state.future.is_none() {
+            return Err(PyRuntimeError::new_err(
+                "cannot reuse already awaited coroutine",
+            ));
+        }
+        if state.waker.is_some() {
+            return Err(PyRuntimeError::new_err(
+                "coroutine already started can only be awaited",
+            ));
         }
+        let future = state.future.take().unwrap();
+        Ok(match state.map_err.clone() {
+            Some(map_err) => Box::pin(MappedErr { future, map_err }),
+            None => future,
+        })
+    }
+
+    pub(crate) fn backend(&self) -> &'static str
+    where
+        W: CoroutineWaker,
+    {
+        self.info().backend.unwrap_or(W::BACKEND)
     }
 
+    pub(crate) fn close(&self, py: Python) -> PyResult<()> {
+        self.state()?.close(py)
+    }
+}
+
+impl<W: CoroutineWaker + Send + Sync + 'static> Coroutine<W> {
+    pub(crate) fn send(
+        &self,
+        py: Python,
+        value: &PyAny,
+    ) -> PyResult<IterNextOutput<PyObject, PyObject>> {
+        let mut state = self.state()?;
+        let exc = (state.waker.as_ref()).and_then(|w| w.inner.unwrap_sent(py, value).err());
+        self.poll_state(py, &mut state, exc)
+    }
+
+    pub(crate) fn poll(
+        &self,
+        py: Python,
+        exc: Option<PyErr>,
+    ) -> PyResult<IterNextOutput<PyObject, PyObject>> {
+        self.poll_state(py, &mut *self.state()?, exc)
+    }
+
+    fn poll_state(
+        &self,
+        py: Python,
+        state: &mut State<W>,
+        exc: Option<PyErr>,
+    ) -> PyResult<IterNextOutput<PyObject, PyObject>> {
+        #[cfg(feature = "diagnostics")]
+        crate::diagnostics::take_gil_released();
+        #[cfg(feature = "diagnostics")]
+        if let Some(waker) = &state.waker {
+            let woken_at = waker
+                .woken_at
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            if let Some(woken_at) = woken_at {
+                let nanos = u64::try_from(woken_at.elapsed().as_nanos()).unwrap_or(u64::MAX - 1);
+                self.last_wake_latency.store(nanos, Ordering::Relaxed);
+            }
+        }
+        #[cfg(feature = "strict-checks")]
+        let scope = crate::strict::enter_coroutine(self.name().as_deref());
+        let prev_waker = state.waker.as_ref().map(Arc::as_ptr);
+        let res = state.poll(py, exc);
+        #[cfg(feature = "strict-checks")]
+        drop(scope);
+        #[cfg(feature = "diagnostics")]
+        (self.last_poll_released_gil)
+            .store(crate::diagnostics::take_gil_released(), Ordering::Relaxed);
+        match &state.waker {
+            Some(waker) if prev_waker != Some(Arc::as_ptr(waker)) => {
+                self.info().backend = Some(waker.inner.backend());
+            }
+            _ => {}
+        }
+        res
+    }
+}
+
+impl<W> State<W> {
     pub(crate) fn close(&mut self, py: Python) -> PyResult<()> {
         if let Some(mut future_rs) = self.future.take() {
             if let Some(ref mut throw) = self.throw {
                 throw(py, None);
                 let waker = futures::task::noop_waker();
-                let res = future_rs
-                    .as_mut()
-                    .poll_py(py, &mut Context::from_waker(&waker));
-                if let Poll::Ready(Err(err)) = res {
-                    return Err(err);
+                let cx = &mut Context::from_waker(&waker);
+                let poll = AssertUnwindSafe(|| future_rs.as_mut().poll_py(py, cx));
+                match panic::catch_unwind(poll) {
+                    Ok(Poll::Ready(Err(err))) => return Err(err),
+                    Ok(_) => {}
+                    Err(payload) => {
+                        return Err(PanicException::new_err(utils::panic_message(&*payload)))
+                    }
                 }
             }
         }
@@ -72,44 +596,139 @@ impl<W> Coroutine<W> {
     }
 }
 
-impl<W: CoroutineWaker + Send + Sync + 'static> Coroutine<W> {
-    pub(crate) fn poll(
+impl<W: CoroutineWaker + Send + Sync + 'static> State<W> {
+    fn poll(
         &mut self,
         py: Python,
         exc: Option<PyErr>,
     ) -> PyResult<IterNextOutput<PyObject, PyObject>> {
         let Some(ref mut future_rs) = self.future else {
+            // e.g. `asyncio.gather` bookkeeping must not replace the original exception
+            if let (Some(_), Some(err)) = (&exc, &self.error) {
+                return Err(PyErr::from_value(err.as_ref(py)));
+            }
+            if self.detached.is_some() {
+                return Err(PyRuntimeError::new_err(
+                    "coroutine has been converted into a future, await the future instead",
+                ));
+            }
             return Err(PyRuntimeError::new_err(
                 "cannot reuse already awaited coroutine",
             ));
         };
-        let exc = exc.or_else(|| self.waker.as_ref().and_then(|w| w.inner.raise(py).err()));
+        // a spurious step, i.e. without wake, reuses the waker, and thus yields the same object
+        let reuse = (self.waker.as_ref())
+            .is_some_and(|w| !w.stale.load(Ordering::Relaxed) && w.inner.reusable(py));
+        let exc = match (exc, &self.waker) {
+            (None, Some(waker)) if !reuse => {
+                let exc = waker.inner.raise(py).err();
+                #[cfg(feature = "tracing")]
+                if let Some(exc) = &exc {
+                    utils::trace!(coroutine = self.id, cycle = self.cycle, %exc, "raise");
+                }
+                exc
+            }
+            (exc, _) => exc,
+        };
         match (exc, &mut self.throw) {
             (Some(exc), Some(throw)) => throw(py, Some(exc)),
             (Some(exc), _) => {
                 self.future.take();
+                self.error = Some(exc.clone_ref(py).into_value(py));
                 return Err(exc);
             }
             _ => {}
         }
-        if let Some(waker) = self.waker.as_mut().and_then(Arc::get_mut) {
-            waker.inner.update(py)?;
-        } else {
-            self.waker = Some(Arc::new(Waker {
-                inner: W::new(py)?,
-                thread_id: current_thread_id(),
-            }));
-        }
-        let waker = futures::task::waker(self.waker.clone().unwrap());
-        let res = future_rs
-            .as_mut()
-            .poll_py(py, &mut Context::from_waker(&waker));
+        if !reuse {
+            #[cfg(feature = "tracing")]
+            {
+                self.cycle += 1;
+            }
+            if let Some(waker) = self.waker.as_mut().and_then(Arc::get_mut) {
+                waker.inner.update(py)?;
+                #[cfg(feature = "tracing")]
+                waker.cycle.store(self.cycle, Ordering::Relaxed);
+                utils::trace!(coroutine = self.id, cycle = self.cycle, "waker updated");
+            } else {
+                utils::ensure_fork_handler(py);
+                let waker = Arc::new(Waker {
+                    inner: W::new(py)?,
+                    thread_id: current_thread_id(),
+                    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+                    priority: self.wake_priority,
+                    polling: AtomicBool::new(false),
+                    woken: AtomicBool::new(false),
+                    stale: AtomicBool::new(false),
+                    #[cfg(feature = "tracing")]
+                    coroutine_id: self.id,
+                    #[cfg(feature = "tracing")]
+                    cycle: AtomicU64::new(self.cycle),
+                    #[cfg(feature = "diagnostics")]
+                    woken_at: Mutex::new(None),
+                });
+                utils::trace!(
+                    coroutine = self.id,
+                    cycle = self.cycle,
+                    backend = waker.inner.backend(),
+                    thread = ?waker.thread_id,
+                    "waker created"
+                );
+                self.waker = Some(waker);
+            }
+        }
+        let arc_waker = self.waker.as_ref().unwrap();
+        arc_waker.stale.store(false, Ordering::Relaxed);
+        let waker = futures::task::waker(arc_waker.clone());
+        arc_waker.polling.store(true, Ordering::Relaxed);
+        let cx = &mut Context::from_waker(&waker);
+        // a panicking future is dropped, and the panic raised as `PanicException`, instead of
+        // polling again a future in a possibly broken state
+        let poll = panic::catch_unwind(AssertUnwindSafe(|| future_rs.as_mut().poll_py(py, cx)));
+        arc_waker.polling.store(false, Ordering::Relaxed);
+        let res = match poll {
+            Ok(res) => res,
+            Err(payload) => {
+                self.future.take();
+                let err = PanicException::new_err(utils::panic_message(&*payload));
+                self.error = Some(err.clone_ref(py).into_value(py));
+                return Err(err);
+            }
+        };
         Ok(match res {
             Poll::Ready(res) => {
                 self.future.take();
+                let res = match &self.map_err {
+                    Some(map_err) => res.map_err(|err| map_err(py, err)),
+                    None => res,
+                };
+                if let Err(err) = &res {
+                    self.error = Some(err.clone_ref(py).into_value(py));
+                }
+                utils::trace!(
+                    coroutine = self.id,
+                    cycle = self.cycle,
+                    ok = res.is_ok(),
+                    "coroutine completed"
+                );
                 IterNextOutput::Return(res?)
             }
-            Poll::Pending => IterNextOutput::Yield(self.waker.as_ref().unwrap().inner.yield_(py)?),
+            Poll::Pending if arc_waker.woken.swap(false, Ordering::Relaxed) => {
+                utils::trace!(coroutine = self.id, cycle = self.cycle, "checkpoint");
+                IterNextOutput::Yield(arc_waker.inner.checkpoint(py)?)
+            }
+            Poll::Pending => {
+                let yielded = arc_waker.inner.yield_(py)?;
+                utils::trace!(
+                    coroutine = self.id,
+                    cycle = self.cycle,
+                    future = crate::compat::as_ptr(&yielded) as usize,
+                    "yield"
+                );
+                match &mut self.yield_ {
+                    Some(yield_) => IterNextOutput::Yield(yield_(py, yielded)?),
+                    None => IterNextOutput::Yield(yielded),
+                }
+            }
         })
     }
 }
@@ -0,0 +1,141 @@
+//! Registry of the live coroutines/async generators, enabled by `debug` feature.
+//!
+//! Each coroutine/async generator of this crate registers itself when constructed, and
+//! unregisters when dropped, so [`live_coroutines`] lists the outstanding ones with their name
+//! and age; it helps finding leaks in long-running processes, e.g. coroutines never awaited, or
+//! async generators never closed and kept alive by a reference cycle.
+//!
+//! The registry doesn't keep the objects alive, only their name and creation time. It is a
+//! global map protected by a mutex, so it can be read from any thread, but the listing is only a
+//! snapshot: objects may be dropped, or created, right after. Each extension module compiling
+//! its own copy of this crate has its own registry.
+//!
+//! The registry costs a mutex lock and a map insertion/removal per object, as well as a lock
+//! per renaming; without the feature, nothing is compiled in.
+//!
+//! # Example
+//!
+//! ```rust
+//! use pyo3::prelude::*;
+//! use pyo3_async::{asyncio::Coroutine, debug};
+//!
+//! pyo3::prepare_freethreaded_python();
+//! Python::with_gil(|py| {
+//!     let coroutine = Coroutine::from_future(async { PyResult::Ok(()) }).with_name("leaky");
+//!     let coroutine = Py::new(py, coroutine)?;
+//!     let is_leaky = |obj: &debug::LiveObject| obj.name.as_deref() == Some("leaky");
+//!     let live = debug::live_coroutines();
+//!     assert!(live.iter().any(|obj| is_leaky(obj) && obj.kind == debug::LiveKind::Coroutine));
+//!     drop(coroutine);
+//!     assert!(!debug::live_coroutines().iter().any(is_leaky));
+//!     PyResult::Ok(())
+//! })
+//! .unwrap();
+//! ```
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use pyo3::prelude::*;
+
+/// Kind of a registered object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LiveKind {
+    /// Coroutine, of any backend.
+    Coroutine,
+    /// Async generator, of any backend.
+    AsyncGenerator,
+}
+
+impl LiveKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Coroutine => "coroutine",
+            Self::AsyncGenerator => "async_generator",
+        }
+    }
+}
+
+/// Live coroutine/async generator, as listed by [`live_coroutines`].
+#[derive(Debug, Clone)]
+pub struct LiveObject {
+    /// Whether it's a coroutine or an async generator.
+    pub kind: LiveKind,
+    /// Name set with `with_name`, or the `name` setter.
+    pub name: Option<String>,
+    /// Time elapsed since the object creation.
+    pub age: Duration,
+}
+
+struct Entry {
+    kind: LiveKind,
+    name: Option<String>,
+    created: Instant,
+}
+
+static REGISTRY: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+
+fn registry() -> MutexGuard<'static, BTreeMap<u64, Entry>> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registration of an object, removed from the registry when dropped.
+pub(crate) struct Registration(u64);
+
+impl Registration {
+    pub(crate) fn new(kind: LiveKind) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let entry = Entry {
+            kind,
+            name: None,
+            created: Instant::now(),
+        };
+        registry().insert(id, entry);
+        Self(id)
+    }
+
+    pub(crate) fn set_name(&self, name: Option<&str>) {
+        if let Some(entry) = registry().get_mut(&self.0) {
+            entry.name = name.map(Into::into);
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        registry().remove(&self.0);
+    }
+}
+
+/// Live coroutines/async generators created by this crate, oldest first.
+pub fn live_coroutines() -> Vec<LiveObject> {
+    let now = Instant::now();
+    // ids are increasing, so the map is ordered by creation
+    (registry().values())
+        .map(|entry| LiveObject {
+            kind: entry.kind,
+            name: entry.name.clone(),
+            age: now.saturating_duration_since(entry.created),
+        })
+        .collect()
+}
+
+/// Python function returning [`live_coroutines`] as a list of `(kind, name, age_in_seconds)`,
+/// `kind` being `"coroutine"` or `"async_generator"`, to be added to an extension module.
+///
+/// ```rust,ignore
+/// m.add_function(wrap_pyfunction!(pyo3_async::debug::py_live_coroutines, m)?)?;
+/// ```
+#[pyfunction]
+#[pyo3(name = "live_coroutines")]
+pub fn py_live_coroutines() -> Vec<(&'static str, Option<String>, f64)> {
+    (live_coroutines().into_iter())
+        .map(|obj| (obj.kind.as_str(), obj.name, obj.age.as_secs_f64()))
+        .collect()
+}
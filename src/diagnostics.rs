@@ -0,0 +1,103 @@
+//! Diagnostics utilities.
+use std::{cell::Cell, sync::Arc};
+#[cfg(not(Py_LIMITED_API))]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(not(Py_LIMITED_API))]
+use futures::Stream;
+#[cfg(not(Py_LIMITED_API))]
+use pin_project::pin_project;
+
+/// Approximate memory footprint of Rust data, e.g. buffers held by a pending future.
+///
+/// A footprint can be attached to a coroutine/async generator, to be reported by its
+/// `__sizeof__` method, and thus by `sys.getsizeof` and related tooling.
+pub trait MemoryFootprint {
+    /// Approximate size in bytes, not including `size_of::<Self>()`.
+    fn approx_bytes(&self) -> usize;
+}
+
+impl<T: MemoryFootprint + ?Sized> MemoryFootprint for Box<T> {
+    fn approx_bytes(&self) -> usize {
+        (**self).approx_bytes()
+    }
+}
+
+impl<T: MemoryFootprint + ?Sized> MemoryFootprint for Arc<T> {
+    fn approx_bytes(&self) -> usize {
+        (**self).approx_bytes()
+    }
+}
+
+impl<T: MemoryFootprint> MemoryFootprint for Option<T> {
+    fn approx_bytes(&self) -> usize {
+        self.as_ref().map_or(0, T::approx_bytes)
+    }
+}
+
+thread_local! {
+    static GIL_RELEASED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Record that the GIL has been released by [`AllowThreads`](crate::AllowThreads) in the current
+/// poll.
+#[cfg(feature = "allow-threads")]
+pub(crate) fn set_gil_released() {
+    GIL_RELEASED.with(|released| released.set(true));
+}
+
+/// Return whether the GIL has been released since the last call, and reset the flag.
+pub(crate) fn take_gil_released() -> bool {
+    GIL_RELEASED.with(|released| released.replace(false))
+}
+
+/// Wrapper for [`Future`]/[`Stream`] asserting that the GIL is held when polled.
+///
+/// [`PyFuture::poll_py`](crate::PyFuture::poll_py)/[`PyStream::poll_next_py`](crate::PyStream::poll_next_py)
+/// always receive a valid `Python` token; but a future relying on the GIL without taking a
+/// token, e.g. using [`Python::assume_gil_acquired`](pyo3::Python::assume_gil_acquired), is
+/// unsound when polled outside of a coroutine, or inside [`AllowThreads`](crate::AllowThreads).
+/// This wrapper panics in this case, instead of triggering undefined behavior.
+///
+/// `PyGILState_Check` is not part of the limited API, so it's not available with `abi3` feature.
+///
+/// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+#[cfg(not(Py_LIMITED_API))]
+#[derive(Debug)]
+#[pin_project]
+pub struct AssertGilHeld<T>(#[pin] pub T);
+
+#[cfg(not(Py_LIMITED_API))]
+fn assert_gil_held() {
+    // SAFETY: `PyGILState_Check` can be called without holding the GIL
+    let held = unsafe { pyo3::ffi::PyGILState_Check() } == 1;
+    assert!(held, "future/stream polled without holding the GIL");
+}
+
+#[cfg(not(Py_LIMITED_API))]
+impl<F: Future> Future for AssertGilHeld<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert_gil_held();
+        self.project().0.poll(cx)
+    }
+}
+
+#[cfg(not(Py_LIMITED_API))]
+impl<S: Stream> Stream for AssertGilHeld<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        assert_gil_held();
+        self.project().0.poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
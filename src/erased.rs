@@ -0,0 +1,336 @@
+//! Type-erased [`PyFuture`] with a C-compatible vtable, enabled by `erased` feature.
+//!
+//! In plugin systems, e.g. Rust dylibs loaded by a host extension module, plugins cannot share
+//! the pyclasses of the host, which are registered in its own module. [`ErasedPyFuture`] is a
+//! `#[repr(C)]` pair of an opaque pointer and an [`ErasedVTable`] of `extern "C"` functions, so
+//! a plugin can return it through a C ABI, and the host wraps it into its own coroutine, e.g.
+//! with [`asyncio::Coroutine::from_erased`](crate::asyncio::Coroutine::from_erased).
+//!
+//! A plugin can build the erased future from any [`PyFuture`] with [`ErasedPyFuture::new`], or
+//! implement the vtable itself and use [`ErasedPyFuture::from_raw_parts`]. The waker is passed
+//! as an [`ErasedWaker`], with its own `extern "C"` vtable, so the plugin may be compiled with
+//! another Rust toolchain, and link its own copy of pyo3, possibly of another version; it must
+//! only be linked to the same Python interpreter as the host.
+//!
+//! As the GIL is acquired by the host pyo3, the plugin pyo3 doesn't know it's held; vtables built
+//! by [`ErasedPyFuture::new`] thus register the GIL to the plugin pyo3 with [`Python::with_gil`],
+//! both when polling and dropping the future, so objects released by the plugin are decref'ed
+//! immediately instead of being deferred to a pool the plugin would never drain.
+//!
+//! # Example
+//!
+//! ```rust
+//! use pyo3::{prelude::*, types::PyDict};
+//! use pyo3_async::{asyncio::Coroutine, erased::ErasedPyFuture};
+//!
+//! // plugin side, e.g. exported with `#[no_mangle]`
+//! extern "C" fn plugin_answer() -> ErasedPyFuture {
+//!     ErasedPyFuture::new(async { PyResult::Ok(42) })
+//! }
+//!
+//! // host side
+//! pyo3::prepare_freethreaded_python();
+//! Python::with_gil(|py| {
+//!     let coroutine = Coroutine::from_erased(plugin_answer());
+//!     let globals = PyDict::new(py);
+//!     globals.set_item("coroutine", Py::new(py, coroutine)?)?;
+//!     let code = "import asyncio; assert asyncio.run(coroutine) == 42";
+//!     py.run(code, Some(globals), None)
+//! })
+//! .unwrap();
+//! ```
+use std::{
+    ffi::c_void,
+    mem::ManuallyDrop,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    ptr,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use pyo3::{ffi, panic::PanicException, prelude::*};
+
+use crate::{utils, PyFuture};
+
+/// Result of [`ErasedVTable::poll`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErasedPoll {
+    /// The future has completed, the result being written to `out`.
+    Ready,
+    /// The future has failed, the exception being written to `out`.
+    Error,
+    /// The future is pending, and will wake the waker.
+    Pending,
+}
+
+/// `extern "C"` vtable of an [`ErasedWaker`], mirroring [`RawWakerVTable`].
+///
+/// None of its functions must unwind.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ErasedWakerVTable {
+    /// Clone the waker pointed by `data`.
+    pub clone: unsafe extern "C" fn(data: *const c_void) -> ErasedWaker,
+    /// Wake the waker pointed by `data`, consuming it.
+    pub wake: unsafe extern "C" fn(data: *const c_void),
+    /// Wake the waker pointed by `data`, without consuming it.
+    pub wake_by_ref: unsafe extern "C" fn(data: *const c_void),
+    /// Drop the waker pointed by `data`.
+    pub drop: unsafe extern "C" fn(data: *const c_void),
+}
+
+/// Waker with a C-compatible layout, passed to [`ErasedVTable::poll`].
+///
+/// The waker passed to `poll` is only borrowed for the duration of the call: it must not be
+/// woken with `wake` nor dropped, but it can be cloned, the clone being owned by the callee.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ErasedWaker {
+    data: *const c_void,
+    vtable: &'static ErasedWakerVTable,
+}
+
+impl ErasedWaker {
+    /// Build an erased waker from a pointer and its vtable.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be valid for the functions of `vtable`, which must implement their
+    /// documented contract; the waker must be sendable to other threads.
+    pub unsafe fn from_raw_parts(data: *const c_void, vtable: &'static ErasedWakerVTable) -> Self {
+        Self { data, vtable }
+    }
+
+    /// Borrow a [`Waker`] as an erased waker, e.g. to poll an erased future.
+    ///
+    /// The returned erased waker must not outlive `waker`, nor be woken with `wake` or dropped.
+    pub fn borrowed(waker: &Waker) -> ManuallyDrop<Self> {
+        let data: *const Waker = waker;
+        ManuallyDrop::new(Self {
+            data: data.cast(),
+            vtable: &ERASED_VTABLE_REF,
+        })
+    }
+
+    /// Call `f` with a [`Waker`] borrowing the erased waker, e.g. to poll a future in a custom
+    /// [`ErasedVTable::poll`]; the erased waker is only cloned if the [`Waker`] is.
+    pub fn with_waker<R>(&self, f: impl FnOnce(&Waker) -> R) -> R {
+        let data: *const Self = self;
+        // SAFETY: `RAW_VTABLE_REF` never consumes the erased waker, which outlives the `Waker`,
+        // as the latter is never dropped
+        let waker = unsafe { Waker::from_raw(RawWaker::new(data.cast(), &RAW_VTABLE_REF)) };
+        f(&ManuallyDrop::new(waker))
+    }
+}
+
+// the waker contract requires it to be sendable
+unsafe impl Send for ErasedWaker {}
+unsafe impl Sync for ErasedWaker {}
+
+impl Clone for ErasedWaker {
+    fn clone(&self) -> Self {
+        // SAFETY: the vtable contract
+        unsafe { (self.vtable.clone)(self.data) }
+    }
+}
+
+impl Drop for ErasedWaker {
+    fn drop(&mut self) {
+        // SAFETY: the vtable contract
+        unsafe { (self.vtable.drop)(self.data) }
+    }
+}
+
+// `Waker` erased as an `ErasedWaker`, borrowed (`ERASED_VTABLE_REF`) or boxed (`ERASED_VTABLE`)
+
+unsafe extern "C" fn erased_clone(data: *const c_void) -> ErasedWaker {
+    let waker = Box::new((*data.cast::<Waker>()).clone());
+    ErasedWaker {
+        data: Box::into_raw(waker).cast(),
+        vtable: &ERASED_VTABLE,
+    }
+}
+
+unsafe extern "C" fn erased_wake(data: *const c_void) {
+    Box::from_raw(data.cast::<Waker>().cast_mut()).wake();
+}
+
+unsafe extern "C" fn erased_wake_by_ref(data: *const c_void) {
+    (*data.cast::<Waker>()).wake_by_ref();
+}
+
+unsafe extern "C" fn erased_drop(data: *const c_void) {
+    drop(Box::from_raw(data.cast::<Waker>().cast_mut()));
+}
+
+unsafe extern "C" fn erased_noop(_data: *const c_void) {}
+
+static ERASED_VTABLE_REF: ErasedWakerVTable = ErasedWakerVTable {
+    clone: erased_clone,
+    wake: erased_wake_by_ref,
+    wake_by_ref: erased_wake_by_ref,
+    drop: erased_noop,
+};
+
+static ERASED_VTABLE: ErasedWakerVTable = ErasedWakerVTable {
+    clone: erased_clone,
+    wake: erased_wake,
+    wake_by_ref: erased_wake_by_ref,
+    drop: erased_drop,
+};
+
+// `ErasedWaker` wrapped in a `Waker`, borrowed (`RAW_VTABLE_REF`) or boxed (`RAW_VTABLE`)
+
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+    let waker = Box::new((*data.cast::<ErasedWaker>()).clone());
+    RawWaker::new(Box::into_raw(waker).cast(), &RAW_VTABLE)
+}
+
+unsafe fn waker_wake(data: *const ()) {
+    let waker = Box::from_raw(data.cast::<ErasedWaker>().cast_mut());
+    let waker = ManuallyDrop::new(*waker);
+    (waker.vtable.wake)(waker.data);
+}
+
+unsafe fn waker_wake_by_ref(data: *const ()) {
+    let waker = &*data.cast::<ErasedWaker>();
+    (waker.vtable.wake_by_ref)(waker.data);
+}
+
+unsafe fn waker_drop(data: *const ()) {
+    drop(Box::from_raw(data.cast::<ErasedWaker>().cast_mut()));
+}
+
+unsafe fn waker_noop(_data: *const ()) {}
+
+static RAW_VTABLE_REF: RawWakerVTable = RawWakerVTable::new(
+    waker_clone,
+    waker_wake_by_ref,
+    waker_wake_by_ref,
+    waker_noop,
+);
+
+static RAW_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+/// `extern "C"` vtable of an [`ErasedPyFuture`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct ErasedVTable {
+    /// Poll the future pointed by `data`, with the GIL held; `waker` is borrowed for the
+    /// duration of the call (see [`ErasedWaker`]). On completion, a new reference to the result,
+    /// or to the exception, is written to `out`.
+    ///
+    /// It must not unwind.
+    pub poll: unsafe extern "C" fn(
+        data: *mut c_void,
+        waker: *const ErasedWaker,
+        out: *mut *mut ffi::PyObject,
+    ) -> ErasedPoll,
+    /// Drop the future pointed by `data`; the GIL may not be held.
+    pub drop: unsafe extern "C" fn(data: *mut c_void),
+}
+
+/// Type-erased [`PyFuture`], with a C-compatible layout.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ErasedPyFuture {
+    data: *mut c_void,
+    vtable: &'static ErasedVTable,
+}
+
+// `new` requires the future to be `Send`, as must the futures of `from_raw_parts`
+unsafe impl Send for ErasedPyFuture {}
+
+struct VTable<F>(F);
+
+impl<F: PyFuture> VTable<F> {
+    const VTABLE: ErasedVTable = ErasedVTable {
+        poll: Self::poll,
+        drop: Self::drop,
+    };
+
+    unsafe extern "C" fn poll(
+        data: *mut c_void,
+        waker: *const ErasedWaker,
+        out: *mut *mut ffi::PyObject,
+    ) -> ErasedPoll {
+        // the future is boxed, so it's never moved
+        let future = Pin::new_unchecked(&mut *data.cast::<F>());
+        // the GIL is already held by the caller, but `with_gil` registers it to this pyo3 copy
+        // (see module documentation)
+        (*waker).with_waker(|waker| {
+            Python::with_gil(|py| {
+                let cx = &mut Context::from_waker(waker);
+                // unwinding through `extern "C"` aborts, so the panic is raised as an exception
+                let poll = panic::catch_unwind(AssertUnwindSafe(|| future.poll_py(py, cx)))
+                    .unwrap_or_else(|payload| {
+                        let msg = utils::panic_message(&*payload);
+                        Poll::Ready(Err(PanicException::new_err(msg)))
+                    });
+                match poll {
+                    Poll::Ready(Ok(obj)) => {
+                        *out = obj.into_ptr();
+                        ErasedPoll::Ready
+                    }
+                    Poll::Ready(Err(err)) => {
+                        *out = err.into_py(py).into_ptr();
+                        ErasedPoll::Error
+                    }
+                    Poll::Pending => ErasedPoll::Pending,
+                }
+            })
+        })
+    }
+
+    unsafe extern "C" fn drop(data: *mut c_void) {
+        // a panicking drop leaks the future instead of aborting
+        let drop_future = || Python::with_gil(|_| drop(Box::from_raw(data.cast::<F>())));
+        panic::catch_unwind(drop_future).ok();
+    }
+}
+
+impl ErasedPyFuture {
+    /// Erase a [`PyFuture`].
+    pub fn new<F: PyFuture + 'static>(future: F) -> Self {
+        Self {
+            data: Box::into_raw(Box::new(future)).cast(),
+            vtable: &VTable::<F>::VTABLE,
+        }
+    }
+
+    /// Build an erased future from a pointer and its vtable.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be valid for the functions of `vtable`, which must implement their
+    /// documented contract, until `vtable.drop` is called on drop; the pointed future must be
+    /// sendable to other threads.
+    pub unsafe fn from_raw_parts(data: *mut c_void, vtable: &'static ErasedVTable) -> Self {
+        Self { data, vtable }
+    }
+}
+
+impl PyFuture for ErasedPyFuture {
+    fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        let mut out = ptr::null_mut();
+        let waker = ErasedWaker::borrowed(cx.waker());
+        // SAFETY: the vtable contract
+        match unsafe { (self.vtable.poll)(self.data, &*waker, &mut out) } {
+            ErasedPoll::Ready => Poll::Ready(Ok(unsafe { PyObject::from_owned_ptr(py, out) })),
+            ErasedPoll::Error => {
+                let exc: &PyAny = unsafe { py.from_owned_ptr(out) };
+                Poll::Ready(Err(PyErr::from_value(exc)))
+            }
+            ErasedPoll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for ErasedPyFuture {
+    fn drop(&mut self) {
+        // SAFETY: the vtable contract
+        unsafe { (self.vtable.drop)(self.data) }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pyo3::Python;
+
+/// [`Future`] calling a poll function with the GIL held, acquired at each poll.
+///
+/// It's the inverse of [`AllowThreads`](crate::AllowThreads): [`PyFuture::poll_py`] provides a
+/// `Python` token, but futures relying on the blanket [`PyFuture`] implementation, e.g. `async`
+/// blocks, don't get one; `GilBound` gives them access to their Python objects in the middle of
+/// an `async` block, without implementing [`PyFuture`] manually. Acquiring the GIL is cheap when
+/// it's already held, e.g. when the future is polled by a coroutine, and it also works on a bare
+/// Rust executor.
+///
+/// # Example
+///
+/// ```rust
+/// use std::task::{Context, Poll};
+///
+/// use pyo3::{prelude::*, types::{PyDict, PyList}};
+/// use pyo3_async::{asyncio::Coroutine, GilBound};
+///
+/// #[pyfunction]
+/// fn wait_len(list: Py<PyList>, n: usize) -> Coroutine {
+///     Coroutine::from_future(async move {
+///         // the list is checked at each poll, yielding to the event loop in between
+///         let len = GilBound::new(|py: Python, cx: &mut Context| {
+///             let len = list.as_ref(py).len();
+///             if len >= n {
+///                 return Poll::Ready(len);
+///             }
+///             cx.waker().wake_by_ref();
+///             Poll::Pending
+///         })
+///         .await;
+///         PyResult::Ok(len)
+///     })
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("wait_len", wrap_pyfunction!(wait_len, py)?)?;
+///     let code = r#"
+/// import asyncio
+/// async def main():
+///     items = []
+///     async def fill():
+///         for i in range(3):
+///             items.append(i)
+///             await asyncio.sleep(0)
+///     task = asyncio.create_task(fill())
+///     assert await wait_len(items, 3) == 3
+///     await task
+/// asyncio.run(main())
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+///
+/// [`PyFuture`]: crate::PyFuture
+/// [`PyFuture::poll_py`]: crate::PyFuture::poll_py
+#[derive(Debug)]
+pub struct GilBound<F>(pub F);
+
+impl<F> GilBound<F> {
+    /// Wrap a poll function.
+    pub fn new<T>(poll: F) -> Self
+    where
+        F: FnMut(Python, &mut Context) -> Poll<T>,
+    {
+        Self(poll)
+    }
+}
+
+impl<F, T> Future for GilBound<F>
+where
+    F: FnMut(Python, &mut Context) -> Poll<T> + Unpin,
+{
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Python::with_gil(|gil| (self.0)(gil, cx))
+    }
+}
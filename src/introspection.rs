@@ -0,0 +1,18 @@
+//! Runtime introspection of the compiled-in features.
+use pyo3::prelude::*;
+
+/// Async backends supported by the coroutines/async generators of this crate.
+pub fn supported_backends() -> &'static [&'static str] {
+    &["asyncio", "trio", "sniffio"]
+}
+
+/// Python function returning [`supported_backends`], to be added to an extension module.
+///
+/// ```rust,ignore
+/// m.add_function(wrap_pyfunction!(pyo3_async::introspection::py_supported_backends, m)?)?;
+/// ```
+#[pyfunction]
+#[pyo3(name = "supported_backends")]
+pub fn py_supported_backends() -> Vec<&'static str> {
+    supported_backends().to_vec()
+}
@@ -0,0 +1,217 @@
+//! [`futures::io`] adapters over Python async file objects, and zero-copy byte buffers.
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::{
+    io::{AsyncBufRead, AsyncRead},
+    Stream,
+};
+use pin_project::pin_project;
+use pyo3::{ffi, prelude::*};
+
+use crate::{
+    compat::{self, intern},
+    sniffio::{await_py, AwaitPy},
+    PyStream,
+};
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// [`AsyncRead`]/[`AsyncBufRead`] wrapper for a Python async reader (in `asyncio` or `trio`
+/// context).
+///
+/// The reader is duck-typed, i.e. any object with an `async read(n)` method returning `bytes`,
+/// e.g. `aiofiles` file handles, `aiohttp` content streams, or `trio` async files. An empty
+/// `bytes` result means EOF. Each `read` coroutine is awaited with [`await_py`], so the async
+/// backend is detected with `sniffio`.
+///
+/// The reader should be polled in the thread where the event loop is running.
+pub struct PyAsyncReader {
+    reader: PyObject,
+    capacity: usize,
+    read: Option<AwaitPy>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl PyAsyncReader {
+    /// Wrap a Python async reader, with a default buffer capacity.
+    pub fn new(reader: impl Into<PyObject>) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, reader)
+    }
+
+    /// Wrap a Python async reader, with the given buffer capacity.
+    ///
+    /// Capacity is passed to `read` at each buffer refill.
+    pub fn with_capacity(capacity: usize, reader: impl Into<PyObject>) -> Self {
+        Self {
+            reader: reader.into(),
+            capacity,
+            read: None,
+            buffer: Vec::with_capacity(capacity),
+            pos: 0,
+        }
+    }
+
+    /// Unwrap the Python async reader, discarding buffered data.
+    pub fn into_inner(self) -> PyObject {
+        self.reader
+    }
+
+    fn poll_refill(&mut self, py: Python<'_>, cx: &mut Context<'_>) -> Poll<PyResult<()>> {
+        if self.read.is_none() {
+            let read = self
+                .reader
+                .call_method1(py, intern!(py, "read"), (self.capacity,))?;
+            self.read = Some(await_py(read));
+        }
+        let res = ready!(self.read.as_mut().unwrap().poll_gil(py, cx));
+        self.read = None;
+        let bytes = res?;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(bytes.extract(py)?);
+        self.pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncBufRead for PyAsyncReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = Pin::into_inner(self);
+        if this.pos >= this.buffer.len() {
+            ready!(Python::with_gil(|gil| this.poll_refill(gil, cx)))?;
+        }
+        Poll::Ready(Ok(&this.buffer[this.pos..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = Pin::into_inner(self);
+        this.pos = (this.pos + amt).min(this.buffer.len());
+    }
+}
+
+impl AsyncRead for PyAsyncReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Read-only Python buffer owning Rust bytes, exposed through the buffer protocol.
+///
+/// # Safety contract
+///
+/// The bytes are owned by the Python object, and never mutated nor reallocated after its
+/// creation. Each buffer view, e.g. a `memoryview`, holds a strong reference to the object (the
+/// `obj` field of `Py_buffer`), so the bytes are only freed once the object and all its views
+/// have been released, in whichever thread drops the last reference. Writable views are
+/// rejected with `BufferError`, so Python code cannot mutate the bytes either.
+///
+/// The buffer protocol is not part of the limited API before Python 3.11, so it's not
+/// available with `abi3` feature of older versions.
+#[cfg(any(not(Py_LIMITED_API), Py_3_11))]
+#[pyclass(frozen)]
+pub struct ByteBuffer(Vec<u8>);
+
+#[cfg(any(not(Py_LIMITED_API), Py_3_11))]
+impl ByteBuffer {
+    /// Wrap bytes without copying them.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+#[cfg(any(not(Py_LIMITED_API), Py_3_11))]
+#[pymethods]
+impl ByteBuffer {
+    unsafe fn __getbuffer__(
+        slf: PyRef<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: std::os::raw::c_int,
+    ) -> PyResult<()> {
+        let bytes = &slf.0;
+        // `view.obj` is set to a new reference to `slf`, keeping the bytes alive
+        let res = ffi::PyBuffer_FillInfo(
+            view,
+            compat::as_ptr(&slf),
+            bytes.as_ptr() as *mut _,
+            bytes.len() as ffi::Py_ssize_t,
+            1,
+            flags,
+        );
+        if res == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Wrap bytes into a read-only `memoryview`, without copying them (see [`ByteBuffer`]).
+///
+/// With `abi3` feature of Python versions older than 3.11, [`ByteBuffer`] is not available, so
+/// the bytes are copied into a `bytes` object instead.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::{prelude::*, types::PyDict};
+/// use pyo3_async::io;
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let globals = PyDict::new(py);
+///     globals.set_item("view", io::memoryview(py, b"zero-copy".to_vec())?)?;
+///     let code = r#"
+/// assert view.readonly and view == b"zero-copy"
+/// "#;
+///     py.run(code, Some(globals), None)
+/// })
+/// .unwrap();
+/// ```
+pub fn memoryview(py: Python, bytes: Vec<u8>) -> PyResult<PyObject> {
+    #[cfg(any(not(Py_LIMITED_API), Py_3_11))]
+    let buffer = Py::new(py, ByteBuffer::new(bytes))?;
+    #[cfg(not(any(not(Py_LIMITED_API), Py_3_11)))]
+    let buffer: Py<pyo3::types::PyBytes> = pyo3::types::PyBytes::new(py, &bytes).into();
+    unsafe {
+        PyObject::from_owned_ptr_or_err(py, ffi::PyMemoryView_FromObject(compat::as_ptr(&buffer)))
+    }
+}
+
+/// [`PyStream`] yielding the byte chunks of a stream as read-only `memoryview`s, without copying
+/// them (see [`asyncio::AsyncGenerator::from_byte_stream`](crate::asyncio::AsyncGenerator::from_byte_stream)).
+#[pin_project]
+pub struct MemoryViews<S>(#[pin] pub S);
+
+impl<S, E> PyStream for MemoryViews<S>
+where
+    S: Stream<Item = Result<Vec<u8>, E>> + Send,
+    PyErr: From<E>,
+{
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let chunk = ready!(self.project().0.poll_next(cx));
+        Poll::Ready(chunk.map(|chunk| memoryview(py, chunk?)))
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
@@ -8,24 +8,58 @@ use std::{
 use futures::Stream;
 use pyo3::prelude::*;
 
+use crate::conversions::TryIntoPy;
+
 #[cfg(feature = "allow-threads")]
 mod allow_threads;
 mod async_generator;
+mod async_resource;
 pub mod asyncio;
+mod broadcast;
+pub mod budget;
+#[cfg(feature = "tokio")]
+pub mod channel;
+pub mod combinators;
+mod compat;
+pub mod conversions;
+pub mod convert;
 mod coroutine;
+#[cfg(feature = "debug")]
+pub mod debug;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "erased")]
+pub mod erased;
+mod gil_bound;
+pub mod introspection;
+pub mod io;
+pub mod manual;
+pub mod progress;
+#[cfg(feature = "tokio")]
+pub mod runtime;
 pub mod sniffio;
+pub mod stream;
+#[cfg(feature = "strict-checks")]
+pub mod strict;
 pub mod trio;
 mod utils;
 
 #[cfg(feature = "allow-threads")]
 pub use allow_threads::{AllowThreads, AllowThreadsExt};
+pub use async_generator::ErrorPolicy;
+pub use broadcast::LagPolicy;
+#[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+pub use coroutine::WakePriority;
+pub use gil_bound::GilBound;
 #[cfg(feature = "macros")]
 pub use pyo3_async_macros::{pyfunction, pymethods};
+pub use sniffio::{await_py, AwaitPy};
 
 /// GIL-bound [`Future`].
 ///
 /// Provided with a blanket implementation for [`Future`]. GIL is maintained during polling
-/// operation. To release the GIL, see [`AllowThreads`].
+/// operation. To release the GIL, see [`AllowThreads`]; to access Python objects from a plain
+/// [`Future`], e.g. in an `async` block, see [`GilBound`].
 pub trait PyFuture: Send {
     /// GIL-bound [`Future::poll`].
     fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>>;
@@ -34,16 +68,303 @@ pub trait PyFuture: Send {
 impl<F, T, E> PyFuture for F
 where
     F: Future<Output = Result<T, E>> + Send,
-    T: IntoPy<PyObject> + Send,
+    T: TryIntoPy + Send,
     E: Send,
     PyErr: From<E>,
 {
     fn poll_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
         let poll = self.poll(cx);
-        poll.map_ok(|ok| ok.into_py(py)).map_err(PyErr::from)
+        poll.map(|res| res.map_err(PyErr::from)?.try_into_py(py))
+    }
+}
+
+/// Extension trait for [`PyStream`] adapters.
+///
+/// It is implemented for every types.
+pub trait PyStreamExt: Sized {
+    /// Flatten a stream of Python async generators, iterating each one to exhaustion in turn
+    /// (see [`asyncio::FlattenAsync`]).
+    fn flatten_async(self) -> asyncio::FlattenAsync<Self>
+    where
+        Self: PyStream,
+    {
+        asyncio::FlattenAsync::new(self)
+    }
+
+    /// Yield the successive states of `f(state, item)`, starting from `init`; `f` is a Python
+    /// callable, whose result is awaited if it's an awaitable (see [`sniffio::ScanPy`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use futures::stream;
+    /// use pyo3::{prelude::*, types::PyDict};
+    /// use pyo3_async::{asyncio::AsyncGenerator, PyStreamExt};
+    ///
+    /// pyo3::prepare_freethreaded_python();
+    /// Python::with_gil(|py| {
+    ///     let globals = PyDict::new(py);
+    ///     let code = r#"
+    /// import asyncio
+    /// async def add(total, item):
+    ///     await asyncio.sleep(0.001)
+    ///     return total + item
+    /// async def collect(agen):
+    ///     return [item async for item in agen]
+    /// "#;
+    ///     py.run(code, Some(globals), None)?;
+    ///     let add = globals.get_item("add")?.unwrap();
+    ///     let items = stream::iter((1..=4).map(PyResult::Ok));
+    ///     let running_sum = items.scan_py(0.into_py(py), add.into());
+    ///     let agen = Py::new(py, AsyncGenerator::from_stream(running_sum))?;
+    ///     globals.set_item("agen", agen)?;
+    ///     let sums = py.eval("asyncio.run(collect(agen))", Some(globals), None)?;
+    ///     assert_eq!(sums.extract::<Vec<i32>>()?, [1, 3, 6, 10]);
+    ///     PyResult::Ok(())
+    /// })
+    /// .unwrap();
+    /// ```
+    fn scan_py(self, init: PyObject, f: PyObject) -> sniffio::ScanPy<Self>
+    where
+        Self: PyStream,
+    {
+        sniffio::ScanPy::new(self, init, f)
+    }
+
+    /// Terminate the stream at the first error matching `predicate`, instead of yielding it;
+    /// other errors are yielded as is (see [`stream::EndOnError`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use futures::stream;
+    /// use pyo3::{
+    ///     exceptions::{PyConnectionResetError, PyValueError},
+    ///     prelude::*,
+    ///     types::PyDict,
+    /// };
+    /// use pyo3_async::{asyncio::AsyncGenerator, PyStreamExt};
+    ///
+    /// fn items(err: PyErr) -> impl futures::Stream<Item = PyResult<i32>> {
+    ///     stream::iter([Ok(1), Err(err), Ok(2)])
+    /// }
+    ///
+    /// fn disconnected(py: Python, err: &PyErr) -> bool {
+    ///     err.is_instance_of::<PyConnectionResetError>(py)
+    /// }
+    ///
+    /// pyo3::prepare_freethreaded_python();
+    /// Python::with_gil(|py| {
+    ///     let reset = items(PyConnectionResetError::new_err("reset"));
+    ///     let invalid = items(PyValueError::new_err("invalid"));
+    ///     let globals = PyDict::new(py);
+    ///     let reset = AsyncGenerator::from_stream(reset.end_on_error(disconnected));
+    ///     let invalid = AsyncGenerator::from_stream(invalid.end_on_error(disconnected));
+    ///     globals.set_item("reset", Py::new(py, reset)?)?;
+    ///     globals.set_item("invalid", Py::new(py, invalid)?)?;
+    ///     let code = r#"
+    /// import asyncio
+    /// async def collect(agen):
+    ///     items = []
+    ///     try:
+    ///         async for item in agen:
+    ///             items.append(item)
+    ///     except ValueError:
+    ///         items.append("error")
+    ///     return items
+    /// assert asyncio.run(collect(reset)) == [1]
+    /// assert asyncio.run(collect(invalid)) == [1, "error"]
+    /// "#;
+    ///     py.run(code, Some(globals), None)
+    /// })
+    /// .unwrap();
+    /// ```
+    fn end_on_error<P>(self, predicate: P) -> stream::EndOnError<Self, P>
+    where
+        Self: PyStream,
+        P: FnMut(Python, &PyErr) -> bool + Send,
+    {
+        stream::EndOnError::new(self, predicate)
+    }
+
+    /// Yield `sentinel` each time no item arrives within `interval`, e.g. a keep-alive ping of a
+    /// quiet server-sent events stream, instead of ending or raising (see
+    /// [`sniffio::Heartbeat`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use pyo3::{prelude::*, types::PyDict};
+    /// use pyo3_async::{
+    ///     asyncio::{AsyncGenerator, AsyncGeneratorWrapper},
+    ///     PyStreamExt,
+    /// };
+    ///
+    /// pyo3::prepare_freethreaded_python();
+    /// Python::with_gil(|py| {
+    ///     let globals = PyDict::new(py);
+    ///     let code = r#"
+    /// import asyncio
+    /// async def quiet():
+    ///     yield 1
+    ///     await asyncio.sleep(0.1)
+    ///     yield 2
+    /// async def collect(agen):
+    ///     return [item async for item in agen]
+    /// "#;
+    ///     py.run(code, Some(globals), None)?;
+    ///     let quiet = py.eval("quiet()", Some(globals), None)?;
+    ///     let stream = AsyncGeneratorWrapper::new(quiet);
+    ///     let ping = "ping".into_py(py);
+    ///     let agen = AsyncGenerator::from_stream(stream.heartbeat(Duration::from_millis(20), ping));
+    ///     globals.set_item("agen", Py::new(py, agen)?)?;
+    ///     let code = r#"
+    /// items = asyncio.run(collect(agen))
+    /// assert items[0] == 1 and items[-1] == 2
+    /// assert len(items) > 2 and set(items[1:-1]) == {"ping"}
+    /// "#;
+    ///     py.run(code, Some(globals), None)
+    /// })
+    /// .unwrap();
+    /// ```
+    fn heartbeat(
+        self,
+        interval: std::time::Duration,
+        sentinel: PyObject,
+    ) -> sniffio::Heartbeat<Self>
+    where
+        Self: PyStream,
+    {
+        sniffio::Heartbeat::new(self, interval, sentinel)
+    }
+
+    /// Prefetch items while their estimated size, given by `size_of`, is below `max_bytes`,
+    /// bounding the memory of the buffer for streams of large items (see
+    /// [`stream::BufferedBytes`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use futures::{stream, StreamExt};
+    /// use pyo3::{prelude::*, types::{PyBytes, PyDict}};
+    /// use pyo3_async::{asyncio::AsyncGenerator, PyStreamExt};
+    ///
+    /// pyo3::prepare_freethreaded_python();
+    /// Python::with_gil(|py| {
+    ///     let chunks = stream::iter([3, 5, 2]).map(|size| {
+    ///         Python::with_gil(|py| PyResult::Ok(PyBytes::new(py, &vec![0; size]).to_object(py)))
+    ///     });
+    ///     let size_of = |py: Python, chunk: &PyObject| chunk.as_ref(py).len().unwrap_or(0);
+    ///     let agen = AsyncGenerator::from_stream(chunks.buffered_bytes(1 << 20, size_of));
+    ///     let globals = PyDict::new(py);
+    ///     globals.set_item("agen", Py::new(py, agen)?)?;
+    ///     let code = r#"
+    /// import asyncio
+    /// async def main():
+    ///     return [len(chunk) async for chunk in agen]
+    /// assert asyncio.run(main()) == [3, 5, 2]
+    /// "#;
+    ///     py.run(code, Some(globals), None)
+    /// })
+    /// .unwrap();
+    /// ```
+    fn buffered_bytes<F>(self, max_bytes: usize, size_of: F) -> stream::BufferedBytes<Self, F>
+    where
+        Self: PyStream,
+        F: FnMut(Python, &PyObject) -> usize + Send,
+    {
+        stream::BufferedBytes::new(self, max_bytes, size_of)
+    }
+}
+
+impl<T> PyStreamExt for T {}
+
+/// Extension trait for [`PyFuture`] adapters.
+///
+/// It is implemented for every types.
+pub trait PyFutureExt: Sized {
+    /// Convert the collection result incrementally, at most `chunk_size` elements per coroutine
+    /// step (see [`convert::ChunkedConvert`]).
+    fn convert_chunked<C, E>(self, chunk_size: usize) -> convert::ChunkedConvert<Self, C>
+    where
+        Self: Future<Output = Result<C, E>>,
+        C: convert::ChunkedCollection,
+    {
+        convert::ChunkedConvert::new(self, chunk_size)
+    }
+
+    /// Convert the mapping result preserving its iteration order (see
+    /// [`convert::IntoPyOrdered`]).
+    fn convert_ordered(self) -> convert::Ordered<Self> {
+        convert::Ordered(self)
+    }
+
+    /// Yield to the event loop at the first [`budget::checkpoint`] reached after `budget` of
+    /// polling time (see [`budget::YieldEvery`]).
+    fn yield_every(self, budget: std::time::Duration) -> budget::YieldEvery<Self>
+    where
+        Self: Future,
+    {
+        budget::YieldEvery::new(self, budget)
+    }
+
+    /// Reschedule the future when it returns `Pending` without registering its waker, at most
+    /// `max_spins` consecutive times (see [`budget::PollLoop`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{future::Future, pin::Pin, task::{Context, Poll}};
+    ///
+    /// use pyo3::{prelude::*, types::PyDict};
+    /// use pyo3_async::{asyncio::Coroutine, PyFutureExt};
+    ///
+    /// // returns `Pending` a few times without registering the waker
+    /// struct Spin(usize);
+    /// impl Future for Spin {
+    ///     type Output = PyResult<usize>;
+    ///     fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
+    ///         if self.0 == 0 {
+    ///             return Poll::Ready(Ok(42));
+    ///         }
+    ///         self.0 -= 1;
+    ///         Poll::Pending
+    ///     }
+    /// }
+    ///
+    /// pyo3::prepare_freethreaded_python();
+    /// Python::with_gil(|py| {
+    ///     let globals = PyDict::new(py);
+    ///     let spin = Py::new(py, Coroutine::from_future(Spin(5).poll_loop(5)))?;
+    ///     let stuck = Py::new(py, Coroutine::from_future(Spin(6).poll_loop(5)))?;
+    ///     globals.set_item("spin", spin)?;
+    ///     globals.set_item("stuck", stuck)?;
+    ///     let code = r#"
+    /// import asyncio
+    /// assert asyncio.run(spin) == 42
+    /// try:
+    ///     asyncio.run(stuck)
+    /// except RuntimeError as err:
+    ///     assert "6 consecutive times" in str(err)
+    /// else:
+    ///     assert False
+    /// "#;
+    ///     py.run(code, Some(globals), None)
+    /// })
+    /// .unwrap();
+    /// ```
+    fn poll_loop(self, max_spins: usize) -> budget::PollLoop<Self>
+    where
+        Self: PyFuture,
+    {
+        budget::PollLoop::new(self, max_spins)
     }
 }
 
+impl<T> PyFutureExt for T {}
+
 /// GIL-bound [`Stream`].
 ///
 /// Provided with a blanket implementation for [`Stream`]. GIL is maintained during polling
@@ -57,12 +378,28 @@ pub trait PyStream: Send {
         py: Python,
         cx: &mut Context,
     ) -> Poll<Option<PyResult<PyObject>>>;
+
+    /// Bounds on the remaining length of the stream (see [`Stream::size_hint`]).
+    ///
+    /// [`Stream::size_hint`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html#method.size_hint
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    /// Handle an exception thrown into the stream by async generator `athrow` method, when no
+    /// throw callback is provided.
+    ///
+    /// Returning `Ok` resumes the iteration, `athrow` then returning the next item; the exception
+    /// is raised otherwise, which is the default.
+    fn throw_py(self: Pin<&mut Self>, _py: Python, exc: PyErr) -> PyResult<()> {
+        Err(exc)
+    }
 }
 
 impl<S, T, E> PyStream for S
 where
     S: Stream<Item = Result<T, E>> + Send,
-    T: IntoPy<PyObject> + Send,
+    T: TryIntoPy + Send,
     E: Send,
     PyErr: From<E>,
 {
@@ -72,10 +409,77 @@ where
         cx: &mut Context,
     ) -> Poll<Option<PyResult<PyObject>>> {
         let poll = self.poll_next(cx);
-        poll.map_ok(|ok| ok.into_py(py)).map_err(PyErr::from)
+        poll.map(|item| item.map(|res| res.map_err(PyErr::from)?.try_into_py(py)))
     }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        self.size_hint()
+    }
+}
+
+/// GIL-bound [`PyStream`] with asynchronous cleanup.
+///
+/// Async generator `aclose` method drives [`PyStreamClose::poll_close_py`] to completion before
+/// dropping the stream (see [`asyncio::AsyncGenerator::from_closeable_stream`]).
+pub trait PyStreamClose: PyStream {
+    /// Poll the stream cleanup, e.g. flushing a buffer.
+    fn poll_close_py(self: Pin<&mut Self>, py: Python, cx: &mut Context) -> Poll<PyResult<()>>;
 }
 
 /// Callback for Python coroutine `throw` method (see [`asyncio::Coroutine::new`]) and
 /// async generator `athrow` method (see [`asyncio::AsyncGenerator::new`]).
 pub type ThrowCallback = Box<dyn FnMut(Python, Option<PyErr>) + Send>;
+
+/// Override of the object yielded by a suspended coroutine (see
+/// [`asyncio::Coroutine::with_yield`]).
+///
+/// It is called with the object the backend would have yielded, e.g. an `asyncio.Future` or
+/// trio `WaitTaskRescheduled` sentinel, and returns the object yielded instead. The returned
+/// object must make the event loop resume the coroutine once the backend object is resolved,
+/// i.e. when the coroutine is woken, typically by wrapping it; the value sent back to the
+/// coroutine must be the one the backend expects, e.g. `None` for asyncio.
+///
+/// It is not called when the coroutine has been woken during its poll, as the backend then
+/// yields a bare checkpoint, nor by the async generators.
+///
+/// # Example
+///
+/// ```rust
+/// use std::task::Poll;
+///
+/// use pyo3::{prelude::*, types::PyTuple};
+/// use pyo3_async::manual::Coroutine;
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let coroutine = Coroutine::from_step_fn(|_, _| Poll::Pending)
+///         .with_yield(|py, wakeup| Ok(("custom", wakeup).into_py(py)));
+///     let coroutine = Py::new(py, coroutine)?.into_ref(py);
+///     let yielded: &PyTuple = coroutine.call_method1("send", (py.None(),))?.downcast()?;
+///     assert_eq!(yielded.get_item(0)?.extract::<&str>()?, "custom");
+///     assert!(!yielded.get_item(1)?.getattr("woken")?.extract::<bool>()?);
+///     PyResult::Ok(())
+/// })
+/// .unwrap();
+/// ```
+pub type YieldCallback = Box<dyn FnMut(Python, PyObject) -> PyResult<PyObject> + Send>;
+
+/// Initialize the crate, to be called in the `#[pymodule]` function of the extension.
+///
+/// It registers the `os.register_at_fork` handler used to detect forked processes in the
+/// wake fast-path. Without this call, the handler is registered when the first coroutine is
+/// suspended, a registration error being then reported as unraisable.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::prelude::*;
+///
+/// #[pymodule]
+/// fn my_module(py: Python, _m: &PyModule) -> PyResult<()> {
+///     pyo3_async::init(py)
+/// }
+/// ```
+pub fn init(py: Python) -> PyResult<()> {
+    utils::register_fork_handler(py)
+}
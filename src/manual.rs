@@ -0,0 +1,99 @@
+//! Coroutine and async generator implementation not depending on any event loop, for testing
+//! purpose.
+//!
+//! Coroutines can be driven step by step with `send(None)`, without a running event loop. Each
+//! suspension yields a [`Wakeup`], whose `woken` attribute tells if the coroutine has been woken
+//! since, i.e. if it is worth sending `None` again to make progress.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::task::Poll;
+//!
+//! use pyo3::{exceptions::PyStopIteration, prelude::*};
+//! use pyo3_async::manual::Coroutine;
+//!
+//! pyo3::prepare_freethreaded_python();
+//! Python::with_gil(|py| {
+//!     let mut steps = 0;
+//!     let coroutine = Coroutine::from_step_fn(move |py, cx| {
+//!         steps += 1;
+//!         if steps < 3 {
+//!             cx.waker().wake_by_ref();
+//!             return Poll::Pending;
+//!         }
+//!         Poll::Ready(Ok(steps.into_py(py)))
+//!     });
+//!     let coroutine = Py::new(py, coroutine)?.into_ref(py);
+//!     for _ in 0..2 {
+//!         let wakeup = coroutine.call_method1("send", (py.None(),))?;
+//!         assert!(wakeup.getattr("woken")?.extract::<bool>()?);
+//!     }
+//!     let err = coroutine.call_method1("send", (py.None(),)).unwrap_err();
+//!     assert!(err.is_instance_of::<PyStopIteration>(py));
+//!     assert_eq!(err.value(py).getattr("value")?.extract::<i32>()?, 3);
+//!     PyResult::Ok(())
+//! })
+//! .unwrap();
+//! ```
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::{coroutine, utils};
+
+/// Object yielded by a suspended [`Coroutine`].
+#[pyclass]
+pub struct Wakeup(Arc<AtomicBool>);
+
+#[pymethods]
+impl Wakeup {
+    /// Whether the coroutine has been woken since it yielded this object.
+    #[getter]
+    fn woken(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Wakeup woken={}>", self.woken())
+    }
+}
+
+pub(crate) struct Waker(Arc<AtomicBool>);
+
+impl coroutine::CoroutineWaker for Waker {
+    const BACKEND: &'static str = "manual";
+
+    fn new(_py: Python) -> PyResult<Self> {
+        Ok(Waker(Arc::new(AtomicBool::new(false))))
+    }
+
+    fn yield_(&self, py: Python) -> PyResult<PyObject> {
+        Ok(Py::new(py, Wakeup(self.0.clone()))?.into_py(py))
+    }
+
+    fn checkpoint(&self, py: Python) -> PyResult<PyObject> {
+        self.0.store(true, Ordering::Release);
+        self.yield_(py)
+    }
+
+    fn run_soon_threadsafe(_py: Python) -> PyResult<PyObject> {
+        // async generator streams are then dropped synchronously
+        Err(PyRuntimeError::new_err(
+            "no event loop with manual coroutines",
+        ))
+    }
+
+    fn wake(&self, _py: Python) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn wake_threadsafe(&self, _py: Python) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+utils::generate!(Waker);
@@ -0,0 +1,99 @@
+//! Progress reporting of long-running futures.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use pin_project::pin_project;
+use pyo3::{exceptions::PyStopAsyncIteration, prelude::*};
+
+use crate::{PyFuture, PyStream};
+
+/// Handle given to a [`ProgressFuture`] future, to report progress updates.
+#[derive(Debug)]
+pub struct ProgressSender<U>(UnboundedSender<U>);
+
+impl<U> Clone for ProgressSender<U> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<U> ProgressSender<U> {
+    /// Send a progress update.
+    ///
+    /// Updates are buffered until they are yielded; they are discarded if the stream has been
+    /// dropped.
+    pub fn send(&self, update: U) {
+        self.0.unbounded_send(update).ok();
+    }
+}
+
+/// [`PyStream`] yielding the progress updates of a future, and then its result.
+///
+/// When wrapped in an async generator, e.g. with
+/// [`asyncio::AsyncGenerator::from_stream`](crate::asyncio::AsyncGenerator::from_stream),
+/// progress updates can be iterated with `async for`; the future result is then passed to the
+/// final `StopAsyncIteration`, i.e. retrieved with `exc.args[0]` when calling `ag.__anext__()`
+/// directly, while an error of the future is raised as is.
+///
+/// The future is only polled when the async generator is iterated; dropping the generator, e.g.
+/// with `aclose`, drops the future, which is then cancelled.
+#[pin_project]
+pub struct ProgressFuture<F, U> {
+    #[pin]
+    future: F,
+    updates: UnboundedReceiver<U>,
+    result: Option<PyResult<PyObject>>,
+    done: bool,
+}
+
+impl<F: PyFuture, U> ProgressFuture<F, U> {
+    /// Instantiate the future with a [`ProgressSender`].
+    pub fn new(future: impl FnOnce(ProgressSender<U>) -> F) -> Self {
+        let (sender, updates) = mpsc::unbounded();
+        Self {
+            future: future(ProgressSender(sender)),
+            updates,
+            result: None,
+            done: false,
+        }
+    }
+}
+
+impl<F, U> PyStream for ProgressFuture<F, U>
+where
+    F: PyFuture,
+    U: IntoPy<PyObject> + Send,
+{
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if this.result.is_none() {
+            if let Poll::Ready(res) = this.future.poll_py(py, cx) {
+                *this.result = Some(res);
+            }
+        }
+        // updates sent before the future completion are yielded before its result
+        if let Poll::Ready(Some(update)) = this.updates.poll_next_unpin(cx) {
+            return Poll::Ready(Some(Ok(update.into_py(py))));
+        }
+        let Some(res) = this.result.take() else {
+            return Poll::Pending;
+        };
+        *this.done = true;
+        Poll::Ready(Some(
+            res.and_then(|res| Err(PyStopAsyncIteration::new_err(res))),
+        ))
+    }
+}
@@ -0,0 +1,87 @@
+//! `tokio` runtime integration.
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    thread::LocalKey,
+};
+
+use pin_project::pin_project;
+use pyo3::{exceptions::PyRuntimeError, PyErr, PyResult};
+use tokio::{
+    runtime::Handle,
+    task::{JoinHandle, LocalSet},
+};
+
+/// Wrap a future to be polled inside a thread-local [`LocalSet`].
+///
+/// [`LocalSet`] is entered at each poll, so the future can use [`tokio::task::spawn_local`] to
+/// run `!Send` tasks; these tasks are then driven while the future is polled.
+///
+/// Because [`LocalSet`] is thread-local, the future must always be polled in the same thread,
+/// which is the case of coroutines polled by a single-threaded event loop. Spawned tasks only
+/// make progress while a future using the [`LocalSet`] is polled.
+pub fn with_local_set<F: Future>(
+    local_set: &'static LocalKey<LocalSet>,
+    future: F,
+) -> WithLocalSet<F> {
+    WithLocalSet { local_set, future }
+}
+
+/// Future returned by [`with_local_set`].
+#[pin_project]
+pub struct WithLocalSet<F> {
+    local_set: &'static LocalKey<LocalSet>,
+    #[pin]
+    future: F,
+}
+
+impl<F: Future> Future for WithLocalSet<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut future = this.future;
+        this.local_set
+            .with(|local_set| pin!(local_set.run_until(future.as_mut())).poll(cx))
+    }
+}
+
+/// Spawn a future on a tokio runtime, returning a future of its output which aborts the task when
+/// dropped.
+///
+/// Dropping a [`JoinHandle`] detaches the task without cancelling it. On the contrary, when the
+/// returned future is wrapped in a coroutine, cancelling the Python task awaiting the coroutine,
+/// or closing the coroutine, drops the future and thus aborts the spawned task.
+///
+/// A panic of the spawned task is raised as `RuntimeError`.
+pub fn spawn<F, T, E>(handle: &Handle, future: F) -> AbortOnDrop<T>
+where
+    F: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+    PyErr: From<E>,
+{
+    AbortOnDrop(handle.spawn(async move { future.await.map_err(PyErr::from) }))
+}
+
+/// Future returned by [`spawn`].
+#[derive(Debug)]
+pub struct AbortOnDrop<T>(JoinHandle<PyResult<T>>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = PyResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map(|res| match res {
+            Ok(res) => res,
+            Err(err) => Err(PyRuntimeError::new_err(format!("tokio task failed: {err}"))),
+        })
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -1,10 +1,106 @@
 //! `asyncio`/`trio` compatible coroutine and async generator implementation, lazily specialized
 //! using `sniffio`.
-use pyo3::{exceptions::PyRuntimeError, prelude::*};
+//!
+//! If `sniffio` is not installed, only `asyncio` is supported.
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
 
-use crate::{asyncio, coroutine, trio, utils};
+use futures::FutureExt;
+use pin_project::pin_project;
+use pyo3::{exceptions::PyRuntimeError, prelude::*, sync::GILOnceCell};
 
-utils::module!(Sniffio, "sniffio", current_async_library);
+use crate::{asyncio, compat::intern, coroutine, trio, utils, PyStream};
+
+crate::cached_import!(Sniffio, "sniffio", current_async_library);
+crate::cached_import!(
+    SniffioImpl,
+    "sniffio._impl",
+    thread_local,
+    current_async_library_cvar
+);
+
+/// Private state of `sniffio`, looked up directly by [`current_async_library`].
+///
+/// Only sniffio 1.x is known to store the current library this way; other versions, or missing
+/// attributes, fall back to calling the public `sniffio.current_async_library`.
+fn sniffio_impl(py: Python<'_>) -> Option<&SniffioImpl> {
+    static SUPPORTED: GILOnceCell<bool> = GILOnceCell::new();
+    // like imports, the check is not cached while sniffio is not importable
+    let supported = SUPPORTED.get_or_try_init(py, || {
+        let version = py.import("sniffio")?.getattr("__version__");
+        PyResult::Ok(
+            version
+                .and_then(PyAny::extract::<&str>)
+                .is_ok_and(|v| v.starts_with("1.")),
+        )
+    });
+    supported.ok()?.then(|| SniffioImpl::get(py).ok()).flatten()
+}
+
+/// `sniffio.current_async_library`, with its lookups done directly instead of calling the Python
+/// function, as it is called for every coroutine.
+///
+/// With `asyncio`, the current task is also returned, to be reused by the waker.
+///
+/// If `sniffio` is not installed, only `asyncio` is detected, using `asyncio.get_running_loop`.
+fn current_async_library(py: Python) -> PyResult<(PyObject, Option<PyObject>)> {
+    let sniffio = match Sniffio::get(py) {
+        Ok(sniffio) => sniffio,
+        Err(err) => {
+            if asyncio::Asyncio::get(py)?
+                .get_running_loop
+                .call0(py)
+                .is_ok()
+            {
+                return Ok((intern!(py, "asyncio").into(), None));
+            }
+            let msg = "unsupported runtime: no running asyncio event loop \
+                (sniffio is not installed, install it to enable trio detection)";
+            let exc = PyRuntimeError::new_err(msg);
+            exc.set_cause(py, Some(err));
+            return Err(exc);
+        }
+    };
+    if let Some(sniffio_impl) = sniffio_impl(py) {
+        let name = sniffio_impl.thread_local.getattr(py, intern!(py, "name"))?;
+        if !name.is_none(py) {
+            return Ok((name, None));
+        }
+        let cvar = &sniffio_impl.current_async_library_cvar;
+        let name = cvar.call_method0(py, intern!(py, "get"))?;
+        if !name.is_none(py) {
+            return Ok((name, None));
+        }
+        let asyncio = asyncio::Asyncio::get(py)?;
+        if let Ok(task) = asyncio.current_task.call0(py) {
+            if !task.is_none(py) {
+                return Ok((intern!(py, "asyncio").into(), Some(task)));
+            }
+        }
+    }
+    // raise the sniffio error, or detect other libraries
+    Ok((sniffio.current_async_library.call0(py)?, None))
+}
+
+/// Error raised when a coroutine is awaited without the event loop it `requires`, naming the
+/// detected async library, if any.
+pub(crate) fn wrong_async_library(py: Python, requires: &str) -> PyErr {
+    let detected = match current_async_library(py) {
+        Ok((name, _)) => name.extract::<String>(py).ok(),
+        // without sniffio, trio is detected by its current task
+        Err(_) => (trio::Trio::get(py).is_ok_and(|trio| trio.current_task.call0(py).is_ok()))
+            .then(|| "trio".into()),
+    };
+    let awaited = match detected {
+        Some(library) => format!("it was awaited under {library} (detected)"),
+        None => "it was awaited outside of any event loop".into(),
+    };
+    PyRuntimeError::new_err(format!("this coroutine requires {requires}; {awaited}"))
+}
 
 enum Waker {
     Asyncio(asyncio::Waker),
@@ -12,15 +108,45 @@ enum Waker {
 }
 
 impl coroutine::CoroutineWaker for Waker {
+    const BACKEND: &'static str = "sniffio(unresolved)";
+
     fn new(py: Python) -> PyResult<Self> {
-        let sniffed = Sniffio::get(py)?.current_async_library.call0(py)?;
+        let (sniffed, task) = current_async_library(py)?;
         match sniffed.extract(py)? {
-            "asyncio" => Ok(Self::Asyncio(asyncio::Waker::new(py)?)),
+            "asyncio" => Ok(Self::Asyncio(match task {
+                Some(task) => asyncio::Waker::with_task(py, task)?,
+                None => asyncio::Waker::new(py)?,
+            })),
             "trio" => Ok(Self::Trio(trio::Waker::new(py)?)),
             rt => Err(PyRuntimeError::new_err(format!("unsupported runtime {rt}"))),
         }
     }
 
+    fn backend(&self) -> &'static str {
+        match self {
+            Self::Asyncio(_) => "sniffio(asyncio)",
+            Self::Trio(_) => "sniffio(trio)",
+        }
+    }
+
+    fn current_backend(py: Python) -> &'static str {
+        let sniffed = current_async_library(py);
+        match sniffed.as_ref().map(|(s, _)| s.extract(py)) {
+            Ok(Ok("asyncio")) => "sniffio(asyncio)",
+            Ok(Ok("trio")) => "sniffio(trio)",
+            _ => Self::BACKEND,
+        }
+    }
+
+    fn run_soon_threadsafe(py: Python) -> PyResult<PyObject> {
+        let (sniffed, _) = current_async_library(py)?;
+        match sniffed.extract(py)? {
+            "asyncio" => asyncio::Waker::run_soon_threadsafe(py),
+            "trio" => trio::Waker::run_soon_threadsafe(py),
+            rt => Err(PyRuntimeError::new_err(format!("unsupported runtime {rt}"))),
+        }
+    }
+
     fn yield_(&self, py: Python) -> PyResult<PyObject> {
         match self {
             Self::Asyncio(w) => w.yield_(py),
@@ -28,6 +154,13 @@ impl coroutine::CoroutineWaker for Waker {
         }
     }
 
+    fn checkpoint(&self, py: Python) -> PyResult<PyObject> {
+        match self {
+            Self::Asyncio(w) => w.checkpoint(py),
+            Self::Trio(w) => w.checkpoint(py),
+        }
+    }
+
     fn wake(&self, py: Python) {
         match self {
             Self::Asyncio(w) => w.wake(py),
@@ -42,6 +175,14 @@ impl coroutine::CoroutineWaker for Waker {
         }
     }
 
+    #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+    fn wake_threadsafe_immediate(&self, py: Python) {
+        match self {
+            Self::Asyncio(w) => w.wake_threadsafe_immediate(py),
+            Self::Trio(w) => w.wake_threadsafe_immediate(py),
+        }
+    }
+
     fn update(&mut self, py: Python) -> PyResult<()> {
         match self {
             Self::Asyncio(w) => w.update(py),
@@ -55,6 +196,207 @@ impl coroutine::CoroutineWaker for Waker {
             Self::Trio(w) => w.raise(py),
         }
     }
+
+    fn reusable(&self, py: Python) -> bool {
+        match self {
+            Self::Asyncio(w) => w.reusable(py),
+            Self::Trio(w) => w.reusable(py),
+        }
+    }
+
+    fn unwrap_sent(&self, py: Python, value: &PyAny) -> PyResult<()> {
+        match self {
+            Self::Asyncio(w) => w.unwrap_sent(py, value),
+            Self::Trio(w) => w.unwrap_sent(py, value),
+        }
+    }
 }
 
 utils::generate!(Waker);
+
+/// Await a Python awaitable from Rust, the async backend being detected with `sniffio` on first
+/// poll.
+///
+/// With `asyncio`, the awaitable is driven by an [`asyncio::AwaitableWrapper`]; with `trio`, it
+/// is awaited in a system task, which is cancelled if the future is dropped before completion.
+///
+/// The future should be polled in the thread where the event loop is running.
+pub fn await_py(awaitable: impl Into<PyObject>) -> AwaitPy {
+    AwaitPy(AwaitPyState::Init(awaitable.into()))
+}
+
+/// [`Future`] returned by [`await_py`].
+pub struct AwaitPy(AwaitPyState);
+
+enum AwaitPyState {
+    Init(PyObject),
+    Asyncio(asyncio::AwaitableWrapper),
+    Trio(trio::AwaitableWrapper),
+}
+
+impl AwaitPy {
+    pub(crate) fn poll_gil(&mut self, py: Python, cx: &mut Context) -> Poll<PyResult<PyObject>> {
+        if let AwaitPyState::Init(awaitable) = &self.0 {
+            let awaitable = awaitable.as_ref(py);
+            let (sniffed, _) = current_async_library(py)?;
+            self.0 = match sniffed.extract(py)? {
+                "asyncio" => AwaitPyState::Asyncio(asyncio::AwaitableWrapper::new(awaitable)?),
+                "trio" => AwaitPyState::Trio(trio::AwaitableWrapper::new(awaitable)),
+                rt => {
+                    let msg = format!("unsupported runtime {rt}");
+                    return Poll::Ready(Err(PyRuntimeError::new_err(msg)));
+                }
+            };
+        }
+        match &mut self.0 {
+            AwaitPyState::Asyncio(wrapper) => wrapper.as_mut(py).poll_unpin(cx),
+            AwaitPyState::Trio(wrapper) => wrapper.as_mut(py).poll_unpin(cx),
+            AwaitPyState::Init(_) => unreachable!(),
+        }
+    }
+}
+
+impl Future for AwaitPy {
+    type Output = PyResult<PyObject>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("AwaitPy");
+        Python::with_gil(|gil| Pin::into_inner(self).poll_gil(gil, cx))
+    }
+}
+
+/// [`PyStream`] yielding the successive states of a Python fold function (see
+/// [`PyStreamExt::scan_py`](crate::PyStreamExt::scan_py)).
+///
+/// If the function returns an awaitable, it is awaited with [`await_py`]. An error raised by the
+/// function is yielded, the state being left unchanged; errors of the underlying stream are
+/// yielded as is.
+///
+/// The stream should be polled in the thread where the event loop is running.
+#[pin_project]
+pub struct ScanPy<S> {
+    #[pin]
+    stream: S,
+    state: PyObject,
+    f: PyObject,
+    pending: Option<AwaitPy>,
+}
+
+impl<S> ScanPy<S> {
+    pub(crate) fn new(stream: S, init: PyObject, f: PyObject) -> Self {
+        Self {
+            stream,
+            state: init,
+            f,
+            pending: None,
+        }
+    }
+}
+
+impl<S: PyStream> PyStream for ScanPy<S> {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = self.project();
+        if this.pending.is_none() {
+            let item = match ready!(this.stream.poll_next_py(py, cx)) {
+                Some(Ok(item)) => item,
+                res => return Poll::Ready(res),
+            };
+            let res = this.f.call1(py, (&*this.state, item))?;
+            if !res.as_ref(py).hasattr(intern!(py, "__await__"))? {
+                *this.state = res.clone_ref(py);
+                return Poll::Ready(Some(Ok(res)));
+            }
+            *this.pending = Some(await_py(res));
+        }
+        let res = ready!(this.pending.as_mut().unwrap().poll_gil(py, cx));
+        *this.pending = None;
+        let state = res?;
+        *this.state = state.clone_ref(py);
+        Poll::Ready(Some(Ok(state)))
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.stream.size_hint_py();
+        let pending = usize::from(self.pending.is_some());
+        (lower + pending, upper.map(|upper| upper + pending))
+    }
+}
+
+/// [`PyStream`] yielding a sentinel when no item arrives within an interval (see
+/// [`PyStreamExt::heartbeat`](crate::PyStreamExt::heartbeat)).
+///
+/// The interval is measured by awaiting `asyncio.sleep`/`trio.sleep`, the backend being detected
+/// with `sniffio` when the first timer is started, and it's restarted after each yielded item,
+/// sentinel included. The underlying stream is not cancelled by a heartbeat, and its items and
+/// errors are yielded as is.
+///
+/// The stream should be polled in the thread where the event loop is running.
+#[pin_project]
+pub struct Heartbeat<S> {
+    #[pin]
+    stream: S,
+    interval: Duration,
+    sentinel: PyObject,
+    // `asyncio.sleep` or `trio.sleep`
+    sleep_fn: Option<PyObject>,
+    sleep: Option<AwaitPy>,
+}
+
+impl<S> Heartbeat<S> {
+    pub(crate) fn new(stream: S, interval: Duration, sentinel: PyObject) -> Self {
+        Self {
+            stream,
+            interval,
+            sentinel,
+            sleep_fn: None,
+            sleep: None,
+        }
+    }
+}
+
+impl<S: PyStream> PyStream for Heartbeat<S> {
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = self.project();
+        if let Poll::Ready(res) = this.stream.poll_next_py(py, cx) {
+            *this.sleep = None;
+            return Poll::Ready(res);
+        }
+        if this.sleep.is_none() {
+            if this.sleep_fn.is_none() {
+                let (sniffed, _) = current_async_library(py)?;
+                *this.sleep_fn = Some(match sniffed.extract(py)? {
+                    "asyncio" => asyncio::Asyncio::get(py)?.sleep.clone_ref(py),
+                    "trio" => py.import(intern!(py, "trio"))?.getattr("sleep")?.into(),
+                    rt => {
+                        let msg = format!("unsupported runtime {rt}");
+                        return Poll::Ready(Some(Err(PyRuntimeError::new_err(msg))));
+                    }
+                });
+            }
+            let sleep_fn = this.sleep_fn.as_ref().unwrap();
+            let sleep = sleep_fn.call1(py, (this.interval.as_secs_f64(),))?;
+            *this.sleep = Some(await_py(sleep));
+        }
+        let res = ready!(this.sleep.as_mut().unwrap().poll_gil(py, cx));
+        *this.sleep = None;
+        Poll::Ready(Some(res.map(|_| this.sentinel.clone_ref(py))))
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        // any number of sentinels may be yielded
+        (self.stream.size_hint_py().0, None)
+    }
+
+    fn throw_py(self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.project().stream.throw_py(py, exc)
+    }
+}
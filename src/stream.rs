@@ -0,0 +1,147 @@
+//! Backend-agnostic [`PyStream`] adapters, built with [`PyStreamExt`](crate::PyStreamExt).
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use pin_project::pin_project;
+use pyo3::prelude::*;
+
+use crate::PyStream;
+
+/// [`PyStream`] terminating at the first error matching a predicate (see
+/// [`PyStreamExt::end_on_error`](crate::PyStreamExt::end_on_error)).
+///
+/// The matching error is swallowed, the async generator then raising `StopAsyncIteration`, and
+/// the underlying stream is not polled anymore; other errors are yielded as is.
+#[pin_project]
+pub struct EndOnError<S, P> {
+    #[pin]
+    stream: S,
+    predicate: P,
+    done: bool,
+}
+
+impl<S, P> EndOnError<S, P> {
+    pub(crate) fn new(stream: S, predicate: P) -> Self {
+        Self {
+            stream,
+            predicate,
+            done: false,
+        }
+    }
+}
+
+impl<S, P> PyStream for EndOnError<S, P>
+where
+    S: PyStream,
+    P: FnMut(Python, &PyErr) -> bool + Send,
+{
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        match ready!(this.stream.poll_next_py(py, cx)) {
+            Some(Err(err)) if (this.predicate)(py, &err) => {
+                *this.done = true;
+                Poll::Ready(None)
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        if self.done {
+            return (0, Some(0));
+        }
+        // the stream can end on any item
+        (0, self.stream.size_hint_py().1)
+    }
+
+    fn throw_py(self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.project().stream.throw_py(py, exc)
+    }
+}
+
+/// [`PyStream`] prefetching items until their estimated size reaches a byte cap (see
+/// [`PyStreamExt::buffered_bytes`](crate::PyStreamExt::buffered_bytes)).
+///
+/// Each time the next item is requested, the underlying stream is polled until it's pending,
+/// terminated, or the buffered items reach `max_bytes`, as estimated by `size_of`; the cap can
+/// only be exceeded by the last prefetched item, and an item is always fetched when the buffer
+/// is empty. Errors are buffered with a null size, and yielded in order.
+#[pin_project]
+pub struct BufferedBytes<S, F> {
+    #[pin]
+    stream: S,
+    max_bytes: usize,
+    size_of: F,
+    buffer: VecDeque<(PyResult<PyObject>, usize)>,
+    buffered_bytes: usize,
+    done: bool,
+}
+
+impl<S, F> BufferedBytes<S, F> {
+    pub(crate) fn new(stream: S, max_bytes: usize, size_of: F) -> Self {
+        Self {
+            stream,
+            max_bytes,
+            size_of,
+            buffer: VecDeque::new(),
+            buffered_bytes: 0,
+            done: false,
+        }
+    }
+}
+
+impl<S, F> PyStream for BufferedBytes<S, F>
+where
+    S: PyStream,
+    F: FnMut(Python, &PyObject) -> usize + Send,
+{
+    fn poll_next_py(
+        self: Pin<&mut Self>,
+        py: Python,
+        cx: &mut Context,
+    ) -> Poll<Option<PyResult<PyObject>>> {
+        let mut this = self.project();
+        while !*this.done && (this.buffer.is_empty() || *this.buffered_bytes < *this.max_bytes) {
+            match this.stream.as_mut().poll_next_py(py, cx) {
+                Poll::Ready(Some(item)) => {
+                    let size = item.as_ref().map_or(0, |obj| (this.size_of)(py, obj));
+                    *this.buffered_bytes += size;
+                    this.buffer.push_back((item, size));
+                }
+                Poll::Ready(None) => *this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        match this.buffer.pop_front() {
+            Some((item, size)) => {
+                *this.buffered_bytes -= size;
+                Poll::Ready(Some(item))
+            }
+            None if *this.done => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    fn size_hint_py(&self) -> (usize, Option<usize>) {
+        let buffered = self.buffer.len();
+        if self.done {
+            return (buffered, Some(buffered));
+        }
+        let (lower, upper) = self.stream.size_hint_py();
+        (lower + buffered, upper.map(|upper| upper + buffered))
+    }
+
+    fn throw_py(self: Pin<&mut Self>, py: Python, exc: PyErr) -> PyResult<()> {
+        self.project().stream.throw_py(py, exc)
+    }
+}
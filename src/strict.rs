@@ -0,0 +1,120 @@
+//! Runtime checks of the `Send` story, enabled by `strict-checks` feature.
+//!
+//! [`PyFuture`]/[`PyStream`](crate::PyStream) are `Send`, but Python objects they capture are
+//! only usable with the GIL held. The futures wrapping Python awaitables, e.g.
+//! [`asyncio::AwaitableWrapper`](crate::asyncio::AwaitableWrapper), acquire the GIL in their
+//! [`Future`](std::future::Future) implementation, which is fine when they are polled by a
+//! coroutine ([`PyFuture::poll_py`] is always called with the GIL held), but hides a bug when
+//! they are polled on a bare Rust executor, or inside [`AllowThreads`](crate::AllowThreads),
+//! which has released the GIL precisely to not hold it.
+//!
+//! With `strict-checks`, coroutines record that they are polling their future, and
+//! [`AllowThreads`](crate::AllowThreads) records that the GIL is released; GIL-bound futures
+//! then panic with a message naming the offending coroutine. These checks have a cost, and are
+//! meant for debug builds only.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::panic;
+//!
+//! use pyo3::prelude::*;
+//! use pyo3_async::{asyncio::AwaitableWrapper, manual::Coroutine, AllowThreadsExt};
+//!
+//! fn wrapper() -> AwaitableWrapper {
+//!     Python::with_gil(|py| {
+//!         let future = py.import("asyncio")?.call_method0("Future")?;
+//!         AwaitableWrapper::new(future)
+//!     })
+//!     .unwrap()
+//! }
+//!
+//! pyo3::prepare_freethreaded_python();
+//! // polled on a bare executor
+//! let panic = panic::catch_unwind(|| futures::executor::block_on(wrapper())).unwrap_err();
+//! let msg = panic.downcast::<String>().unwrap();
+//! assert!(msg.contains("AwaitableWrapper polled outside of a pyo3-async coroutine"));
+//! // polled inside `AllowThreads`, the panic being resumed when raised back to Rust
+//! let coroutine = Coroutine::from_future(wrapper().allow_threads()).with_name("misused");
+//! let panic = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+//!     Python::with_gil(|py| Py::new(py, coroutine)?.call_method1(py, "send", (py.None(),)))
+//! }))
+//! .unwrap_err();
+//! let msg = panic.downcast::<String>().unwrap();
+//! assert!(msg.contains("AwaitableWrapper polled inside `AllowThreads` in coroutine 'misused'"));
+//! ```
+use std::cell::RefCell;
+
+#[cfg(doc)]
+use crate::PyFuture;
+
+struct PollScope {
+    coroutine: Option<String>,
+    gil_held: bool,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Option<PollScope>> = const { RefCell::new(None) };
+}
+
+/// Guard restoring the previous poll scope when dropped.
+pub(crate) struct ScopeGuard(Option<PollScope>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        SCOPE.with(|scope| *scope.borrow_mut() = self.0.take());
+    }
+}
+
+/// Record that the coroutine `name` is polling its future, with the GIL held.
+pub(crate) fn enter_coroutine(name: Option<&str>) -> ScopeGuard {
+    let scope = PollScope {
+        coroutine: name.map(Into::into),
+        gil_held: true,
+    };
+    ScopeGuard(SCOPE.with(|s| s.borrow_mut().replace(scope)))
+}
+
+/// Record that [`AllowThreads`](crate::AllowThreads) has released the GIL.
+pub(crate) fn release_gil() -> ScopeGuard {
+    SCOPE.with(|scope| {
+        let mut scope = scope.borrow_mut();
+        let previous = scope.take();
+        let coroutine = previous.as_ref().and_then(|s| s.coroutine.clone());
+        // `AllowThreads` polled outside of a coroutine still releases the GIL
+        *scope = Some(PollScope {
+            coroutine,
+            gil_held: false,
+        });
+        ScopeGuard(previous)
+    })
+}
+
+/// Assert that a GIL-bound future/stream, described by `what`, is polled by a coroutine with
+/// the GIL held.
+///
+/// It can be called by user futures relying on the GIL, in addition to the ones of this crate.
+///
+/// # Panics
+///
+/// Panics if polled outside of a coroutine, e.g. on a bare Rust executor, or inside
+/// [`AllowThreads`](crate::AllowThreads).
+pub fn assert_gil_bound_poll(what: &str) {
+    SCOPE.with(|scope| match &*scope.borrow() {
+        Some(PollScope { gil_held: true, .. }) => {}
+        Some(PollScope {
+            coroutine,
+            gil_held: false,
+        }) => {
+            let coroutine = coroutine.as_deref().unwrap_or("<unnamed>");
+            panic!(
+                "{what} polled inside `AllowThreads` in coroutine '{coroutine}'; \
+                 GIL-bound futures must not be polled with the GIL released"
+            )
+        }
+        None => panic!(
+            "{what} polled outside of a pyo3-async coroutine, e.g. on a bare Rust executor; \
+             GIL-bound futures must be awaited by a future wrapped with `Coroutine::from_future`"
+        ),
+    });
+}
@@ -1,12 +1,33 @@
 //! `trio` compatible coroutine and async generator implementation.
-use pyo3::{intern, prelude::*};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+};
 
-use crate::{coroutine, utils};
+use futures::{FutureExt, Stream, StreamExt};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyStopAsyncIteration},
+    prelude::*,
+    sync::GILOnceCell,
+    types::{PyDict, PyTuple},
+};
 
-utils::module!(
-    Trio,
+use crate::{
+    asyncio::CancelOnDrop,
+    compat::{self, intern},
+    coroutine, sniffio, utils,
+};
+
+crate::cached_import!(
+    pub(crate) Trio,
     "trio.lowlevel",
     Abort,
+    cancel_shielded_checkpoint,
     current_task,
     current_trio_token,
     reschedule,
@@ -16,26 +37,81 @@ utils::module!(
 pub(crate) struct Waker {
     task: PyObject,
     token: PyObject,
+    // set while the task is waiting in `wait_task_rescheduled`, as rescheduling a task which is
+    // not waiting (e.g. already cancelled) corrupts trio internal state
+    waiting: Arc<AtomicBool>,
+    // `run_sync_soon` callback of threadsafe wakes, created on the first one
+    reschedule: GILOnceCell<PyObject>,
 }
 
 impl coroutine::CoroutineWaker for Waker {
+    const BACKEND: &'static str = "trio";
+
     fn new(py: Python) -> PyResult<Self> {
         let trio = Trio::get(py)?;
+        let task = match trio.current_task.call0(py) {
+            Ok(task) => task,
+            // trio error doesn't tell the coroutine has been awaited under the wrong library
+            Err(err) if err.is_instance_of::<PyRuntimeError>(py) => {
+                let exc = sniffio::wrong_async_library(py, "a trio event loop");
+                exc.set_cause(py, Some(err));
+                return Err(exc);
+            }
+            Err(err) => return Err(err),
+        };
         Ok(Waker {
-            task: trio.current_task.call0(py)?,
+            task,
             token: trio.current_trio_token.call0(py)?,
+            waiting: Arc::new(AtomicBool::new(false)),
+            reschedule: GILOnceCell::new(),
         })
     }
 
+    // `wait_task_rescheduled(abort_func).__await__().__next__()` is still executed with the GIL
+    // held, even when the future is polled with `AllowThreads`, but only the unavoidable calls
+    // remain, the callable and the abort function being cached.
     fn yield_(&self, py: Python) -> PyResult<PyObject> {
-        Trio::get(py)?
+        static ABORT_FUNC: GILOnceCell<PyObject> = GILOnceCell::new();
+        let abort_func = ABORT_FUNC.get_or_try_init(py, || {
+            PyResult::Ok(wrap_pyfunction!(abort_func, py)?.into())
+        })?;
+        let yielded = Trio::get(py)?
             .wait_task_rescheduled
-            .call1(py, (wrap_pyfunction!(abort_func, py)?,))?
+            .call1(py, (abort_func,))?
+            .call_method0(py, intern!(py, "__await__"))?
+            .call_method0(py, intern!(py, "__next__"))?;
+        self.waiting.store(true, Ordering::Release);
+        Ok(yielded)
+    }
+
+    fn checkpoint(&self, py: Python) -> PyResult<PyObject> {
+        Trio::get(py)?
+            .cancel_shielded_checkpoint
+            .call0(py)?
             .call_method0(py, intern!(py, "__await__"))?
             .call_method0(py, intern!(py, "__next__"))
     }
 
+    fn run_soon_threadsafe(py: Python) -> PyResult<PyObject> {
+        Trio::get(py)?
+            .current_trio_token
+            .call0(py)?
+            .getattr(py, intern!(py, "run_sync_soon"))
+    }
+
+    fn unwrap_sent(&self, py: Python, value: &PyAny) -> PyResult<()> {
+        self.waiting.store(false, Ordering::Release);
+        // `reschedule` sends an outcome, which raises `Cancelled` if the task was cancelled
+        if !value.is_none() {
+            value.call_method0(intern!(py, "unwrap"))?;
+        }
+        Ok(())
+    }
+
     fn wake(&self, py: Python) {
+        if !self.waiting.swap(false, Ordering::AcqRel) {
+            return;
+        }
         let reschedule = &Trio::get(py).unwrap().reschedule;
         reschedule
             .call1(py, (&self.task,))
@@ -43,13 +119,317 @@ impl coroutine::CoroutineWaker for Waker {
     }
 
     fn wake_threadsafe(&self, py: Python) {
-        let reschedule = &Trio::get(py).unwrap().reschedule;
-        self.token
-            .call_method1(py, intern!(py, "run_sync_soon"), (reschedule, &self.task))
-            .expect("unexpected error while scheduling TrioToken.run_sync_soon");
+        let reschedule = self.reschedule.get_or_try_init(py, || {
+            // the waiting state is checked in the event loop thread, when the callback is run
+            let (task, waiting) = (self.task.clone_ref(py), self.waiting.clone());
+            let reschedule = move |args: &PyTuple, _: Option<&PyDict>| {
+                if waiting.swap(false, Ordering::AcqRel) {
+                    let py = args.py();
+                    Trio::get(py)?.reschedule.call1(py, (&task,))?;
+                }
+                PyResult::Ok(())
+            };
+            PyResult::Ok(compat::new_closure(py, reschedule)?.into())
+        });
+        let scheduled = reschedule.and_then(|reschedule| {
+            self.token
+                .call_method1(py, intern!(py, "run_sync_soon"), (reschedule,))
+        });
+        // the trio run may be finished, the task being then necessarily done
+        scheduled.ok();
+    }
+}
+
+const HELPERS: &str = r#"
+import trio
+
+class Step:
+    def __init__(self):
+        self.scope = trio.CancelScope()
+        self.done = trio.Event()
+
+    def cancel(self):
+        self.scope.cancel()
+
+async def drive(awaitable, step, callback):
+    try:
+        with step.scope:
+            try:
+                result = await awaitable
+            except BaseException as exc:
+                callback(None, exc)
+                # `Cancelled` belongs to trio, e.g. to be caught by the step scope
+                if isinstance(exc, trio.Cancelled):
+                    raise
+            else:
+                callback(result, None)
+    finally:
+        step.done.set()
+
+def spawn_awaitable(awaitable, callback):
+    step = Step()
+    trio.lowlevel.spawn_system_task(drive, awaitable, step, callback)
+    return step
+
+async def aclose(async_generator, step, on_error):
+    # the pending step must be finished, otherwise `aclose` raises "already running"
+    if step is not None:
+        step.cancel()
+        await step.done.wait()
+    try:
+        await async_generator.aclose()
+    except Exception as exc:
+        on_error(exc)
+
+def schedule_aclose(token, async_generator, step, on_error):
+    spawn = lambda: trio.lowlevel.spawn_system_task(aclose, async_generator, step, on_error)
+    try:
+        token.run_sync_soon(spawn)
+    except trio.RunFinishedError:
+        pass
+
+def start_soon(nursery, coroutine):
+    try:
+        nursery.start_soon(lambda: coroutine)
+    except BaseException:
+        coroutine.close()
+        raise
+"#;
+
+fn helpers(py: Python<'_>) -> PyResult<&PyModule> {
+    static HELPERS_MODULE: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+    let module = HELPERS_MODULE.get_or_try_init(py, || {
+        PyResult::Ok(
+            PyModule::from_code(py, HELPERS, "pyo3_async_trio.py", "pyo3_async_trio")?.into(),
+        )
+    })?;
+    Ok(module.as_ref(py))
+}
+
+#[derive(Default)]
+struct Completion {
+    result: Option<PyResult<PyObject>>,
+    waker: Option<std::task::Waker>,
+}
+
+/// [`Future`] wrapper for a Python awaitable (in `trio` context).
+///
+/// The awaitable is awaited in a system task spawned on first poll, which is cancelled if the
+/// wrapper is dropped before completion. Contrary to a task awaiting the awaitable directly,
+/// the system task doesn't inherit the context variables of the polling task.
+///
+/// The future should be polled in the thread where the event loop is running.
+pub struct AwaitableWrapper {
+    awaitable: Option<PyObject>,
+    cancel_scope: Option<PyObject>,
+    completion: Arc<Mutex<Completion>>,
+}
+
+impl AwaitableWrapper {
+    /// Wrap a Python awaitable.
+    pub fn new(awaitable: &PyAny) -> Self {
+        Self {
+            awaitable: Some(awaitable.into()),
+            cancel_scope: None,
+            completion: Default::default(),
+        }
+    }
+
+    /// GIL-bound [`Future`] reference.
+    pub fn as_mut<'a>(
+        &'a mut self,
+        py: Python<'a>,
+    ) -> impl Future<Output = PyResult<PyObject>> + Unpin + 'a {
+        utils::WithGil { inner: self, py }
     }
 }
 
+impl Future for utils::WithGil<'_, &mut AwaitableWrapper> {
+    type Output = PyResult<PyObject>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let py = self.py;
+        if let Some(awaitable) = self.inner.awaitable.take() {
+            let completion = self.inner.completion.clone();
+            let callback = move |args: &PyTuple, _: Option<&PyDict>| {
+                let (result, exc): (PyObject, Option<&PyAny>) = args.extract()?;
+                let mut completion = completion.lock().unwrap();
+                completion.result = Some(exc.map_or(Ok(result), |exc| Err(PyErr::from_value(exc))));
+                if let Some(waker) = completion.waker.take() {
+                    waker.wake();
+                }
+                PyResult::Ok(())
+            };
+            let callback = compat::new_closure(py, callback)?;
+            let spawn_awaitable = helpers(py)?.getattr(intern!(py, "spawn_awaitable"))?;
+            self.inner.cancel_scope = Some(spawn_awaitable.call1((awaitable, callback))?.into());
+        }
+        let mut completion = self.inner.completion.lock().unwrap();
+        let Some(res) = completion.result.take() else {
+            completion.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        drop(completion);
+        self.inner.cancel_scope = None;
+        Poll::Ready(res)
+    }
+}
+
+impl Future for AwaitableWrapper {
+    type Output = PyResult<PyObject>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("AwaitableWrapper");
+        Python::with_gil(|gil| Pin::into_inner(self).as_mut(gil).poll_unpin(cx))
+    }
+}
+
+impl Drop for AwaitableWrapper {
+    fn drop(&mut self) {
+        if let Some(cancel_scope) = self.cancel_scope.take() {
+            Python::with_gil(|gil| {
+                let cancel = || cancel_scope.call_method0(gil, intern!(gil, "cancel")).ok();
+                utils::preserve_exception(gil, cancel)
+            });
+        }
+    }
+}
+
+/// [`Stream`] wrapper for a Python async generator (in `trio` context).
+///
+/// Each item is awaited with an [`AwaitableWrapper`]. If the stream is dropped before the async
+/// generator exhaustion, `aclose` is awaited in a system task, scheduled with the trio token,
+/// after the pending `__anext__`, if any, has been cancelled and has finished. `aclose` error is
+/// logged like [`CancelOnDrop::LogOnError`].
+///
+/// The stream should be polled in the thread where the event loop is running.
+///
+/// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+pub struct AsyncGeneratorWrapper {
+    async_generator: PyObject,
+    next: Option<AwaitableWrapper>,
+    // captured on first poll, and reset when the async generator is exhausted
+    token: Option<PyObject>,
+    exhausted: bool,
+}
+
+impl AsyncGeneratorWrapper {
+    /// Wrap a Python async generator.
+    pub fn new(async_generator: &PyAny) -> Self {
+        Self {
+            async_generator: async_generator.into(),
+            next: None,
+            token: None,
+            exhausted: false,
+        }
+    }
+
+    /// GIL-bound [`Stream`] reference.
+    ///
+    /// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+    pub fn as_mut<'a>(
+        &'a mut self,
+        py: Python<'a>,
+    ) -> impl Stream<Item = PyResult<PyObject>> + Unpin + 'a {
+        utils::WithGil { inner: self, py }
+    }
+}
+
+impl Stream for utils::WithGil<'_, &mut AsyncGeneratorWrapper> {
+    type Item = PyResult<PyObject>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let py = self.py;
+        if self.inner.exhausted {
+            return Poll::Ready(None);
+        }
+        if self.inner.token.is_none() {
+            self.inner.token = Some(Trio::get(py)?.current_trio_token.call0(py)?);
+        }
+        if self.inner.next.is_none() {
+            let async_generator = self.inner.async_generator.as_ref(py);
+            let next = async_generator.call_method0(intern!(py, "__anext__"))?;
+            self.inner.next = Some(AwaitableWrapper::new(next));
+        }
+        let res = ready!(self.inner.next.as_mut().unwrap().as_mut(py).poll_unpin(cx));
+        self.inner.next = None;
+        Poll::Ready(match res {
+            Ok(obj) => Some(Ok(obj)),
+            Err(err) if err.is_instance_of::<PyStopAsyncIteration>(py) => {
+                self.inner.exhausted = true;
+                None
+            }
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+impl Stream for AsyncGeneratorWrapper {
+    type Item = PyResult<PyObject>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        #[cfg(feature = "strict-checks")]
+        crate::strict::assert_gil_bound_poll("AsyncGeneratorWrapper");
+        Python::with_gil(|gil| Pin::into_inner(self).as_mut(gil).poll_next_unpin(cx))
+    }
+}
+
+impl Drop for AsyncGeneratorWrapper {
+    fn drop(&mut self) {
+        let Some(token) = self.token.take().filter(|_| !self.exhausted) else {
+            return;
+        };
+        // the pending `__anext__` is cancelled and awaited before closing the async generator,
+        // in the event loop thread
+        let step = self
+            .next
+            .take()
+            .and_then(|mut next| next.cancel_scope.take());
+        Python::with_gil(|gil| {
+            let schedule_aclose = || {
+                let on_error = |args: &PyTuple, _: Option<&PyDict>| {
+                    let py = args.py();
+                    let (exc,): (&PyAny,) = args.extract()?;
+                    let err = Err(PyErr::from_value(exc));
+                    CancelOnDrop::LogOnError.handle_error(py, "AsyncGeneratorWrapper", err);
+                    PyResult::Ok(())
+                };
+                let on_error = compat::new_closure(gil, on_error)?;
+                let schedule_aclose = helpers(gil)?.getattr(intern!(gil, "schedule_aclose"))?;
+                schedule_aclose.call1((token, &self.async_generator, step, on_error))?;
+                PyResult::Ok(())
+            };
+            utils::preserve_exception(gil, || {
+                let res = schedule_aclose();
+                CancelOnDrop::LogOnError.handle_error(gil, "AsyncGeneratorWrapper", res);
+            });
+        });
+    }
+}
+
+/// Spawn a [`PyFuture`] as a child task of a `trio.Nursery`, like `nursery.start_soon`.
+///
+/// The future is wrapped into a trio [`Coroutine`], so it follows the nursery structured
+/// concurrency: it's cancelled, i.e. dropped, when the nursery cancel scope is cancelled, and
+/// its error, if any, is propagated to the nursery, cancelling the other children. It's the trio
+/// analog of `asyncio.create_task`.
+///
+/// If the nursery is closed, `RuntimeError` is raised, and the future is dropped without being
+/// polled.
+///
+/// [`PyFuture`]: crate::PyFuture
+pub fn spawn_in_nursery(
+    py: Python,
+    nursery: &PyAny,
+    future: impl crate::PyFuture + 'static,
+) -> PyResult<()> {
+    let coroutine = Py::new(py, Coroutine::from_future(future))?;
+    let start_soon = helpers(py)?.getattr(intern!(py, "start_soon"))?;
+    start_soon.call1((nursery, coroutine))?;
+    Ok(())
+}
+
 #[pyfunction]
 fn abort_func(py: Python, _arg: PyObject) -> PyResult<PyObject> {
     Trio::get(py)?.Abort.getattr(py, intern!(py, "SUCCEEDED"))
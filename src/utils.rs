@@ -1,15 +1,90 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    any::Any,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
-use pyo3::{exceptions::PyStopIteration, prelude::*, pyclass::IterNextOutput, types::PyCFunction};
+use pyo3::{
+    exceptions::{PyBaseException, PyStopIteration, PyTypeError},
+    ffi,
+    prelude::*,
+    sync::GILOnceCell,
+    types::{PyDict, PyString, PyTuple, PyType},
+    PyTypeInfo,
+};
+
+use crate::compat::{self, intern, IterNextOutput};
+
+/// Identifier of a thread, compared by the wake fast-path to know if the waking thread is the
+/// one where the waker was created.
+///
+/// Thread numbers are never reused within a process, but a child forked with `os.fork`
+/// inherits the thread-locals of the forking thread, so the fork generation, incremented in the
+/// child (see [`register_fork_handler`]), prevents the ids of the child from aliasing the ones
+/// captured before the fork.
+///
+/// The event loop is deliberately not part of the identity: only one event loop can run in a
+/// thread at a time, and the same-thread wake of a loop which is not running, e.g. stopped
+/// or closed, has nothing to synchronize with; it's queued like a threadsafe wake would be,
+/// or reports the error of the closed loop. Capturing the running loop would also cost a
+/// Python call per waker creation, for a wake fast-path which is meant to avoid them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ThreadId {
+    thread: usize,
+    generation: usize,
+}
+
+static FORK_GENERATION: AtomicUsize = AtomicUsize::new(0);
+static FORK_HANDLER_REGISTERED: AtomicBool = AtomicBool::new(false);
 
 // Don't use `std::thread::current` because of unnecessary Arc clone + drop.
-pub(crate) type ThreadId = usize;
 pub(crate) fn current_thread_id() -> ThreadId {
     static THREAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
     thread_local! {
-        pub(crate) static THREAD_ID: ThreadId = THREAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+        static THREAD: usize = THREAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    ThreadId {
+        thread: THREAD.with(|thread| *thread),
+        generation: FORK_GENERATION.load(Ordering::Relaxed),
+    }
+}
+
+/// Increment the fork generation in the child process of `os.fork` (and of `multiprocessing`
+/// fork start method), with `os.register_at_fork`; it's registered by [`crate::init`], or
+/// otherwise when the first waker is created (see [`ensure_fork_handler`]), as no thread id
+/// can be captured before.
+///
+/// A raw `fork` not going through the Python hooks is not detected, but a process forked this
+/// way cannot safely run Python code anyway.
+pub(crate) fn register_fork_handler(py: Python) -> PyResult<()> {
+    static REGISTERED: GILOnceCell<()> = GILOnceCell::new();
+    REGISTERED.get_or_try_init(py, || {
+        let after_in_child = compat::new_closure(py, |_, _| {
+            FORK_GENERATION.fetch_add(1, Ordering::Relaxed);
+        })?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item(intern!(py, "after_in_child"), after_in_child)?;
+        let os = py.import(intern!(py, "os"))?;
+        // not available on Windows
+        if os.hasattr(intern!(py, "register_at_fork"))? {
+            os.call_method(intern!(py, "register_at_fork"), (), Some(kwargs))?;
+        }
+        PyResult::Ok(())
+    })?;
+    FORK_HANDLER_REGISTERED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Lazy registration of the fork handler, for extensions not calling [`crate::init`]; it's a
+/// single atomic load once registered, and a registration error is reported as unraisable
+/// instead of failing the poll, the only consequence being a missed fork detection.
+pub(crate) fn ensure_fork_handler(py: Python) {
+    if FORK_HANDLER_REGISTERED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Err(err) = register_fork_handler(py) {
+        err.write_unraisable(py, None);
     }
-    THREAD_ID.with(|id| *id)
 }
 
 pub(crate) struct WithGil<'py, T> {
@@ -17,35 +92,157 @@ pub(crate) struct WithGil<'py, T> {
     pub(crate) py: Python<'py>,
 }
 
-pub(crate) fn wake_callback(py: Python, waker: std::task::Waker) -> PyResult<&PyAny> {
-    let func = PyCFunction::new_closure(py, None, None, move |_, _| waker.wake_by_ref())?;
-    Ok(func)
+/// Run `f` with the exception being raised, if any, set aside.
+///
+/// Objects may be dropped while an exception is propagating, e.g. when the frame holding them is
+/// cleared; Python calls made by `Drop` implementations would then fail, and the original
+/// exception would be lost.
+pub(crate) fn preserve_exception<R>(_py: Python, f: impl FnOnce() -> R) -> R {
+    let (mut ptype, mut pvalue, mut ptraceback) =
+        (ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+    // SAFETY: the GIL is held
+    unsafe { ffi::PyErr_Fetch(&mut ptype, &mut pvalue, &mut ptraceback) };
+    let res = f();
+    // SAFETY: the GIL is held, and the fetched references are given back
+    unsafe { ffi::PyErr_Restore(ptype, pvalue, ptraceback) };
+    res
+}
+
+/// Message of a panic caught with [`std::panic::catch_unwind`].
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
 }
 
-macro_rules! module {
-    ($name:ident ,$path:literal, $($field:ident),* $(,)?) => {
+/// Cache a Python module import with some of its attributes.
+///
+/// `cached_import!(Name, "module.path", attr1, attr2)` generates a struct `Name`, with one
+/// `PyObject` field per attribute, and a `Name::get(py)` method importing the module on first
+/// call. A successful import is cached in a [`GILOnceCell`](pyo3::sync::GILOnceCell); a failed
+/// one is retried by subsequent calls, so a module installed, or made importable, afterwards is
+/// picked up. A missing attribute is raised as `ImportError`, like `from module import attr`.
+///
+/// A visibility can be passed before the struct name, and then applies to the struct and its
+/// fields.
+///
+/// # Example
+///
+/// ```rust
+/// use pyo3::prelude::*;
+///
+/// pyo3_async::cached_import!(pub Json, "json", dumps, loads);
+/// pyo3_async::cached_import!(Missing, "json", dumps, not_an_attribute);
+///
+/// pyo3::prepare_freethreaded_python();
+/// Python::with_gil(|py| {
+///     let dumped = Json::get(py)?.dumps.call1(py, ((1, 2),))?;
+///     assert_eq!(dumped.extract::<String>(py)?, "[1, 2]");
+///     let err = Missing::get(py).err().unwrap();
+///     assert!(err.is_instance_of::<pyo3::exceptions::PyImportError>(py));
+///     assert_eq!(
+///         err.value(py).to_string(),
+///         "cannot import name 'not_an_attribute' from 'json'"
+///     );
+///     // the import is retried
+///     assert!(Missing::get(py).is_err());
+///     PyResult::Ok(())
+/// })
+/// .unwrap();
+/// ```
+#[macro_export]
+macro_rules! cached_import {
+    ($vis:vis $name:ident, $path:literal, $($field:ident),* $(,)?) => {
         #[allow(non_upper_case_globals)]
         static $name: ::pyo3::sync::GILOnceCell<$name> = ::pyo3::sync::GILOnceCell::new();
 
         #[allow(non_snake_case)]
-        struct $name {
-            $($field: PyObject),*
+        $vis struct $name {
+            $($vis $field: ::pyo3::PyObject),*
         }
 
         impl $name {
-            fn get(py: Python) -> PyResult<&Self> {
-                $name.get_or_try_init(py, || {
+            $vis fn get(py: ::pyo3::Python<'_>) -> ::pyo3::PyResult<&Self> {
+                let import = || {
                     let module = py.import($path)?;
+                    let getattr = |attr: &str| {
+                        let obj = module.getattr(attr).map_err(|_| {
+                            ::pyo3::exceptions::PyImportError::new_err(format!(
+                                "cannot import name '{attr}' from '{}'",
+                                $path
+                            ))
+                        })?;
+                        ::pyo3::PyResult::Ok(::pyo3::PyObject::from(obj))
+                    };
                     Ok(Self {
-                        $($field: module.getattr(stringify!($field))?.into(),)*
+                        $($field: getattr(stringify!($field))?,)*
                     })
-                })
+                };
+                $name.get_or_try_init(py, import)
             }
         }
     };
 }
 
-pub(crate) use module;
+/// `repr` of coroutines/async generators, e.g. `<Coroutine name='name' at 0x7f...>`.
+pub(crate) fn repr(obj: &PyAny, name: Option<&str>) -> PyResult<String> {
+    let ty = obj.get_type().name()?;
+    Ok(match name {
+        Some(name) => {
+            let name = PyString::new(obj.py(), name).repr()?;
+            format!("<{ty} name={name} at {:p}>", compat::as_ptr(obj))
+        }
+        None => format!("<{ty} object at {:p}>", compat::as_ptr(obj)),
+    })
+}
+
+/// Build the exception of `throw`/`athrow` arguments, like Python generators do: `typ` can be an
+/// exception class, instantiated with `val` unless it's already an instance, or an exception
+/// instance without `val`.
+pub(crate) fn throw_exception(
+    typ: &PyAny,
+    val: Option<&PyAny>,
+    tb: Option<&PyAny>,
+) -> PyResult<PyErr> {
+    let py = typ.py();
+    let val = val.filter(|val| !val.is_none());
+    let exc = if let Ok(cls) = typ.downcast::<PyType>() {
+        if !cls.is_subclass_of::<PyBaseException>()? {
+            return Err(throw_type_error(typ));
+        }
+        match val {
+            Some(val) if val.is_instance(cls)? => val,
+            Some(val) => match val.downcast::<PyTuple>() {
+                Ok(args) => cls.call1(args)?,
+                Err(_) => cls.call1((val,))?,
+            },
+            None => cls.call0()?,
+        }
+    } else if PyBaseException::is_type_of(typ) {
+        if val.is_some() {
+            let msg = "instance exception may not have a separate value";
+            return Err(PyTypeError::new_err(msg));
+        }
+        typ
+    } else {
+        return Err(throw_type_error(typ));
+    };
+    if let Some(tb) = tb.filter(|tb| !tb.is_none()) {
+        exc.setattr(intern!(py, "__traceback__"), tb)?;
+    }
+    Ok(PyErr::from_value(exc))
+}
+
+fn throw_type_error(typ: &PyAny) -> PyErr {
+    let ty = typ.get_type().name().unwrap_or("?");
+    PyTypeError::new_err(format!(
+        "exceptions must be classes or instances deriving from BaseException, not {ty}"
+    ))
+}
 
 pub(crate) fn poll_result(result: IterNextOutput<PyObject, PyObject>) -> PyResult<PyObject> {
     match result {
@@ -55,9 +252,32 @@ pub(crate) fn poll_result(result: IterNextOutput<PyObject, PyObject>) -> PyResul
 }
 
 macro_rules! generate {
-    ($waker:ty) => {
+    ($waker:ty $(, coroutine_methods { $($coroutine_methods:tt)* })?) => {
         /// Python coroutine wrapping a [`PyFuture`](crate::PyFuture).
-        #[pyclass]
+        ///
+        /// Calling `send`/`throw`/`close` while the coroutine is executing, e.g. from the
+        /// future being polled, raises `ValueError`, like Python coroutines. It holds as well
+        /// while the future has released the GIL with [`AllowThreads`](crate::AllowThreads), the
+        /// coroutine being executing for the whole poll; in particular, `close` never drops the
+        /// future under a pending poll.
+        ///
+        /// Between the chunks of [`ChunkedConvert`](crate::convert::ChunkedConvert), the
+        /// coroutine yields a checkpoint, i.e. a bare `yield` for `asyncio`, or
+        /// `trio.lowlevel.cancel_shielded_checkpoint`, so other tasks can run before it's polled
+        /// again. Other wakes of the future, including while it's polled, are forwarded to the
+        /// event loop.
+        ///
+        /// # Panics
+        ///
+        /// A panic never unwinds into CPython, which would be undefined behavior: a panic of the
+        /// future is caught, the future dropped, and `pyo3_runtime.PanicException` raised, the
+        /// coroutine then behaving like after any other exception; other panics, e.g. in a
+        /// throw callback, are caught by pyo3 at the method boundary, and raised the same way.
+        /// `PanicException` raised back into Rust, e.g. by a Python call, resumes the panic.
+        ///
+        /// The process aborts instead if the crate is compiled with `panic = "abort"`, or if a
+        /// `Drop` implementation panics while a panic is already unwinding.
+        #[pyclass(frozen)]
         pub struct Coroutine($crate::coroutine::Coroutine<$waker>);
 
         impl Coroutine {
@@ -67,6 +287,7 @@ macro_rules! generate {
             /// - coroutine `throw` method will call it with the passed exception before polling;
             /// - coroutine `close` method will call it with `None` before polling and dropping
             ///   the future.
+            ///
             /// If `throw` callback is not provided, the future will dropped without additional
             /// poll.
             pub fn new(
@@ -80,22 +301,176 @@ macro_rules! generate {
             pub fn from_future(future: impl $crate::PyFuture + 'static) -> Self {
                 Self::new(Box::pin(future), None)
             }
+
+            /// Wrap a future into a Python coroutine, running `guard` when the future is dropped,
+            /// e.g. to release a resource deterministically.
+            ///
+            /// Contrary to a [`ThrowCallback`](crate::ThrowCallback), only called on `close`,
+            /// the guard runs on every drop path: completion, failure, cancellation, `close`, or
+            /// garbage collection of an abandoned coroutine. It runs after the future is
+            /// dropped, possibly without the GIL held.
+            pub fn from_future_with_guard(
+                future: impl $crate::PyFuture + 'static,
+                guard: impl FnOnce() + Send + 'static,
+            ) -> Self {
+                Self::from_future($crate::coroutine::Guarded {
+                    future: Box::pin(future),
+                    _guard: $crate::coroutine::Guard(Some(guard)),
+                })
+            }
+
+            /// Wrap a type-erased future, e.g. returned by a plugin, into a Python coroutine.
+            #[cfg(feature = "erased")]
+            pub fn from_erased(future: $crate::erased::ErasedPyFuture) -> Self {
+                Self::from_future(future)
+            }
+
+            /// Wrap a step function into a Python coroutine.
+            ///
+            /// The function is called at each poll of the coroutine, until it returns `Ready`.
+            /// When returning `Pending`, it must arrange for the context waker to be woken, or the
+            /// coroutine will never be resumed; calling `cx.waker().wake_by_ref()` makes the
+            /// coroutine yield to the event loop and be resumed immediately, letting other tasks
+            /// run between steps.
+            pub fn from_step_fn(
+                step: impl FnMut(Python, &mut ::std::task::Context) -> ::std::task::Poll<PyResult<PyObject>>
+                    + Send
+                    + 'static,
+            ) -> Self {
+                Self::from_future($crate::coroutine::StepFn(step))
+            }
+
+            /// Wrap a future into a Python coroutine which doesn't raise the future error, but
+            /// logs it with `logger.exception`, and returns `None` instead.
+            ///
+            /// It is intended for fire-and-forget background tasks, to avoid "Task exception was
+            /// never retrieved" warnings. The error is swallowed: the code awaiting the coroutine
+            /// cannot observe it. Only the future error is concerned, exceptions thrown into the
+            /// coroutine, e.g. cancellation, are still raised. If `logger.exception` fails, both
+            /// errors are written with `sys.unraisablehook`.
+            ///
+            /// `logger` is expected to be a `logging.Logger`, or to provide a compatible
+            /// `exception(msg, exc_info=...)` method.
+            pub fn from_future_logged(
+                future: impl $crate::PyFuture + 'static,
+                logger: PyObject,
+            ) -> Self {
+                Self::from_future($crate::coroutine::Logged {
+                    future: Box::pin(future),
+                    logger,
+                })
+            }
+
+            /// Set the name of the coroutine, shown in its `repr`.
+            pub fn with_name(self, name: impl Into<String>) -> Self {
+                self.0.set_name(Some(name.into()));
+                self
+            }
+
+            /// Map the error returned by the future before raising it, e.g. into the exception
+            /// classes of the extension module, instead of relying on a single global
+            /// `From<E> for PyErr` conversion.
+            ///
+            /// Exceptions thrown into the coroutine, e.g. cancellation, are not mapped.
+            pub fn with_map_err(
+                mut self,
+                map_err: impl Fn(Python, PyErr) -> PyErr + Send + Sync + 'static,
+            ) -> Self {
+                self.0.state_mut().map_err = Some(::std::sync::Arc::new(map_err));
+                self
+            }
+
+            /// Set the latency priority of the wakes coming from other threads (see
+            /// [`WakePriority`](crate::WakePriority)).
+            #[cfg(any(feature = "coalesce-wakes", feature = "batch-wakes"))]
+            pub fn with_wake_priority(mut self, priority: $crate::WakePriority) -> Self {
+                self.0.state_mut().wake_priority = priority;
+                self
+            }
+
+            /// Override the object yielded when the coroutine is suspended, e.g. to yield the
+            /// sentinel expected by an event loop variant (see
+            /// [`YieldCallback`](crate::YieldCallback)).
+            pub fn with_yield(
+                mut self,
+                yield_: impl FnMut(Python, PyObject) -> PyResult<PyObject> + Send + 'static,
+            ) -> Self {
+                self.0.state_mut().yield_ = Some(Box::new(yield_));
+                self
+            }
+
+            /// Attach a memory footprint, reported by `__sizeof__`.
+            ///
+            /// The footprint is owned separately from the wrapped future/stream, so it should
+            /// share the measured state with it, e.g. through an `Arc`.
+            #[cfg(feature = "diagnostics")]
+            pub fn with_footprint(
+                mut self,
+                footprint: impl $crate::diagnostics::MemoryFootprint + Send + 'static,
+            ) -> Self {
+                self.0.set_footprint(Box::new(footprint));
+                self
+            }
+
+            /// Whether the GIL has been released by [`AllowThreads`](crate::AllowThreads) during
+            /// the last poll of the future.
+            #[cfg(feature = "diagnostics")]
+            pub fn last_poll_released_gil(&self) -> bool {
+                self.0.last_poll_released_gil()
+            }
+
+            /// Time elapsed between the last wake, from any thread, and the poll it has
+            /// triggered, i.e. the wake→resume latency added by the event loop; `None` until
+            /// the coroutine has been resumed after a wake.
+            #[cfg(feature = "diagnostics")]
+            pub fn last_wake_latency(&self) -> Option<::std::time::Duration> {
+                self.0.last_wake_latency()
+            }
         }
 
         #[pymethods]
         impl Coroutine {
-            fn send(&mut self, py: Python, _value: &PyAny) -> PyResult<PyObject> {
-                $crate::utils::poll_result(self.0.poll(py, None)?)
+            fn send(&self, py: Python, value: &PyAny) -> PyResult<PyObject> {
+                $crate::utils::poll_result(self.0.send(py, value)?)
             }
 
-            fn throw(&mut self, py: Python, exc: &PyAny) -> PyResult<PyObject> {
-                $crate::utils::poll_result(self.0.poll(py, Some(PyErr::from_value(exc)))?)
+            #[pyo3(signature = (typ, val = None, tb = None))]
+            fn throw(
+                &self,
+                py: Python,
+                typ: &PyAny,
+                val: Option<&PyAny>,
+                tb: Option<&PyAny>,
+            ) -> PyResult<PyObject> {
+                let exc = $crate::utils::throw_exception(typ, val, tb)?;
+                $crate::utils::poll_result(self.0.poll(py, Some(exc))?)
             }
 
-            fn close(&mut self, py: Python) -> PyResult<()> {
+            fn close(&self, py: Python) -> PyResult<()> {
                 self.0.close(py)
             }
 
+            /// Async backend driving the coroutine, resolved on first poll for `sniffio`.
+            #[getter]
+            fn backend(&self) -> &'static str {
+                self.0.backend()
+            }
+
+            /// Name of the coroutine, shown in its `repr`.
+            #[getter]
+            fn name(&self) -> Option<String> {
+                self.0.name()
+            }
+
+            #[setter]
+            fn set_name(&self, name: Option<String>) {
+                self.0.set_name(name);
+            }
+
+            fn __repr__(self_: &PyCell<Self>) -> PyResult<String> {
+                $crate::utils::repr(self_, $crate::compat::get(self_).0.name().as_deref())
+            }
+
             fn __await__(self_: &PyCell<Self>) -> PyResult<&PyAny> {
                 Ok(self_)
             }
@@ -105,11 +480,26 @@ macro_rules! generate {
             }
 
             fn __next__(
-                &mut self,
+                &self,
                 py: Python,
-            ) -> PyResult<::pyo3::pyclass::IterNextOutput<PyObject, PyObject>> {
+            ) -> PyResult<$crate::compat::IterNextOutput<PyObject, PyObject>> {
                 self.0.poll(py, None)
             }
+
+            fn __traverse__(&self, visit: ::pyo3::PyVisit) -> Result<(), ::pyo3::PyTraverseError> {
+                self.0.traverse(&visit)
+            }
+
+            fn __clear__(&self) {
+                self.0.clear();
+            }
+
+            #[cfg(feature = "diagnostics")]
+            fn __sizeof__(&self) -> usize {
+                ::std::mem::size_of::<::pyo3::PyCell<Self>>() + self.0.footprint_bytes()
+            }
+
+            $($($coroutine_methods)*)?
         }
 
         impl $crate::async_generator::CoroutineFactory for Coroutine {
@@ -117,9 +507,50 @@ macro_rules! generate {
             fn coroutine(future: impl $crate::PyFuture + 'static) -> Self::Coroutine {
                 Self::from_future(future)
             }
+            fn run_soon_threadsafe(py: Python) -> PyResult<PyObject> {
+                <$waker as $crate::coroutine::CoroutineWaker>::run_soon_threadsafe(py)
+            }
+            const BACKEND: &'static str = <$waker as $crate::coroutine::CoroutineWaker>::BACKEND;
+            fn current_backend(py: Python) -> &'static str {
+                <$waker as $crate::coroutine::CoroutineWaker>::current_backend(py)
+            }
+        }
+
+        /// Build a coroutine from Rust state extracted from Python objects.
+        ///
+        /// `extract` is called synchronously, with the GIL held, to convert the Python objects,
+        /// e.g. `&PyAny` arguments, into owned `Send` state; this state is then moved into the
+        /// future returned by `f`, which can't capture GIL-bound references itself. An error
+        /// returned by `extract` is returned immediately, instead of being raised when the
+        /// coroutine is awaited.
+        ///
+        /// See the example of [`asyncio`](crate::asyncio) module.
+        pub fn coroutine_from<S, F>(
+            extract: impl FnOnce(Python) -> PyResult<S>,
+            f: impl FnOnce(S) -> F,
+        ) -> PyResult<Coroutine>
+        where
+            S: Send + 'static,
+            F: $crate::PyFuture + 'static,
+        {
+            let state = Python::with_gil(extract)?;
+            Ok(Coroutine::from_future(f(state)))
         }
 
         /// Python async generator wrapping a [`PyStream`](crate::PyStream).
+        ///
+        /// A panic of the stream is raised as `pyo3_runtime.PanicException` by the `__anext__`
+        /// coroutine, the stream being dropped; subsequent iterations raise `RuntimeError` (see
+        /// [`Coroutine`] for the handling of panics).
+        ///
+        /// # Finalization
+        ///
+        /// Like a Python async generator finalized without being exhausted or closed, the throw
+        /// callback is called with `None`, and the stream is dropped in the thread of the event
+        /// loop where it was first iterated, unless [`drop_on_gc`](Self::drop_on_gc) is set.
+        /// pyo3 cannot give a `__del__` (`tp_finalize`) to a pyclass, so it's done when the
+        /// async generator is deallocated, which happens at the same point of the garbage
+        /// collection for an object without reference cycles to clear.
         #[pyclass]
         pub struct AsyncGenerator($crate::async_generator::AsyncGenerator<Coroutine>);
 
@@ -131,12 +562,18 @@ macro_rules! generate {
             ///   before polling;
             /// - async generator `aclose` method will call it with `None` before polling and
             ///   dropping the stream.
+            ///
             /// If `throw` callback is not provided, the stream will dropped without additional
-            /// poll.
+            /// poll, and the exception passed to `athrow` is delivered to
+            /// [`PyStream::throw_py`](crate::PyStream::throw_py).
+            ///
+            /// Like Python async generators, `aclose` returns `None`, and is a no-op once the
+            /// async generator is terminated.
             pub fn new(
                 stream: ::std::pin::Pin<Box<dyn $crate::PyStream>>,
                 throw: Option<$crate::ThrowCallback>,
             ) -> Self {
+                let stream = Box::pin($crate::async_generator::NoClose(stream));
                 Self($crate::async_generator::AsyncGenerator::new(stream, throw))
             }
 
@@ -144,22 +581,250 @@ macro_rules! generate {
             pub fn from_stream(stream: impl $crate::PyStream + 'static) -> Self {
                 Self::new(Box::pin(stream), None)
             }
+
+            /// Wrap a stream instantiated lazily.
+            ///
+            /// The factory is only called on the first poll of `__anext__` coroutine, so a
+            /// generator which is never iterated doesn't trigger stream side effects, e.g.
+            /// opening a connection, even if it's closed with `aclose`.
+            pub fn from_stream_fn<S: $crate::PyStream + 'static>(
+                factory: impl FnOnce() -> S + Send + 'static,
+            ) -> Self {
+                let factory = Box::new(move || Box::pin(factory()) as _);
+                Self::from_closeable_stream($crate::async_generator::LazyStream::new(factory))
+            }
+
+            /// Wrap a stream of byte chunks, yielded as read-only `memoryview`s backed by the
+            /// Rust buffers, instead of being copied into `bytes` objects.
+            ///
+            /// Each buffer is kept alive until Python releases its last view (see
+            /// [`io::memoryview`](crate::io::memoryview), which falls back to copying with
+            /// `abi3` feature of Python versions older than 3.11). Only owned `Vec<u8>` can be handed
+            /// over without copy; other buffer types, e.g. `bytes::Bytes`, have to be converted
+            /// first, which may copy them.
+            pub fn from_byte_stream<E>(
+                stream: impl ::futures::Stream<Item = Result<Vec<u8>, E>> + Send + 'static,
+            ) -> Self
+            where
+                PyErr: From<E>,
+            {
+                Self::from_stream($crate::io::MemoryViews(stream))
+            }
+
+            /// Wrap a stream built asynchronously, e.g. after opening a connection.
+            ///
+            /// The first `__anext__` drives the construction future, then polls the stream once
+            /// it's built. A construction error is raised from this first `__anext__`, the async
+            /// generator being then exhausted.
+            pub fn from_stream_future<S, E>(
+                future: impl ::std::future::Future<Output = Result<S, E>> + Send + 'static,
+            ) -> Self
+            where
+                S: $crate::PyStream + 'static,
+                E: 'static,
+                PyErr: From<E>,
+            {
+                Self::from_stream($crate::async_generator::StreamFuture::new(future))
+            }
+
+            /// Wrap a stream with asynchronous cleanup.
+            ///
+            /// Async generator `aclose` method will drive
+            /// [`PyStreamClose::poll_close_py`](crate::PyStreamClose::poll_close_py) to
+            /// completion before dropping the stream.
+            pub fn from_closeable_stream(stream: impl $crate::PyStreamClose + 'static) -> Self {
+                Self($crate::async_generator::AsyncGenerator::new(
+                    Box::pin(stream),
+                    None,
+                ))
+            }
+
+            /// Wrap a stream with a Python finalizer, e.g. an async function, called without
+            /// argument and awaited when the stream is exhausted, or when the async generator is
+            /// closed with `aclose`, like a `finally` block of a Python async generator.
+            ///
+            /// The finalizer is called at most once. Its exception, if any, is raised by the
+            /// `__anext__`/`aclose` call having awaited it.
+            pub fn from_stream_with_finalizer(
+                stream: impl $crate::PyStream + 'static,
+                finalizer: PyObject,
+            ) -> Self {
+                Self::from_closeable_stream($crate::async_generator::Finalized::new(
+                    Box::pin(stream),
+                    finalizer,
+                ))
+            }
+
+            /// Drop the stream synchronously when the async generator is garbage-collected
+            /// without being exhausted or closed.
+            ///
+            /// By default, the drop is scheduled in the thread of the event loop where the
+            /// async generator was iterated.
+            pub fn drop_on_gc(mut self) -> Self {
+                self.0.drop_on_gc = true;
+                self
+            }
+
+            /// Set the name of the async generator, shown in its `repr`.
+            pub fn with_name(mut self, name: impl Into<String>) -> Self {
+                self.0.set_name(Some(name.into()));
+                self
+            }
+
+            /// Feed the values sent to the async generator to `sink`, converted to `T`.
+            ///
+            /// `asend(value)` feeds a non-`None` value, waiting for the sink to accept it, before
+            /// awaiting the next item of the stream; `asend(None)` is `__anext__`. The
+            /// additional `asend_many(iterable)` method converts a whole batch with the GIL held,
+            /// then returns a single coroutine feeding it to the sink with backpressure, which
+            /// completes when all the values have been accepted and flushed. It saves a
+            /// coroutine, and a stream poll, per value.
+            ///
+            /// Errors are raised with the index of the failing value in the batch set as their
+            /// `index` attribute; if a conversion fails, no value is fed, while if the sink
+            /// fails, the values before the failing one have been accepted. Without sink,
+            /// `asend_many` raises `TypeError`.
+            ///
+            /// The sink is shared by the batches, which are fed one at a time, and polled with
+            /// the GIL released.
+            pub fn with_sink<T, S>(mut self, sink: S) -> Self
+            where
+                S: ::futures::Sink<T> + Unpin + Send + 'static,
+                T: for<'py> FromPyObject<'py> + Send + 'static,
+                PyErr: From<S::Error>,
+            {
+                self.0.sink = Some($crate::async_generator::send_sink(sink));
+                self
+            }
+
+            /// Set the policy applied to errors yielded by the stream, default to
+            /// [`ErrorPolicy::RaiseAndContinue`](crate::ErrorPolicy::RaiseAndContinue).
+            pub fn error_policy(mut self, policy: $crate::ErrorPolicy) -> Self {
+                self.0.error_policy = policy;
+                self
+            }
+
+            /// Map the errors yielded by the stream before raising them, e.g. into the exception
+            /// classes of the extension module; the error policy applies to the mapped error.
+            pub fn with_map_err(
+                mut self,
+                map_err: impl Fn(Python, PyErr) -> PyErr + Send + Sync + 'static,
+            ) -> Self {
+                self.0.map_err = Some(::std::sync::Arc::new(map_err));
+                self
+            }
+
+            /// Share a stream between multiple async generators, each one receiving every item.
+            ///
+            /// Subscribers only receive items yielded after their subscription. The stream is
+            /// polled by whichever subscriber has consumed all the buffered items; at most `buffer`
+            /// items are kept, and `lag` policy applies to subscribers falling further behind.
+            pub fn broadcast(
+                stream: impl $crate::PyStream + 'static,
+                buffer: usize,
+                lag: $crate::LagPolicy,
+            ) -> Broadcast {
+                Broadcast($crate::broadcast::Broadcast::new(
+                    Box::pin(stream),
+                    buffer,
+                    lag,
+                ))
+            }
+
+            /// Attach a memory footprint, reported by `__sizeof__`.
+            ///
+            /// The footprint is owned separately from the wrapped future/stream, so it should
+            /// share the measured state with it, e.g. through an `Arc`.
+            #[cfg(feature = "diagnostics")]
+            pub fn with_footprint(
+                mut self,
+                footprint: impl $crate::diagnostics::MemoryFootprint + Send + 'static,
+            ) -> Self {
+                self.0.footprint = Some(Box::new(footprint));
+                self
+            }
+        }
+
+        /// Factory of async generators sharing a broadcast stream (see
+        /// [`AsyncGenerator::broadcast`]).
+        #[pyclass]
+        pub struct Broadcast($crate::broadcast::Broadcast);
+
+        impl Broadcast {
+            /// Spawn a task driving the stream, so items are buffered even when no subscriber
+            /// is iterating.
+            ///
+            /// Must be called in the context of a tokio runtime.
+            #[cfg(feature = "tokio")]
+            pub fn spawn_driver(&self) -> ::tokio::task::JoinHandle<()> {
+                ::tokio::spawn(self.0.drive())
+            }
+        }
+
+        #[pymethods]
+        impl Broadcast {
+            fn subscribe(&self) -> AsyncGenerator {
+                AsyncGenerator::from_stream(self.0.subscribe())
+            }
+
+            fn __call__(&self) -> AsyncGenerator {
+                self.subscribe()
+            }
         }
 
         #[pymethods]
         impl AsyncGenerator {
-            fn asend(&mut self, py: Python, _value: &PyAny) -> PyResult<PyObject> {
-                self.0.next(py)
+            fn asend(&mut self, py: Python, value: &PyAny) -> PyResult<PyObject> {
+                self.0.send(py, value)
+            }
+
+            /// Feed all the values of an iterable to the sink, in a single coroutine.
+            fn asend_many(&mut self, py: Python, values: &PyAny) -> PyResult<PyObject> {
+                self.0.send_many(py, values)
             }
 
-            fn athrow(&mut self, py: Python, exc: &PyAny) -> PyResult<PyObject> {
-                self.0.throw(py, PyErr::from_value(exc))
+            #[pyo3(signature = (typ, val = None, tb = None))]
+            fn athrow(
+                &mut self,
+                py: Python,
+                typ: &PyAny,
+                val: Option<&PyAny>,
+                tb: Option<&PyAny>,
+            ) -> PyResult<PyObject> {
+                self.0.throw(py, $crate::utils::throw_exception(typ, val, tb)?)
             }
 
             fn aclose(&mut self, py: Python) -> PyResult<PyObject> {
                 self.0.close(py)
             }
 
+            /// Current `(lower, upper)` bounds on the number of remaining items, `upper` being
+            /// `None` if unknown.
+            fn remaining_hint(&self) -> (usize, Option<usize>) {
+                self.0.remaining_hint()
+            }
+
+            /// Name of the async generator, shown in its `repr`.
+            #[getter]
+            fn name(&self) -> Option<String> {
+                self.0.name().map(Into::into)
+            }
+
+            #[setter]
+            fn set_name(&mut self, name: Option<String>) {
+                self.0.set_name(name);
+            }
+
+            fn __repr__(self_: &PyCell<Self>) -> PyResult<String> {
+                $crate::utils::repr(self_, self_.try_borrow()?.0.name())
+            }
+
+            /// Async backend iterating the generator, resolved on first iteration for `sniffio`.
+            #[getter]
+            fn backend(&self) -> &'static str {
+                self.0.backend()
+            }
+
             fn __aiter__(self_: &PyCell<Self>) -> PyResult<&PyAny> {
                 Ok(self_)
             }
@@ -168,7 +833,172 @@ macro_rules! generate {
             fn __anext__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
                 self.0.next(py).map(Some)
             }
+
+            #[cfg(feature = "diagnostics")]
+            fn __sizeof__(&self) -> usize {
+                use $crate::diagnostics::MemoryFootprint;
+                ::std::mem::size_of::<::pyo3::PyCell<Self>>() + self.0.footprint.approx_bytes()
+            }
+        }
+
+        /// Python async context manager acquiring and releasing a Rust resource.
+        #[pyclass]
+        pub struct AsyncResource($crate::async_resource::AsyncResource);
+
+        impl AsyncResource {
+            /// Wrap the acquisition and release of a resource.
+            ///
+            /// `__aenter__` awaits `acquire` and returns the resource; `__aexit__` then awaits
+            /// `release`, called with the resource and the exception raised in the `async with`
+            /// block, if any. This exception is not suppressed, and an error returned by
+            /// `release` is raised from `__aexit__`, chained to the block exception as its
+            /// `__context__`. `release` is not called if the acquisition has failed.
+            pub fn new<T, A, R, RF>(acquire: A, release: R) -> Self
+            where
+                T: Clone + IntoPy<PyObject> + Send + 'static,
+                A: ::std::future::Future<Output = PyResult<T>> + Send + 'static,
+                R: FnOnce(T, Option<PyErr>) -> RF + Send + 'static,
+                RF: ::std::future::Future<Output = PyResult<()>> + Send + 'static,
+            {
+                Self($crate::async_resource::AsyncResource::new(acquire, release))
+            }
+        }
+
+        #[pymethods]
+        impl AsyncResource {
+            fn __aenter__(&mut self) -> PyResult<Coroutine> {
+                Ok(Coroutine::new(self.0.acquire()?, None))
+            }
+
+            #[pyo3(signature = (_exc_type, exc, _traceback))]
+            fn __aexit__(
+                &mut self,
+                _exc_type: &PyAny,
+                exc: Option<&PyAny>,
+                _traceback: &PyAny,
+            ) -> PyResult<Coroutine> {
+                Ok(Coroutine::new(self.0.release(exc.map(PyErr::from_value))?, None))
+            }
         }
     };
 }
 pub(crate) use generate;
+
+/// Trace an event of the coroutine/waker lifecycle, at trace level with `pyo3_async` target,
+/// for post-mortem analysis of lost wakeups.
+///
+/// Events carry the `coroutine` id and its poll `cycle`, incremented each time the waker is
+/// renewed, so a wake can be correlated with the yield it targeted:
+/// - `waker created`, with `backend` and `thread`;
+/// - `waker updated`;
+/// - `yield`, with the `future` id (address of the yielded object), or `checkpoint`;
+/// - `wake`, with `thread`, `same_thread` and `polling`;
+/// - `raise`, with the exception raised by the waker, e.g. a cancellation;
+/// - `coroutine completed`, with `ok`, and `coroutine dropped`, with `pending`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{
+///     fmt,
+///     sync::{Arc, Mutex},
+///     task::{Poll, Waker},
+/// };
+///
+/// use pyo3::prelude::*;
+/// use pyo3_async::manual::Coroutine;
+/// use tracing::{
+///     field::{Field, Visit},
+///     span, Event, Metadata, Subscriber,
+/// };
+///
+/// /// Collect `message cycle=...` of each event.
+/// #[derive(Clone, Default)]
+/// struct Collect(Arc<Mutex<Vec<String>>>);
+///
+/// struct Fields(String, u64);
+/// impl Visit for Fields {
+///     fn record_u64(&mut self, field: &Field, value: u64) {
+///         if field.name() == "cycle" {
+///             self.1 = value;
+///         }
+///     }
+///     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+///         if field.name() == "message" {
+///             self.0 = format!("{value:?}");
+///         }
+///     }
+/// }
+///
+/// impl Subscriber for Collect {
+///     fn enabled(&self, metadata: &Metadata) -> bool {
+///         metadata.target() == "pyo3_async"
+///     }
+///     fn event(&self, event: &Event) {
+///         let mut fields = Fields(String::new(), 0);
+///         event.record(&mut fields);
+///         let Fields(message, cycle) = fields;
+///         self.0.lock().unwrap().push(format!("{message} cycle={cycle}"));
+///     }
+///     fn new_span(&self, _: &span::Attributes) -> span::Id {
+///         span::Id::from_u64(1)
+///     }
+///     fn record(&self, _: &span::Id, _: &span::Record) {}
+///     fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+///     fn enter(&self, _: &span::Id) {}
+///     fn exit(&self, _: &span::Id) {}
+/// }
+///
+/// pyo3::prepare_freethreaded_python();
+/// let collect = Collect::default();
+/// tracing::subscriber::with_default(collect.clone(), || {
+///     Python::with_gil(|py| {
+///         // pending for two cycles, its waker being woken from Rust
+///         let waker = Arc::new(Mutex::new(None::<Waker>));
+///         let stored = waker.clone();
+///         let mut steps = 0;
+///         let coroutine = Coroutine::from_step_fn(move |py, cx| {
+///             steps += 1;
+///             if steps < 3 {
+///                 *stored.lock().unwrap() = Some(cx.waker().clone());
+///                 return Poll::Pending;
+///             }
+///             Poll::Ready(Ok(py.None()))
+///         });
+///         let coroutine = Py::new(py, coroutine)?;
+///         for _ in 0..2 {
+///             coroutine.call_method1(py, "send", (py.None(),))?;
+///             waker.lock().unwrap().take().unwrap().wake();
+///         }
+///         assert!(coroutine.call_method1(py, "send", (py.None(),)).is_err());
+///         PyResult::Ok(())
+///     })
+/// })
+/// .unwrap();
+/// let events = collect.0.lock().unwrap().clone();
+/// let expected = [
+///     "waker created cycle=1",
+///     "yield cycle=1",
+///     "wake cycle=1",
+///     "waker updated cycle=2",
+///     "yield cycle=2",
+///     "wake cycle=2",
+///     "waker updated cycle=3",
+///     "coroutine completed cycle=3",
+///     "coroutine dropped cycle=3",
+/// ];
+/// assert_eq!(events, expected);
+/// ```
+#[cfg(feature = "tracing")]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        ::tracing::trace!(target: "pyo3_async", $($arg)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+pub(crate) use trace;